@@ -0,0 +1,97 @@
+//! Stamps the firmware with the git commit and build date, and turns the
+//! partition table into constants
+//!
+//! Both end up in GetVersion's build id (`config::protocol::BUILD_ID`), so two
+//! builds of the same version can be told apart. Outside a git checkout the
+//! hash is `unknown`. The script reruns when the commit changes, so the date
+//! is that of the first build of each commit; `SOURCE_DATE_EPOCH` overrides
+//! it for reproducible builds.
+//!
+//! Each row of `partitions.csv` becomes a `<NAME>_OFFSET` and `<NAME>_SIZE`
+//! in `config::partitions`, so the flash offsets the firmware uses are the
+//! ones espflash writes the table with.
+
+use std::fmt::Write as _;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=8", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs()));
+    let (year, month, day) = civil_date(secs / 86_400);
+
+    println!("cargo:rustc-env=WT_GIT_HASH={}", hash);
+    println!("cargo:rustc-env=WT_BUILD_DATE={:04}-{:02}-{:02}", year, month, day);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let table = std::fs::read_to_string("partitions.csv").expect("partitions.csv is missing");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+    std::fs::write(format!("{}/partitions.rs", out_dir), partition_consts(&table)).expect("cannot write partitions.rs");
+    println!("cargo:rerun-if-changed=partitions.csv");
+}
+
+/// Offset and size constants for every row of an ESP-IDF partition table
+///
+/// Rows must give their offset: the firmware cannot follow one that espflash
+/// would place itself.
+fn partition_consts(table: &str) -> String {
+    let mut consts = String::new();
+    for line in table.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [name, _, _, offset, size, ..] = fields[..] else {
+            panic!("partitions.csv: malformed row `{}`", line);
+        };
+        let name = name.to_uppercase();
+        let offset = parse_size(offset).unwrap_or_else(|| panic!("partitions.csv: {} needs an offset", name));
+        let size = parse_size(size).unwrap_or_else(|| panic!("partitions.csv: bad size for {}", name));
+        let _ = writeln!(consts, "pub const {}_OFFSET: u32 = {:#x};", name, offset);
+        let _ = writeln!(consts, "pub const {}_SIZE: u32 = {:#x};", name, size);
+    }
+    consts
+}
+
+/// A partition table number: decimal or `0x` hex, optionally in K or M
+fn parse_size(text: &str) -> Option<u32> {
+    let (digits, scale) = match text.as_bytes().last()? {
+        b'K' | b'k' => (&text[..text.len() - 1], 1024),
+        b'M' | b'm' => (&text[..text.len() - 1], 1024 * 1024),
+        _ => (text, 1),
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    value.checked_mul(scale)
+}
+
+/// Year, month and day of `days` since 1970-01-01 (proleptic Gregorian)
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Shift to years starting 1 March 0000, so the leap day ends the year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
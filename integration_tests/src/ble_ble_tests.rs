@@ -9,6 +9,7 @@
 
 mod ble_client;
 mod protocol;
+mod report;
 
 use std::time::Duration;
 
@@ -16,7 +17,8 @@ use clap::Parser;
 use colored::Colorize;
 
 use ble_client::BleClient;
-use protocol::ResponseId;
+use protocol::{parse_version, tx_complete_parts, ResponseId};
+use report::{progress, progressln, Format, Report};
 
 #[derive(Parser)]
 #[command(name = "ble-ble-tests")]
@@ -33,166 +35,88 @@ struct Args {
     /// BLE scan timeout in seconds
     #[arg(long, default_value = "10")]
     scan_timeout: u64,
+
+    /// Result output: coloured text, or a JSON array on stdout for CI
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    report::set_format(args.format);
 
-    println!("{}", "BLE-BLE Integration Tests".bold());
-    println!("Device A: BLE (scanning for \"{}\")", args.ble_name_a);
-    println!("Device B: BLE (scanning for \"{}\")", args.ble_name_b);
-    println!();
+    progressln!("{}", "BLE-BLE Integration Tests".bold());
+    progressln!("Device A: BLE (scanning for \"{}\")", args.ble_name_a);
+    progressln!("Device B: BLE (scanning for \"{}\")", args.ble_name_b);
+    progressln!();
 
     // Connect to Device A via BLE
-    println!("Scanning for BLE device A \"{}\"...", args.ble_name_a);
+    progressln!("Scanning for BLE device A \"{}\"...", args.ble_name_a);
     let device_a = BleClient::connect_by_name(
         &args.ble_name_a,
         Duration::from_secs(args.scan_timeout),
     )
     .await?;
-    println!("{}", "  Device A connected!".green());
+    progressln!("{}", "  Device A connected!".green());
 
     // Connect to Device B via BLE
-    println!("Scanning for BLE device B \"{}\"...", args.ble_name_b);
+    progressln!("Scanning for BLE device B \"{}\"...", args.ble_name_b);
     let device_b = BleClient::connect_by_name(
         &args.ble_name_b,
         Duration::from_secs(args.scan_timeout),
     )
     .await?;
-    println!("{}", "  Device B connected!".green());
-
-    // Allow time for LoRa radios to enter RX mode after init
-    println!("Waiting for LoRa radios to initialise...");
-    tokio::time::sleep(Duration::from_millis(500)).await;
+    progressln!("{}", "  Device B connected!".green());
 
-    // Clear any pending data
-    device_a.clear_buffer().await;
-    device_b.clear_buffer().await;
+    // Both LoRa tasks answer Sync only once their radios are initialised,
+    // and the handshake leaves each stream clean.
+    progressln!("Waiting for devices to be ready...");
+    device_a.wait_ready(Duration::from_secs(3)).await?;
+    device_b.wait_ready(Duration::from_secs(3)).await?;
 
     // Prime both directions so the first scored LoRa test does not eat the
     // cold-start packet miss (the receiver re-arms RX between poll cycles).
-    print!("Warming up LoRa link... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
+    progress!("Warming up LoRa link... ");
     warm_up(&device_a, &device_b).await;
-    println!("done");
+    progressln!("done");
 
-    println!("\n{}", "Running tests...".bold());
-    println!();
+    progressln!("\n{}", "Running tests...".bold());
+    progressln!();
 
-    let mut passed = 0;
-    let mut failed = 0;
+    let mut report = Report::new();
 
     // Test 1: BLE GetVersion (Device A)
-    print!("  Test 1: BLE GetVersion (Device A) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ble_get_version(&device_a, "A").await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 1: BLE GetVersion (Device A)");
+    report.finish(test, test_ble_get_version(&device_a, "A").await);
 
     // Test 2: BLE GetVersion (Device B)
-    print!("  Test 2: BLE GetVersion (Device B) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ble_get_version(&device_b, "B").await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 2: BLE GetVersion (Device B)");
+    report.finish(test, test_ble_get_version(&device_b, "B").await);
 
     // Test 3: Device A to LoRa to Device B
-    print!("  Test 3: BLE A to LoRa to BLE B ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_a_to_b(&device_a, &device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 3: BLE A to LoRa to BLE B");
+    report.finish(test, test_a_to_b(&device_a, &device_b).await);
 
     // Test 4: Device B to LoRa to Device A
-    print!("  Test 4: BLE B to LoRa to BLE A ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_b_to_a(&device_a, &device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 4: BLE B to LoRa to BLE A");
+    report.finish(test, test_b_to_a(&device_a, &device_b).await);
 
     // Test 5: Bidirectional Ping-Pong
-    print!("  Test 5: Bidirectional Ping-Pong ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ping_pong(&device_a, &device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 5: Bidirectional Ping-Pong");
+    report.finish(test, test_ping_pong(&device_a, &device_b).await);
 
     // Test 6: Multiple Messages (10 round trips)
-    print!("  Test 6: Multiple Messages (10 round trips) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_multiple_messages(&device_a, &device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 6: Multiple Messages (10 round trips)");
+    report.finish(test, test_multiple_messages(&device_a, &device_b).await);
 
     // Disconnect BLE
     let _ = device_a.disconnect().await;
     let _ = device_b.disconnect().await;
 
-    // Summary
-    println!("\n{}", "=".repeat(60));
-    println!("{}", "Test Results".bold());
-    println!("{}", "=".repeat(60));
-    println!(
-        "  Total: {} passed, {} failed",
-        passed.to_string().green(),
-        if failed > 0 {
-            failed.to_string().red()
-        } else {
-            failed.to_string().normal()
-        }
-    );
-    println!("{}", "=".repeat(60));
+    report.print();
 
-    if failed > 0 {
+    if report.failed() > 0 {
         std::process::exit(1);
     }
 
@@ -230,19 +154,10 @@ async fn test_ble_get_version(device: &BleClient, name: &str) -> anyhow::Result<
         anyhow::bail!("Expected Version response, got {:?}", response.resp_id);
     }
 
-    if response.payload.len() < 3 {
-        anyhow::bail!(
-            "Version payload too short: {} bytes",
-            response.payload.len()
-        );
-    }
-
-    let (major, minor, patch) = (
-        response.payload[0],
-        response.payload[1],
-        response.payload[2],
-    );
-    println!("    Device {} (BLE): v{}.{}.{}", name, major, minor, patch);
+    let Some(version) = parse_version(&response.payload) else {
+        anyhow::bail!("Malformed version payload: {:02x?}", response.payload);
+    };
+    progressln!("    Device {} (BLE): {}", name, version);
 
     Ok(())
 }
@@ -262,6 +177,10 @@ async fn test_a_to_b(device_a: &BleClient, device_b: &BleClient) -> anyhow::Resu
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // B should receive the packet
     device_b
@@ -286,6 +205,10 @@ async fn test_b_to_a(device_a: &BleClient, device_b: &BleClient) -> anyhow::Resu
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // A should receive the packet
     device_a
@@ -2,6 +2,7 @@
 
 #![allow(dead_code)]
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -12,13 +13,24 @@ use btleplug::api::{
 use btleplug::platform::{Adapter, Manager, Peripheral};
 use futures::StreamExt;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 use uuid::Uuid;
 
 use crate::protocol::{
-    build_command, build_command_payload, cobs_decode, cobs_encode, parse_response, CommandId,
-    Response, ResponseId,
+    build_command, build_command_payload, cobs_decode, cobs_encode, is_unsolicited, parse_response,
+    rx_packet_parts, sync_nonce, CommandId, Response, ResponseId,
 };
+use crate::report::progressln;
+
+/// How long each Sync attempt waits for its SyncAck before resending.
+const SYNC_ATTEMPT: Duration = Duration::from_millis(300);
+
+/// Attempts at the initial connection, and at reconnecting before a command.
+const CONNECT_ATTEMPTS: u32 = 3;
+
+/// Pause between connection attempts.
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 /// Nordic UART Service UUIDs
 const NUS_SERVICE_UUID: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
@@ -32,10 +44,18 @@ pub struct BleClient {
     tx_char: Characteristic,
     /// Buffer for accumulating notification data
     notification_buffer: Arc<Mutex<Vec<u8>>>,
+    /// Set while the notification task is reading; cleared when its stream
+    /// ends (the link dropped) or could not be opened
+    listening: Arc<AtomicBool>,
+    /// The notification task, replaced on reconnection
+    listener: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// Print frames `wait_for_response_id` discards
+    log_skipped: bool,
 }
 
 impl BleClient {
-    /// Scan for a device by name and connect.
+    /// Scan for a device by name and connect, retrying up to
+    /// `CONNECT_ATTEMPTS` times.
     pub async fn connect_by_name(name: &str, scan_timeout: Duration) -> Result<Self> {
         let manager = Manager::new().await?;
         let adapters = manager.adapters().await?;
@@ -44,19 +64,18 @@ impl BleClient {
             .next()
             .ok_or_else(|| anyhow!("No Bluetooth adapters found"))?;
 
-        // Start scanning
-        adapter.start_scan(ScanFilter::default()).await?;
-
-        // Wait for the device to appear
-        let peripheral = Self::find_device_by_name(&adapter, name, scan_timeout).await?;
-
-        adapter.stop_scan().await?;
-
-        // Connect to the device
-        peripheral.connect().await?;
-
-        // Discover services
-        peripheral.discover_services().await?;
+        let mut attempt = 1;
+        let peripheral = loop {
+            match Self::find_and_connect(&adapter, name, scan_timeout).await {
+                Ok(peripheral) => break peripheral,
+                Err(e) if attempt < CONNECT_ATTEMPTS => {
+                    progressln!("  BLE connect attempt {} failed ({}), retrying...", attempt, e);
+                    attempt += 1;
+                    tokio::time::sleep(CONNECT_RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e.context(format!("BLE connect failed after {} attempts", attempt))),
+            }
+        };
 
         // Find NUS characteristics
         let characteristics = peripheral.characteristics();
@@ -73,34 +92,102 @@ impl BleClient {
             .cloned()
             .ok_or_else(|| anyhow!("NUS TX characteristic not found"))?;
 
-        // Subscribe to notifications on TX characteristic
-        peripheral.subscribe(&tx_char).await?;
+        let client = Self {
+            peripheral,
+            rx_char,
+            tx_char,
+            notification_buffer: Arc::new(Mutex::new(Vec::new())),
+            listening: Arc::new(AtomicBool::new(false)),
+            listener: std::sync::Mutex::new(None),
+            log_skipped: false,
+        };
+        client.listen().await?;
+        Ok(client)
+    }
 
-        let notification_buffer = Arc::new(Mutex::new(Vec::new()));
+    /// Scan for the device, connect and discover its services.
+    async fn find_and_connect(adapter: &Adapter, name: &str, scan_timeout: Duration) -> Result<Peripheral> {
+        adapter.start_scan(ScanFilter::default()).await?;
+        let found = Self::find_device_by_name(adapter, name, scan_timeout).await;
+        adapter.stop_scan().await?;
+        let peripheral = found?;
 
-        // Spawn notification handler
-        let buffer_clone = notification_buffer.clone();
-        let peripheral_clone = peripheral.clone();
-        tokio::spawn(async move {
-            let mut stream = match peripheral_clone.notifications().await {
-                Ok(s) => s,
-                Err(_) => return,
-            };
+        peripheral.connect().await?;
+        if let Err(e) = peripheral.discover_services().await {
+            let _ = peripheral.disconnect().await;
+            return Err(e.into());
+        }
+        Ok(peripheral)
+    }
 
-            while let Some(data) = stream.next().await {
-                if data.uuid == NUS_TX_UUID {
-                    let mut buf = buffer_clone.lock().await;
-                    buf.extend_from_slice(&data.value);
+    /// Subscribe to notifications on the TX characteristic and (re)start the
+    /// task that copies them into the buffer.
+    async fn listen(&self) -> Result<()> {
+        self.peripheral.subscribe(&self.tx_char).await?;
+
+        let buffer = self.notification_buffer.clone();
+        let listening = self.listening.clone();
+        let peripheral = self.peripheral.clone();
+        self.listening.store(true, Ordering::SeqCst);
+        let task = tokio::spawn(async move {
+            if let Ok(mut stream) = peripheral.notifications().await {
+                while let Some(data) = stream.next().await {
+                    if data.uuid == NUS_TX_UUID {
+                        let mut buf = buffer.lock().await;
+                        buf.extend_from_slice(&data.value);
+                    }
                 }
             }
+            // Reads now fail instead of waiting out their timeout, and the
+            // next command reconnects.
+            listening.store(false, Ordering::SeqCst);
         });
 
-        Ok(Self {
-            peripheral,
-            rx_char,
-            tx_char,
-            notification_buffer,
-        })
+        if let Some(old) = self.listener.lock().unwrap().replace(task) {
+            old.abort();
+        }
+        Ok(())
+    }
+
+    /// Reconnect if the link dropped or the notification stream died,
+    /// retrying up to `CONNECT_ATTEMPTS` times.
+    async fn ensure_connected(&self) -> Result<()> {
+        let connected = self.peripheral.is_connected().await.unwrap_or(false);
+        if connected && self.listening.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let mut attempt = 1;
+        loop {
+            match self.reconnect().await {
+                Ok(()) => {
+                    progressln!("    (BLE reconnected)");
+                    return Ok(());
+                }
+                Err(_) if attempt < CONNECT_ATTEMPTS => {
+                    attempt += 1;
+                    tokio::time::sleep(CONNECT_RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e.context(format!("BLE reconnect failed after {} attempts", attempt))),
+            }
+        }
+    }
+
+    /// Connect again if needed and restart notifications.
+    ///
+    /// Any partial frame left from before the drop is discarded.
+    async fn reconnect(&self) -> Result<()> {
+        if !self.peripheral.is_connected().await? {
+            self.peripheral.connect().await?;
+            self.peripheral.discover_services().await?;
+        }
+        self.clear_buffer().await;
+        self.listen().await
+    }
+
+    /// Print every frame discarded while waiting for a specific response.
+    pub fn set_log_skipped(&mut self, log_skipped: bool) {
+        self.log_skipped = log_skipped;
     }
 
     /// Find a device by name within the scan timeout.
@@ -146,6 +233,7 @@ impl BleClient {
         }
 
         // Build and send command
+        self.ensure_connected().await?;
         let frame = build_command(cmd_id, payload);
         self.peripheral
             .write(&self.rx_char, &frame, WriteType::WithoutResponse)
@@ -168,6 +256,7 @@ impl BleClient {
             buf.clear();
         }
 
+        self.ensure_connected().await?;
         let frame = cobs_encode(&build_command_payload(cmd_id, payload));
         self.peripheral
             .write(&self.rx_char, &frame, WriteType::WithoutResponse)
@@ -179,6 +268,8 @@ impl BleClient {
     /// Read the next complete notification frame (any response type).
     ///
     /// Awaits until a whole COBS frame is available; never returns a partial.
+    /// Fails once the buffer is drained if the notification stream has died,
+    /// rather than waiting out the caller's timeout.
     async fn read_next_response(&self) -> Result<Response> {
         loop {
             let mut buf = self.notification_buffer.lock().await;
@@ -191,6 +282,8 @@ impl BleClient {
                 } else {
                     buf.remove(0); // empty frame, skip delimiter
                 }
+            } else if !self.listening.load(Ordering::SeqCst) {
+                return Err(anyhow!("BLE notification stream closed (connection dropped?)"));
             } else {
                 drop(buf);
                 tokio::time::sleep(Duration::from_millis(10)).await;
@@ -198,16 +291,16 @@ impl BleClient {
         }
     }
 
-    /// Wait for a command reply, skipping unsolicited RxPackets.
+    /// Wait for a command reply, skipping unsolicited RxPackets and events.
     ///
     /// The device shares one notify stream for command replies and unsolicited
-    /// LoRa RxPackets, and the slow radio can deliver a packet late. Command
-    /// replies are Version / TxComplete / Error, never RxPacket.
+    /// LoRa RxPackets and BLE events, and the slow radio can deliver a packet
+    /// late. Command replies are never RxPacket or Event.
     pub async fn wait_for_response(&self, response_timeout: Duration) -> Result<Response> {
         timeout(response_timeout, async {
             loop {
                 let response = self.read_next_response().await?;
-                if response.resp_id != ResponseId::RxPacket {
+                if !is_unsolicited(response.resp_id) {
                     return Ok::<_, anyhow::Error>(response);
                 }
             }
@@ -225,22 +318,31 @@ impl BleClient {
         }
     }
 
-    /// Wait for any unsolicited RxPacket response.
-    pub async fn wait_for_rx_packet(&self, timeout_duration: Duration) -> Result<Response> {
+    /// Wait for a response with the given id, discarding anything else (a
+    /// stray TxComplete, Error or event).
+    pub async fn wait_for_response_id(&self, id: ResponseId, timeout_duration: Duration) -> Result<Response> {
         timeout(timeout_duration, async {
             loop {
                 let response = self.read_next_response().await?;
-                if response.resp_id == ResponseId::RxPacket {
+                if response.resp_id == id {
                     return Ok::<_, anyhow::Error>(response);
                 }
+                if self.log_skipped {
+                    progressln!("    (skipped {:?} {:02x?})", response.resp_id, response.payload);
+                }
             }
         })
         .await
-        .map_err(|_| anyhow!("Timeout waiting for BLE RxPacket"))?
+        .map_err(|_| anyhow!("Timeout waiting for BLE {:?}", id))?
+    }
+
+    /// Wait for any unsolicited RxPacket response.
+    pub async fn wait_for_rx_packet(&self, timeout_duration: Duration) -> Result<Response> {
+        self.wait_for_response_id(ResponseId::RxPacket, timeout_duration).await
     }
 
     /// Wait for an RxPacket whose data payload equals `expected`, skipping stale
-    /// or late packets from earlier exchanges. Trailing 3 bytes are rssi+snr.
+    /// or late packets from earlier exchanges. The payload is source, data, rssi+snr.
     pub async fn wait_for_rx_packet_matching(
         &self,
         expected: &[u8],
@@ -250,25 +352,93 @@ impl BleClient {
             loop {
                 let response = self.read_next_response().await?;
                 if response.resp_id == ResponseId::RxPacket
-                    && response.payload.len() >= 3
-                    && &response.payload[..response.payload.len() - 3] == expected
+                    && rx_packet_parts(&response.payload).is_some_and(|(_, data)| data == expected)
                 {
                     return Ok::<_, anyhow::Error>(response);
                 }
+                if self.log_skipped {
+                    progressln!("    (skipped {:?} {:02x?})", response.resp_id, response.payload);
+                }
             }
         })
         .await
         .map_err(|_| anyhow!("Timeout waiting for BLE RxPacket matching {:?}", expected))?
     }
 
+    /// Wait until the firmware answers Sync and the stream is clean, or the
+    /// timeout elapses.
+    ///
+    /// Sends Sync with a new nonce each attempt and discards every
+    /// notification until the SyncAck echoing the current nonce, so stale
+    /// packets and replies cannot leak into the first test. The LoRa task
+    /// answers only once its radio is initialised.
+    pub async fn wait_ready(&self, timeout_duration: Duration) -> Result<()> {
+        let start = std::time::Instant::now();
+        let mut nonce = sync_nonce();
+        while start.elapsed() < timeout_duration {
+            nonce = nonce.wrapping_add(1);
+            let expected = nonce.to_le_bytes();
+            self.ensure_connected().await?;
+            self.peripheral
+                .write(&self.rx_char, &build_command(CommandId::Sync, &expected), WriteType::WithoutResponse)
+                .await?;
+
+            let acked = timeout(SYNC_ATTEMPT, async {
+                loop {
+                    let response = self.read_next_response().await?;
+                    if response.resp_id == ResponseId::SyncAck && response.payload == expected {
+                        return Ok::<_, anyhow::Error>(());
+                    }
+                }
+            })
+            .await;
+            if let Ok(result) = acked {
+                return result;
+            }
+        }
+        Err(anyhow!("Timeout waiting for BLE SyncAck"))
+    }
+
     /// Send LoRa TX command with data.
     pub async fn lora_tx(&self, data: &[u8], response_timeout: Duration) -> Result<Response> {
         self.send_command(CommandId::LoraTx, data, response_timeout)
             .await
     }
 
+    /// Send a Ping stamped with the current time and return the round trip.
+    ///
+    /// The firmware echoes the payload without touching the radio, so this
+    /// measures pure BLE transport latency. Fails if the echo does not match.
+    pub async fn ping(&self, response_timeout: Duration) -> Result<Duration> {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_micros() as u64;
+        let payload = stamp.to_le_bytes();
+
+        let start = std::time::Instant::now();
+        let response = self
+            .send_command(CommandId::Ping, &payload, response_timeout)
+            .await?;
+        let round_trip = start.elapsed();
+
+        if response.resp_id != ResponseId::Pong {
+            return Err(anyhow!("Expected Pong response, got {:?}", response.resp_id));
+        }
+        if response.payload != payload {
+            return Err(anyhow!(
+                "Pong payload mismatch: sent {:02x?}, got {:02x?}",
+                payload,
+                response.payload
+            ));
+        }
+        Ok(round_trip)
+    }
+
     /// Disconnect from the device.
     pub async fn disconnect(&self) -> Result<()> {
+        if let Some(task) = self.listener.lock().unwrap().take() {
+            task.abort();
+        }
         self.peripheral.unsubscribe(&self.tx_char).await?;
         self.peripheral.disconnect().await?;
         Ok(())
@@ -285,5 +455,8 @@ impl Drop for BleClient {
     fn drop(&mut self) {
         // Note: We can't do async cleanup in Drop, but the peripheral
         // will be disconnected when it goes out of scope anyway
+        if let Some(task) = self.listener.get_mut().unwrap().take() {
+            task.abort();
+        }
     }
 }
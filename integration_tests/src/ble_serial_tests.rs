@@ -10,6 +10,7 @@
 mod ble_client;
 mod device;
 mod protocol;
+mod report;
 
 use std::time::Duration;
 
@@ -18,7 +19,8 @@ use colored::Colorize;
 
 use ble_client::BleClient;
 use device::{resolve_port, DeviceClient};
-use protocol::ResponseId;
+use protocol::{parse_version, tx_complete_parts, ResponseId};
+use report::{progress, progressln, Format, Report};
 
 #[derive(Parser)]
 #[command(name = "ble-serial-tests")]
@@ -36,155 +38,98 @@ struct Args {
     #[arg(short, long, default_value = "115200")]
     baud: u32,
 
+    /// Print frames skipped while waiting for a specific response
+    #[arg(long)]
+    log_skipped: bool,
+
     /// BLE scan timeout in seconds
     #[arg(long, default_value = "10")]
     scan_timeout: u64,
+
+    /// Result output: coloured text, or a JSON array on stdout for CI
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    report::set_format(args.format);
 
     // Resolve port (auto-detect if "auto")
     let port_b = resolve_port(&args.port_b)?;
 
-    println!("{}", "BLE-Serial Integration Tests".bold());
-    println!("Device A: BLE (scanning for \"{}\")", args.ble_name);
-    println!("Device B: Serial ({})", port_b);
-    println!();
+    progressln!("{}", "BLE-Serial Integration Tests".bold());
+    progressln!("Device A: BLE (scanning for \"{}\")", args.ble_name);
+    progressln!("Device B: Serial ({})", port_b);
+    progressln!();
 
     // Connect to Device B via serial
-    println!("Connecting to Device B via serial...");
+    progressln!("Connecting to Device B via serial...");
     let mut device_b = DeviceClient::new(&port_b, args.baud)?;
+    device_b.set_log_skipped(args.log_skipped);
 
     // Confirm the serial device responds (also warms up the link).
     device_b.wait_ready(Duration::from_secs(3))?;
-    println!("{}", "  Serial connected!".green());
+    progressln!("{}", "  Serial connected!".green());
 
     // Connect to Device A via BLE
-    println!("Scanning for BLE device \"{}\"...", args.ble_name);
-    let device_a = BleClient::connect_by_name(
+    progressln!("Scanning for BLE device \"{}\"...", args.ble_name);
+    let mut device_a = BleClient::connect_by_name(
         &args.ble_name,
         Duration::from_secs(args.scan_timeout),
     )
     .await?;
-    println!("{}", "  BLE connected!".green());
-
-    // Allow time for LoRa radios to enter RX mode after init
-    println!("Waiting for LoRa radios to initialise...");
-    tokio::time::sleep(Duration::from_millis(500)).await;
+    device_a.set_log_skipped(args.log_skipped);
+    progressln!("{}", "  BLE connected!".green());
 
-    // Clear any pending data
-    device_a.clear_buffer().await;
-    device_b.clear_buffer()?;
+    // The LoRa task answers Sync only once its radio is initialised, and the
+    // handshake leaves each stream clean.
+    progressln!("Waiting for devices to be ready...");
+    device_a.wait_ready(Duration::from_secs(3)).await?;
+    device_b.wait_ready(Duration::from_secs(3))?;
 
     // Prime both directions so the first scored LoRa test does not eat the
     // cold-start packet miss (the receiver re-arms RX between poll cycles).
-    print!("Warming up LoRa link... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
+    progress!("Warming up LoRa link... ");
     warm_up(&device_a, &mut device_b).await;
-    println!("done");
+    progressln!("done");
 
-    println!("\n{}", "Running tests...".bold());
-    println!();
+    progressln!("\n{}", "Running tests...".bold());
+    progressln!();
 
-    let mut passed = 0;
-    let mut failed = 0;
+    let mut report = Report::new();
 
     // Test 1: BLE GetVersion (Device A)
-    print!("  Test 1: BLE GetVersion (Device A) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ble_get_version(&device_a).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 1: BLE GetVersion (Device A)");
+    report.finish(test, test_ble_get_version(&device_a).await);
 
     // Test 2: Serial GetVersion (Device B)
-    print!("  Test 2: Serial GetVersion (Device B) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_serial_get_version(&mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 2: Serial GetVersion (Device B)");
+    report.finish(test, test_serial_get_version(&mut device_b));
 
     // Test 3: BLE to LoRa to Serial
-    print!("  Test 3: BLE to LoRa to Serial ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ble_to_serial(&device_a, &mut device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 3: BLE to LoRa to Serial");
+    report.finish(test, test_ble_to_serial(&device_a, &mut device_b).await);
 
     // Test 4: Serial to LoRa to BLE
-    print!("  Test 4: Serial to LoRa to BLE ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_serial_to_ble(&device_a, &mut device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 4: Serial to LoRa to BLE");
+    report.finish(test, test_serial_to_ble(&device_a, &mut device_b).await);
 
     // Test 5: Bidirectional Ping-Pong
-    print!("  Test 5: Bidirectional Ping-Pong ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ping_pong(&device_a, &mut device_b).await {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 5: Bidirectional Ping-Pong");
+    report.finish(test, test_ping_pong(&device_a, &mut device_b).await);
+
+    // Test 6: Large packet (200 bytes) Serial to LoRa to BLE
+    let test = report.start("Test 6: Large packet (200 bytes) Serial to LoRa to BLE");
+    report.finish(test, test_large_packet_to_ble(&device_a, &mut device_b).await);
 
     // Disconnect BLE
     let _ = device_a.disconnect().await;
 
-    // Summary
-    println!("\n{}", "=".repeat(60));
-    println!("{}", "Test Results".bold());
-    println!("{}", "=".repeat(60));
-    println!(
-        "  Total: {} passed, {} failed",
-        passed.to_string().green(),
-        if failed > 0 {
-            failed.to_string().red()
-        } else {
-            failed.to_string().normal()
-        }
-    );
-    println!("{}", "=".repeat(60));
+    report.print();
 
-    if failed > 0 {
+    if report.failed() > 0 {
         std::process::exit(1);
     }
 
@@ -232,19 +177,10 @@ async fn test_ble_get_version(device_a: &BleClient) -> anyhow::Result<()> {
         anyhow::bail!("Expected Version response, got {:?}", response.resp_id);
     }
 
-    if response.payload.len() < 3 {
-        anyhow::bail!(
-            "Version payload too short: {} bytes",
-            response.payload.len()
-        );
-    }
-
-    let (major, minor, patch) = (
-        response.payload[0],
-        response.payload[1],
-        response.payload[2],
-    );
-    println!("    Device A (BLE): v{}.{}.{}", major, minor, patch);
+    let Some(version) = parse_version(&response.payload) else {
+        anyhow::bail!("Malformed version payload: {:02x?}", response.payload);
+    };
+    progressln!("    Device A (BLE): {}", version);
 
     Ok(())
 }
@@ -257,12 +193,10 @@ fn test_serial_get_version(device_b: &mut DeviceClient) -> anyhow::Result<()> {
         anyhow::bail!("Expected Version response, got {:?}", response.resp_id);
     }
 
-    let (major, minor, patch) = (
-        response.payload[0],
-        response.payload[1],
-        response.payload[2],
-    );
-    println!("    Device B (Serial): v{}.{}.{}", major, minor, patch);
+    let Some(version) = parse_version(&response.payload) else {
+        anyhow::bail!("Malformed version payload: {:02x?}", response.payload);
+    };
+    progressln!("    Device B (Serial): {}", version);
 
     Ok(())
 }
@@ -285,6 +219,10 @@ async fn test_ble_to_serial(
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // B (serial) should receive the packet as an unsolicited RxPacket
     device_b.wait_for_rx_packet_matching(test_data, Duration::from_secs(8))?;
@@ -308,6 +246,10 @@ async fn test_serial_to_ble(
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // A (BLE) should receive the packet via notification
     device_a
@@ -353,3 +295,33 @@ async fn test_ping_pong(
 
     Ok(())
 }
+
+/// Test: a 200-byte packet reaches the BLE client intact.
+///
+/// The RxPacket frame is larger than a default 23-byte MTU notification, so it
+/// is either sent in one notification after MTU negotiation or split across
+/// several; both must reassemble to the exact data (the frame CRC is checked
+/// on parse).
+async fn test_large_packet_to_ble(
+    device_a: &BleClient,
+    device_b: &mut DeviceClient,
+) -> anyhow::Result<()> {
+    let test_data: Vec<u8> = (0..200u16).map(|i| (i * 7) as u8).collect();
+
+    device_a.clear_buffer().await;
+
+    let tx_response = device_b.lora_tx(&test_data)?;
+    if tx_response.resp_id != ResponseId::TxComplete {
+        anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
+    }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
+
+    device_a
+        .wait_for_rx_packet_matching(&test_data, Duration::from_secs(8))
+        .await?;
+
+    Ok(())
+}
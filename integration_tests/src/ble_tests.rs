@@ -8,6 +8,7 @@
 
 mod ble_client;
 mod protocol;
+mod report;
 
 use std::time::Duration;
 
@@ -15,7 +16,8 @@ use clap::Parser;
 use colored::Colorize;
 
 use ble_client::BleClient;
-use protocol::{CommandId, ResponseId, ResponseStatus};
+use protocol::{parse_version, CommandId, ResponseId, ResponseStatus};
+use report::{progress, progressln, Format, Report};
 
 #[derive(Parser)]
 #[command(name = "ble-tests")]
@@ -28,45 +30,39 @@ struct Args {
     /// BLE scan timeout in seconds.
     #[arg(long, default_value = "15")]
     scan_timeout: u64,
+
+    /// Result output: coloured text, or a JSON array on stdout for CI
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    report::set_format(args.format);
 
-    println!("{}", "Walkie-Textie BLE Integration Tests".bold());
-    println!("Scanning for \"{}\" ...", args.ble_name);
+    progressln!("{}", "Walkie-Textie BLE Integration Tests".bold());
+    progressln!("Scanning for \"{}\" ...", args.ble_name);
 
     let client = BleClient::connect_by_name(&args.ble_name, Duration::from_secs(args.scan_timeout))
         .await?;
-    println!("{}", "Connected!".green());
-    println!("\nRunning tests...\n");
-
-    let mut passed = 0usize;
-    let mut failed = 0usize;
-    for (name, result) in [
-        ("GetVersion returns version bytes", test_get_version(&client).await),
-        ("Invalid command returns error", test_invalid_command(&client).await),
-        ("Multiple GetVersion calls succeed", test_multiple_get_version(&client).await),
-    ] {
-        match result {
-            Ok(()) => {
-                println!("  {} ... {}", name, "PASS".green());
-                passed += 1;
-            }
-            Err(e) => {
-                println!("  {} ... {} ({e})", name, "FAIL".red());
-                failed += 1;
-            }
-        }
-    }
+    progressln!("{}", "Connected!".green());
+    progressln!("\nRunning tests...\n");
 
-    println!("\n{}", "=".repeat(60));
-    println!("  Total: {passed} passed, {failed} failed");
-    println!("{}", "=".repeat(60));
+    let mut report = Report::new();
+    let test = report.start("GetVersion returns version bytes");
+    report.finish(test, test_get_version(&client).await);
+    let test = report.start("Invalid command returns error");
+    report.finish(test, test_invalid_command(&client).await);
+    let test = report.start("Multiple GetVersion calls succeed");
+    report.finish(test, test_multiple_get_version(&client).await);
+    let test = report.start("Ping echoes payload");
+    report.finish(test, test_ping(&client).await);
+
+    report.print();
 
     let _ = client.disconnect().await;
-    if failed > 0 {
+    if report.failed() > 0 {
         std::process::exit(1);
     }
     Ok(())
@@ -79,14 +75,13 @@ async fn test_get_version(client: &BleClient) -> anyhow::Result<()> {
     if response.resp_id != ResponseId::Version {
         anyhow::bail!("expected Version response, got {:?}", response.resp_id);
     }
-    if response.payload.len() != 3 {
-        anyhow::bail!("expected 3 version bytes, got {}", response.payload.len());
+    match parse_version(&response.payload) {
+        Some(version) if version.max_payload.is_some() && version.build.is_some() => {
+            progress!("({}) ", version);
+            Ok(())
+        }
+        _ => anyhow::bail!("malformed version payload {:02x?}", response.payload),
     }
-    println!(
-        "    (v{}.{}.{})",
-        response.payload[0], response.payload[1], response.payload[2]
-    );
-    Ok(())
 }
 
 async fn test_invalid_command(client: &BleClient) -> anyhow::Result<()> {
@@ -113,3 +108,9 @@ async fn test_multiple_get_version(client: &BleClient) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+async fn test_ping(client: &BleClient) -> anyhow::Result<()> {
+    let round_trip = client.ping(Duration::from_secs(2)).await?;
+    progress!("({:.1} ms) ", round_trip.as_secs_f64() * 1000.0);
+    Ok(())
+}
@@ -2,18 +2,27 @@
 
 #![allow(dead_code)]
 
+use std::collections::BTreeMap;
 use std::io::{Read, Write};
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use serialport::{SerialPort, SerialPortType};
 
-use crate::protocol::{build_command, cobs_decode, cobs_encode, build_command_payload, parse_response, CommandId, Response, ResponseId};
+use crate::protocol::{build_command, build_command_with_host_sequence, cobs_decode, cobs_encode, build_command_payload, is_unsolicited, parse_packet_status, parse_response, parse_stats, parse_version, rx_packet_parts, sync_nonce, uptime_millis, CommandId, PacketStatus, Response, ResponseId, Stats, Version};
+use crate::report::progressln;
 
 /// USB vendor/product id of the Walkie-Textie firmware (dual CDC-ACM device).
 const USB_VID: u16 = 0x303A;
 const USB_PID: u16 = 0x1001;
 
+/// How long each Sync attempt waits for its SyncAck before resending.
+const SYNC_ATTEMPT: Duration = Duration::from_millis(300);
+
+/// How long a port without interface metadata gets to answer before it is
+/// taken for the debug port, which never answers.
+const UNLABELLED_PROBE: Duration = Duration::from_millis(600);
+
 /// Find the live data port of every connected board.
 ///
 /// The firmware exposes two CDC-ACM functions in a fixed order: the data port is
@@ -22,34 +31,33 @@ const USB_PID: u16 = 0x1001;
 /// interface role, so the data port is identified by its USB interface number,
 /// never by the port number being even or odd (which is not deterministic - two
 /// boards' data ports can both enumerate before either debug port).
+///
+/// Ports are grouped by board (USB serial number) so each board yields at most
+/// one data port, and the debug port is never probed when the interface number
+/// is known (see `board_data_port`).
 pub fn find_data_ports() -> Result<Vec<String>> {
-    let ports = serialport::available_ports()?;
-
-    let mut data_ports = Vec::new();
-    for port_info in ports {
+    let mut boards: BTreeMap<String, Vec<(String, Option<u8>)>> = BTreeMap::new();
+    for port_info in serialport::available_ports()? {
         let SerialPortType::UsbPort(usb) = &port_info.port_type else {
             continue;
         };
         if usb.vid != USB_VID || usb.pid != USB_PID {
             continue;
         }
-        // Interface 0 is the data CDC; interface 2 is the debug/log CDC.
-        if usb.interface != Some(0) {
-            continue;
-        }
+        // Without a serial number the ports cannot be paired, so each stands alone
+        let board = usb.serial_number.clone().unwrap_or_else(|| port_info.port_name.clone());
+        boards
+            .entry(board)
+            .or_default()
+            .push((port_info.port_name.clone(), usb.interface));
+    }
 
-        // Confirm the firmware is actually responding before claiming the port.
-        let responds = DeviceClient::new(&port_info.port_name, 115200)
-            .map(|mut client| client.wait_ready(Duration::from_secs(2)).is_ok())
-            .unwrap_or(false);
-        if responds {
-            data_ports.push(port_info.port_name.clone());
-        } else {
-            eprintln!(
-                "  warning: {} ({}) is a data port but did not respond",
-                port_info.port_name,
-                usb.serial_number.as_deref().unwrap_or("unknown")
-            );
+    let mut data_ports = Vec::new();
+    for (board, mut ports) in boards {
+        ports.sort();
+        match board_data_port(&ports) {
+            Some(port) => data_ports.push(port),
+            None => eprintln!("  warning: no responding data port on {} ({:?})", board, ports),
         }
     }
 
@@ -57,6 +65,32 @@ pub fn find_data_ports() -> Result<Vec<String>> {
     Ok(data_ports)
 }
 
+/// Pick one board's data port from its CDC ports.
+///
+/// When the platform reports interface numbers only interface 0 is probed, so
+/// the silent debug port costs nothing. Otherwise each port is probed briefly
+/// in name order, stopping at the first that answers.
+fn board_data_port(ports: &[(String, Option<u8>)]) -> Option<String> {
+    let labelled = ports.iter().any(|(_, interface)| interface.is_some());
+    let (candidates, timeout): (Vec<&String>, _) = if labelled {
+        // Interface 0 is the data CDC; interface 2 is the debug/log CDC.
+        let data = ports.iter().filter(|(_, interface)| *interface == Some(0));
+        (data.map(|(name, _)| name).collect(), Duration::from_secs(2))
+    } else {
+        (ports.iter().map(|(name, _)| name).collect(), UNLABELLED_PROBE)
+    };
+
+    // Confirm the firmware is actually responding before claiming the port.
+    candidates
+        .into_iter()
+        .find(|name| {
+            DeviceClient::new(name, 115200)
+                .map(|mut client| client.wait_ready(timeout).is_ok())
+                .unwrap_or(false)
+        })
+        .cloned()
+}
+
 /// Find a single data port. Returns error if none found.
 pub fn find_data_port() -> Result<String> {
     let ports = find_data_ports()?;
@@ -109,6 +143,8 @@ pub fn resolve_two_ports(port_a: &str, port_b: &str) -> Result<(String, String)>
 pub struct DeviceClient {
     port: Box<dyn SerialPort>,
     timeout: Duration,
+    /// Print frames `wait_for_response_id` discards
+    log_skipped: bool,
 }
 
 impl DeviceClient {
@@ -121,16 +157,88 @@ impl DeviceClient {
             .timeout(timeout)
             .open()?;
 
-        Ok(Self { port, timeout })
+        Ok(Self {
+            port,
+            timeout,
+            log_skipped: false,
+        })
+    }
+
+    /// Print every frame discarded while waiting for a specific response.
+    pub fn set_log_skipped(&mut self, log_skipped: bool) {
+        self.log_skipped = log_skipped;
+    }
+
+    /// Reboot the device and reconnect once it is back.
+    ///
+    /// The firmware acks the Reboot before resetting. The board then drops
+    /// off USB while it restarts, so the same port path is reopened and the
+    /// Sync handshake retried until the firmware answers or `timeout` passes.
+    pub fn reboot(&mut self, timeout: Duration) -> Result<()> {
+        let name = self.port_name()?;
+        let baud = self.port.baud_rate()?;
+        let response = self.send_command(CommandId::Reboot, &[])?;
+        if response.resp_id != ResponseId::Ack {
+            anyhow::bail!("Reboot answered with {:?}", response.resp_id);
+        }
+
+        // Let the old port go away before reopening it
+        std::thread::sleep(Duration::from_secs(1));
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Ok(port) = serialport::new(&name, baud).timeout(self.timeout).open() {
+                self.port = port;
+                if self.wait_ready(Duration::from_secs(3)).is_ok() {
+                    return Ok(());
+                }
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        anyhow::bail!("device did not come back within {:?} of Reboot", timeout)
     }
 
-    /// Wait until the firmware answers GetVersion, or the timeout elapses.
+    /// Wait until the firmware answers Sync and the stream is clean, or the
+    /// timeout elapses.
     ///
-    /// Drains any stale bytes, then retries with a short per-attempt timeout so
-    /// the occasional dropped first command after a fresh CDC connection does not
-    /// fail the caller. Doubles as a warm-up for the link.
+    /// Sends Sync with a new nonce each attempt and discards every frame (boot
+    /// output, stale replies, acks of earlier attempts) until the SyncAck
+    /// echoing the current nonce. Anything read afterwards belongs to this
+    /// session. Resending covers the occasional dropped first command after a
+    /// fresh CDC connection. Doubles as a warm-up for the link.
     pub fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
-        self.get_version(timeout).map(|_| ())
+        let previous = self.timeout;
+        let result = self.sync(timeout);
+        self.set_timeout(previous);
+        result
+    }
+
+    /// Run the Sync handshake (see `wait_ready`).
+    fn sync(&mut self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        let mut nonce = sync_nonce();
+        while start.elapsed() < timeout {
+            nonce = nonce.wrapping_add(1);
+            let expected = nonce.to_le_bytes();
+            self.port.write_all(&build_command(CommandId::Sync, &expected))?;
+            self.port.flush()?;
+
+            let deadline = Instant::now() + SYNC_ATTEMPT;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                self.set_timeout(remaining);
+                let Ok(mut frame) = self.read_frame() else {
+                    break; // no ack this attempt - resend with a new nonce
+                };
+                frame.push(0x00);
+                // Undecodable bytes are boot output or a partial frame
+                let Ok(response) = cobs_decode(&frame).and_then(|decoded| parse_response(&decoded)) else {
+                    continue;
+                };
+                if response.resp_id == ResponseId::SyncAck && response.payload == expected {
+                    return Ok(());
+                }
+            }
+        }
+        anyhow::bail!("device did not answer Sync within {:?}", timeout)
     }
 
     /// Retry GetVersion until it answers, returning (major, minor, patch).
@@ -167,6 +275,241 @@ impl DeviceClient {
         }
     }
 
+    /// Read the longest LoRa packet the device can receive, from the end of
+    /// its Version response.
+    pub fn get_max_payload(&mut self) -> Result<u16> {
+        let response = self.send_command(CommandId::GetVersion, &[])?;
+        match (response.resp_id, parse_version(&response.payload)) {
+            (ResponseId::Version, Some(Version { max_payload: Some(max_payload), .. })) => Ok(max_payload),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Limit transmissions to packets a peer with this `max_payload` can
+    /// receive.
+    pub fn set_peer_max_payload(&mut self, max_payload: u16) -> Result<Response> {
+        self.send_command(CommandId::SetPeerMaxPayload, &max_payload.to_le_bytes())
+    }
+
+    /// Space transmissions at least `gap_ms` apart (0 = back-to-back).
+    pub fn set_tx_gap(&mut self, gap_ms: u32) -> Result<Response> {
+        self.send_command(CommandId::SetTxGap, &gap_ms.to_le_bytes())
+    }
+
+    /// Set what happens below `threshold_mv` (0 = never): transmit at no
+    /// more than `tx_power_dbm`, and optionally duty-cycle RX when idle.
+    pub fn set_low_battery_policy(&mut self, threshold_mv: u16, tx_power_dbm: i8, rx_duty_cycle: bool) -> Result<Response> {
+        let mut payload = threshold_mv.to_le_bytes().to_vec();
+        payload.push(tx_power_dbm as u8);
+        payload.push(rx_duty_cycle as u8);
+        self.send_command(CommandId::SetLowBatteryPolicy, &payload)
+    }
+
+    /// Switch the radio to GFSK, sending and receiving raw packets.
+    /// `gaussian_bt` is the filter BT x 10 (0 = no filter).
+    pub fn set_fsk(&mut self, bitrate_bps: u32, deviation_hz: u32, rx_bandwidth_hz: u32, gaussian_bt: u8) -> Result<Response> {
+        let mut payload = vec![0x01];
+        payload.extend_from_slice(&bitrate_bps.to_le_bytes());
+        payload.extend_from_slice(&deviation_hz.to_le_bytes());
+        payload.extend_from_slice(&rx_bandwidth_hz.to_le_bytes());
+        payload.push(gaussian_bt);
+        self.send_command(CommandId::SetModulation, &payload)
+    }
+
+    /// Switch the radio back to LoRa.
+    pub fn set_lora_modulation(&mut self) -> Result<Response> {
+        self.send_command(CommandId::SetModulation, &[0x00])
+    }
+
+    /// Replay the messages the device received most recently, oldest first.
+    ///
+    /// The device answers with one RxPacket per message, then an Ack.
+    pub fn get_recent_packets(&mut self) -> Result<Vec<Response>> {
+        let frame = build_command(CommandId::GetRecentPackets, &[]);
+        self.port.write_all(&frame)?;
+        self.port.flush()?;
+        let mut packets = Vec::new();
+        loop {
+            let Some(response) = self.try_read_response(self.timeout)? else {
+                anyhow::bail!("Timeout waiting for the end of the replay");
+            };
+            match response.resp_id {
+                ResponseId::RxPacket => packets.push(response),
+                ResponseId::Ack => return Ok(packets),
+                ResponseId::Event => {}
+                other => anyhow::bail!("unexpected response {:?} {:02x?}", other, response.payload),
+            }
+        }
+    }
+
+    /// Read the device's 3-byte hardware id (as used for its LoRa address).
+    pub fn get_device_id(&mut self) -> Result<[u8; 3]> {
+        let response = self.send_command(CommandId::GetDeviceId, &[])?;
+        if response.resp_id != ResponseId::DeviceId || response.payload.len() != 3 {
+            anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload);
+        }
+        Ok([response.payload[0], response.payload[1], response.payload[2]])
+    }
+
+    /// Read the device's uptime in milliseconds since boot.
+    pub fn get_uptime(&mut self) -> Result<u64> {
+        let response = self.send_command(CommandId::GetUptime, &[])?;
+        match (response.resp_id, uptime_millis(&response.payload)) {
+            (ResponseId::Uptime, Some(millis)) => Ok(millis),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read why the chip last reset (an ESP32-S3 reset reason code).
+    pub fn get_reset_reason(&mut self) -> Result<u8> {
+        let response = self.send_command(CommandId::GetResetReason, &[])?;
+        match (response.resp_id, response.payload.as_slice()) {
+            (ResponseId::ResetReason, [code]) => Ok(*code),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read what the radio is doing (the SX1262 chip mode, 0 for sleep).
+    pub fn get_radio_state(&mut self) -> Result<u8> {
+        let response = self.send_command(CommandId::GetRadioState, &[])?;
+        match (response.resp_id, response.payload.as_slice()) {
+            (ResponseId::RadioState, [state]) => Ok(*state),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read the protocol versions the firmware speaks: `(current, min_supported)`.
+    pub fn get_protocol_versions(&mut self) -> Result<(u8, u8)> {
+        let response = self.send_command(CommandId::GetProtocolVersions, &[])?;
+        match (response.resp_id, response.payload.as_slice()) {
+            (ResponseId::ProtocolVersions, &[current, min_supported]) => Ok((current, min_supported)),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Turn the sequence trailer on every command response on or off.
+    pub fn set_sequence_echo(&mut self, enabled: bool) -> Result<Response> {
+        self.send_command(CommandId::SetSequenceEcho, &[enabled as u8])
+    }
+
+    /// Turn the CRC32 on every RxPacket on or off.
+    pub fn set_rx_checksum(&mut self, enabled: bool) -> Result<Response> {
+        self.send_command(CommandId::SetRxChecksum, &[enabled as u8])
+    }
+
+    /// Read the bitmap of command IDs the firmware accepts (bit `id % 8` of
+    /// byte `id / 8`).
+    pub fn get_capabilities(&mut self) -> Result<[u8; 32]> {
+        let response = self.send_command(CommandId::GetCapabilities, &[])?;
+        match (response.resp_id, <[u8; 32]>::try_from(response.payload.as_slice())) {
+            (ResponseId::Capabilities, Ok(bitmap)) => Ok(bitmap),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Set the device label (stored in flash; empty clears it).
+    pub fn set_label(&mut self, label: &[u8]) -> Result<Response> {
+        self.send_command(CommandId::SetLabel, label)
+    }
+
+    /// Take over the status LED: 0 = automatic, 1 = off, 2 = on, 3 = blink.
+    pub fn set_led(&mut self, mode: u8) -> Result<Response> {
+        self.send_command(CommandId::SetLed, &[mode])
+    }
+
+    /// Turn repeater mode (passing on other devices' frames) on or off.
+    pub fn set_repeater(&mut self, enabled: bool) -> Result<Response> {
+        self.send_command(CommandId::SetRepeater, &[enabled as u8])
+    }
+
+    /// Read the RSSI in dBm of `count` channels from `start_hz`, `step_hz`
+    /// apart.
+    pub fn spectrum_scan(&mut self, start_hz: u32, step_hz: u32, count: u8) -> Result<Vec<i16>> {
+        let mut payload = start_hz.to_le_bytes().to_vec();
+        payload.extend_from_slice(&step_hz.to_le_bytes());
+        payload.push(count);
+        let response = self.send_command(CommandId::SpectrumScan, &payload)?;
+        // Payload: [start_hz: u32 LE][step_hz: u32 LE][rssi: i16 LE...]
+        match (response.resp_id, response.payload.get(8..)) {
+            (ResponseId::SpectrumScan, Some(samples)) if samples.len() % 2 == 0 => {
+                Ok(samples.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect())
+            }
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read the device label.
+    pub fn get_label(&mut self) -> Result<Vec<u8>> {
+        let response = self.send_command(CommandId::GetLabel, &[])?;
+        if response.resp_id != ResponseId::Label {
+            anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload);
+        }
+        Ok(response.payload)
+    }
+
+    /// Read the whole flash crash log, one chunk at a time. Reading to the
+    /// end also releases a log kept after a panic.
+    pub fn get_crash_log(&mut self) -> Result<Vec<u8>> {
+        let mut log = Vec::new();
+        loop {
+            let offset = log.len() as u32;
+            let response = self.send_command(CommandId::GetCrashLog, &offset.to_le_bytes())?;
+            // Payload: [offset: u32 LE][text]
+            let text = match (response.resp_id, response.payload.split_first_chunk::<4>()) {
+                (ResponseId::CrashLog, Some((echoed, text))) if u32::from_le_bytes(*echoed) == offset => text,
+                _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+            };
+            if text.is_empty() {
+                return Ok(log);
+            }
+            log.extend_from_slice(text);
+        }
+    }
+
+    /// Run the radio self-test, returning its result bits.
+    pub fn self_test(&mut self) -> Result<u8> {
+        let response = self.send_command(CommandId::SelfTest, &[])?;
+        if response.resp_id != ResponseId::SelfTest || response.payload.len() != 1 {
+            anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload);
+        }
+        Ok(response.payload[0])
+    }
+
+    /// Send the loopback pattern and listen for it back:
+    /// `(passed, rssi, snr)`, with 0 dBm and 0 dB when it did not return.
+    pub fn lora_loopback(&mut self) -> Result<(bool, i16, i8)> {
+        let response = self.send_command(CommandId::LoraLoopback, &[])?;
+        match (response.resp_id, response.payload.as_slice()) {
+            (ResponseId::Loopback, &[passed, rssi_lo, rssi_hi, snr]) => {
+                Ok((passed != 0, i16::from_le_bytes([rssi_lo, rssi_hi]), snr as i8))
+            }
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read the device's link counters.
+    pub fn get_stats(&mut self) -> Result<Stats> {
+        let response = self.send_command(CommandId::GetStats, &[])?;
+        match (response.resp_id, parse_stats(&response.payload)) {
+            (ResponseId::Stats, Some(stats)) => Ok(stats),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Read the link quality of the last packet the device received.
+    pub fn get_last_packet_status(&mut self) -> Result<PacketStatus> {
+        let response = self.send_command(CommandId::GetLastPacketStatus, &[])?;
+        match (response.resp_id, parse_packet_status(&response.payload)) {
+            (ResponseId::LastPacketStatus, Some(status)) => Ok(status),
+            _ => anyhow::bail!("unexpected response {:?} {:02x?}", response.resp_id, response.payload),
+        }
+    }
+
+    /// Zero the device's link counters.
+    pub fn clear_stats(&mut self) -> Result<Response> {
+        self.send_command(CommandId::ClearStats, &[])
+    }
+
     /// Set the response timeout.
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = timeout;
@@ -206,37 +549,51 @@ impl DeviceClient {
 
     /// Send a command and wait for its reply.
     pub fn send_command(&mut self, cmd_id: CommandId, payload: &[u8]) -> Result<Response> {
+        self.send_command_observed(cmd_id, payload, |_| {})
+    }
+
+    /// Send a command and wait for its reply, handing any unsolicited frames
+    /// that arrive first to `on_unsolicited` rather than discarding them.
+    pub fn send_command_observed(
+        &mut self,
+        cmd_id: CommandId,
+        payload: &[u8],
+        mut on_unsolicited: impl FnMut(&Response),
+    ) -> Result<Response> {
         let frame = build_command(cmd_id, payload);
         self.port.write_all(&frame)?;
         self.port.flush()?;
-        self.read_command_response_resync()
+        self.read_command_response_resync(&mut on_unsolicited)
     }
 
     /// Read the command reply, draining the buffer on failure so a timed-out or
     /// corrupt exchange cannot leave a partial frame that desyncs the next one.
-    fn read_command_response_resync(&mut self) -> Result<Response> {
-        let result = self.read_command_response();
+    fn read_command_response_resync(&mut self, on_unsolicited: &mut dyn FnMut(&Response)) -> Result<Response> {
+        let result = self.read_command_response(on_unsolicited);
         if result.is_err() {
             let _ = self.drain_buffer();
         }
         result
     }
 
-    /// Read frames until the command reply arrives, skipping unsolicited packets.
+    /// Read frames until the command reply arrives, passing unsolicited packets
+    /// and events to `on_unsolicited` and otherwise skipping them.
     ///
     /// The device shares one stream for command replies and unsolicited LoRa
-    /// RxPackets, and the slow radio can deliver a packet from an earlier
-    /// exchange late. Reading whole frames (never clearing mid-frame, which would
-    /// split one) and skipping RxPackets keeps the strict request/response model
-    /// in sync. Command replies are Version / TxComplete / Error, never RxPacket.
-    fn read_command_response(&mut self) -> Result<Response> {
+    /// RxPackets and BLE events, and the slow radio can deliver a packet from an
+    /// earlier exchange late. Reading whole frames (never clearing mid-frame,
+    /// which would split one) and skipping unsolicited frames keeps the strict
+    /// request/response model in sync. Command replies are never RxPacket or
+    /// Event.
+    fn read_command_response(&mut self, on_unsolicited: &mut dyn FnMut(&Response)) -> Result<Response> {
         loop {
             let mut frame = self.read_frame()?;
             frame.push(0x00); // corncobs expects the delimiter
             let decoded = cobs_decode(&frame)?;
             let response = parse_response(&decoded)?;
-            if response.resp_id == ResponseId::RxPacket {
-                continue; // unsolicited - not the reply to our command
+            if is_unsolicited(response.resp_id) {
+                on_unsolicited(&response);
+                continue; // not the reply to our command
             }
             return Ok(response);
         }
@@ -282,7 +639,25 @@ impl DeviceClient {
         self.port.write_all(&encoded)?;
         self.port.flush()?;
 
-        self.read_command_response_resync()
+        self.read_command_response_resync(&mut |_| {})
+    }
+
+    /// Send a command carrying `host_sequence` and wait for its reply.
+    pub fn send_command_with_host_sequence(
+        &mut self,
+        cmd_id: CommandId,
+        payload: &[u8],
+        host_sequence: u16,
+    ) -> Result<Response> {
+        self.send_raw_bytes(&build_command_with_host_sequence(cmd_id, payload, host_sequence))
+    }
+
+    /// Write raw bytes (no framing added) and wait for the reply.
+    pub fn send_raw_bytes(&mut self, bytes: &[u8]) -> Result<Response> {
+        self.port.write_all(bytes)?;
+        self.port.flush()?;
+
+        self.read_command_response_resync(&mut |_| {})
     }
 
     /// Send LoRa TX command with data.
@@ -290,6 +665,68 @@ impl DeviceClient {
         self.send_command(CommandId::LoraTx, data)
     }
 
+    /// Send LoRa TX, handing packets and events received while waiting for
+    /// TxComplete to `on_unsolicited`.
+    pub fn lora_tx_observed(&mut self, data: &[u8], on_unsolicited: impl FnMut(&Response)) -> Result<Response> {
+        self.send_command_observed(CommandId::LoraTx, data, on_unsolicited)
+    }
+
+    /// Send LoRa TX addressed to a single device (`dest` is its 3-byte id).
+    pub fn lora_tx_addressed(&mut self, dest: [u8; 3], data: &[u8]) -> Result<Response> {
+        let mut payload = dest.to_vec();
+        payload.extend_from_slice(data);
+        self.send_command(CommandId::LoraTxAddressed, &payload)
+    }
+
+    /// Broadcast over LoRa at a one-off TX power (dBm, -9 to 22).
+    pub fn lora_tx_power(&mut self, power_dbm: i8, data: &[u8]) -> Result<Response> {
+        let mut payload = vec![power_dbm as u8];
+        payload.extend_from_slice(data);
+        self.send_command(CommandId::LoraTxPower, &payload)
+    }
+
+    /// Send LoRa TX to a single device and wait for its ACK.
+    ///
+    /// The firmware retries until the ACK arrives or its retries run out, so
+    /// this waits longer than other commands for the response.
+    pub fn lora_tx_reliable(&mut self, dest: [u8; 3], data: &[u8]) -> Result<Response> {
+        let mut payload = dest.to_vec();
+        payload.extend_from_slice(data);
+        let previous = self.timeout;
+        self.set_timeout(Duration::from_secs(20));
+        let response = self.send_command(CommandId::LoraTxReliable, &payload);
+        self.set_timeout(previous);
+        response
+    }
+
+    /// Set the BLE advertising name (1-20 printable ASCII bytes).
+    pub fn set_ble_name(&mut self, name: &[u8]) -> Result<Response> {
+        self.send_command(CommandId::SetBleName, name)
+    }
+
+    /// Send a Ping stamped with the current time and return the round trip.
+    ///
+    /// The firmware echoes the payload without touching the radio, so this
+    /// measures pure transport latency. Fails if the echo does not match.
+    pub fn ping(&mut self) -> Result<Duration> {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_micros() as u64;
+        let payload = stamp.to_le_bytes();
+
+        let start = Instant::now();
+        let response = self.send_command(CommandId::Ping, &payload)?;
+        let round_trip = start.elapsed();
+
+        if response.resp_id != ResponseId::Pong {
+            anyhow::bail!("Expected Pong response, got {:?}", response.resp_id);
+        }
+        if response.payload != payload {
+            anyhow::bail!("Pong payload mismatch: sent {:02x?}, got {:02x?}", payload, response.payload);
+        }
+        Ok(round_trip)
+    }
+
     /// Try to read an unsolicited response (non-blocking with short timeout).
     /// Returns None if no response available within timeout.
     pub fn try_read_response(&mut self, timeout: Duration) -> Result<Option<Response>> {
@@ -318,15 +755,38 @@ impl DeviceClient {
         }
     }
 
-    /// Wait for an unsolicited RxPacket response.
-    pub fn wait_for_rx_packet(&mut self, timeout: Duration) -> Result<Response> {
+    /// Wait for a response with the given id, discarding anything else.
+    ///
+    /// Command replies, unsolicited RxPackets and events share the stream, so
+    /// the next frame is not always the one a test is after: a stray
+    /// TxComplete or Error would otherwise fail it confusingly.
+    pub fn wait_for_response_id(&mut self, id: ResponseId, timeout: Duration) -> Result<Response> {
+        match self.skip_until(id, timeout)? {
+            Some(response) => Ok(response),
+            None => anyhow::bail!("Timeout waiting for {:?}", id),
+        }
+    }
+
+    /// Read frames until one has the given id, or None once `timeout` passes.
+    fn skip_until(&mut self, id: ResponseId, timeout: Duration) -> Result<Option<Response>> {
         let start = Instant::now();
-        while start.elapsed() < timeout {
-            if let Some(response) = self.try_read_response(Duration::from_millis(100))? {
-                return Ok(response);
+        while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+            let Some(response) = self.try_read_response(remaining.min(Duration::from_millis(200)))? else {
+                continue;
+            };
+            if response.resp_id == id {
+                return Ok(Some(response));
+            }
+            if self.log_skipped {
+                progressln!("    (skipped {:?} {:02x?})", response.resp_id, response.payload);
             }
         }
-        anyhow::bail!("Timeout waiting for RxPacket")
+        Ok(None)
+    }
+
+    /// Wait for an unsolicited RxPacket response, skipping everything else.
+    pub fn wait_for_rx_packet(&mut self, timeout: Duration) -> Result<Response> {
+        self.wait_for_response_id(ResponseId::RxPacket, timeout)
     }
 
     /// Wait for an RxPacket whose data payload equals `expected`.
@@ -334,24 +794,24 @@ impl DeviceClient {
     /// The radio is slow (SF11), so a packet from an earlier exchange can be
     /// delivered late and land in the next read; skipping non-matching frames
     /// keeps the strict per-message tests in sync without masking real loss (a
-    /// missing packet still times out). The trailing 3 bytes are rssi+snr.
+    /// missing packet still times out). The payload is source, data, rssi+snr.
     pub fn wait_for_rx_packet_matching(
         &mut self,
         expected: &[u8],
         timeout: Duration,
     ) -> Result<Response> {
         let start = Instant::now();
-        while start.elapsed() < timeout {
-            let Some(response) = self.try_read_response(Duration::from_millis(200))? else {
-                continue;
+        while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+            let Some(response) = self.skip_until(ResponseId::RxPacket, remaining)? else {
+                break;
             };
-            if response.resp_id != ResponseId::RxPacket || response.payload.len() < 3 {
-                continue;
-            }
-            if &response.payload[..response.payload.len() - 3] == expected {
+            if rx_packet_parts(&response.payload).is_some_and(|(_, data)| data == expected) {
                 return Ok(response);
             }
             // Stale or unexpected packet - keep waiting for the one we want.
+            if self.log_skipped {
+                progressln!("    (skipped RxPacket {:02x?})", response.payload);
+            }
         }
         anyhow::bail!("Timeout waiting for RxPacket matching {:?}", expected)
     }
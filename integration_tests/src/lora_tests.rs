@@ -5,6 +5,7 @@
 
 mod device;
 mod protocol;
+mod report;
 
 use std::time::Duration;
 
@@ -12,7 +13,8 @@ use clap::Parser;
 use colored::Colorize;
 
 use device::{resolve_two_ports, DeviceClient};
-use protocol::ResponseId;
+use protocol::{rx_packet_checked, rx_packet_parts, tx_complete_parts, ResponseId};
+use report::{progress, progressln, Format, Report};
 
 #[derive(Parser)]
 #[command(name = "lora-tests")]
@@ -29,34 +31,45 @@ struct Args {
     /// Baud rate
     #[arg(short, long, default_value = "115200")]
     baud: u32,
+
+    /// Print frames skipped while waiting for a specific response
+    #[arg(long)]
+    log_skipped: bool,
+
+    /// Result output: coloured text, or a JSON array on stdout for CI
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    report::set_format(args.format);
 
     // Resolve ports (auto-detect if "auto")
     let (port_a, port_b) = resolve_two_ports(&args.port_a, &args.port_b)?;
 
-    println!("{}", "LoRa Two-Device Integration Tests".bold());
-    println!("Device A: {}", port_a);
-    println!("Device B: {}", port_b);
-    println!("Baud: {}", args.baud);
-    println!();
+    progressln!("{}", "LoRa Two-Device Integration Tests".bold());
+    progressln!("Device A: {}", port_a);
+    progressln!("Device B: {}", port_b);
+    progressln!("Baud: {}", args.baud);
+    progressln!();
 
     // Connect to both devices
-    println!("Connecting to devices...");
+    progressln!("Connecting to devices...");
     let mut device_a = DeviceClient::new(&port_a, args.baud)?;
     let mut device_b = DeviceClient::new(&port_b, args.baud)?;
+    device_a.set_log_skipped(args.log_skipped);
+    device_b.set_log_skipped(args.log_skipped);
 
     // Wait for both radios to settle and confirm they respond (warms up each
     // link, absorbing the occasional dropped first command on a fresh connection).
-    println!("Waiting for devices to become ready...");
+    progressln!("Waiting for devices to become ready...");
     device_a.wait_ready(Duration::from_secs(3))?;
     device_b.wait_ready(Duration::from_secs(3))?;
-    println!("{}", "Connected to both devices!".green());
+    progressln!("{}", "Connected to both devices!".green());
 
     // Verify both devices respond
-    println!("\nVerifying device connectivity...");
+    progressln!("\nVerifying device connectivity...");
     verify_device(&mut device_a, "A")?;
     verify_device(&mut device_b, "B")?;
 
@@ -64,108 +77,54 @@ fn main() -> anyhow::Result<()> {
     // radios have been idle is often missed: the receiver re-arms RX between poll
     // cycles, so a packet can land in that gap. (The app layer handles this with
     // delivery acks and retries; the tests below measure steady-state delivery.)
-    print!("\nWarming up LoRa link... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
+    progress!("\nWarming up LoRa link... ");
     warm_up(&mut device_a, &mut device_b);
-    println!("done");
+    progressln!("done");
 
-    println!("\n{}", "Running LoRa tests...".bold());
-    println!();
+    progressln!("\n{}", "Running LoRa tests...".bold());
+    progressln!();
 
-    let mut passed = 0;
-    let mut failed = 0;
+    let mut report = Report::new();
 
     // Test 1: A transmits, B receives
-    print!("  Test 1: A -> B transmission ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_a_to_b(&mut device_a, &mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 1: A -> B transmission");
+    report.finish(test, test_a_to_b(&mut device_a, &mut device_b));
 
     // Test 2: B transmits, A receives
-    print!("  Test 2: B -> A transmission ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_b_to_a(&mut device_a, &mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 2: B -> A transmission");
+    report.finish(test, test_b_to_a(&mut device_a, &mut device_b));
 
     // Test 3: Bidirectional ping-pong
-    print!("  Test 3: Bidirectional ping-pong ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_ping_pong(&mut device_a, &mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 3: Bidirectional ping-pong");
+    report.finish(test, test_ping_pong(&mut device_a, &mut device_b));
 
     // Test 4: Multiple messages
-    print!("  Test 4: Multiple sequential messages ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_multiple_messages(&mut device_a, &mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 4: Multiple sequential messages");
+    report.finish(test, test_multiple_messages(&mut device_a, &mut device_b));
 
     // Test 5: Reliability test - 10 messages back and forth
-    print!("  Test 5: Reliability (10 round trips) ... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
-    match test_reliability(&mut device_a, &mut device_b) {
-        Ok(()) => {
-            println!("{}", "PASS".green().bold());
-            passed += 1;
-        }
-        Err(e) => {
-            println!("{}", "FAIL".red().bold());
-            println!("    {}", e.to_string().red());
-            failed += 1;
-        }
-    }
+    let test = report.start("Test 5: Reliability (10 round trips)");
+    report.finish(test, test_reliability(&mut device_a, &mut device_b));
 
-    // Summary
-    println!("\n{}", "=".repeat(60));
-    println!("{}", "Test Results".bold());
-    println!("{}", "=".repeat(60));
-    println!(
-        "  Total: {} passed, {} failed",
-        passed.to_string().green(),
-        if failed > 0 {
-            failed.to_string().red()
-        } else {
-            failed.to_string().normal()
-        }
-    );
-    println!("{}", "=".repeat(60));
+    // Test 6: Addressed delivery and filtering
+    let test = report.start("Test 6: Addressed packets are filtered");
+    report.finish(test, test_addressing(&mut device_a, &mut device_b));
+
+    // Test 7: Acknowledged delivery
+    let test = report.start("Test 7: Reliable TX is acknowledged");
+    report.finish(test, test_reliable_tx(&mut device_a, &mut device_b));
+
+    // Test 8: End-to-end checksum
+    let test = report.start("Test 8: RxPacket checksum matches the data");
+    report.finish(test, test_rx_checksum(&mut device_a, &mut device_b));
 
-    if failed > 0 {
+    // Test 9: Link quality of the last packet
+    let test = report.start("Test 9: Last packet status matches the RxPacket");
+    report.finish(test, test_last_packet_status(&mut device_a, &mut device_b));
+
+    report.print();
+
+    if report.failed() > 0 {
         std::process::exit(1);
     }
 
@@ -175,7 +134,7 @@ fn main() -> anyhow::Result<()> {
 /// Verify a device responds to GetVersion.
 fn verify_device(device: &mut DeviceClient, name: &str) -> anyhow::Result<()> {
     let (major, minor, patch) = device.get_version(Duration::from_secs(3))?;
-    println!("  Device {}: v{}.{}.{}", name, major, minor, patch);
+    progressln!("  Device {}: v{}.{}.{}", name, major, minor, patch);
     Ok(())
 }
 
@@ -216,6 +175,10 @@ fn test_a_to_b(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyh
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // B should receive the packet as an unsolicited RxPacket
     device_b.wait_for_rx_packet_matching(test_data, Duration::from_secs(8))?;
@@ -235,6 +198,10 @@ fn test_b_to_a(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyh
     if tx_response.resp_id != ResponseId::TxComplete {
         anyhow::bail!("Expected TxComplete, got {:?}", tx_response.resp_id);
     }
+    match tx_complete_parts(&tx_response.payload) {
+        Some((_, len)) if len as usize == test_data.len() => {}
+        other => anyhow::bail!("TxComplete should echo length {}, got {:?}", test_data.len(), other),
+    }
 
     // A should receive the packet
     device_a.wait_for_rx_packet_matching(test_data, Duration::from_secs(8))?;
@@ -331,3 +298,123 @@ fn test_reliability(
 
     Ok(())
 }
+
+/// Test: Addressed packets reach only their destination.
+///
+/// B learns A's address from the source field of a broadcast, then sends one
+/// packet addressed to A (which must arrive) and one addressed to a device that
+/// does not exist (which A must ignore).
+fn test_addressing(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyhow::Result<()> {
+    device_a.clear_buffer()?;
+    device_b.clear_buffer()?;
+
+    // Learn A's address from a broadcast it sends to B
+    let hello = b"Who am I?";
+    device_a.lora_tx(hello)?;
+    let rx = device_b.wait_for_rx_packet_matching(hello, Duration::from_secs(8))?;
+    let (addr_a, _) = rx_packet_parts(&rx.payload)
+        .ok_or_else(|| anyhow::anyhow!("RxPacket payload too short"))?;
+
+    std::thread::sleep(Duration::from_millis(200));
+
+    // Addressed to A: must be delivered
+    let direct = b"Just for A";
+    let tx = device_b.lora_tx_addressed(addr_a, direct)?;
+    if tx.resp_id != ResponseId::TxComplete {
+        anyhow::bail!("Addressed TX failed: {:?}", tx.resp_id);
+    }
+    device_a.wait_for_rx_packet_matching(direct, Duration::from_secs(8))?;
+
+    std::thread::sleep(Duration::from_millis(200));
+
+    // Addressed elsewhere: must be filtered out
+    let elsewhere = [addr_a[0] ^ 0x01, addr_a[1], addr_a[2]];
+    let stray = b"Not for A";
+    device_b.lora_tx_addressed(elsewhere, stray)?;
+    if device_a
+        .wait_for_rx_packet_matching(stray, Duration::from_secs(4))
+        .is_ok()
+    {
+        anyhow::bail!("A received a packet addressed to another device");
+    }
+
+    Ok(())
+}
+
+/// Test: LoraTxReliable completes only once the destination has ACKed.
+///
+/// B sends to A with LoraTxReliable; A's firmware ACKs automatically, so B
+/// must report TxComplete and A must surface the message exactly once.
+fn test_reliable_tx(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyhow::Result<()> {
+    device_a.clear_buffer()?;
+    device_b.clear_buffer()?;
+
+    let addr_a = device_a.get_device_id()?;
+    let message = b"Please confirm";
+    let tx = device_b.lora_tx_reliable(addr_a, message)?;
+    if tx.resp_id != ResponseId::TxComplete {
+        anyhow::bail!("Reliable TX was not acknowledged: {:?}", tx.resp_id);
+    }
+    device_a.wait_for_rx_packet_matching(message, Duration::from_secs(8))?;
+
+    // A retransmission after a lost ACK must not be delivered twice
+    if device_a
+        .wait_for_rx_packet_matching(message, Duration::from_secs(2))
+        .is_ok()
+    {
+        anyhow::bail!("A received the reliable message twice");
+    }
+
+    Ok(())
+}
+
+/// Test: with SetRxChecksum on, B's RxPacket carries a CRC32 that matches the
+/// data A sent.
+fn test_rx_checksum(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyhow::Result<()> {
+    device_a.clear_buffer()?;
+    device_b.clear_buffer()?;
+
+    let enabled = device_b.set_rx_checksum(true)?;
+    if enabled.resp_id != ResponseId::Ack {
+        anyhow::bail!("SetRxChecksum failed: {:?}", enabled.resp_id);
+    }
+
+    let message = b"Check me";
+    let result = device_a.lora_tx(message).and_then(|_| device_b.wait_for_rx_packet(Duration::from_secs(8)));
+    // Restore plain RxPackets for whatever runs next
+    device_b.set_rx_checksum(false)?;
+
+    let rx = result?;
+    let packet = rx_packet_checked(&rx.payload)
+        .ok_or_else(|| anyhow::anyhow!("Checksum missing or wrong: {:02x?}", rx.payload))?;
+    match rx_packet_parts(packet) {
+        Some((_, data)) if data == message => Ok(()),
+        other => anyhow::bail!("Expected {:?}, got {:?}", message, other),
+    }
+}
+
+/// A -> B, then check B's GetLastPacketStatus agrees with the RxPacket it
+/// delivered.
+fn test_last_packet_status(device_a: &mut DeviceClient, device_b: &mut DeviceClient) -> anyhow::Result<()> {
+    device_a.clear_buffer()?;
+    device_b.clear_buffer()?;
+
+    device_a.lora_tx(b"Status")?;
+    let rx = device_b.wait_for_rx_packet(Duration::from_secs(8))?;
+    // The RxPacket ends [rssi: i16 LE][snr: i8]
+    let tail = rx
+        .payload
+        .last_chunk::<3>()
+        .ok_or_else(|| anyhow::anyhow!("RxPacket too short: {:02x?}", rx.payload))?;
+    let (rssi, snr) = (i16::from_le_bytes([tail[0], tail[1]]), tail[2] as i8);
+
+    let status = device_b.get_last_packet_status()?;
+    progress!(
+        "(rssi {} dBm, signal {} dBm, snr {} dB, {:+} Hz) ",
+        status.rssi, status.signal_rssi, status.snr, status.freq_error_hz
+    );
+    if (status.rssi, status.snr) != (rssi, snr) {
+        anyhow::bail!("Status {:?} does not match RxPacket rssi {} snr {}", status, rssi, snr);
+    }
+    Ok(())
+}
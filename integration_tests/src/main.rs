@@ -1,57 +1,85 @@
 //! Integration tests for walkie-textie firmware.
 //!
-//! Run after flashing the firmware to test basic serial communication.
+//! Run after flashing the firmware to test basic serial communication, or
+//! with `monitor` to watch and send LoRa traffic interactively.
 
 mod device;
+mod monitor;
 mod protocol;
+mod report;
 mod tests;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 
 use device::{resolve_port, DeviceClient};
-use tests::{print_results, run_all_tests};
+use report::{progressln, Format, Report};
+use tests::run_all_tests;
 
 #[derive(Parser)]
 #[command(name = "integration-tests")]
 #[command(about = "Integration tests for walkie-textie firmware")]
 struct Args {
     /// Serial port for the device (use "auto" to auto-detect)
-    #[arg(short, long, default_value = "auto")]
+    #[arg(short, long, default_value = "auto", global = true)]
     port: String,
 
     /// Baud rate
-    #[arg(short, long, default_value = "115200")]
+    #[arg(short, long, default_value = "115200", global = true)]
     baud: u32,
+
+    /// Result output: coloured text, or a JSON array on stdout for CI
+    #[arg(long, value_enum, default_value = "human", global = true)]
+    format: Format,
+
+    /// What to do once connected (default: run the tests)
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Subcommand)]
+enum Mode {
+    /// Print received packets with RSSI/SNR and broadcast typed lines
+    Monitor,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    report::set_format(args.format);
 
     // Resolve port (auto-detect if "auto")
     let port = resolve_port(&args.port)?;
 
-    println!("{}", "Walkie-Textie Integration Tests".bold());
-    println!("Port: {}", port);
-    println!("Baud: {}", args.baud);
-    println!();
+    let monitoring = matches!(args.mode, Some(Mode::Monitor));
+    if monitoring {
+        progressln!("{}", "Walkie-Textie Monitor".bold());
+    } else {
+        progressln!("{}", "Walkie-Textie Integration Tests".bold());
+    }
+    progressln!("Port: {}", port);
+    progressln!("Baud: {}", args.baud);
+    progressln!();
 
-    println!("Connecting to device...");
+    progressln!("Connecting to device...");
     let mut device = DeviceClient::new(&port, args.baud)?;
 
     // Wait for the firmware to settle and confirm it responds (also warms up the
     // link, absorbing the occasional dropped first command on a fresh connection).
     device.wait_ready(std::time::Duration::from_secs(3))?;
-    println!("{}", "Connected!".green());
+    progressln!("{}", "Connected!".green());
+
+    if monitoring {
+        return monitor::run(&mut device);
+    }
 
-    println!("\nRunning tests...\n");
+    progressln!("\nRunning tests...\n");
 
-    let results = run_all_tests(&mut device);
-    print_results(&results);
+    let mut report = Report::new();
+    run_all_tests(&mut device, &mut report);
+    report.print();
 
     // Exit with error code if any tests failed
-    let failed = results.iter().filter(|r| !r.passed).count();
-    if failed > 0 {
+    if report.failed() > 0 {
         std::process::exit(1);
     }
 
@@ -0,0 +1,109 @@
+//! Interactive monitor: prints received packets and transmits typed lines.
+
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::device::DeviceClient;
+use crate::protocol::{
+    parse_event, rx_packet_parts, rx_packet_signal, tx_complete_parts, Response, ResponseId, ResponseStatus,
+};
+
+/// How long each read waits for a frame before checking for typed input.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Print every packet and event the device reports, and broadcast each line
+/// typed on stdin with LoraTx, until Ctrl-C or end of input.
+pub fn run(device: &mut DeviceClient) -> Result<()> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_flag = running.clone();
+    ctrlc::set_handler(move || handler_flag.store(false, Ordering::SeqCst))?;
+
+    // Stdin blocks, so lines are read on their own thread and picked up
+    // between reads from the device
+    let (lines_tx, lines) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    println!("Listening. Type a line and press Enter to broadcast it; Ctrl-C to quit.");
+
+    while running.load(Ordering::SeqCst) {
+        if let Some(response) = device.try_read_response(POLL)? {
+            print_unsolicited(&response);
+        }
+
+        match lines.try_recv() {
+            Ok(line) if line.is_empty() => {}
+            Ok(line) => transmit(device, line.as_bytes())?,
+            Err(TryRecvError::Empty) => {}
+            // Input closed (e.g. piped file finished)
+            Err(TryRecvError::Disconnected) => break,
+        }
+    }
+
+    println!("\n{}", "Monitor stopped".bold());
+    Ok(())
+}
+
+/// Broadcast `data`, still printing anything received while the radio is busy.
+fn transmit(device: &mut DeviceClient, data: &[u8]) -> Result<()> {
+    let response = device.lora_tx_observed(data, print_unsolicited)?;
+    match response.resp_id {
+        ResponseId::TxComplete => {
+            let len = tx_complete_parts(&response.payload).map_or(data.len() as u16, |(_, len)| len);
+            println!("{} {} bytes", "tx".green(), len);
+        }
+        ResponseId::Error => {
+            let status = response
+                .payload
+                .first()
+                .map(|&code| match ResponseStatus::try_from(code) {
+                    Ok(status) => format!("{:?}", status),
+                    Err(code) => format!("{:#04x}", code),
+                })
+                .unwrap_or_default();
+            println!("{} {}", "tx failed:".red(), status);
+        }
+        other => println!("{} unexpected {:?}", "tx".yellow(), other),
+    }
+    Ok(())
+}
+
+/// Print a received packet or event; anything else is ignored.
+fn print_unsolicited(response: &Response) {
+    match response.resp_id {
+        ResponseId::RxPacket => {
+            let (Some((source, data)), Some((rssi, snr))) =
+                (rx_packet_parts(&response.payload), rx_packet_signal(&response.payload))
+            else {
+                println!("{} malformed packet {:02x?}", "rx".yellow(), response.payload);
+                return;
+            };
+            println!(
+                "{} {:02X}{:02X}{:02X} [{} dBm, {} dB] {}",
+                "rx".cyan(),
+                source[0],
+                source[1],
+                source[2],
+                rssi,
+                snr,
+                String::from_utf8_lossy(data)
+            );
+        }
+        ResponseId::Event => match parse_event(&response.payload) {
+            Some(event) => println!("{} {:?}", "event".magenta(), event),
+            None => println!("{} unknown {:02x?}", "event".magenta(), response.payload),
+        },
+        _ => {}
+    }
+}
@@ -4,15 +4,75 @@
 
 use crc::{Crc, CRC_16_XMODEM};
 
+/// The firmware's CRC-32, built from the same source so both ends agree
+#[path = "../../src/crc32.rs"]
+pub mod crc32;
+
 /// Protocol version (must match firmware)
 pub const PROTOCOL_VERSION: u8 = 1;
 
+/// Oldest protocol version the firmware still accepts commands in.
+pub const MIN_SUPPORTED_VERSION: u8 = 1;
+
 /// Command IDs matching the firmware protocol.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum CommandId {
     GetVersion = 0x01,
+    Reboot = 0x03,
+    GetStats = 0x04,
+    Ping = 0x05,
+    Sleep = 0x06,
+    GetDeviceId = 0x07,
+    GetUptime = 0x08,
+    Sync = 0x09,
+    GetProtocolVersions = 0x0A,
+    GetCapabilities = 0x0B,
     LoraTx = 0x10,
+    LoraRx = 0x11,
+    LoraTxAddressed = 0x12,
+    LoraTxPower = 0x13,
+    LoraTxReliable = 0x14,
+    StreamBegin = 0x15,
+    StreamData = 0x16,
+    StreamEnd = 0x17,
+    SetLoraConfig = 0x20,
+    GetTemperature = 0x23,
+    TxCarrier = 0x24,
+    GetRssi = 0x25,
+    SetLogLevel = 0x26,
+    SelfTest = 0x27,
+    ClearStats = 0x28,
+    SetBand = 0x29,
+    ReadReg = 0x2A,
+    WriteReg = 0x2B,
+    EstimateAirtime = 0x2C,
+    SetRole = 0x2D,
+    VerifyFirmware = 0x2E,
+    GetRandom = 0x2F,
+    SetRxFilter = 0x30,
+    SetHeartbeatInterval = 0x31,
+    GetRecentPackets = 0x32,
+    SetLabel = 0x33,
+    GetLabel = 0x34,
+    SetLed = 0x35,
+    GetCrashLog = 0x36,
+    SetRepeater = 0x37,
+    SpectrumScan = 0x38,
+    GetLastPacketStatus = 0x39,
+    SetTxGap = 0x3A,
+    GetResetReason = 0x3B,
+    SetModulation = 0x3C,
+    SetLowBatteryPolicy = 0x3D,
+    GetRadioState = 0x3E,
+    LoraLoopback = 0x3F,
+    SetBleName = 0x40,
+    SetRxPollInterval = 0x41,
+    SetBeaconInterval = 0x42,
+    SetRxDutyCycle = 0x43,
+    SetSequenceEcho = 0x44,
+    SetRxChecksum = 0x45,
+    SetPeerMaxPayload = 0x47,
 }
 
 /// Response status codes matching the firmware protocol.
@@ -24,8 +84,18 @@ pub enum ResponseStatus {
     InvalidLength = 0x02,
     CrcError = 0x03,
     InvalidVersion = 0x04,
+    FramingError = 0x05,
+    Busy = 0x06,
     LoraError = 0x10,
     Timeout = 0x11,
+    DutyCycleExceeded = 0x12,
+    RadioCrcError = 0x13,
+    TransmitFailed = 0x14,
+    ReceiveFailed = 0x15,
+    RadioBusy = 0x16,
+    SpiError = 0x17,
+    NotInitialised = 0x18,
+    Unsupported = 0x19,
 }
 
 impl TryFrom<u8> for ResponseStatus {
@@ -38,8 +108,18 @@ impl TryFrom<u8> for ResponseStatus {
             0x02 => Ok(ResponseStatus::InvalidLength),
             0x03 => Ok(ResponseStatus::CrcError),
             0x04 => Ok(ResponseStatus::InvalidVersion),
+            0x05 => Ok(ResponseStatus::FramingError),
+            0x06 => Ok(ResponseStatus::Busy),
             0x10 => Ok(ResponseStatus::LoraError),
             0x11 => Ok(ResponseStatus::Timeout),
+            0x12 => Ok(ResponseStatus::DutyCycleExceeded),
+            0x13 => Ok(ResponseStatus::RadioCrcError),
+            0x14 => Ok(ResponseStatus::TransmitFailed),
+            0x15 => Ok(ResponseStatus::ReceiveFailed),
+            0x16 => Ok(ResponseStatus::RadioBusy),
+            0x17 => Ok(ResponseStatus::SpiError),
+            0x18 => Ok(ResponseStatus::NotInitialised),
+            0x19 => Ok(ResponseStatus::Unsupported),
             _ => Err(value),
         }
     }
@@ -79,13 +159,48 @@ pub fn build_command(cmd_id: CommandId, payload: &[u8]) -> Vec<u8> {
     cobs_encode(&raw)
 }
 
+/// Build a complete COBS-encoded command frame carrying the host's own
+/// sequence number in a trailer before the CRC.
+/// Format: [version: u8][cmd_id: u8][length: u16 LE][payload][host_sequence: u16 LE][crc16: u16 LE]
+pub fn build_command_with_host_sequence(cmd_id: CommandId, payload: &[u8], host_sequence: u16) -> Vec<u8> {
+    let mut data = build_command_payload(cmd_id as u8, payload);
+    data.truncate(data.len() - 2);
+    data.extend_from_slice(&host_sequence.to_le_bytes());
+    let checksum = CRC.checksum(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    cobs_encode(&data)
+}
+
 /// Response IDs matching the firmware
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ResponseId {
     Version = 0x01,
+    Ack = 0x02,
+    Stats = 0x04,
+    Pong = 0x05,
+    DeviceId = 0x07,
+    Uptime = 0x08,
+    SyncAck = 0x09,
+    ProtocolVersions = 0x0A,
+    Capabilities = 0x0B,
     TxComplete = 0x10,
     RxPacket = 0x11,
+    Temperature = 0x23,
+    Rssi = 0x25,
+    SelfTest = 0x27,
+    Register = 0x2A,
+    Airtime = 0x2C,
+    FirmwareCrc = 0x2E,
+    Random = 0x2F,
+    Label = 0x34,
+    CrashLog = 0x36,
+    SpectrumScan = 0x38,
+    LastPacketStatus = 0x39,
+    ResetReason = 0x3B,
+    RadioState = 0x3E,
+    Loopback = 0x3F,
+    Event = 0x50,
     Error = 0xFF,
 }
 
@@ -95,24 +210,292 @@ impl TryFrom<u8> for ResponseId {
     fn try_from(value: u8) -> Result<Self, <Self as TryFrom<u8>>::Error> {
         match value {
             0x01 => Ok(ResponseId::Version),
+            0x02 => Ok(ResponseId::Ack),
+            0x04 => Ok(ResponseId::Stats),
+            0x05 => Ok(ResponseId::Pong),
+            0x07 => Ok(ResponseId::DeviceId),
+            0x08 => Ok(ResponseId::Uptime),
+            0x09 => Ok(ResponseId::SyncAck),
+            0x0A => Ok(ResponseId::ProtocolVersions),
+            0x0B => Ok(ResponseId::Capabilities),
             0x10 => Ok(ResponseId::TxComplete),
             0x11 => Ok(ResponseId::RxPacket),
+            0x23 => Ok(ResponseId::Temperature),
+            0x25 => Ok(ResponseId::Rssi),
+            0x27 => Ok(ResponseId::SelfTest),
+            0x2A => Ok(ResponseId::Register),
+            0x2C => Ok(ResponseId::Airtime),
+            0x2E => Ok(ResponseId::FirmwareCrc),
+            0x2F => Ok(ResponseId::Random),
+            0x34 => Ok(ResponseId::Label),
+            0x36 => Ok(ResponseId::CrashLog),
+            0x38 => Ok(ResponseId::SpectrumScan),
+            0x39 => Ok(ResponseId::LastPacketStatus),
+            0x3B => Ok(ResponseId::ResetReason),
+            0x3E => Ok(ResponseId::RadioState),
+            0x3F => Ok(ResponseId::Loopback),
+            0x50 => Ok(ResponseId::Event),
             0xFF => Ok(ResponseId::Error),
             _ => Err(value),
         }
     }
 }
 
+/// Address that every device accepts.
+pub const BROADCAST_ADDRESS: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+/// Split an RxPacket payload into `(source, data)`.
+/// Layout: [source: 3 bytes][data][rssi: i16 LE][snr: i8]
+pub fn rx_packet_parts(payload: &[u8]) -> Option<([u8; 3], &[u8])> {
+    if payload.len() < 6 {
+        return None;
+    }
+    let source = [payload[0], payload[1], payload[2]];
+    Some((source, &payload[3..payload.len() - 3]))
+}
+
+/// Check and strip the CRC32 that SetRxChecksum appends to an RxPacket.
+/// Returns the payload without it (for `rx_packet_parts`), or None if it is
+/// missing or does not match the data.
+pub fn rx_packet_checked(payload: &[u8]) -> Option<&[u8]> {
+    let (packet, crc) = payload.split_at(payload.len().checked_sub(4)?);
+    let (_, data) = rx_packet_parts(packet)?;
+    (crc32::checksum(data) == u32::from_le_bytes(crc.try_into().ok()?)).then_some(packet)
+}
+
+/// Signal quality of an RxPacket: `(rssi_dbm, snr_db)`, from its last three
+/// bytes.
+pub fn rx_packet_signal(payload: &[u8]) -> Option<(i16, i8)> {
+    if payload.len() < 6 {
+        return None;
+    }
+    let tail = &payload[payload.len() - 3..];
+    Some((i16::from_le_bytes([tail[0], tail[1]]), tail[2] as i8))
+}
+
+/// Decode a TxComplete payload into `(seq, len)`: the command's sequence id
+/// and the message length sent, both u16 LE.
+pub fn tx_complete_parts(payload: &[u8]) -> Option<(u16, u16)> {
+    let [s0, s1, l0, l1] = payload.try_into().ok()?;
+    Some((u16::from_le_bytes([s0, s1]), u16::from_le_bytes([l0, l1])))
+}
+
+/// Decode an Uptime payload: milliseconds since boot as a u64 LE.
+pub fn uptime_millis(payload: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(payload.try_into().ok()?))
+}
+
+/// Name of an ESP32-S3 reset reason code, for display.
+pub fn reset_reason_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "power-on",
+        0x03 => "software",
+        0x05 => "deep sleep",
+        0x07 | 0x08 | 0x0B | 0x11 => "task watchdog",
+        0x09 | 0x0D | 0x10 => "RTC watchdog",
+        0x0F => "brownout",
+        0x12 => "super watchdog",
+        0x15 | 0x16 => "USB",
+        _ => "other",
+    }
+}
+
+/// Name of a GetRadioState code, or None for a code the radio never reports.
+pub fn radio_state_name(code: u8) -> Option<&'static str> {
+    match code {
+        0x00 => Some("sleep"),
+        0x02 => Some("standby (RC)"),
+        0x03 => Some("standby (XOSC)"),
+        0x04 => Some("frequency synthesis"),
+        0x05 => Some("RX"),
+        0x06 => Some("TX"),
+        _ => None,
+    }
+}
+
+/// Unsolicited device event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A BLE client connected on the given connection slot.
+    BleConnected { slot: u8 },
+    /// The BLE client on the given connection slot disconnected.
+    BleDisconnected { slot: u8 },
+    /// The device hopped to a new channel after repeated collisions.
+    ChannelChanged { freq_hz: u32 },
+    /// Periodic keepalive, with the milliseconds since boot (wrapping).
+    Heartbeat { uptime_ms: u32 },
+    /// A StreamData chunk went out; bytes sent so far of the announced total.
+    StreamProgress { sent: u32, total: u32 },
+    /// The radio failed to initialise; carries the error status byte.
+    RadioFault { status: u8 },
+    /// The battery fell below the SetLowBatteryPolicy threshold.
+    LowBattery { battery_mv: u16 },
+}
+
+/// Decode an Event payload: [kind: u8][slot: u8] for BLE events,
+/// [kind: u8][freq_hz: u32 LE] for a channel change, [kind: u8][uptime_ms:
+/// u32 LE] for a heartbeat, [kind: u8][sent: u32 LE][total: u32 LE] for
+/// stream progress, [kind: u8][status: u8] for a radio fault,
+/// [kind: u8][battery_mv: u16 LE] for a low battery.
+pub fn parse_event(payload: &[u8]) -> Option<Event> {
+    match payload {
+        [0x01, slot] => Some(Event::BleConnected { slot: *slot }),
+        [0x02, slot] => Some(Event::BleDisconnected { slot: *slot }),
+        [0x03, freq @ ..] => Some(Event::ChannelChanged {
+            freq_hz: u32::from_le_bytes(freq.try_into().ok()?),
+        }),
+        [0x04, uptime @ ..] => Some(Event::Heartbeat {
+            uptime_ms: u32::from_le_bytes(uptime.try_into().ok()?),
+        }),
+        [0x05, rest @ ..] if rest.len() == 8 => Some(Event::StreamProgress {
+            sent: u32::from_le_bytes(rest[..4].try_into().ok()?),
+            total: u32::from_le_bytes(rest[4..].try_into().ok()?),
+        }),
+        [0x06, status] => Some(Event::RadioFault { status: *status }),
+        [0x07, lo, hi] => Some(Event::LowBattery {
+            battery_mv: u16::from_le_bytes([*lo, *hi]),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether a response is unsolicited (a received packet or an event) rather
+/// than the reply to a command.
+pub fn is_unsolicited(resp_id: ResponseId) -> bool {
+    matches!(resp_id, ResponseId::RxPacket | ResponseId::Event)
+}
+
+/// Starting nonce for a Sync handshake, different on every run.
+pub fn sync_nonce() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or_default()
+}
+
+/// Link counters reported by GetStats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub tx_messages: u32,
+    pub tx_errors: u32,
+    pub rx_messages: u32,
+    pub rx_errors: u32,
+    pub duty_cycle_rejections: u32,
+    pub rx_filtered: u32,
+    pub rx_duplicates: u32,
+}
+
+/// Decode a Stats payload: seven u32 LE counters.
+pub fn parse_stats(payload: &[u8]) -> Option<Stats> {
+    if payload.len() != 28 {
+        return None;
+    }
+    let field = |i: usize| u32::from_le_bytes(payload[i * 4..i * 4 + 4].try_into().unwrap());
+    Some(Stats {
+        tx_messages: field(0),
+        tx_errors: field(1),
+        rx_messages: field(2),
+        rx_errors: field(3),
+        duty_cycle_rejections: field(4),
+        rx_filtered: field(5),
+        rx_duplicates: field(6),
+    })
+}
+
+/// Firmware version, reported by GetVersion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    /// Longest LoRa packet the device can receive; absent from older firmware
+    pub max_payload: Option<u16>,
+    /// Short git hash and build date; absent from older firmware
+    pub build: Option<String>,
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(build) = &self.build {
+            write!(f, " ({})", build)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a Version payload:
+/// `[major][minor][patch]`, then `[max_payload: u16 LE]` and the build id
+/// (ASCII) from firmware new enough to send them.
+pub fn parse_version(payload: &[u8]) -> Option<Version> {
+    let [major, minor, patch, rest @ ..] = payload else {
+        return None;
+    };
+    let (max_payload, build) = match rest {
+        [] => (None, None),
+        [lo, hi, build @ ..] => (
+            Some(u16::from_le_bytes([*lo, *hi])),
+            (!build.is_empty()).then(|| String::from_utf8_lossy(build).into_owned()),
+        ),
+        _ => return None,
+    };
+    Some(Version {
+        major: *major,
+        minor: *minor,
+        patch: *patch,
+        max_payload,
+        build,
+    })
+}
+
+/// Link quality of the last packet received, reported by GetLastPacketStatus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketStatus {
+    pub rssi: i16,
+    pub signal_rssi: i16,
+    pub snr: i8,
+    pub freq_error_hz: i32,
+}
+
+/// Decode a LastPacketStatus payload:
+/// `[rssi: i16 LE][signal_rssi: i16 LE][snr: i8][freq_error_hz: i32 LE]`.
+pub fn parse_packet_status(payload: &[u8]) -> Option<PacketStatus> {
+    if payload.len() != 9 {
+        return None;
+    }
+    Some(PacketStatus {
+        rssi: i16::from_le_bytes([payload[0], payload[1]]),
+        signal_rssi: i16::from_le_bytes([payload[2], payload[3]]),
+        snr: payload[4] as i8,
+        freq_error_hz: i32::from_le_bytes(payload[5..9].try_into().unwrap()),
+    })
+}
+
+/// SelfTest result bits.
+pub mod self_test {
+    pub const SPI_OK: u8 = 0x01;
+    pub const RADIO_RESPONSIVE: u8 = 0x02;
+    pub const TCXO_READY: u8 = 0x04;
+    pub const ALL: u8 = SPI_OK | RADIO_RESPONSIVE | TCXO_READY;
+}
+
 /// Parsed response from the device.
 #[derive(Debug)]
 pub struct Response {
     pub version: u8,
     pub resp_id: ResponseId,
     pub payload: Vec<u8>,
+    /// Sequence id of the command answered, if SetSequenceEcho is on or the
+    /// command carried a host sequence number
+    pub sequence: Option<u16>,
+    /// The host sequence number the command carried, if any
+    pub host_sequence: Option<u16>,
 }
 
 /// Parse a COBS-decoded response.
-/// Format: [version: u8][resp_id: u8][length: u16 LE][payload][crc: u16 LE]
+/// Format: [version: u8][resp_id: u8][length: u16 LE][payload][trailer][crc: u16 LE]
+/// The trailer is empty, [sequence: u16 LE], or
+/// [host_sequence: u16 LE][sequence: u16 LE] for a command that carried one.
 pub fn parse_response(data: &[u8]) -> anyhow::Result<Response> {
     if data.len() < 6 {
         anyhow::bail!("Response too short: {} bytes", data.len());
@@ -122,19 +505,27 @@ pub fn parse_response(data: &[u8]) -> anyhow::Result<Response> {
     let resp_id_byte = data[1];
     let length = u16::from_le_bytes([data[2], data[3]]) as usize;
 
-    if data.len() < 4 + length + 2 {
-        anyhow::bail!(
-            "Response payload incomplete: expected {}, got {}",
+    // Bytes beyond what the length accounts for are the trailer
+    let trailer_u16 = |at: usize| u16::from_le_bytes([data[4 + length + at], data[4 + length + at + 1]]);
+    let (host_sequence, sequence) = match data.len().checked_sub(4 + length + 2) {
+        Some(0) => (None, None),
+        Some(2) => (None, Some(trailer_u16(0))),
+        Some(4) => (Some(trailer_u16(0)), Some(trailer_u16(2))),
+        _ => anyhow::bail!(
+            "Response length mismatch: expected {}, {} or {}, got {}",
             4 + length + 2,
+            4 + length + 4,
+            4 + length + 6,
             data.len()
-        );
-    }
+        ),
+    };
+    let crc_at = data.len() - 2;
 
     let payload = data[4..4 + length].to_vec();
-    let received_crc = u16::from_le_bytes([data[4 + length], data[4 + length + 1]]);
+    let received_crc = u16::from_le_bytes([data[crc_at], data[crc_at + 1]]);
 
-    // Verify CRC over version + resp_id + length + payload
-    let calculated_crc = CRC.checksum(&data[..4 + length]);
+    // Verify CRC over everything before it
+    let calculated_crc = CRC.checksum(&data[..crc_at]);
     if calculated_crc != received_crc {
         anyhow::bail!(
             "CRC mismatch: expected {:04x}, got {:04x}",
@@ -159,6 +550,8 @@ pub fn parse_response(data: &[u8]) -> anyhow::Result<Response> {
         version,
         resp_id,
         payload,
+        sequence,
+        host_sequence,
     })
 }
 
@@ -0,0 +1,213 @@
+//! Test result collection and output.
+//!
+//! Every runner records its tests through a `Report`, which times each one,
+//! prints the live PASS/FAIL line and renders the results once at the end:
+//! the coloured summary by default, or a JSON array with `--format json`.
+//! In JSON mode everything else a runner prints goes to stderr (use
+//! `progress!`/`progressln!` rather than `print!`/`println!`), so stdout is
+//! just the array.
+
+#![allow(dead_code)]
+
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use colored::Colorize;
+
+/// How results are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Coloured text for a terminal
+    Human,
+    /// A JSON array of results on stdout
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Select the output format. Call before printing anything.
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Write progress text: to stdout, or to stderr in JSON mode. Flushed so a
+/// line left open ("  name ... ") shows before the test runs.
+pub fn write_progress(args: std::fmt::Arguments) {
+    if json() {
+        let mut err = std::io::stderr();
+        let _ = err.write_fmt(args);
+        let _ = err.flush();
+    } else {
+        let mut out = std::io::stdout();
+        let _ = out.write_fmt(args);
+        let _ = out.flush();
+    }
+}
+
+/// `print!` for progress text (see `write_progress`).
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        $crate::report::write_progress(format_args!($($arg)*))
+    };
+}
+
+/// `println!` for progress text (see `write_progress`).
+macro_rules! progressln {
+    () => {
+        $crate::report::write_progress(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::report::write_progress(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use {progress, progressln};
+
+/// Test result.
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    pub message: Option<String>,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn pass(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: true,
+            message: None,
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub fn fail(name: &str, message: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            message: Some(message.to_string()),
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+/// A test that has been announced and is being timed.
+pub struct Running {
+    name: String,
+    started: Instant,
+}
+
+/// Results of one run, in the order the tests ran.
+#[derive(Default)]
+pub struct Report {
+    results: Vec<TestResult>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Announce a test and start its clock.
+    pub fn start(&self, name: &str) -> Running {
+        progress!("  {} ... ", name);
+        Running {
+            name: name.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Record a test that returned `Ok` or an error.
+    pub fn finish<E: Display>(&mut self, test: Running, outcome: Result<(), E>) {
+        let result = match outcome {
+            Ok(()) => TestResult::pass(&test.name),
+            Err(e) => TestResult::fail(&test.name, &e.to_string()),
+        };
+        self.record(test, result);
+    }
+
+    /// Record a test's result under the name and time it was started with.
+    pub fn record(&mut self, test: Running, mut result: TestResult) {
+        result.name = test.name;
+        result.duration = test.started.elapsed();
+
+        if result.passed {
+            progressln!("{}", "PASS".green().bold());
+        } else {
+            progressln!("{}", "FAIL".red().bold());
+            if let Some(msg) = &result.message {
+                progressln!("    {}", msg.red());
+            }
+        }
+
+        self.results.push(result);
+    }
+
+    /// Number of tests that failed.
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed).count()
+    }
+
+    /// Print the results in the selected format.
+    pub fn print(&self) {
+        if json() {
+            self.print_json();
+        } else {
+            self.print_summary();
+        }
+    }
+
+    fn print_json(&self) {
+        let results: Vec<serde_json::Value> = self
+            .results
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "name": r.name,
+                    "passed": r.passed,
+                    "message": r.message,
+                    "duration_ms": r.duration.as_millis() as u64,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(results));
+    }
+
+    fn print_summary(&self) {
+        println!("\n{}", "=".repeat(60));
+        println!("{}", "Test Results".bold());
+        println!("{}", "=".repeat(60));
+
+        for result in &self.results {
+            if result.passed {
+                println!("  {} {}", "[PASS]".green().bold(), result.name);
+            } else {
+                println!("  {} {}", "[FAIL]".red().bold(), result.name);
+                if let Some(msg) = &result.message {
+                    println!("         {}", msg.red());
+                }
+            }
+        }
+
+        let failed = self.failed();
+        let passed = self.results.len() - failed;
+        println!("{}", "-".repeat(60));
+        println!(
+            "  Total: {} passed, {} failed",
+            passed.to_string().green(),
+            if failed > 0 {
+                failed.to_string().red()
+            } else {
+                failed.to_string().normal()
+            }
+        );
+        println!("{}", "=".repeat(60));
+    }
+}
@@ -1,100 +1,54 @@
 //! Integration test cases.
 
-use colored::Colorize;
-
 use crate::device::DeviceClient;
-use crate::protocol::{CommandId, ResponseId, ResponseStatus};
-
-/// Test result.
-pub struct TestResult {
-    pub name: String,
-    pub passed: bool,
-    pub message: Option<String>,
-}
-
-impl TestResult {
-    fn pass(name: &str) -> Self {
-        Self {
-            name: name.to_string(),
-            passed: true,
-            message: None,
-        }
-    }
-
-    fn fail(name: &str, message: &str) -> Self {
-        Self {
-            name: name.to_string(),
-            passed: false,
-            message: Some(message.to_string()),
-        }
-    }
-}
+use crate::protocol::{
+    parse_version, radio_state_name, reset_reason_name, self_test, CommandId, ResponseId, ResponseStatus, Stats,
+    PROTOCOL_VERSION,
+};
+use crate::report::{progress, Report, TestResult};
 
-/// Run a test function and print results as it happens.
-fn run_test<F>(name: &str, device: &mut DeviceClient, test_fn: F) -> TestResult
+/// Run a test function and record its result.
+fn run_test<F>(report: &mut Report, name: &str, device: &mut DeviceClient, test_fn: F)
 where
     F: FnOnce(&mut DeviceClient) -> TestResult,
 {
-    print!("  {} ... ", name);
-    std::io::Write::flush(&mut std::io::stdout()).ok();
-
-    let mut result = test_fn(device);
-    result.name = name.to_string();
-
-    if result.passed {
-        println!("{}", "PASS".green().bold());
-    } else {
-        println!("{}", "FAIL".red().bold());
-        if let Some(msg) = &result.message {
-            println!("    {}", msg.red());
-        }
-    }
-
-    result
+    let test = report.start(name);
+    let result = test_fn(device);
+    report.record(test, result);
 }
 
-/// Run all tests and return results.
-pub fn run_all_tests(device: &mut DeviceClient) -> Vec<TestResult> {
-    vec![
-        run_test("GetVersion returns version bytes", device, test_get_version),
-        run_test("Invalid command returns error", device, test_invalid_command),
-        run_test("Multiple GetVersion calls succeed", device, test_multiple_get_version),
-    ]
-}
-
-/// Print test results summary.
-pub fn print_results(results: &[TestResult]) {
-    println!("\n{}", "=".repeat(60));
-    println!("{}", "Test Results".bold());
-    println!("{}", "=".repeat(60));
-
-    let mut passed = 0;
-    let mut failed = 0;
-
-    for result in results {
-        if result.passed {
-            println!("  {} {}", "[PASS]".green().bold(), result.name);
-            passed += 1;
-        } else {
-            println!("  {} {}", "[FAIL]".red().bold(), result.name);
-            if let Some(msg) = &result.message {
-                println!("         {}", msg.red());
-            }
-            failed += 1;
-        }
-    }
-
-    println!("{}", "-".repeat(60));
-    println!(
-        "  Total: {} passed, {} failed",
-        passed.to_string().green(),
-        if failed > 0 {
-            failed.to_string().red()
-        } else {
-            failed.to_string().normal()
-        }
-    );
-    println!("{}", "=".repeat(60));
+/// Run all tests, recording each in `report`.
+pub fn run_all_tests(device: &mut DeviceClient, report: &mut Report) {
+    run_test(report, "GetVersion returns version bytes", device, test_get_version);
+    run_test(report, "Invalid command returns error", device, test_invalid_command);
+    run_test(report, "Multiple GetVersion calls succeed", device, test_multiple_get_version);
+    run_test(report, "SetBleName rejects invalid names", device, test_set_ble_name_invalid);
+    run_test(report, "Oversized frame returns error and resyncs", device, test_oversized_frame);
+    run_test(report, "Malformed COBS frame returns FramingError", device, test_malformed_cobs);
+    run_test(report, "Ping echoes payload", device, test_ping);
+    run_test(report, "GetDeviceId returns a stable id", device, test_get_device_id);
+    run_test(report, "GetUptime advances", device, test_get_uptime);
+    run_test(report, "GetProtocolVersions covers this host", device, test_get_protocol_versions);
+    run_test(report, "GetCapabilities lists the core commands", device, test_get_capabilities);
+    run_test(report, "SetSequenceEcho numbers every response", device, test_sequence_echo);
+    run_test(report, "Host sequence numbers are echoed", device, test_host_sequence);
+    run_test(report, "SetLabel round-trips and rejects oversized labels", device, test_label);
+    run_test(report, "SetLed takes and releases the LED", device, test_set_led);
+    run_test(report, "GetCrashLog holds the boot marker", device, test_crash_log);
+    run_test(report, "SetRepeater turns on and off", device, test_set_repeater);
+    run_test(report, "SpectrumScan samples every channel", device, test_spectrum_scan);
+    run_test(report, "SelfTest reports a healthy radio", device, test_self_test);
+    run_test(report, "ClearStats zeroes the counters", device, test_clear_stats);
+    run_test(report, "LoraTxPower rejects out-of-range power", device, test_lora_tx_power_out_of_range);
+    run_test(report, "LoraTx is held to the peer's max payload", device, test_peer_max_payload);
+    run_test(report, "SetTxGap spaces consecutive transmissions", device, test_tx_gap);
+    run_test(report, "GetResetReason reports why the chip reset", device, test_reset_reason);
+    run_test(report, "SetModulation switches to FSK and back", device, test_fsk_modulation);
+    run_test(report, "SetLowBatteryPolicy validates its TX power", device, test_low_battery_policy);
+    run_test(report, "GetRadioState reports a known chip mode", device, test_radio_state);
+    run_test(report, "LoraLoopback answers with its result", device, test_lora_loopback);
+    // Last: it resets the device
+    run_test(report, "Reboot restarts the device", device, test_reboot);
 }
 
 // --- Individual Tests ---
@@ -105,19 +59,14 @@ fn test_get_version(device: &mut DeviceClient) -> TestResult {
             if response.resp_id != ResponseId::Version {
                 return TestResult::fail("test", &format!("Expected Version response, got {:?}", response.resp_id));
             }
-            if response.payload.len() != 3 {
-                return TestResult::fail(
-                    "test",
-                    &format!("Expected 3 bytes, got {}", response.payload.len()),
-                );
-            }
-
-            let (major, minor, patch) = (
-                response.payload[0],
-                response.payload[1],
-                response.payload[2],
-            );
-            print!("(v{}.{}.{}) ", major, minor, patch);
+            let version = match parse_version(&response.payload) {
+                Some(version) => version,
+                None => return TestResult::fail("test", &format!("Malformed payload {:02x?}", response.payload)),
+            };
+            let (Some(max_payload), Some(_)) = (version.max_payload, &version.build) else {
+                return TestResult::fail("test", &format!("Missing max payload or build id in {:02x?}", response.payload));
+            };
+            progress!("({}, max payload {}) ", version, max_payload);
             TestResult::pass("test")
         }
         Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
@@ -172,3 +121,556 @@ fn test_multiple_get_version(device: &mut DeviceClient) -> TestResult {
 
     TestResult::pass("test")
 }
+
+fn test_set_ble_name_invalid(device: &mut DeviceClient) -> TestResult {
+    // Only invalid names are sent so the advertised name (which the BLE tests
+    // scan for) is left unchanged.
+    let cases: [(&str, &[u8]); 3] = [
+        ("empty", b""),
+        ("too long", b"ThisNameIsWayTooLongToAdvertise"),
+        ("non-printable", b"Bad\x01Name"),
+    ];
+
+    for (label, name) in cases {
+        match device.set_ble_name(name) {
+            Ok(response) => {
+                if response.resp_id != ResponseId::Error {
+                    return TestResult::fail(
+                        "test",
+                        &format!("{} name: expected Error response, got {:?}", label, response.resp_id),
+                    );
+                }
+                if response.payload.first() != Some(&(ResponseStatus::InvalidLength as u8)) {
+                    return TestResult::fail(
+                        "test",
+                        &format!("{} name: expected InvalidLength status, got {:02x?}", label, response.payload),
+                    );
+                }
+            }
+            Err(e) => return TestResult::fail("test", &format!("{} name error: {}", label, e)),
+        }
+    }
+
+    TestResult::pass("test")
+}
+
+fn test_oversized_frame(device: &mut DeviceClient) -> TestResult {
+    // Far larger than the firmware's MAX_FRAME_SIZE, with no zero bytes until
+    // the final delimiter, so the accumulator overflows mid-frame.
+    let mut oversized = vec![0x55u8; 4096];
+    oversized.push(0x00);
+
+    match device.send_raw_bytes(&oversized) {
+        Ok(response) => {
+            if response.resp_id != ResponseId::Error {
+                return TestResult::fail(
+                    "test",
+                    &format!("Expected Error response, got {:?}", response.resp_id),
+                );
+            }
+            if response.payload.first() != Some(&(ResponseStatus::InvalidLength as u8)) {
+                return TestResult::fail(
+                    "test",
+                    &format!("Expected InvalidLength status, got {:02x?}", response.payload),
+                );
+            }
+        }
+        Err(e) => return TestResult::fail("test", &format!("Oversized frame error: {}", e)),
+    }
+
+    // The next valid frame must still be accepted.
+    match device.send_command(CommandId::GetVersion, &[]) {
+        Ok(response) if response.resp_id == ResponseId::Version => TestResult::pass("test"),
+        Ok(response) => TestResult::fail(
+            "test",
+            &format!("GetVersion after overflow: got {:?}", response.resp_id),
+        ),
+        Err(e) => TestResult::fail("test", &format!("GetVersion after overflow error: {}", e)),
+    }
+}
+
+fn test_malformed_cobs(device: &mut DeviceClient) -> TestResult {
+    // The code byte 0x05 promises four data bytes, but the delimiter comes
+    // after one, so the frame cannot be COBS-decoded.
+    match device.send_raw_bytes(&[0x05, 0x01, 0x00]) {
+        Ok(response) => {
+            if response.resp_id != ResponseId::Error {
+                return TestResult::fail(
+                    "test",
+                    &format!("Expected Error response, got {:?}", response.resp_id),
+                );
+            }
+            match response.payload.first().copied().map(ResponseStatus::try_from) {
+                Some(Ok(ResponseStatus::FramingError)) => TestResult::pass("test"),
+                _ => TestResult::fail(
+                    "test",
+                    &format!("Expected FramingError status, got {:02x?}", response.payload),
+                ),
+            }
+        }
+        Err(e) => TestResult::fail("test", &format!("Malformed frame error: {}", e)),
+    }
+}
+
+fn test_ping(device: &mut DeviceClient) -> TestResult {
+    match device.ping() {
+        Ok(round_trip) => {
+            progress!("({:.1} ms) ", round_trip.as_secs_f64() * 1000.0);
+            TestResult::pass("test")
+        }
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_get_device_id(device: &mut DeviceClient) -> TestResult {
+    let first = match device.get_device_id() {
+        Ok(id) => id,
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    };
+    match device.get_device_id() {
+        Ok(second) if second == first => {
+            progress!("({:02X}{:02X}{:02X}) ", first[0], first[1], first[2]);
+            TestResult::pass("test")
+        }
+        Ok(second) => TestResult::fail("test", &format!("Id changed: {:02x?} then {:02x?}", first, second)),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_get_uptime(device: &mut DeviceClient) -> TestResult {
+    let first = match device.get_uptime() {
+        Ok(millis) => millis,
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    };
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    match device.get_uptime() {
+        // The 8-byte payload made it through COBS and the CRC intact
+        Ok(second) if second >= first + 200 => {
+            progress!("({} ms) ", second);
+            TestResult::pass("test")
+        }
+        Ok(second) => TestResult::fail("test", &format!("Uptime went from {} to {} ms", first, second)),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_get_protocol_versions(device: &mut DeviceClient) -> TestResult {
+    match device.get_protocol_versions() {
+        Ok((current, min_supported)) if (min_supported..=current).contains(&PROTOCOL_VERSION) => {
+            progress!("(v{}, min v{}) ", current, min_supported);
+            TestResult::pass("test")
+        }
+        Ok((current, min_supported)) => TestResult::fail(
+            "test",
+            &format!("Host speaks v{}, device accepts v{}-v{}", PROTOCOL_VERSION, min_supported, current),
+        ),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_get_capabilities(device: &mut DeviceClient) -> TestResult {
+    let bitmap = match device.get_capabilities() {
+        Ok(bitmap) => bitmap,
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    };
+    let supported = |id: CommandId| bitmap[id as usize / 8] & (1 << (id as u8 % 8)) != 0;
+    let core = [
+        CommandId::GetVersion,
+        CommandId::GetCapabilities,
+        CommandId::LoraTx,
+        CommandId::LoraRx,
+        CommandId::SetLoraConfig,
+    ];
+    match core.into_iter().find(|&id| !supported(id)) {
+        None => TestResult::pass("test"),
+        Some(id) => TestResult::fail("test", &format!("{:?} missing from {:02x?}", id, bitmap)),
+    }
+}
+
+fn test_sequence_echo(device: &mut DeviceClient) -> TestResult {
+    match device.set_sequence_echo(true) {
+        Ok(r) if r.resp_id == ResponseId::Ack => {}
+        Ok(r) => return TestResult::fail("test", &format!("Enable: unexpected {:?}", r.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("Enable error: {}", e)),
+    }
+    let first = device.send_command(CommandId::GetVersion, &[]);
+    let second = device.send_command(CommandId::GetVersion, &[]);
+    let disabled = device.set_sequence_echo(false);
+    let after = device.send_command(CommandId::GetVersion, &[]);
+
+    match (first, second, disabled, after) {
+        (Ok(first), Ok(second), Ok(_), Ok(after)) => match (first.sequence, second.sequence, after.sequence) {
+            (Some(a), Some(b), None) if b == a.wrapping_add(1) => {
+                progress!("(#{}, #{}) ", a, b);
+                TestResult::pass("test")
+            }
+            other => TestResult::fail("test", &format!("Unexpected sequences {:?}", other)),
+        },
+        (first, second, disabled, after) => TestResult::fail(
+            "test",
+            &format!("Errors: {:?} {:?} {:?} {:?}", first.err(), second.err(), disabled.err(), after.err()),
+        ),
+    }
+}
+
+fn test_host_sequence(device: &mut DeviceClient) -> TestResult {
+    let first = device.send_command_with_host_sequence(CommandId::GetVersion, &[], 0xFFFF);
+    let second = device.send_command_with_host_sequence(CommandId::GetVersion, &[], 0x0000);
+    let plain = device.send_command(CommandId::GetVersion, &[]);
+
+    match (first, second, plain) {
+        (Ok(first), Ok(second), Ok(plain)) => {
+            match (first.host_sequence, second.host_sequence, first.sequence, second.sequence, plain.sequence) {
+                // Consecutive device counts: no command was lost in between
+                (Some(0xFFFF), Some(0x0000), Some(a), Some(b), None) if b == a.wrapping_add(1) => {
+                    progress!("(device #{}, #{}) ", a, b);
+                    TestResult::pass("test")
+                }
+                other => TestResult::fail("test", &format!("Unexpected sequences {:?}", other)),
+            }
+        }
+        (first, second, plain) => TestResult::fail(
+            "test",
+            &format!("Errors: {:?} {:?} {:?}", first.err(), second.err(), plain.err()),
+        ),
+    }
+}
+
+fn test_set_led(device: &mut DeviceClient) -> TestResult {
+    // Blink, an unknown mode, then back to automatic
+    for (mode, expected) in [(3, ResponseId::Ack), (9, ResponseId::Error), (0, ResponseId::Ack)] {
+        match device.set_led(mode) {
+            Ok(response) if response.resp_id == expected => {}
+            Ok(response) => {
+                return TestResult::fail("test", &format!("Mode {}: expected {:?}, got {:?}", mode, expected, response.resp_id))
+            }
+            Err(e) => return TestResult::fail("test", &format!("Mode {} error: {}", mode, e)),
+        }
+    }
+
+    TestResult::pass("test")
+}
+
+fn test_set_repeater(device: &mut DeviceClient) -> TestResult {
+    // Left off, so later tests see only their own traffic
+    for enabled in [true, false] {
+        match device.set_repeater(enabled) {
+            Ok(response) if response.resp_id == ResponseId::Ack => {}
+            Ok(response) => return TestResult::fail("test", &format!("Expected Ack, got {:?}", response.resp_id)),
+            Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+        }
+    }
+
+    TestResult::pass("test")
+}
+
+fn test_spectrum_scan(device: &mut DeviceClient) -> TestResult {
+    // The EU 869.4-869.65 MHz sub-band in 25 kHz steps
+    match device.spectrum_scan(869_400_000, 25_000, 11) {
+        Ok(rssi) if rssi.len() == 11 && rssi.iter().all(|dbm| (-150..=0).contains(dbm)) => {
+            progress!("({:?} dBm) ", rssi);
+            TestResult::pass("test")
+        }
+        Ok(rssi) => TestResult::fail("test", &format!("Implausible samples {:?}", rssi)),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_crash_log(device: &mut DeviceClient) -> TestResult {
+    match device.get_crash_log() {
+        Ok(log) => {
+            let log = String::from_utf8_lossy(&log);
+            // Every boot writes a marker, so at least this run's is there
+            if log.lines().any(|line| line.starts_with("--- boot")) {
+                progress!("({} lines) ", log.lines().count());
+                TestResult::pass("test")
+            } else {
+                TestResult::fail("test", &format!("No boot marker in {:?}", log))
+            }
+        }
+        Err(e) => TestResult::fail("test", &format!("GetCrashLog error: {}", e)),
+    }
+}
+
+fn test_reset_reason(device: &mut DeviceClient) -> TestResult {
+    match device.get_reset_reason() {
+        Ok(code) => {
+            progress!("({:#04x}, {}) ", code, reset_reason_name(code));
+            TestResult::pass("test")
+        }
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_radio_state(device: &mut DeviceClient) -> TestResult {
+    match device.get_radio_state() {
+        Ok(state) => match radio_state_name(state) {
+            Some(name) => {
+                progress!("({}) ", name);
+                TestResult::pass("test")
+            }
+            None => TestResult::fail("test", &format!("Unknown radio state {:#04x}", state)),
+        },
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_lora_loopback(device: &mut DeviceClient) -> TestResult {
+    // Without a fixture to send the pattern back it fails, which is still an
+    // answer
+    match device.lora_loopback() {
+        Ok((true, rssi, snr)) => {
+            progress!("(passed, {} dBm, {} dB) ", rssi, snr);
+            TestResult::pass("test")
+        }
+        Ok((false, _, _)) => {
+            progress!("(no fixture) ");
+            TestResult::pass("test")
+        }
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_reboot(device: &mut DeviceClient) -> TestResult {
+    let before = match device.get_uptime() {
+        Ok(millis) => millis,
+        Err(e) => return TestResult::fail("test", &format!("GetUptime error: {}", e)),
+    };
+    if let Err(e) = device.reboot(std::time::Duration::from_secs(15)) {
+        return TestResult::fail("test", &format!("Error: {}", e));
+    }
+    if let Err(e) = device.get_version(std::time::Duration::from_secs(3)) {
+        return TestResult::fail("test", &format!("GetVersion after reboot: {}", e));
+    }
+    match device.get_uptime() {
+        Ok(after) if after < before => progress!("(back after {} ms) ", after),
+        Ok(after) => return TestResult::fail("test", &format!("Uptime did not reset: {} then {} ms", before, after)),
+        Err(e) => return TestResult::fail("test", &format!("GetUptime error: {}", e)),
+    }
+    // Reboot is a software reset
+    match device.get_reset_reason() {
+        Ok(0x03) => TestResult::pass("test"),
+        Ok(code) => TestResult::fail(
+            "test",
+            &format!("Reset reason {:#04x} ({}), expected software", code, reset_reason_name(code)),
+        ),
+        Err(e) => TestResult::fail("test", &format!("GetResetReason error: {}", e)),
+    }
+}
+
+fn test_label(device: &mut DeviceClient) -> TestResult {
+    // Put back whatever the operator had set once done
+    let original = match device.get_label() {
+        Ok(label) => label,
+        Err(e) => return TestResult::fail("test", &format!("GetLabel error: {}", e)),
+    };
+
+    match device.set_label("Test bench ✓".as_bytes()) {
+        Ok(response) if response.resp_id == ResponseId::Ack => {}
+        Ok(response) => return TestResult::fail("test", &format!("SetLabel: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("SetLabel error: {}", e)),
+    }
+    match device.get_label() {
+        Ok(label) if label == "Test bench ✓".as_bytes() => {}
+        Ok(label) => return TestResult::fail("test", &format!("Label read back as {:02x?}", label)),
+        Err(e) => return TestResult::fail("test", &format!("GetLabel error: {}", e)),
+    }
+
+    match device.set_label(&[b'x'; 33]) {
+        Ok(response) if response.resp_id == ResponseId::Error => {
+            if response.payload.first() != Some(&(ResponseStatus::InvalidLength as u8)) {
+                return TestResult::fail("test", &format!("Expected InvalidLength status, got {:02x?}", response.payload));
+            }
+        }
+        Ok(response) => return TestResult::fail("test", &format!("Oversized label: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("Oversized label error: {}", e)),
+    }
+
+    match device.set_label(&original) {
+        Ok(response) if response.resp_id == ResponseId::Ack => TestResult::pass("test"),
+        Ok(response) => TestResult::fail("test", &format!("Restoring label: got {:?}", response.resp_id)),
+        Err(e) => TestResult::fail("test", &format!("Restoring label error: {}", e)),
+    }
+}
+
+fn test_self_test(device: &mut DeviceClient) -> TestResult {
+    match device.self_test() {
+        Ok(self_test::ALL) => TestResult::pass("test"),
+        Ok(flags) => TestResult::fail("test", &format!("Checks failed, flags {:#04x}", flags)),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_clear_stats(device: &mut DeviceClient) -> TestResult {
+    // A broadcast needs no second device to complete.
+    match device.lora_tx(b"stats") {
+        Ok(response) if response.resp_id == ResponseId::TxComplete => {}
+        Ok(response) => return TestResult::fail("test", &format!("LoraTx returned {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    }
+    match device.get_stats() {
+        Ok(stats) if stats.tx_messages >= 1 => {}
+        Ok(stats) => return TestResult::fail("test", &format!("TX not counted: {:?}", stats)),
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    }
+
+    match device.clear_stats() {
+        Ok(response) if response.resp_id == ResponseId::Ack => {}
+        Ok(response) => return TestResult::fail("test", &format!("ClearStats returned {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("Error: {}", e)),
+    }
+    match device.get_stats() {
+        Ok(stats) if stats == Stats::default() => TestResult::pass("test"),
+        Ok(stats) => TestResult::fail("test", &format!("Counters not cleared: {:?}", stats)),
+        Err(e) => TestResult::fail("test", &format!("Error: {}", e)),
+    }
+}
+
+fn test_lora_tx_power_out_of_range(device: &mut DeviceClient) -> TestResult {
+    // Rejected before anything is transmitted, so no second device is needed.
+    for power_dbm in [-10i8, 23] {
+        match device.lora_tx_power(power_dbm, b"test") {
+            Ok(response) => {
+                if response.resp_id != ResponseId::Error {
+                    return TestResult::fail(
+                        "test",
+                        &format!("{} dBm: expected Error response, got {:?}", power_dbm, response.resp_id),
+                    );
+                }
+                if response.payload.first() != Some(&(ResponseStatus::LoraError as u8)) {
+                    return TestResult::fail(
+                        "test",
+                        &format!("{} dBm: expected LoraError status, got {:02x?}", power_dbm, response.payload),
+                    );
+                }
+            }
+            Err(e) => return TestResult::fail("test", &format!("{} dBm error: {}", power_dbm, e)),
+        }
+    }
+
+    TestResult::pass("test")
+}
+
+fn test_peer_max_payload(device: &mut DeviceClient) -> TestResult {
+    let own = match device.get_max_payload() {
+        Ok(max_payload) => max_payload,
+        Err(e) => return TestResult::fail("test", &format!("GetVersion error: {}", e)),
+    };
+    match device.set_peer_max_payload(64) {
+        Ok(response) if response.resp_id == ResponseId::Ack => {}
+        Ok(response) => return TestResult::fail("test", &format!("SetPeerMaxPayload: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("SetPeerMaxPayload error: {}", e)),
+    }
+
+    // Rejected before anything is transmitted
+    let result = device.lora_tx(&[b'x'; 100]);
+    let restored = device.set_peer_max_payload(own);
+    match result {
+        Ok(response) if response.resp_id == ResponseId::Error => {
+            if response.payload.first() != Some(&(ResponseStatus::InvalidLength as u8)) {
+                return TestResult::fail("test", &format!("Expected InvalidLength status, got {:02x?}", response.payload));
+            }
+        }
+        Ok(response) => return TestResult::fail("test", &format!("Oversized LoraTx: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("LoraTx error: {}", e)),
+    }
+    match restored {
+        Ok(response) if response.resp_id == ResponseId::Ack => TestResult::pass("test"),
+        Ok(response) => TestResult::fail("test", &format!("Restoring max payload: got {:?}", response.resp_id)),
+        Err(e) => TestResult::fail("test", &format!("Restoring max payload error: {}", e)),
+    }
+}
+
+fn test_low_battery_policy(device: &mut DeviceClient) -> TestResult {
+    // Out of the radio's power range
+    match device.set_low_battery_policy(3500, 23, false) {
+        Ok(r) if r.resp_id == ResponseId::Error => {}
+        Ok(r) => return TestResult::fail("test", &format!("Power 23: unexpected {:?}", r.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("Power 23 error: {}", e)),
+    }
+    let set = device.set_low_battery_policy(3500, 5, true);
+    let off = device.set_low_battery_policy(0, 0, false);
+    match (set, off) {
+        (Ok(set), Ok(off)) if set.resp_id == ResponseId::Ack && off.resp_id == ResponseId::Ack => {
+            TestResult::pass("test")
+        }
+        (Ok(set), Ok(off)) => TestResult::fail("test", &format!("Unexpected {:?} {:?}", set.resp_id, off.resp_id)),
+        (set, off) => TestResult::fail("test", &format!("Errors: {:?} {:?}", set.err(), off.err())),
+    }
+}
+
+fn test_tx_gap(device: &mut DeviceClient) -> TestResult {
+    const GAP_MS: u32 = 1000;
+
+    match device.set_tx_gap(GAP_MS) {
+        Ok(response) if response.resp_id == ResponseId::Ack => {}
+        Ok(response) => return TestResult::fail("test", &format!("SetTxGap: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("SetTxGap error: {}", e)),
+    }
+
+    // The second transmission is held until the gap has passed since the
+    // first finished
+    let mut result = Ok(std::time::Duration::ZERO);
+    let mut finished = None;
+    for _ in 0..2 {
+        match device.lora_tx(b"gap") {
+            Ok(response) if response.resp_id == ResponseId::TxComplete => {
+                if let Some(first) = finished {
+                    result = Ok(std::time::Instant::now().duration_since(first));
+                }
+                finished = Some(std::time::Instant::now());
+            }
+            Ok(response) => {
+                result = Err(format!("LoraTx: got {:?}", response.resp_id));
+                break;
+            }
+            Err(e) => {
+                result = Err(format!("LoraTx error: {}", e));
+                break;
+            }
+        }
+    }
+    let restored = device.set_tx_gap(0);
+
+    match result {
+        Ok(spacing) if spacing >= std::time::Duration::from_millis(u64::from(GAP_MS)) => {
+            progress!("({} ms apart) ", spacing.as_millis());
+        }
+        Ok(spacing) => return TestResult::fail("test", &format!("Transmissions only {} ms apart", spacing.as_millis())),
+        Err(e) => return TestResult::fail("test", &e),
+    }
+    match restored {
+        Ok(response) if response.resp_id == ResponseId::Ack => TestResult::pass("test"),
+        Ok(response) => TestResult::fail("test", &format!("Restoring TX gap: got {:?}", response.resp_id)),
+        Err(e) => TestResult::fail("test", &format!("Restoring TX gap error: {}", e)),
+    }
+}
+
+fn test_fsk_modulation(device: &mut DeviceClient) -> TestResult {
+    // 20 kHz is not one of the radio's receiver bandwidths
+    match device.set_fsk(4_800, 5_000, 20_000, 5) {
+        Ok(response) if response.resp_id == ResponseId::Error => {}
+        Ok(response) => return TestResult::fail("test", &format!("Bad bandwidth: got {:?}", response.resp_id)),
+        Err(e) => return TestResult::fail("test", &format!("SetModulation error: {}", e)),
+    }
+
+    let result = match device.set_fsk(4_800, 5_000, 19_500, 5) {
+        Ok(response) if response.resp_id == ResponseId::Ack => match device.lora_tx(b"fsk") {
+            Ok(response) if response.resp_id == ResponseId::TxComplete => Ok(()),
+            Ok(response) => Err(format!("LoraTx in FSK: got {:?}", response.resp_id)),
+            Err(e) => Err(format!("LoraTx in FSK error: {}", e)),
+        },
+        Ok(response) => Err(format!("SetModulation FSK: got {:?}", response.resp_id)),
+        Err(e) => Err(format!("SetModulation FSK error: {}", e)),
+    };
+    let restored = device.set_lora_modulation();
+
+    if let Err(e) = result {
+        return TestResult::fail("test", &e);
+    }
+    match restored {
+        Ok(response) if response.resp_id == ResponseId::Ack => TestResult::pass("test"),
+        Ok(response) => TestResult::fail("test", &format!("Restoring LoRa: got {:?}", response.resp_id)),
+        Err(e) => TestResult::fail("test", &format!("Restoring LoRa error: {}", e)),
+    }
+}
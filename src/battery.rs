@@ -0,0 +1,186 @@
+//! Battery voltage sensing
+//!
+//! The battery is measured through a resistor divider on an ADC1 pin (see
+//! `config::battery`), and the charge percentage is estimated linearly from
+//! the voltage between `EMPTY_MV` and `FULL_MV`.
+//!
+//! The LoRa task checks each sample against the SetLowBatteryPolicy threshold
+//! (see `LowBatteryPolicy`).
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::config::battery::{DIVIDER_DEN, DIVIDER_NUM, EMPTY_MV, FULL_MV, LOW_HYSTERESIS_MV};
+
+/// Most recent battery level in percent. Starts at 100 so a board without a
+/// sense pin always reports full.
+static LEVEL: AtomicU8 = AtomicU8::new(100);
+
+/// Most recently measured battery level (0-100).
+pub fn level() -> u8 {
+    LEVEL.load(Ordering::Relaxed)
+}
+
+/// Record a new battery level.
+pub fn set_level(percent: u8) {
+    LEVEL.store(percent.min(100), Ordering::Relaxed);
+}
+
+/// Most recent battery voltage in millivolts. Stays 0 (not measured) on a
+/// board without a sense pin.
+static MILLIVOLTS: AtomicU32 = AtomicU32::new(0);
+
+/// Most recently measured battery voltage, or 0 before the first sample.
+pub fn millivolts() -> u32 {
+    MILLIVOLTS.load(Ordering::Relaxed)
+}
+
+/// Record a new battery voltage.
+pub fn set_millivolts(battery_mv: u32) {
+    MILLIVOLTS.store(battery_mv, Ordering::Relaxed);
+}
+
+/// What to do when the battery runs low, set with SetLowBatteryPolicy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LowBatteryPolicy {
+    /// Battery voltage below which the battery counts as low
+    pub threshold_mv: u16,
+    /// Most power any transmission may use while low
+    pub tx_power_dbm: i8,
+    /// Whether to drop to duty-cycled RX once idle while low
+    pub rx_duty_cycle: bool,
+}
+
+impl LowBatteryPolicy {
+    /// Whether `battery_mv` counts as low, given whether the last sample did.
+    ///
+    /// Once low, the battery has to climb `LOW_HYSTERESIS_MV` above the
+    /// threshold to recover. An unmeasured battery (0) is never low.
+    pub fn is_low(&self, battery_mv: u32, was_low: bool) -> bool {
+        let mut threshold_mv = u32::from(self.threshold_mv);
+        if was_low {
+            threshold_mv += u32::from(LOW_HYSTERESIS_MV);
+        }
+        battery_mv != 0 && battery_mv < threshold_mv
+    }
+}
+
+/// Scale a voltage measured at the ADC pin back up to the battery voltage.
+pub fn battery_mv(pin_mv: u32) -> u32 {
+    pin_mv * DIVIDER_NUM / DIVIDER_DEN
+}
+
+/// Convert a battery voltage to a 0-100 percentage.
+///
+/// Linear between `EMPTY_MV` and `FULL_MV`. A Li-ion discharge curve is not
+/// linear, but this is close enough for a coarse level indicator.
+pub fn percent_from_mv(battery_mv: u32) -> u8 {
+    if battery_mv <= EMPTY_MV {
+        return 0;
+    }
+    if battery_mv >= FULL_MV {
+        return 100;
+    }
+    ((battery_mv - EMPTY_MV) * 100 / (FULL_MV - EMPTY_MV)) as u8
+}
+
+/// Something that can measure the voltage at the battery sense pin.
+///
+/// Lets the battery task own an ADC pin without knowing which GPIO it is.
+#[cfg(feature = "embedded")]
+pub trait BatterySense {
+    /// Read the (calibrated) voltage at the sense pin in millivolts.
+    fn read_pin_mv(&mut self) -> u32;
+}
+
+/// ADC1 oneshot reader for a battery divider on `PIN`.
+#[cfg(feature = "embedded")]
+pub struct AdcBatterySense<PIN> {
+    adc: esp_hal::analog::adc::Adc<'static, esp_hal::peripherals::ADC1<'static>, esp_hal::Blocking>,
+    pin: esp_hal::analog::adc::AdcPin<
+        PIN,
+        esp_hal::peripherals::ADC1<'static>,
+        esp_hal::analog::adc::AdcCalCurve<esp_hal::peripherals::ADC1<'static>>,
+    >,
+}
+
+#[cfg(feature = "embedded")]
+impl<PIN> AdcBatterySense<PIN>
+where
+    PIN: esp_hal::analog::adc::AdcChannel + esp_hal::gpio::AnalogPin,
+{
+    /// Configure ADC1 for `pin` with curve calibration and 11 dB attenuation
+    /// (full scale ~3.1 V at the pin).
+    pub fn new(adc1: esp_hal::peripherals::ADC1<'static>, pin: PIN) -> Self {
+        use esp_hal::analog::adc::{Adc, AdcCalCurve, AdcConfig, Attenuation};
+
+        let mut config = AdcConfig::new();
+        let pin = config.enable_pin_with_cal::<_, AdcCalCurve<esp_hal::peripherals::ADC1<'static>>>(
+            pin,
+            Attenuation::_11dB,
+        );
+        Self {
+            adc: Adc::new(adc1, config),
+            pin,
+        }
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<PIN> BatterySense for AdcBatterySense<PIN>
+where
+    PIN: esp_hal::analog::adc::AdcChannel + esp_hal::gpio::AnalogPin,
+{
+    fn read_pin_mv(&mut self) -> u32 {
+        // With curve calibration the reading is already in millivolts.
+        self.adc.read_blocking(&mut self.pin) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divider_scales_pin_voltage() {
+        assert_eq!(battery_mv(2000), 2000 * DIVIDER_NUM / DIVIDER_DEN);
+    }
+
+    #[test]
+    fn percent_is_clamped_to_range() {
+        assert_eq!(percent_from_mv(0), 0);
+        assert_eq!(percent_from_mv(EMPTY_MV), 0);
+        assert_eq!(percent_from_mv(FULL_MV), 100);
+        assert_eq!(percent_from_mv(FULL_MV + 500), 100);
+    }
+
+    #[test]
+    fn percent_is_linear_between_empty_and_full() {
+        let mid = (EMPTY_MV + FULL_MV) / 2;
+        assert_eq!(percent_from_mv(mid), 50);
+    }
+
+    #[test]
+    fn low_battery_recovers_only_past_the_hysteresis() {
+        let policy = LowBatteryPolicy {
+            threshold_mv: 3500,
+            tx_power_dbm: 2,
+            rx_duty_cycle: false,
+        };
+        assert!(!policy.is_low(3500, false));
+        assert!(policy.is_low(3499, false));
+        assert!(policy.is_low(3500, true));
+        assert!(policy.is_low(3500 + u32::from(LOW_HYSTERESIS_MV) - 1, true));
+        assert!(!policy.is_low(3500 + u32::from(LOW_HYSTERESIS_MV), true));
+        // No sample yet
+        assert!(!policy.is_low(0, false));
+    }
+
+    #[test]
+    fn set_level_clamps_to_100() {
+        set_level(150);
+        assert_eq!(level(), 100);
+        set_level(42);
+        assert_eq!(level(), 42);
+        set_level(100);
+    }
+}
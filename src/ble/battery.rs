@@ -0,0 +1,22 @@
+//! Battery Service (BAS) definition
+//!
+//! Implements the standard Bluetooth SIG Battery Service so phone apps can
+//! show the charge level without speaking the command protocol.
+//! - Service UUID: 0x180F
+//! - Battery Level Characteristic: 0x2A19 (read, notify), 0-100 percent
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use trouble_host::prelude::*;
+
+/// Raised by the battery task after each sample so `ble_task` can notify the
+/// connected client.
+pub static LEVEL_UPDATED: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+
+/// Battery Service
+#[gatt_service(uuid = service::BATTERY)]
+pub struct BatteryService {
+    /// Battery Level Characteristic - charge level in percent
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify, value = 100)]
+    pub level: u8,
+}
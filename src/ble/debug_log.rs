@@ -0,0 +1,21 @@
+//! Debug Log Service definition
+//!
+//! Streams the debug log over BLE for units deployed without USB. A custom
+//! service rather than an extra NUS characteristic, so generic NUS apps keep
+//! seeing the standard two characteristics.
+//! - Service UUID: 7A1B0001-5C3E-4F2A-9D6B-8E0C4A2F1D30
+//! - Log Characteristic: 7A1B0002-... (notify), one UTF-8 log line per
+//!   message, split across notifications if longer than the ATT MTU
+
+use trouble_host::prelude::*;
+
+/// Largest log notification: a full debug message plus its newline
+pub const LOG_MAX_PACKET_SIZE: usize = crate::debug::MAX_DEBUG_MSG_LEN + 1;
+
+/// Debug Log Service
+#[gatt_service(uuid = "7a1b0001-5c3e-4f2a-9d6b-8e0c4a2f1d30")]
+pub struct DebugLogService {
+    /// Log Characteristic - server notifies timestamped log lines here
+    #[characteristic(uuid = "7a1b0002-5c3e-4f2a-9d6b-8e0c4a2f1d30", notify)]
+    pub log: heapless::Vec<u8, LOG_MAX_PACKET_SIZE>,
+}
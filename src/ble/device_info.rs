@@ -0,0 +1,69 @@
+//! Device Information Service (DIS) definition
+//!
+//! Implements the standard Bluetooth SIG Device Information Service so apps
+//! can identify the board and its firmware without sending a command frame.
+//! - Service UUID: 0x180A
+//! - Manufacturer Name (0x2A29), Model Number (0x2A24), Serial Number (0x2A25)
+//!   and Firmware Revision (0x2A26) string characteristics, all read-only
+
+use core::fmt::Write;
+
+use heapless::String;
+use trouble_host::prelude::*;
+
+use crate::config;
+
+/// Manufacturer name reported over BLE (matches the USB descriptor)
+pub const MANUFACTURER_NAME: &str = "Walkie-Textie";
+
+/// Model number reported over BLE
+pub const MODEL_NUMBER: &str = "WT-ESP32S3-SX1262";
+
+/// Serial number string: the device id as 6 uppercase hex characters
+pub type SerialNumber = String<6>;
+
+/// Firmware revision string: "major.minor.patch"
+pub type FirmwareRevision = String<11>;
+
+/// Device Information Service
+///
+/// Serial Number and Firmware Revision are set by `ble_task` once the server
+/// is built, as they are formatted at runtime.
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+pub struct DeviceInfoService {
+    /// Manufacturer Name String
+    #[characteristic(uuid = characteristic::MANUFACTURER_NAME_STRING, read, value = MANUFACTURER_NAME)]
+    pub manufacturer_name: &'static str,
+
+    /// Model Number String
+    #[characteristic(uuid = characteristic::MODEL_NUMBER_STRING, read, value = MODEL_NUMBER)]
+    pub model_number: &'static str,
+
+    /// Serial Number String (device id in hex)
+    #[characteristic(uuid = characteristic::SERIAL_NUMBER_STRING, read)]
+    pub serial_number: SerialNumber,
+
+    /// Firmware Revision String (from `config::protocol::VERSION_*`)
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read)]
+    pub firmware_revision: FirmwareRevision,
+}
+
+/// Format the device id as the serial number
+pub fn serial_number(device_id: &[u8; 3]) -> SerialNumber {
+    let mut serial = SerialNumber::new();
+    let _ = write!(serial, "{:02X}{:02X}{:02X}", device_id[0], device_id[1], device_id[2]);
+    serial
+}
+
+/// Format the firmware version as the firmware revision
+pub fn firmware_revision() -> FirmwareRevision {
+    let mut revision = FirmwareRevision::new();
+    let _ = write!(
+        revision,
+        "{}.{}.{}",
+        config::protocol::VERSION_MAJOR,
+        config::protocol::VERSION_MINOR,
+        config::protocol::VERSION_PATCH
+    );
+    revision
+}
@@ -1,6 +1,11 @@
 //! Bluetooth Low Energy module
 //!
 //! Provides BLE connectivity using Nordic UART Service (NUS) for
-//! command/response communication alongside serial.
+//! command/response communication alongside serial, plus a Debug Log Service
+//! that streams the debug output.
 
+pub mod battery;
+pub mod debug_log;
+pub mod device_info;
+pub mod name;
 pub mod service;
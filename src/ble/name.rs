@@ -0,0 +1,113 @@
+//! Runtime-configurable BLE advertising name
+//!
+//! The host can replace the default `WalkieTextie-XXXXXX` name with a friendly
+//! one via SetBleName. The name is saved in flash next to the device label
+//! (`config::ble::NAME_FLASH_OFFSET`), so it survives reboots, and is picked
+//! up by `ble_task` on its next advertising cycle; setting it signals the BLE
+//! task to drop any current connection so the change takes effect straight
+//! away.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embedded_storage::{ReadStorage, Storage};
+use heapless::String;
+
+use crate::config::ble::NAME_FLASH_OFFSET;
+use crate::config::partitions::{SETTINGS_OFFSET, SETTINGS_SIZE};
+use crate::firmware::with_flash;
+use crate::record;
+
+/// Maximum advertised name length in bytes (fits the 31-byte advertising payload).
+pub const MAX_NAME_LEN: usize = 20;
+
+/// An advertised device name.
+pub type BleName = String<MAX_NAME_LEN>;
+
+/// Marks a BLE name record (see `record`)
+const MAGIC: [u8; 2] = *b"WN";
+
+/// Stored record: the name padded to `MAX_NAME_LEN`, framed by `record`
+const RECORD_LEN: usize = MAX_NAME_LEN + record::OVERHEAD;
+
+const _: () = assert!(SETTINGS_OFFSET + crate::label::RECORD_LEN as u32 <= NAME_FLASH_OFFSET);
+const _: () = assert!(NAME_FLASH_OFFSET + RECORD_LEN as u32 <= SETTINGS_OFFSET + SETTINGS_SIZE);
+
+/// Host-set name, or `None` to use the default derived from the device id.
+static CUSTOM_NAME: Mutex<CriticalSectionRawMutex, RefCell<Option<BleName>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Raised when the name changes so `ble_task` can re-advertise.
+pub static NAME_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Why a name could not be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameError {
+    /// Empty, over-long or not printable ASCII
+    Invalid,
+    /// The flash is lent to a running VerifyFirmware
+    Busy,
+    /// The flash is unavailable or the write failed
+    Storage,
+}
+
+/// Check `name` and copy it into a `BleName`.
+///
+/// The name must be 1-20 bytes of printable ASCII (0x20-0x7E).
+pub fn parse(name: &[u8]) -> Result<BleName, NameError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(NameError::Invalid);
+    }
+    if !name.iter().all(|&b| (0x20..=0x7E).contains(&b)) {
+        return Err(NameError::Invalid);
+    }
+    // Printable ASCII is always valid UTF-8.
+    let name = core::str::from_utf8(name).map_err(|_| NameError::Invalid)?;
+    let mut parsed = BleName::new();
+    parsed.push_str(name).map_err(|_| NameError::Invalid)?;
+    Ok(parsed)
+}
+
+/// Read the saved name into RAM; call once at boot after `firmware::init`.
+pub fn load() {
+    let mut stored = [0u8; RECORD_LEN];
+    let read = with_flash(|flash| flash.read(NAME_FLASH_OFFSET, &mut stored).is_ok()).unwrap_or(false);
+    if !read {
+        crate::error!("BLE name: flash read failed");
+        return;
+    }
+    if let Some(name) = record::decode(MAGIC, &stored).and_then(|name| parse(name).ok()) {
+        apply(name);
+    }
+}
+
+/// Check, save and apply a new name.
+///
+/// Rewrites the settings sector, which stalls the CPU for a few tens of ms.
+/// A flash failure, or the flash being lent to VerifyFirmware, leaves the
+/// previous name in place.
+pub fn set_custom_name(name: &[u8]) -> Result<(), NameError> {
+    let name = parse(name)?;
+    let mut stored = [0u8; RECORD_LEN];
+    record::encode(MAGIC, name.as_bytes(), &mut stored);
+    let written = with_flash(|flash| flash.write(NAME_FLASH_OFFSET, &stored).is_ok()).ok_or(NameError::Busy)?;
+    if !written {
+        crate::error!("BLE name: flash write failed");
+        return Err(NameError::Storage);
+    }
+    apply(name);
+    Ok(())
+}
+
+/// Use `name` from the next advertising cycle.
+fn apply(name: BleName) {
+    CUSTOM_NAME.lock(|cell| *cell.borrow_mut() = Some(name));
+    NAME_CHANGED.signal(());
+}
+
+/// The host-set name, if any.
+pub fn custom_name() -> Option<BleName> {
+    CUSTOM_NAME.lock(|cell| cell.borrow().clone())
+}
@@ -8,8 +8,11 @@
 use trouble_host::prelude::*;
 
 /// Maximum BLE packet size for NUS
-/// Using a fixed-size array that fits within GATT constraints
-pub const NUS_MAX_PACKET_SIZE: usize = 128;
+///
+/// The largest ATT payload the default packet pool allows: a 247-byte ATT MTU
+/// minus the 3-byte notification header. Links that negotiate a smaller MTU get
+/// frames split across several notifications.
+pub const NUS_MAX_PACKET_SIZE: usize = 244;
 
 /// Nordic UART Service
 ///
@@ -18,10 +21,13 @@ pub const NUS_MAX_PACKET_SIZE: usize = 128;
 #[gatt_service(uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e")]
 pub struct NordicUartService {
     /// RX Characteristic - client writes COBS frames here
-    #[characteristic(uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e", write, write_without_response, value = [0u8; 128])]
+    #[characteristic(uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e", write, write_without_response, value = [0u8; NUS_MAX_PACKET_SIZE])]
     pub rx: [u8; NUS_MAX_PACKET_SIZE],
 
     /// TX Characteristic - server notifies COBS frames here
-    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e", notify, value = [0u8; 128])]
-    pub tx: [u8; NUS_MAX_PACKET_SIZE],
+    ///
+    /// Variable length so each notification carries only the bytes of the
+    /// frame, not a zero-padded buffer.
+    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e", notify)]
+    pub tx: heapless::Vec<u8, NUS_MAX_PACKET_SIZE>,
 }
@@ -0,0 +1,61 @@
+//! Milliseconds since boot, as read by duty-cycle accounting and timeouts
+//!
+//! Embedded builds read `embassy_time`. Unit tests get a settable clock
+//! instead, kept per test thread, so time-dependent logic can be stepped
+//! deterministically without sleeping or sharing the global mock time
+//! driver between tests.
+
+/// Milliseconds since boot
+#[cfg(not(test))]
+pub fn now_ms() -> u64 {
+    embassy_time::Instant::now().as_millis()
+}
+
+#[cfg(test)]
+mod mock {
+    use std::cell::Cell;
+
+    std::thread_local! {
+        static NOW_MS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    /// Milliseconds since the test set or advanced the clock (0 at start)
+    pub fn now_ms() -> u64 {
+        NOW_MS.with(Cell::get)
+    }
+
+    /// Set the clock to `ms`
+    pub fn set_ms(ms: u64) {
+        NOW_MS.with(|now| now.set(ms));
+    }
+
+    /// Move the clock on by `ms`
+    pub fn advance_ms(ms: u64) {
+        NOW_MS.with(|now| now.set(now.get() + ms));
+    }
+}
+
+#[cfg(test)]
+pub use mock::{advance_ms, now_ms, set_ms};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_only_moves_when_told() {
+        set_ms(1_000);
+        assert_eq!(now_ms(), 1_000);
+        assert_eq!(now_ms(), 1_000);
+        advance_ms(250);
+        assert_eq!(now_ms(), 1_250);
+    }
+
+    #[test]
+    fn each_test_thread_has_its_own_clock() {
+        set_ms(5);
+        let other = std::thread::spawn(now_ms).join().unwrap();
+        assert_eq!(other, 0);
+        assert_eq!(now_ms(), 5);
+    }
+}
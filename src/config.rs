@@ -1,5 +1,107 @@
 //! Configuration constants for the ESP32-S3 with WIO-SX1262
 
+/// Pin assignments for the board the firmware is built for
+///
+/// Selected at compile time by a `board-*` cargo feature; exactly one must be
+/// enabled. To support another board, add a feature and a `BOARD` constant
+/// for it here.
+pub mod board {
+    /// GPIO numbers of the pins wired to the radio, LED and battery sense
+    ///
+    /// The USB pins are not listed: the ESP32-S3 has its USB PHY on GPIO19/20
+    /// only, so every board uses those.
+    pub struct BoardConfig {
+        /// SPI clock to the SX1262
+        pub spi_sclk: u8,
+        pub spi_miso: u8,
+        pub spi_mosi: u8,
+        /// SX1262 chip select
+        pub lora_nss: u8,
+        /// SX1262 IRQ line
+        pub lora_dio1: u8,
+        /// SX1262 reset (active low)
+        pub lora_nrst: u8,
+        /// SX1262 busy indicator
+        pub lora_busy: u8,
+        /// External TX/RX switch control (high for TX), or None if the
+        /// module switches itself from the SX1262's DIO2
+        pub lora_rf_switch: Option<u8>,
+        /// Status LED (active low)
+        pub led: u8,
+        /// ADC1 GPIO (1-6) wired to a battery voltage divider, or None if the
+        /// board has none
+        pub battery_sense: Option<u8>,
+    }
+
+    impl BoardConfig {
+        /// Every assigned pin, in field order
+        pub const fn pins(&self) -> [u8; 8] {
+            [
+                self.spi_sclk,
+                self.spi_miso,
+                self.spi_mosi,
+                self.lora_nss,
+                self.lora_dio1,
+                self.lora_nrst,
+                self.lora_busy,
+                self.led,
+            ]
+        }
+    }
+
+    /// ESP32-S3 with the Seeed WIO-SX1262 module
+    #[cfg(feature = "board-wio")]
+    pub const BOARD: BoardConfig = BoardConfig {
+        spi_sclk: 7,
+        spi_miso: 8,
+        spi_mosi: 9,
+        lora_nss: 41,
+        lora_dio1: 39,
+        lora_nrst: 42,
+        lora_busy: 40,
+        lora_rf_switch: None,
+        led: 48,
+        // USB-powered, with no battery divider: GPIO1 is not connected
+        battery_sense: None,
+    };
+
+    #[cfg(not(feature = "board-wio"))]
+    compile_error!("select the target board with a `board-*` cargo feature");
+
+    // Each pin is handed out once at boot, so an assignment must not repeat
+    // a pin or reuse one taken elsewhere (USB, battery sense)
+    const _: () = {
+        let pins = BOARD.pins();
+        if let Some(sense) = BOARD.battery_sense {
+            assert!(sense >= 1 && sense <= 6, "battery sense must be an ADC1 pin, GPIO1-6");
+        }
+        if let Some(rf_switch) = BOARD.lora_rf_switch {
+            assert!(rf_switch <= 48, "ESP32-S3 has GPIO0-48");
+            assert!(rf_switch != 19 && rf_switch != 20, "GPIO19/20 are the USB pins");
+            if let Some(sense) = super::battery::SENSE_GPIO {
+                assert!(rf_switch != sense, "pin is used for battery sense");
+            }
+        }
+        let mut i = 0;
+        while i < pins.len() {
+            assert!(pins[i] <= 48, "ESP32-S3 has GPIO0-48");
+            assert!(pins[i] != 19 && pins[i] != 20, "GPIO19/20 are the USB pins");
+            if let Some(sense) = super::battery::SENSE_GPIO {
+                assert!(pins[i] != sense, "pin is used for battery sense");
+            }
+            if let Some(rf_switch) = BOARD.lora_rf_switch {
+                assert!(pins[i] != rf_switch, "pin is used for the RF switch");
+            }
+            let mut j = i + 1;
+            while j < pins.len() {
+                assert!(pins[i] != pins[j], "pin assigned twice");
+                j += 1;
+            }
+            i += 1;
+        }
+    };
+}
+
 /// TCXO configuration
 pub mod tcxo {
     /// TCXO voltage code for SX1262 register
@@ -7,6 +109,21 @@ pub mod tcxo {
     pub const VOLTAGE_CODE: u8 = 0x02;
 }
 
+/// SPI bus to the SX1262
+pub mod spi {
+    /// SPI clock in MHz. The SX1262 accepts up to 16 MHz; 8 MHz leaves margin
+    /// for the board-to-board connector, but has not yet been verified on a
+    /// board. Lower it for radios on long wires.
+    pub const FREQUENCY_MHZ: u32 = 8;
+    /// DMA buffer size: the largest buffer transfer is a ReadBuffer of 256
+    /// bytes plus its opcode, offset and status bytes
+    pub const DMA_BUFFER_LEN: usize = 260;
+    /// Time NSS is held high after each command, in microseconds. Covers
+    /// BUSY's rise after NSS (up to 600 ns), which the next command's BUSY
+    /// poll would otherwise miss at higher clocks.
+    pub const NSS_HIGH_US: u64 = 1;
+}
+
 /// Default LoRa configuration
 pub mod lora_defaults {
     /// Frequency in Hz (869.525 MHz)
@@ -17,16 +134,408 @@ pub mod lora_defaults {
     pub const CODING_RATE: u8 = 8;
     /// TX power in dBm (supports -9 to +22)
     pub const TX_POWER_DBM: i8 = 22;
+    /// Preamble length in symbols. Longer preambles help other LoRa gear sync.
+    pub const PREAMBLE_SYMBOLS: u16 = 8;
+    /// LoRa sync word: 0x1424 is the private-network value (0x3444 is public
+    /// LoRaWAN), so our fleet does not collide with public gateways.
+    pub const SYNC_WORD: u16 = 0x1424;
+    /// Implicit (fixed-length) header mode. Saves the header on air for
+    /// maximum range, but every frame must be exactly `IMPLICIT_LEN` bytes
+    /// and both ends must agree.
+    pub const IMPLICIT_HEADER: bool = false;
+    /// Frame length in implicit header mode
+    pub const IMPLICIT_LEN: u8 = 0;
+    /// Append a payload CRC to transmitted frames
+    pub const CRC_ENABLED: bool = true;
+    /// Inverted IQ, as used by LoRaWAN downlinks. Both ends must agree.
+    pub const IQ_INVERTED: bool = false;
+    /// PA over-current protection limit in mA (the SX1262 default)
+    pub const OCP_MA: u16 = 140;
+    /// Highest OCP limit accepted. The register tops out at 157.5 mA, so
+    /// larger values clamp; anything above this is a host mistake.
+    pub const MAX_OCP_MA: u16 = 240;
+    /// PA ramp time as an SX1262 SetTxParams code (0x04 = 200 us). Codes run
+    /// 0x00-0x07 for 10, 20, 40, 80, 200, 800, 1700 and 3400 us.
+    pub const RAMP_TIME: u8 = 0x04;
+    /// Highest ramp time code (3400 us)
+    pub const MAX_RAMP_TIME: u8 = 0x07;
+    /// Idle in STDBY_XOSC (TCXO running) rather than STDBY_RC. Costs about
+    /// 0.6 mA more in standby but starts TX and RX without waiting for the
+    /// TCXO.
+    pub const STANDBY_XOSC: bool = false;
+    /// Longest carrier-wave test transmission (TxCarrier), so a lost host
+    /// cannot leave the transmitter keyed
+    pub const MAX_CARRIER_MS: u32 = 60_000;
+    /// Longest LoraRx wait. The LoRa task cannot pulse the watchdog while it
+    /// waits.
+    pub const MAX_RX_TIMEOUT_MS: u32 = 60_000;
+}
+
+/// GFSK modulation limits (SetModulation)
+pub mod fsk {
+    /// Bit rate range the SX1262 supports, in bits/s
+    pub const MIN_BITRATE_BPS: u32 = 600;
+    pub const MAX_BITRATE_BPS: u32 = 300_000;
+    /// Frequency deviation range the SX1262 supports, in Hz
+    pub const MIN_DEVIATION_HZ: u32 = 600;
+    pub const MAX_DEVIATION_HZ: u32 = 200_000;
+    /// Longest preamble in bytes. The radio takes it in bits, in a 16-bit
+    /// field.
+    pub const MAX_PREAMBLE_BYTES: u16 = 0x1FFF;
+}
+
+/// Band occupancy sweep (SpectrumScan)
+pub mod spectrum_scan {
+    /// Most channels in one scan. Each sample is 2 bytes of the response, so
+    /// this keeps it well inside `MAX_FRAME_SIZE`.
+    pub const MAX_STEPS: usize = 64;
+    /// Lowest and highest frequency the SX1262 tunes to
+    pub const MIN_FREQ_HZ: u32 = 150_000_000;
+    pub const MAX_FREQ_HZ: u32 = 960_000_000;
+    /// Time in RX on each channel before the RSSI is read, for the estimate
+    /// to settle
+    pub const DWELL_MS: u64 = 1;
+}
+
+/// Background RX polling in the LoRa task
+pub mod rx_poll {
+    /// Default RX listen window before the radio is re-armed
+    pub const DEFAULT_INTERVAL_MS: u32 = 500;
+    /// Shortest window accepted by SetRxPollInterval. Shorter windows spend a
+    /// larger share of the time re-arming RX rather than listening.
+    pub const MIN_INTERVAL_MS: u32 = 50;
+    /// Longest window accepted by SetRxPollInterval. Stale partial messages
+    /// are only expired between windows, so this also bounds how late that runs.
+    pub const MAX_INTERVAL_MS: u32 = 5_000;
+}
+
+/// Spacing between consecutive transmissions (SetTxGap)
+pub mod tx_gap {
+    /// Default gap: transmissions go out back-to-back
+    pub const DEFAULT_MS: u32 = 0;
+    /// Longest gap accepted by SetTxGap. Commands queued behind a waiting
+    /// transmission wait too, so keep this well inside a host's timeout.
+    pub const MAX_MS: u32 = 10_000;
+}
+
+/// Minimum signal quality for received packets (SetRxFilter)
+pub mod rx_filter {
+    /// Default minimum RSSI in dBm. The radio never reports below -127, so
+    /// this accepts everything.
+    pub const DEFAULT_MIN_RSSI_DBM: i8 = i8::MIN;
+    /// Default minimum SNR in dB, accepting everything
+    pub const DEFAULT_MIN_SNR_DB: i8 = i8::MIN;
+}
+
+/// Recently received messages, replayed by GetRecentPackets
+pub mod recent_packets {
+    /// Messages kept. A replay publishes one response per message plus an
+    /// Ack on the 8-deep response channel, so keep this below 8.
+    pub const COUNT: usize = 4;
+    /// Data bytes kept per message; longer messages are truncated. Each entry
+    /// takes about DATA_CAP + 8 bytes (source, RSSI, SNR, length), so the
+    /// buffer costs about 300 bytes of RAM. Keeping a whole LoRa frame
+    /// (`MAX_LORA_PAYLOAD`, 255 bytes) would cost about 1 KiB, and a whole
+    /// reassembled message (`MAX_MESSAGE_SIZE`) about 4 KiB.
+    pub const DATA_CAP: usize = 64;
+}
+
+/// Radio bring-up at boot
+pub mod radio_init {
+    /// Further init attempts after the first one fails
+    pub const RETRIES: u32 = 3;
+    /// Wait before the first retry, doubled for each retry after it
+    pub const BACKOFF_MS: u64 = 500;
+    /// How often a radio that never came up is reported again with a
+    /// RadioFault event
+    pub const FAULT_REPORT_SECS: u64 = 30;
+}
+
+/// Recovery from radio errors during background RX
+pub mod radio_recovery {
+    /// Wait after a receive that failed in the radio itself (SPI or BUSY),
+    /// so a dead bus is not hammered in a tight loop
+    pub const ERROR_BACKOFF_MS: u64 = 100;
+    /// Radio errors in a row before the radio is re-initialised
+    pub const REINIT_AFTER: u32 = 5;
+}
+
+/// Duty-cycled receive when idle (SetRxDutyCycle)
+pub mod rx_duty_cycle {
+    /// Seconds without host commands or received packets before the radio
+    /// drops from continuous RX to the configured listen/sleep windows
+    pub const IDLE_SECS: u64 = 60;
+}
+
+/// Acknowledged transmission (LoraTxReliable)
+pub mod reliable {
+    /// Retransmissions after the first attempt before giving up
+    pub const MAX_RETRIES: u8 = 3;
+    /// How long to wait for the ACK after each attempt. Covers the ACK's time
+    /// on air (about 0.4 s at the default SF11/250 kHz) with margin for the
+    /// receiver to turn around.
+    pub const ACK_TIMEOUT_MS: u32 = 2_000;
+}
+
+/// RF loopback self-test (LoraLoopback)
+pub mod loopback {
+    /// Packet sent, and expected back from the loopback fixture
+    pub const PATTERN: [u8; 16] = *b"WT-LOOPBACK-TEST";
+    /// How long to listen for the pattern to come back. Covers the fixture
+    /// hearing it and sending it back (about 0.25 s each way at the default
+    /// SF11/250 kHz), with margin to turn around.
+    pub const TIMEOUT_MS: u32 = 2_000;
+}
+
+/// Repeater mode (SetRepeater)
+pub mod repeater {
+    /// Hop count stamped on every frame this device sends. A repeater
+    /// retransmits a frame with one less, and never one that arrives with 0.
+    pub const DEFAULT_TTL: u8 = 3;
+    /// Frames remembered to drop relayed copies and stop repeaters bouncing
+    /// frames between them
+    pub const SEEN_FRAMES: usize = 16;
+    /// How long a frame is remembered. Shorter than `reliable::ACK_TIMEOUT_MS`
+    /// so a LoraTxReliable retry is repeated again.
+    pub const SEEN_WINDOW_MS: u64 = 1_500;
+    /// How long the channel must stay quiet before queued frames are
+    /// repeated, so the rest of a multi-fragment message is not talked over
+    pub const HOLDOFF_MS: u32 = 100;
+}
+
+/// Dropping messages delivered more than once (see `lora::dedup`)
+pub mod dedup {
+    /// Messages remembered by sender and message id
+    pub const MESSAGES: usize = 16;
+    /// How long a delivered message is remembered. Well short of the time a
+    /// busy sender takes to wrap its 256 message ids, or to reboot and start
+    /// them again from 0.
+    pub const WINDOW_MS: u64 = 30_000;
+}
+
+/// Streamed transmission (StreamBegin / StreamData / StreamEnd)
+pub mod stream {
+    /// How long an open stream may go without a StreamData before it is
+    /// dropped, so a host that disconnects mid-stream does not leave it open
+    pub const IDLE_TIMEOUT_MS: u64 = 30_000;
+}
+
+/// Presence beacons
+pub mod beacon {
+    /// Shortest interval accepted by SetBeaconInterval, so beacons cannot
+    /// crowd out other traffic or the duty-cycle budget
+    pub const MIN_INTERVAL_SECS: u32 = 10;
+    /// Longest interval accepted by SetBeaconInterval (one day)
+    pub const MAX_INTERVAL_SECS: u32 = 86_400;
+}
+
+/// Host keepalive events
+pub mod heartbeat {
+    /// Shortest interval accepted by SetHeartbeatInterval
+    pub const MIN_INTERVAL_SECS: u32 = 1;
+    /// Longest interval accepted by SetHeartbeatInterval (one hour)
+    pub const MAX_INTERVAL_SECS: u32 = 3_600;
+}
+
+/// Transmit duty-cycle limit
+pub mod duty_cycle {
+    /// Reject LoRa transmissions once the budget below is spent
+    pub const ENABLED: bool = true;
+    /// Allowed time on air per window at boot, in tenths of a percent. The
+    /// default 869.525 MHz sits in the EU 869.40-869.65 MHz sub-band, which
+    /// allows 10%; use 10 (1%) or 1 (0.1%) for the other EU868 sub-bands.
+    /// SetBand replaces it with the selected band's limit.
+    pub const LIMIT_PERMILLE: u32 = 100;
+    /// Rolling window the limit applies over
+    pub const WINDOW_SECS: u64 = 3600;
+}
+
+/// Channel agility: move to another channel after repeated collisions
+pub mod channel_hop {
+    /// A candidate channel and the duty-cycle limit of its sub-band
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HopChannel {
+        pub freq_hz: u32,
+        /// Tenths of a percent, applied to the duty-cycle budget on arrival
+        pub duty_cycle_permille: u32,
+    }
+
+    /// Off by default. A node moves only once a peer on the channel confirms
+    /// the hop, so enable it on every node of a network or none.
+    pub const ENABLED: bool = false;
+    /// Candidate channels: the 10% EU868 869.40-869.65 MHz sub-band and the
+    /// three 1% channels of 868.0-868.6 MHz
+    pub const CHANNELS: [HopChannel; 4] = [
+        HopChannel { freq_hz: 869_525_000, duty_cycle_permille: 100 },
+        HopChannel { freq_hz: 868_100_000, duty_cycle_permille: 10 },
+        HopChannel { freq_hz: 868_300_000, duty_cycle_permille: 10 },
+        HopChannel { freq_hz: 868_500_000, duty_cycle_permille: 10 },
+    ];
+    /// Seed of the hop order. Must match on every node of a network.
+    pub const SEED: u32 = 0x5754_4843;
+    /// Failures (CRC errors, reliable sends without an ACK) within
+    /// `WINDOW_MS` that trigger a hop
+    pub const ERROR_THRESHOLD: u32 = 3;
+    pub const WINDOW_MS: u64 = 30_000;
+    /// How long to wait for a peer to confirm a hop announcement, on each
+    /// of the old and the new channel
+    pub const CONFIRM_TIMEOUT_MS: u32 = 2_000;
+}
+
+/// Task watchdog
+pub mod watchdog {
+    /// A task that has not pulsed its heartbeat for this long is considered
+    /// hung and the device reboots. The LoRa task does not pulse while a
+    /// command runs, so this must exceed `COMMAND_BUDGET_MS` plus the longest
+    /// SetTxGap hold before it.
+    pub const TASK_TIMEOUT_SECS: u64 = 90;
+    /// Longest a single command may keep the LoRa task busy. LoraRx and
+    /// TxCarrier refuse longer durations, a message that would take longer on
+    /// air is refused, and LoraTxReliable makes only as many attempts as fit.
+    pub const COMMAND_BUDGET_MS: u32 = 75_000;
+    /// How often idle tasks wake just to pulse their heartbeat
+    pub const IDLE_PULSE_SECS: u64 = 10;
+    /// Hardware watchdog timeout once feeding stops
+    pub const HW_TIMEOUT_SECS: u64 = 5;
+    /// How often the watchdog task checks heartbeats and feeds the hardware
+    pub const FEED_INTERVAL_MS: u64 = 1000;
+
+    const _: () = assert!(
+        COMMAND_BUDGET_MS as u64 + super::tx_gap::MAX_MS as u64 < TASK_TIMEOUT_SECS * 1000,
+        "a command and the TX gap before it must finish before the watchdog fires"
+    );
+    const _: () = assert!(
+        2 * super::channel_hop::CONFIRM_TIMEOUT_MS < COMMAND_BUDGET_MS,
+        "a hop handshake must fit in a step"
+    );
+    const _: () = assert!(super::lora_defaults::MAX_CARRIER_MS <= COMMAND_BUDGET_MS);
+    const _: () = assert!(super::lora_defaults::MAX_RX_TIMEOUT_MS <= COMMAND_BUDGET_MS);
+}
+
+/// LoRa payload encryption
+pub mod crypto {
+    /// Encrypt every LoRa frame with `PRESHARED_KEY`. All devices on a
+    /// network must agree on this and the key.
+    pub const ENABLED: bool = true;
+
+    /// AES-128 preshared key. Change this for any real deployment.
+    pub const PRESHARED_KEY: [u8; 16] = [
+        0x57, 0x61, 0x6C, 0x6B, 0x69, 0x65, 0x2D, 0x54,
+        0x65, 0x78, 0x74, 0x69, 0x65, 0x2D, 0x50, 0x53,
+    ];
+}
+
+/// Battery sensing for the BLE Battery Service
+pub mod battery {
+    /// ADC1 GPIO wired to the battery voltage divider, from the selected
+    /// board's `battery_sense`. `None` if the board has no battery sense (the
+    /// Battery Service then reports a constant 100%).
+    pub const SENSE_GPIO: Option<u8> = super::board::BOARD.battery_sense;
+
+    /// Divider ratio as a fraction: battery voltage = pin voltage * NUM / DEN.
+    /// 2/1 matches a 100k/100k divider.
+    pub const DIVIDER_NUM: u32 = 2;
+    pub const DIVIDER_DEN: u32 = 1;
+
+    /// Battery voltage reported as 0% (single Li-ion cell)
+    pub const EMPTY_MV: u32 = 3300;
+    /// Battery voltage reported as 100%
+    pub const FULL_MV: u32 = 4200;
+
+    /// How often the battery is sampled and subscribers notified
+    pub const SAMPLE_INTERVAL_SECS: u64 = 30;
+
+    /// How far above the SetLowBatteryPolicy threshold the battery must
+    /// recover before the low-battery actions are lifted, so a voltage
+    /// sagging under load does not flap in and out
+    pub const LOW_HYSTERESIS_MV: u16 = 100;
+    /// Listen window used on low battery when the policy asks for duty-cycled
+    /// RX and SetRxDutyCycle has set none
+    pub const LOW_RX_MS: u32 = 100;
+    /// Sleep window to go with `LOW_RX_MS`
+    pub const LOW_SLEEP_MS: u32 = 900;
+}
+
+/// Bluetooth LE
+pub mod ble {
+    /// Clients that can be connected at once (e.g. several phones on a shared
+    /// base station). Each holds a response subscriber and host resources.
+    pub const MAX_CONNECTIONS: usize = 3;
+    /// Where the host-set advertising name is kept: in the `settings`
+    /// partition, after the space reserved for the device label
+    pub const NAME_FLASH_OFFSET: u32 = super::partitions::SETTINGS_OFFSET + 64;
+}
+
+/// WiFi command server (`wifi` feature)
+///
+/// The device joins the network as a DHCP station and serves the command
+/// protocol over TCP to one client at a time. Credentials come from the build
+/// environment so they stay out of the source:
+/// `WT_WIFI_SSID=... WT_WIFI_PASSWORD=... cargo build --features wifi`.
+pub mod wifi {
+    /// Network to join
+    pub const SSID: &str = match option_env!("WT_WIFI_SSID") {
+        Some(ssid) => ssid,
+        None => "",
+    };
+    /// WPA2 passphrase (empty for an open network)
+    pub const PASSWORD: &str = match option_env!("WT_WIFI_PASSWORD") {
+        Some(password) => password,
+        None => "",
+    };
+    /// TCP port the command server listens on
+    pub const TCP_PORT: u16 = 4210;
+    /// Wait before rejoining after the network drops or a join fails
+    pub const RECONNECT_SECS: u64 = 5;
+}
+
+/// Flash partitions, one `<NAME>_OFFSET` and `<NAME>_SIZE` per row of
+/// `partitions.csv` (generated by build.rs)
+pub mod partitions {
+    include!(concat!(env!("OUT_DIR"), "/partitions.rs"));
+}
+
+/// Application image checked by VerifyFirmware
+pub mod firmware {
+    /// Flash offset of the application partition (`factory`)
+    pub const APP_OFFSET: u32 = super::partitions::FACTORY_OFFSET;
+    /// Application partition size in bytes. VerifyFirmware checksums only
+    /// the image at its start, which must end within it.
+    pub const APP_SIZE: u32 = super::partitions::FACTORY_SIZE;
+}
+
+/// Device label (SetLabel / GetLabel)
+pub mod label {
+    /// Start of the `settings` data partition, whose first bytes hold the
+    /// label. Flashing the firmware writes only the bootloader, the table and
+    /// the app, so it leaves the label alone.
+    pub const FLASH_OFFSET: u32 = super::partitions::SETTINGS_OFFSET;
+}
+
+/// Flash log for post-mortem (GetCrashLog)
+pub mod crash_log {
+    /// First of the log's flash sectors: the `crash_log` data partition
+    pub const FLASH_OFFSET: u32 = super::partitions::CRASH_LOG_OFFSET;
+    /// Sectors in the ring (4 KB each), filling the partition; the oldest is
+    /// erased when the newest fills
+    pub const SECTORS: u32 = super::partitions::CRASH_LOG_SIZE / 4096;
+    /// Lines waiting to be written; more are dropped
+    pub const QUEUE_SIZE: usize = 8;
+    /// Most log text returned by one GetCrashLog
+    pub const CHUNK_LEN: usize = 128;
 }
 
 /// Protocol constants
 pub mod protocol {
     /// Wire size limits are owned by the shared `wt-protocol` crate so the
     /// firmware and app cannot drift.
-    pub use wt_protocol::{MAX_FRAME_SIZE, MAX_LORA_PAYLOAD};
+    pub use wt_protocol::{MAX_FRAME_SIZE, MAX_LORA_PAYLOAD, MAX_MESSAGE_SIZE};
 
     /// Firmware version, reported by GetVersion.
     pub const VERSION_MAJOR: u8 = 0;
     pub const VERSION_MINOR: u8 = 1;
     pub const VERSION_PATCH: u8 = 0;
+    /// Build id appended to the version: the short git hash and build date,
+    /// stamped by `build.rs`
+    pub const BUILD_ID: &str = concat!(env!("WT_GIT_HASH"), " ", env!("WT_BUILD_DATE"));
 }
@@ -0,0 +1,430 @@
+//! Flash-backed log for post-mortem
+//!
+//! Recent debug lines are also kept in a ring of flash sectors, the
+//! `crash_log` data partition (`config::crash_log`), so a unit that
+//! misbehaved in the field can be asked what happened with GetCrashLog after
+//! the fact. Each sector
+//! starts with a header carrying a sequence number, and lines are appended
+//! as length-prefixed records padded to the flash write size. When the newest
+//! sector fills, the oldest one is erased and reused.
+//!
+//! After a panic the log is frozen until the host has read it to the end, so
+//! the lines leading up to the panic are not pushed out by the next run.
+
+use crate::config::crash_log::SECTORS;
+
+/// Size of one flash sector (the erase unit)
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Longest line kept; longer lines are cut
+pub const MAX_LINE: usize = 128;
+
+/// Flash write granularity; records are padded to a multiple of it
+const WRITE_SIZE: usize = 4;
+
+/// Marks a sector in use, so erased flash (all 0xFF) reads as empty
+const MAGIC: [u8; 4] = *b"WTLG";
+
+/// Sector header: magic, then the sector's sequence number (LE)
+const HEADER_LEN: u32 = MAGIC.len() as u32 + 4;
+
+/// Length byte of an unwritten record
+const ERASED: u8 = 0xFF;
+
+/// Flash access the ring needs, with offsets relative to the start of the
+/// log region
+pub trait LogFlash {
+    /// Read `buf.len()` bytes at `offset`
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool;
+    /// Program erased flash at `offset` with `data`; both are multiples of
+    /// the write size
+    fn write(&mut self, offset: u32, data: &[u8]) -> bool;
+    /// Erase the sector starting at `offset`
+    fn erase(&mut self, offset: u32) -> bool;
+}
+
+/// Bytes a record for a line of `len` bytes takes in flash
+fn record_len(len: usize) -> u32 {
+    (1 + len).next_multiple_of(WRITE_SIZE) as u32
+}
+
+/// Sequence number of sector `index`, or `None` if it is not in use
+fn sector_seq(flash: &mut impl LogFlash, index: u32) -> Option<u32> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    if !flash.read(index * SECTOR_SIZE, &mut header) || header[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    Some(u32::from_le_bytes(header[MAGIC.len()..].try_into().ok()?))
+}
+
+/// Walk the records of sector `index`, handing each line to `f`, and return
+/// the offset just past the last one
+fn walk_sector(flash: &mut impl LogFlash, index: u32, mut f: impl FnMut(&[u8])) -> u32 {
+    let base = index * SECTOR_SIZE;
+    let mut offset = HEADER_LEN;
+    let mut line = [0u8; MAX_LINE];
+    while offset < SECTOR_SIZE {
+        let mut len = [ERASED];
+        if !flash.read(base + offset, &mut len) || len[0] == ERASED {
+            break;
+        }
+        let len = len[0] as usize;
+        // A length no line can have means the sector is damaged; treat it
+        // as full so nothing is written after it
+        if len > MAX_LINE || offset + record_len(len) > SECTOR_SIZE {
+            return SECTOR_SIZE;
+        }
+        if !flash.read(base + offset + 1, &mut line[..len]) {
+            break;
+        }
+        f(&line[..len]);
+        offset += record_len(len);
+    }
+    offset
+}
+
+/// Where the log stands in flash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ring {
+    /// Sector being appended to
+    sector: u32,
+    /// Its sequence number (0 = nothing written yet)
+    seq: u32,
+    /// Offset of the next record within it
+    offset: u32,
+}
+
+impl Ring {
+    /// Find the newest sector and the end of its records
+    pub fn open(flash: &mut impl LogFlash) -> Self {
+        let newest = (0..SECTORS)
+            .filter_map(|index| Some((sector_seq(flash, index)?, index)))
+            .max();
+        match newest {
+            Some((seq, sector)) => Self {
+                sector,
+                seq,
+                offset: walk_sector(flash, sector, |_| {}),
+            },
+            None => Self {
+                sector: 0,
+                seq: 0,
+                offset: SECTOR_SIZE,
+            },
+        }
+    }
+
+    /// Append `line` (cut to `MAX_LINE`), erasing the oldest sector first
+    /// if the newest is full. Returns whether it was written.
+    pub fn append(&mut self, flash: &mut impl LogFlash, line: &[u8]) -> bool {
+        let line = &line[..line.len().min(MAX_LINE)];
+        let len = record_len(line.len());
+        if self.offset + len > SECTOR_SIZE && !self.rotate(flash) {
+            return false;
+        }
+
+        let mut record = [ERASED; MAX_LINE + WRITE_SIZE];
+        record[0] = line.len() as u8;
+        record[1..][..line.len()].copy_from_slice(line);
+        if !flash.write(self.sector * SECTOR_SIZE + self.offset, &record[..len as usize]) {
+            return false;
+        }
+        self.offset += len;
+        true
+    }
+
+    /// Start the next sector, erasing it
+    fn rotate(&mut self, flash: &mut impl LogFlash) -> bool {
+        let sector = if self.seq == 0 { 0 } else { (self.sector + 1) % SECTORS };
+        let seq = self.seq + 1;
+        let mut header = [0u8; HEADER_LEN as usize];
+        header[..MAGIC.len()].copy_from_slice(&MAGIC);
+        header[MAGIC.len()..].copy_from_slice(&seq.to_le_bytes());
+        if !flash.erase(sector * SECTOR_SIZE) || !flash.write(sector * SECTOR_SIZE, &header) {
+            // Nothing more is appended until a rotation succeeds
+            self.offset = SECTOR_SIZE;
+            return false;
+        }
+        *self = Self {
+            sector,
+            seq,
+            offset: HEADER_LEN,
+        };
+        true
+    }
+
+    /// Copy the log text from byte `offset` into `buf`, oldest line first,
+    /// each line followed by `\n`. Returns the number of bytes copied: 0 once
+    /// `offset` is past the end.
+    pub fn read(&self, flash: &mut impl LogFlash, offset: usize, buf: &mut [u8]) -> usize {
+        if self.seq == 0 {
+            return 0;
+        }
+        let end = offset + buf.len();
+        let mut position = 0;
+        let mut copied = 0;
+        let mut copy = |text: &[u8]| {
+            for &byte in text.iter().chain(b"\n") {
+                if (offset..end).contains(&position) {
+                    buf[position - offset] = byte;
+                    copied += 1;
+                }
+                position += 1;
+            }
+        };
+
+        // Oldest first: the sectors after the newest, wrapping round to it.
+        // Ones left over from an older ring (or never used) are skipped.
+        for step in 1..=SECTORS {
+            let index = (self.sector + step) % SECTORS;
+            let oldest_kept = self.seq.saturating_sub(SECTORS - 1).max(1);
+            if sector_seq(flash, index).is_some_and(|seq| (oldest_kept..=self.seq).contains(&seq)) {
+                walk_sector(flash, index, &mut copy);
+            }
+        }
+        copied
+    }
+}
+
+#[cfg(feature = "embedded")]
+mod flash {
+    use core::cell::RefCell;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embassy_sync::channel::Channel;
+    use embassy_time::{Duration, Timer};
+    use embedded_storage::nor_flash::NorFlash;
+    use embedded_storage::ReadStorage;
+    use esp_storage::FlashStorage;
+    use heapless::String;
+
+    use super::{LogFlash, Ring, MAX_LINE, SECTOR_SIZE};
+    use crate::config::crash_log::{FLASH_OFFSET, QUEUE_SIZE};
+    use crate::firmware::with_flash;
+
+    /// How often a line waiting on the flash checks whether it is free
+    const FLASH_RETRY_MS: u64 = 10;
+
+    /// Written by the panic hook; anything else (such as the garbage left at
+    /// power-on) means the last reset was not a panic
+    const PANIC_MARK: u32 = 0x5041_4E43;
+
+    /// Survives the watchdog reset that follows a panic
+    #[esp_hal::ram(unstable(rtc_fast, persistent))]
+    static mut PANICKED: u32 = 0;
+
+    /// Where the log stands, once `init` has found it
+    static RING: Mutex<CriticalSectionRawMutex, RefCell<Option<Ring>>> = Mutex::new(RefCell::new(None));
+
+    /// Set after a panic, until the host has read the log to the end
+    static FROZEN: AtomicBool = AtomicBool::new(false);
+
+    /// Lines waiting for `crash_log_task` to write them
+    static LINES: Channel<CriticalSectionRawMutex, String<MAX_LINE>, QUEUE_SIZE> = Channel::new();
+
+    /// The log region of the real flash
+    struct Region<'a>(&'a mut FlashStorage<'static>);
+
+    impl LogFlash for Region<'_> {
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool {
+            ReadStorage::read(self.0, FLASH_OFFSET + offset, buf).is_ok()
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> bool {
+            NorFlash::write(self.0, FLASH_OFFSET + offset, data).is_ok()
+        }
+
+        fn erase(&mut self, offset: u32) -> bool {
+            let from = FLASH_OFFSET + offset;
+            NorFlash::erase(self.0, from, from + SECTOR_SIZE).is_ok()
+        }
+    }
+
+    /// Note a panic for the next boot. Called from the panic hook, so it only
+    /// touches RTC memory.
+    pub fn mark_panic() {
+        // SAFETY: single word written from the panic path, read only by
+        // `init` before any task runs
+        unsafe { core::ptr::addr_of_mut!(PANICKED).write_volatile(PANIC_MARK) };
+    }
+
+    /// Find the log in flash and mark the boot; call once at boot after
+    /// `firmware::init`
+    ///
+    /// If the last reset followed a panic the log is frozen, so the lines
+    /// before it stay until the host reads them.
+    pub fn init() {
+        // SAFETY: no task is running yet, and the panic hook only runs on the
+        // way down
+        let panicked = unsafe {
+            let panicked = core::ptr::addr_of!(PANICKED).read_volatile() == PANIC_MARK;
+            core::ptr::addr_of_mut!(PANICKED).write_volatile(0);
+            panicked
+        };
+        let opened = with_flash(|flash| {
+            let mut region = Region(flash);
+            let mut ring = Ring::open(&mut region);
+            let marker: &[u8] = if panicked { b"--- boot after panic ---" } else { b"--- boot ---" };
+            ring.append(&mut region, marker);
+            ring
+        });
+        let Some(ring) = opened else {
+            crate::error!("Crash log: flash unavailable");
+            return;
+        };
+        RING.lock(|cell| *cell.borrow_mut() = Some(ring));
+        FROZEN.store(panicked, Ordering::Relaxed);
+    }
+
+    /// Queue a line for the flash log; dropped if the queue is full or the
+    /// log is frozen
+    pub fn record(line: &str) {
+        if FROZEN.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut kept = String::new();
+        for c in line.chars() {
+            if kept.push(c).is_err() {
+                break;
+            }
+        }
+        let _ = LINES.try_send(kept);
+    }
+
+    /// Copy the log text from byte `offset` into `buf` (see `Ring::read`)
+    ///
+    /// Returns `None` if the flash is busy. Reading to the end unfreezes a
+    /// log kept after a panic.
+    pub fn read(offset: u32, buf: &mut [u8]) -> Option<usize> {
+        let ring = RING.lock(|cell| *cell.borrow())?;
+        let len = with_flash(|flash| ring.read(&mut Region(flash), offset as usize, buf))?;
+        if len == 0 {
+            FROZEN.store(false, Ordering::Relaxed);
+        }
+        Some(len)
+    }
+
+    /// Task that appends queued lines to the flash log
+    ///
+    /// Runs apart from `debug_print` so logging never waits on the flash.
+    /// Filling a sector erases the next, which stalls the CPU for a few tens
+    /// of ms.
+    pub async fn crash_log_task() {
+        loop {
+            let line = LINES.receive().await;
+            let Some(mut ring) = RING.lock(|cell| *cell.borrow()) else {
+                continue;
+            };
+            // While the flash is lent to VerifyFirmware the line waits for it,
+            // and later lines queue up behind it
+            while with_flash(|flash| ring.append(&mut Region(flash), line.as_bytes())).is_none() {
+                Timer::after(Duration::from_millis(FLASH_RETRY_MS)).await;
+            }
+            RING.lock(|cell| *cell.borrow_mut() = Some(ring));
+        }
+    }
+}
+
+#[cfg(feature = "embedded")]
+pub use flash::{crash_log_task, init, mark_panic, read, record};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Log region held in RAM, starting erased
+    struct RamFlash(std::vec::Vec<u8>);
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self(std::vec![0xFF; (SECTORS * SECTOR_SIZE) as usize])
+        }
+    }
+
+    impl LogFlash for RamFlash {
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool {
+            buf.copy_from_slice(&self.0[offset as usize..][..buf.len()]);
+            true
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> bool {
+            assert_eq!((offset as usize % WRITE_SIZE, data.len() % WRITE_SIZE), (0, 0));
+            for (cell, &byte) in self.0[offset as usize..].iter_mut().zip(data) {
+                // NOR flash can only clear bits
+                *cell &= byte;
+            }
+            true
+        }
+
+        fn erase(&mut self, offset: u32) -> bool {
+            self.0[offset as usize..][..SECTOR_SIZE as usize].fill(0xFF);
+            true
+        }
+    }
+
+    fn text(ring: &Ring, flash: &mut RamFlash) -> std::string::String {
+        let mut buf = std::vec![0u8; (SECTORS * SECTOR_SIZE) as usize];
+        let len = ring.read(flash, 0, &mut buf);
+        std::string::String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn lines_survive_a_reopen() {
+        let mut flash = RamFlash::new();
+        let mut ring = Ring::open(&mut flash);
+        assert_eq!(text(&ring, &mut flash), "");
+        assert!(ring.append(&mut flash, b"first"));
+        assert!(ring.append(&mut flash, b"second line"));
+
+        let mut reopened = Ring::open(&mut flash);
+        assert_eq!(reopened, ring);
+        assert!(reopened.append(&mut flash, b"third"));
+        assert_eq!(text(&reopened, &mut flash), "first\nsecond line\nthird\n");
+    }
+
+    #[test]
+    fn reads_resume_at_an_offset() {
+        let mut flash = RamFlash::new();
+        let mut ring = Ring::open(&mut flash);
+        ring.append(&mut flash, b"abc");
+        ring.append(&mut flash, b"defg");
+
+        let mut buf = [0u8; 3];
+        assert_eq!(ring.read(&mut flash, 2, &mut buf), 3);
+        assert_eq!(&buf, b"c\nd");
+        assert_eq!(ring.read(&mut flash, 7, &mut buf), 2);
+        assert_eq!(&buf[..2], b"g\n");
+        assert_eq!(ring.read(&mut flash, 9, &mut buf), 0);
+    }
+
+    #[test]
+    fn a_full_ring_drops_the_oldest_sector() {
+        let mut flash = RamFlash::new();
+        let mut ring = Ring::open(&mut flash);
+        let line = [b'x'; MAX_LINE];
+        let per_sector = ((SECTOR_SIZE - HEADER_LEN) / record_len(MAX_LINE)) as usize;
+        let total = per_sector * SECTORS as usize + 1;
+        for n in 0..total {
+            let mut numbered = line;
+            numbered[..8].copy_from_slice(std::format!("{:08}", n).as_bytes());
+            assert!(ring.append(&mut flash, &numbered));
+        }
+
+        // The first sector was reused for the last line, so its lines are gone
+        let log = text(&Ring::open(&mut flash), &mut flash);
+        let first: usize = log[..8].parse().unwrap();
+        assert_eq!(first, per_sector);
+        assert_eq!(log.lines().count(), total - per_sector);
+        assert!(log.lines().last().unwrap().starts_with(&std::format!("{:08}", total - 1)));
+    }
+
+    #[test]
+    fn long_lines_are_cut() {
+        let mut flash = RamFlash::new();
+        let mut ring = Ring::open(&mut flash);
+        ring.append(&mut flash, &[b'y'; MAX_LINE + 20]);
+        assert_eq!(text(&ring, &mut flash).len(), MAX_LINE + 1);
+    }
+}
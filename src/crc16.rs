@@ -0,0 +1,33 @@
+//! CRC-16/XMODEM
+//!
+//! The checksum in every host frame trailer. Used to check and restamp a
+//! frame in an older protocol version before wt-protocol parses it.
+
+/// CRC-16/XMODEM of `data`
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_check_value() {
+        // The standard CRC-16/XMODEM check value
+        assert_eq!(checksum(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn matches_the_frame_examples() {
+        // GetVersion, as in the README
+        assert_eq!(checksum(&[0x01, 0x01, 0x00, 0x00]), 0x4184);
+    }
+}
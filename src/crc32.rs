@@ -0,0 +1,85 @@
+//! CRC-32 (IEEE 802.3)
+//!
+//! Used for the firmware image check (VerifyFirmware) and the optional
+//! checksum on RxPacket (SetRxChecksum). The integration test crate builds this
+//! same file to check what it receives.
+
+/// Lookup table for the reflected CRC-32 polynomial
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3, as computed by zlib and the `crc32` tool), fed in
+/// chunks
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    /// Feed the next chunk of data
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    /// CRC of everything fed so far
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-32 of `data` in one go
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_check_value() {
+        // The standard CRC-32 check value
+        assert_eq!(checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(checksum(b""), 0);
+    }
+
+    #[test]
+    fn chunks_give_the_same_crc() {
+        let data: std::vec::Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+        let mut crc = Crc32::new();
+        for chunk in data.chunks(4096) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finish(), checksum(&data));
+    }
+
+    #[test]
+    fn erased_flash_is_not_zero() {
+        // An erased (all 0xFF) partition must not look like a valid checksum
+        assert_ne!(checksum(&[0xFF; 4096]), 0);
+    }
+}
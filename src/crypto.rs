@@ -0,0 +1,177 @@
+//! LoRa payload encryption
+//!
+//! Each radio frame is sealed with AES-128-CTR under the preshared key in
+//! `config::crypto`. The frame on air is:
+//!
+//! ```text
+//! [nonce: 13 bytes][ciphertext of (MAGIC || plaintext)]
+//! ```
+//!
+//! The nonce is random per frame and forms the top of the 128-bit CTR counter
+//! block. CTR mode has no integrity check of its own, so a two-byte magic is
+//! encrypted ahead of the plaintext; a frame that does not decrypt to the magic
+//! (wrong key, corruption, foreign traffic) is rejected.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use heapless::Vec;
+
+use crate::config;
+
+/// Length of the per-frame nonce prepended to the ciphertext.
+pub const NONCE_LEN: usize = 13;
+
+/// Marker encrypted ahead of the plaintext to detect a bad decrypt.
+const MAGIC: [u8; 2] = *b"WT";
+
+/// Bytes added to every frame by [`seal`] (zero when encryption is disabled).
+pub const OVERHEAD: usize = if config::crypto::ENABLED {
+    NONCE_LEN + MAGIC.len()
+} else {
+    0
+};
+
+/// Largest frame handled by this layer (the radio length field is one byte).
+const MAX_SEALED_LEN: usize = 255;
+
+/// AES-128 in CTR mode with a big-endian 128-bit counter.
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// Received frames dropped because they failed to decrypt.
+static DECRYPT_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Number of received frames rejected by [`open`] since boot.
+pub fn decrypt_errors() -> u32 {
+    DECRYPT_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Build the initial counter block: nonce followed by a zeroed block counter.
+fn counter_block(nonce: &[u8; NONCE_LEN]) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..NONCE_LEN].copy_from_slice(nonce);
+    iv
+}
+
+/// Seal a plaintext frame for transmission.
+///
+/// Returns `None` if the sealed frame would not fit in a single radio frame.
+/// When encryption is disabled the plaintext is passed through unchanged.
+pub fn seal(plaintext: &[u8], nonce: &[u8; NONCE_LEN]) -> Option<Vec<u8, MAX_SEALED_LEN>> {
+    let mut frame = Vec::new();
+    if !config::crypto::ENABLED {
+        frame.extend_from_slice(plaintext).ok()?;
+        return Some(frame);
+    }
+
+    frame.extend_from_slice(nonce).ok()?;
+    frame.extend_from_slice(&MAGIC).ok()?;
+    frame.extend_from_slice(plaintext).ok()?;
+
+    let mut cipher = Aes128Ctr::new(
+        &config::crypto::PRESHARED_KEY.into(),
+        &counter_block(nonce).into(),
+    );
+    cipher.apply_keystream(&mut frame[NONCE_LEN..]);
+    Some(frame)
+}
+
+/// Open a received frame, returning the plaintext.
+///
+/// Fails closed: a frame that is too short or does not decrypt to the expected
+/// structure returns `None` and bumps the decrypt-error counter.
+pub fn open(frame: &[u8]) -> Option<Vec<u8, MAX_SEALED_LEN>> {
+    let mut plaintext = Vec::new();
+    if !config::crypto::ENABLED {
+        plaintext.extend_from_slice(frame).ok()?;
+        return Some(plaintext);
+    }
+
+    if frame.len() < OVERHEAD {
+        DECRYPT_ERRORS.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&frame[..NONCE_LEN]);
+
+    let mut body: Vec<u8, MAX_SEALED_LEN> = Vec::new();
+    body.extend_from_slice(&frame[NONCE_LEN..]).ok()?;
+    let mut cipher = Aes128Ctr::new(
+        &config::crypto::PRESHARED_KEY.into(),
+        &counter_block(&nonce).into(),
+    );
+    cipher.apply_keystream(&mut body);
+
+    if body[..MAGIC.len()] != MAGIC {
+        DECRYPT_ERRORS.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+
+    plaintext.extend_from_slice(&body[MAGIC.len()..]).ok()?;
+    Some(plaintext)
+}
+
+/// Generate a fresh nonce from the hardware RNG.
+#[cfg(feature = "embedded")]
+pub fn random_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    esp_hal::rng::Rng::new().read(&mut nonce);
+    nonce
+}
+
+/// Nonce source for non-embedded builds (tests): a simple per-call counter.
+#[cfg(not(feature = "embedded"))]
+pub fn random_nonce() -> [u8; NONCE_LEN] {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..4].copy_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: [u8; NONCE_LEN] = [0x42; NONCE_LEN];
+
+    #[test]
+    fn seal_then_open_round_trips() {
+        let sealed = seal(b"Hello", &NONCE).expect("fits in a frame");
+        assert_eq!(sealed.len(), 5 + OVERHEAD);
+        assert_eq!(&sealed[..NONCE_LEN], &NONCE);
+        assert_ne!(&sealed[NONCE_LEN + MAGIC.len()..], b"Hello");
+
+        let opened = open(&sealed).expect("valid frame opens");
+        assert_eq!(opened.as_slice(), b"Hello");
+    }
+
+    #[test]
+    fn nonce_changes_ciphertext() {
+        let a = seal(b"Hello", &NONCE).unwrap();
+        let b = seal(b"Hello", &[0x24; NONCE_LEN]).unwrap();
+        assert_ne!(&a[NONCE_LEN..], &b[NONCE_LEN..]);
+    }
+
+    #[test]
+    fn tampered_frame_is_rejected() {
+        let mut sealed = seal(b"Hello", &NONCE).unwrap();
+        sealed[NONCE_LEN] ^= 0x01;
+
+        let before = decrypt_errors();
+        assert!(open(&sealed).is_none());
+        assert!(decrypt_errors() > before);
+    }
+
+    #[test]
+    fn short_frame_is_rejected() {
+        assert!(open(&[0u8; NONCE_LEN]).is_none());
+    }
+
+    #[test]
+    fn oversized_plaintext_does_not_fit() {
+        assert!(seal(&[0u8; MAX_SEALED_LEN], &NONCE).is_none());
+        assert!(seal(&[0u8; MAX_SEALED_LEN - OVERHEAD], &NONCE).is_some());
+    }
+}
@@ -2,9 +2,14 @@
 //!
 //! Provides macros for writing debug output to the secondary CDC-ACM port.
 //! Output is non-blocking and will be dropped if the queue is full or
-//! the debug port is not connected.
+//! the debug port is not connected; messages lost to a full queue are
+//! counted and reported in the output. Messages below the runtime log level
+//! (SetLogLevel) are discarded before they are formatted. While a BLE client
+//! is connected, messages are also queued for the Debug Log Service, and
+//! messages up to `Info` are copied to the flash crash log.
 
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
 
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
@@ -14,19 +19,97 @@ use esp_hal::otg_fs::asynch::Driver;
 use heapless::String;
 
 /// Maximum length of a single debug message
-const MAX_DEBUG_MSG_LEN: usize = 128;
+pub const MAX_DEBUG_MSG_LEN: usize = 128;
+
+/// Maximum number of debug messages queued for BLE
+const BLE_QUEUE_SIZE: usize = 8;
 
 /// Maximum number of queued debug messages
 const DEBUG_QUEUE_SIZE: usize = 16;
 
+/// Most verbose level also kept in the flash crash log; per-packet trace
+/// output would wear the flash out
+const CRASH_LOG_LEVEL: LogLevel = LogLevel::Info;
+
+/// Debug output verbosity, lowest to highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// Nothing is logged
+    Off = 0,
+    /// Failures only (`error!`)
+    Error = 1,
+    /// Status and lifecycle messages (`debug!`); the default
+    Info = 2,
+    /// Per-packet and per-command detail (`trace!`)
+    Trace = 3,
+}
+
+impl TryFrom<u8> for LogLevel {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LogLevel::Off),
+            1 => Ok(LogLevel::Error),
+            2 => Ok(LogLevel::Info),
+            3 => Ok(LogLevel::Trace),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Current log level; held in RAM only, so it returns to `Info` on reboot
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Set the runtime log level
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are currently logged
+pub fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
 /// Channel for debug messages (proper queue instead of single buffer)
 static DEBUG_CHANNEL: Channel<CriticalSectionRawMutex, String<MAX_DEBUG_MSG_LEN>, DEBUG_QUEUE_SIZE> =
     Channel::new();
 
+/// Messages dropped because `DEBUG_CHANNEL` was full, since last reported
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Copy of the debug output for the BLE Debug Log Service
+static BLE_DEBUG_CHANNEL: Channel<CriticalSectionRawMutex, String<MAX_DEBUG_MSG_LEN>, BLE_QUEUE_SIZE> =
+    Channel::new();
+
+/// Whether messages are copied to `BLE_DEBUG_CHANNEL` (a client is connected)
+static BLE_FORWARDING: AtomicBool = AtomicBool::new(false);
+
+/// Start or stop copying debug output for BLE
+///
+/// Set by `ble_task` while a client is connected. Stopping discards anything
+/// still queued, so a new connection does not start with stale lines.
+pub fn set_ble_forwarding(enabled: bool) {
+    BLE_FORWARDING.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        BLE_DEBUG_CHANNEL.clear();
+    }
+}
+
+/// Wait for the next debug message queued for BLE
+pub async fn next_ble_message() -> String<MAX_DEBUG_MSG_LEN> {
+    BLE_DEBUG_CHANNEL.receive().await
+}
+
 /// Debug writer task that sends queued messages to the CDC port.
 ///
 /// This task should be spawned and will continuously send debug messages
 /// to the CDC sender when they become available.
+///
+/// After each message, a "[N messages dropped]" line reports anything lost
+/// to a full queue since the last report. A drop only happens while the
+/// queue is full, so there is always a message behind it to trigger one.
 pub async fn debug_writer_task(mut sender: Sender<'static, Driver<'static>>) {
     let receiver = DEBUG_CHANNEL.receiver();
 
@@ -38,14 +121,26 @@ pub async fn debug_writer_task(mut sender: Sender<'static, Driver<'static>>) {
         let _ = sender.write_packet(msg.as_bytes()).await;
         // Send newline
         let _ = sender.write_packet(b"\r\n").await;
+
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let mut notice: String<32> = String::new();
+            let _ = write!(notice, "[{} messages dropped]\r\n", dropped);
+            let _ = sender.write_packet(notice.as_bytes()).await;
+        }
     }
 }
 
 /// Format and write a debug message with timestamp.
 ///
-/// This is the implementation behind the debug! macro.
-/// Messages are prefixed with a timestamp in [MM:SS.mmm] format.
-pub fn debug_print(args: core::fmt::Arguments) {
+/// This is the implementation behind the debug!/error!/trace! macros.
+/// Messages are prefixed with a timestamp in [MM:SS.mmm] format, and dropped
+/// without formatting if `level` is filtered out.
+pub fn debug_print(level: LogLevel, args: core::fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+
     let mut s: String<MAX_DEBUG_MSG_LEN> = String::new();
 
     // Get time since boot
@@ -59,10 +154,18 @@ pub fn debug_print(args: core::fmt::Arguments) {
     // Format timestamp as [MM:SS.mmm]
     let _ = write!(s, "[{:02}:{:02}.{:03}] ", mins, secs, ms);
     let _ = s.write_fmt(args);
-    let _ = DEBUG_CHANNEL.try_send(s);
+    if BLE_FORWARDING.load(Ordering::Relaxed) {
+        let _ = BLE_DEBUG_CHANNEL.try_send(s.clone());
+    }
+    if level <= CRASH_LOG_LEVEL {
+        crate::crash_log::record(&s);
+    }
+    if DEBUG_CHANNEL.try_send(s).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
-/// Print a debug message to the debug CDC port.
+/// Print a debug message to the debug CDC port at `Info` level.
 ///
 /// Usage: `debug!("Hello, {}!", "world");`
 ///
@@ -71,6 +174,22 @@ pub fn debug_print(args: core::fmt::Arguments) {
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {
-        $crate::debug::debug_print(format_args!($($arg)*))
+        $crate::debug::debug_print($crate::debug::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+/// Print a failure to the debug CDC port (`Error` level).
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::debug::debug_print($crate::debug::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+/// Print per-packet or per-command detail to the debug CDC port (`Trace` level).
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::debug::debug_print($crate::debug::LogLevel::Trace, format_args!($($arg)*))
     };
 }
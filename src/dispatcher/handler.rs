@@ -3,26 +3,84 @@
 //! This module defines the channel architecture for multi-source command handling
 //! and the dispatcher that executes commands.
 
-use crate::config::protocol;
-use crate::lora::traits::{LoraError, LoraRadio};
-use wt_protocol::{Command, Response, ResponseStatus};
+use crate::battery::LowBatteryPolicy;
+use crate::ble::name::NameError;
+use crate::clock;
+use crate::config::{loopback, protocol, watchdog};
+use crate::crc32;
+use crate::crypto;
+use crate::framing::Trailer;
+use crate::label::LabelError;
+use crate::lora::addressing::{self, Address};
+use crate::lora::band::LoraBand;
+use crate::lora::dedup::DeliveredMessages;
+use crate::lora::fragment::{self, Hop, Reassembler};
+use crate::lora::repeater::{self, SeenFrames};
+use crate::lora::traits::{
+    lora_time_on_air_ms, FskParams, LoraConfig, LoraError, LoraRadio, Modulation, RxDutyCycle,
+    RxPacket, SleepMode, StandbyMode, MAX_TX_POWER_DBM, MIN_TX_POWER_DBM,
+};
+use super::version;
+use wt_protocol::{Command, CommandId, Response, ResponseStatus};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
-use embassy_sync::pubsub::{ImmediatePublisher, PubSubChannel};
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use heapless::{Deque, Vec};
+use core::sync::atomic::{AtomicU8, Ordering};
 
 /// Channel capacity for incoming commands
 const COMMAND_CHANNEL_SIZE: usize = 8;
 
+/// Response subscribers: the serial writer, one per BLE connection and the
+/// WiFi client when built with the `wifi` feature
+///
+/// Each is held by exactly one consumer. The serial writer subscribes for
+/// life; a BLE connection or WiFi client takes its slot when it connects and
+/// gives it back when it disconnects. The LoRa task and other publishers take
+/// no slot. A new consumer of responses needs a slot added here, or a
+/// connection will be refused for want of one.
+const RESPONSE_SUBSCRIBERS: usize =
+    1 + crate::config::ble::MAX_CONNECTIONS + if cfg!(feature = "wifi") { 1 } else { 0 };
+
 /// Identifies the source of a command for routing responses
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommandSource {
     /// Command received via serial/UART
     Serial,
-    /// Command received via BLE
-    Ble,
-    /// Command received via WiFi (future)
-    #[allow(dead_code)]
+    /// Command received via BLE, on the given connection slot
+    Ble(u8),
+    /// Command received from the TCP client (`wifi` feature)
+    #[cfg_attr(not(feature = "wifi"), allow(dead_code))]
     WiFi,
+    /// Command generated on the device (e.g. beacons); its response is not
+    /// delivered to any interface
+    Internal,
+}
+
+/// Whether the device may transmit, set with SetRole
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Role {
+    /// Handheld: sends and receives (the firmware default)
+    #[default]
+    Transceiver = 0,
+    /// Gateway: only listens and forwards what it hears to the host, never
+    /// transmitting, not even ACKs
+    Listener = 1,
+}
+
+impl TryFrom<u8> for Role {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Role::Transceiver),
+            1 => Ok(Role::Listener),
+            _ => Err(value),
+        }
+    }
 }
 
 /// Envelope wrapping a command with metadata
@@ -34,6 +92,30 @@ pub struct CommandEnvelope {
     pub source: CommandSource,
     /// Sequence ID for matching responses to requests
     pub sequence_id: u16,
+    /// The host's own sequence number, if the command frame carried one;
+    /// echoed with `sequence_id` so the host can spot lost commands
+    pub host_sequence: Option<u16>,
+}
+
+/// System commands executed by the admin task
+#[derive(Clone, Copy, Debug)]
+pub enum AdminCommand {
+    /// Normal reboot (restart firmware)
+    Reboot,
+    /// Light-sleep the MCU for `duration_ms`, or until DIO1 rises when 0
+    Sleep { duration_ms: u32 },
+}
+
+impl AdminCommand {
+    /// The admin command a host command maps to, for the ones the interface
+    /// readers hand straight to the admin task instead of queueing. Sleep is
+    /// not one of them: the LoRa task has to park the radio first.
+    pub fn from_command(command: &Command) -> Option<Self> {
+        match command {
+            Command::Reboot => Some(Self::Reboot),
+            _ => None,
+        }
+    }
 }
 
 /// Message type for all outgoing responses
@@ -46,14 +128,54 @@ pub enum ResponseMessage {
     /// Command response - should be filtered by source
     Command {
         source: CommandSource,
-        #[allow(dead_code)]
+        /// Echoed in the frame trailer if the source enabled SetSequenceEcho
         sequence_id: u16,
+        /// Host sequence from the command frame, echoed in the trailer
+        host_sequence: Option<u16>,
         response: Response,
     },
-    /// Unsolicited packet (RxPacket) - delivered to all connected interfaces
+    /// Unsolicited packet (RxPacket) or event - delivered to all connected
+    /// interfaces
     Unsolicited(Response),
 }
 
+impl ResponseMessage {
+    /// `response` to the command in `envelope`
+    pub fn reply(envelope: &CommandEnvelope, response: Response) -> Self {
+        ResponseMessage::Command {
+            source: envelope.source,
+            sequence_id: envelope.sequence_id,
+            host_sequence: envelope.host_sequence,
+            response,
+        }
+    }
+
+    /// What the interface serving `source` should send for this message: the
+    /// response plus its frame trailer (see `response_trailer`). None for
+    /// another interface's command response.
+    ///
+    /// An RxPacket keeps its checksum only if the interface enabled
+    /// SetRxChecksum.
+    pub fn for_interface(self, source: CommandSource) -> Option<(Response, Trailer)> {
+        let (mut response, trailer) = match self {
+            ResponseMessage::Command {
+                source: origin,
+                sequence_id,
+                host_sequence,
+                response,
+            } if origin == source => (response, response_trailer(source, sequence_id, host_sequence)),
+            ResponseMessage::Command { .. } => return None,
+            ResponseMessage::Unsolicited(response) => (response, Trailer::None),
+        };
+        if let Response::RxPacket { crc32, .. } = &mut response {
+            if !rx_checksum(source) {
+                *crc32 = None;
+            }
+        }
+        Some((response, trailer))
+    }
+}
+
 /// Global channel for commands from all sources
 ///
 /// Multiple producers (serial, BLE, WiFi) send commands here.
@@ -68,136 +190,3209 @@ pub static COMMAND_CHANNEL: Channel<CriticalSectionRawMutex, CommandEnvelope, CO
 /// - Command responses: only accepted if source matches the subscriber's interface
 /// - Unsolicited: always accepted by all subscribers
 ///
-/// Parameters: CAP=8 messages, SUBS=2 subscribers (serial, BLE), PUBS=1 publisher (lora_task)
-pub static RESPONSE_CHANNEL: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 8, 2, 1> =
-    PubSubChannel::new();
+/// Parameters: CAP=8 messages, SUBS=RESPONSE_SUBSCRIBERS (serial, one per
+/// BLE connection and the WiFi client), PUBS=1. The LoRa runner and the BLE
+/// task (connection events) and the heartbeat task publish through immediate
+/// publishers, which take no slot.
+pub static RESPONSE_CHANNEL: ResponseChannel = PubSubChannel::new();
+
+/// Type of `RESPONSE_CHANNEL`
+pub type ResponseChannel = PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 8, RESPONSE_SUBSCRIBERS, 1>;
+
+/// Beacon interval in seconds (0 = off), raised by SetBeaconInterval for the
+/// beacon task
+pub static BEACON_INTERVAL: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+
+/// Heartbeat interval in seconds (0 = off), raised by SetHeartbeatInterval
+/// for the heartbeat task
+pub static HEARTBEAT_INTERVAL: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+
+/// Interfaces that enabled SetSequenceEcho, one bit each (see `interface_bit`)
+static SEQUENCE_ECHO: AtomicU8 = AtomicU8::new(0);
+
+/// Interfaces that enabled SetRxChecksum, one bit each
+static RX_CHECKSUM: AtomicU8 = AtomicU8::new(0);
+
+const _: () = assert!(crate::config::ble::MAX_CONNECTIONS < 7, "interface flags have one bit per slot");
+
+/// Bit for `source` in the per-interface flags: bit 0 for serial, bit
+/// `1 + slot` for each BLE connection and bit 7 for the WiFi client (none for
+/// sources nobody reads)
+fn interface_bit(source: CommandSource) -> u8 {
+    match source {
+        CommandSource::Serial => 1,
+        CommandSource::Ble(slot) => 1 << (1 + slot),
+        CommandSource::WiFi => 1 << 7,
+        CommandSource::Internal => 0,
+    }
+}
+
+/// Set or clear the bit for `source` in `flags`
+fn set_interface_flag(flags: &AtomicU8, source: CommandSource, enabled: bool) {
+    let bit = interface_bit(source);
+    if enabled {
+        flags.fetch_or(bit, Ordering::Relaxed);
+    } else {
+        flags.fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+/// Whether command responses sent to `source` carry the sequence trailer
+pub fn sequence_echo(source: CommandSource) -> bool {
+    SEQUENCE_ECHO.load(Ordering::Relaxed) & interface_bit(source) != 0
+}
+
+/// Turn the sequence trailer on or off for `source` (SetSequenceEcho)
+pub fn set_sequence_echo(source: CommandSource, enabled: bool) {
+    set_interface_flag(&SEQUENCE_ECHO, source, enabled);
+}
+
+/// Trailer for a command response to `source`
+///
+/// A command that carried a host sequence number gets it back with the
+/// device's sequence id, whatever SetSequenceEcho says; otherwise the
+/// sequence id alone if the interface enabled SetSequenceEcho.
+pub fn response_trailer(source: CommandSource, sequence_id: u16, host_sequence: Option<u16>) -> Trailer {
+    match host_sequence {
+        Some(host) => Trailer::HostSequence {
+            host,
+            device: sequence_id,
+        },
+        None if sequence_echo(source) => Trailer::Sequence(sequence_id),
+        None => Trailer::None,
+    }
+}
+
+/// Whether RxPackets sent to `source` carry the payload CRC32
+pub fn rx_checksum(source: CommandSource) -> bool {
+    RX_CHECKSUM.load(Ordering::Relaxed) & interface_bit(source) != 0
+}
+
+/// Turn the RxPacket CRC32 on or off for `source` (SetRxChecksum)
+pub fn set_rx_checksum(source: CommandSource, enabled: bool) {
+    set_interface_flag(&RX_CHECKSUM, source, enabled);
+}
+
+/// Clear every per-interface setting for `source`, when its client goes away,
+/// so the next client on that interface starts with plain frames
+pub fn reset_interface(source: CommandSource) {
+    set_sequence_echo(source, false);
+    set_rx_checksum(source, false);
+}
 
-/// Immediate publisher for `RESPONSE_CHANNEL` (the LoRa task broadcasts here).
-pub type ResponsePublisher =
-    ImmediatePublisher<'static, CriticalSectionRawMutex, ResponseMessage, 8, 2, 1>;
+/// Subscriber to `RESPONSE_CHANNEL` (one per interface or BLE connection).
+pub type ResponseSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, ResponseMessage, 8, RESPONSE_SUBSCRIBERS, 1>;
 
 /// Command dispatcher
 ///
 /// Receives commands from the channel and dispatches them to the appropriate
 /// handler, returning responses via the appropriate response channel.
-pub struct CommandDispatcher;
+pub struct CommandDispatcher {
+    /// This device's address, stamped as the source of every frame
+    device_id: Address,
+    /// Message id stamped on the fragments of the next LoraTx
+    next_msg_id: u8,
+    /// Background RX listen window used by the LoRa task
+    rx_poll_interval_ms: u32,
+    /// Weakest RSSI (dBm) a background packet may have, set with SetRxFilter
+    min_rssi_dbm: i8,
+    /// Weakest SNR (dB) a background packet may have, set with SetRxFilter
+    min_snr_db: i8,
+    /// Receive windows used once the link goes idle, set with SetRxDutyCycle
+    /// (None = always continuous RX)
+    rx_duty_cycle: Option<RxDutyCycle>,
+    /// Radio configuration last applied, for airtime estimates
+    config: LoraConfig,
+    /// Regional band last selected with SetBand
+    band: LoraBand,
+    /// Whether transmission is allowed, set with SetRole
+    role: Role,
+    /// Whether other devices' frames are passed on, set with SetRepeater
+    repeater: bool,
+    /// Frames heard recently, to drop relayed copies and not repeat a frame
+    /// twice
+    seen: SeenFrames,
+    /// Messages delivered recently, to drop copies that arrive by another path
+    delivered: DeliveredMessages,
+    /// Copies dropped since the LoRa task last collected the count
+    duplicates: u32,
+    /// Sealed frames waiting to be repeated once the channel is quiet
+    relays: Deque<Vec<u8, { protocol::MAX_LORA_PAYLOAD }>, { fragment::MAX_FRAGMENTS }>,
+    /// Partial multi-fragment messages, shared by background RX and LoraRx
+    reassembler: Reassembler,
+    /// ACK owed to `(source, msg_id)` for a message that asked for one
+    pending_ack: Option<(Address, u8)>,
+    /// Time on air of ACKs sent while a command was running, for the runner
+    /// to charge to the duty-cycle budget
+    ack_airtime_ms: u32,
+    /// Channel hop frame heard and its sender, for the runner to act on
+    pending_hop: Option<(Address, Hop)>,
+    /// Last message ACKed, so a retransmission (after a lost ACK) is only
+    /// delivered once
+    last_acked: Option<(Address, u8)>,
+    /// Messages received while a command was listening for something else,
+    /// for the LoRa task to publish afterwards
+    deferred: Deque<Response, MAX_DEFERRED>,
+    /// Transmit stream opened by StreamBegin
+    stream: Option<Stream>,
+    /// Longest packet the peer can receive, set with SetPeerMaxPayload
+    peer_max_payload: usize,
+    /// Least time between the end of one transmission and the start of the
+    /// next, set with SetTxGap
+    tx_gap_ms: u32,
+    /// What to do when the battery runs low, set with SetLowBatteryPolicy
+    low_battery_policy: Option<LowBatteryPolicy>,
+    /// Whether the battery was below the policy's threshold at the last check
+    battery_low: bool,
+}
+
+/// Blob being sent one StreamData chunk at a time
+struct Stream {
+    /// Addressee of every chunk
+    dest: Address,
+    /// Length announced by StreamBegin
+    total: u32,
+    /// Bytes transmitted so far
+    sent: u32,
+    /// When the last StreamBegin or StreamData arrived
+    last_activity_ms: u64,
+}
+
+/// Messages held back while LoraTxReliable waits for its ACK
+const MAX_DEFERRED: usize = 2;
+
+/// Outcome of decoding one received frame
+enum Received {
+    /// Nothing to surface: undecryptable, not for us, partial or a duplicate
+    Nothing,
+    /// An ACK from `source` for our message `msg_id`
+    Ack { source: Address, msg_id: u8 },
+    /// A channel hop frame, also left for `take_hop`
+    Hop(Hop),
+    /// A complete message for this device
+    Message(Response),
+}
 
 impl CommandDispatcher {
-    /// Create a new command dispatcher
-    pub fn new() -> Self {
-        Self
+    /// Create a new command dispatcher for the device with the given id
+    pub fn new(device_id: Address) -> Self {
+        Self {
+            device_id,
+            next_msg_id: 0,
+            rx_poll_interval_ms: crate::config::rx_poll::DEFAULT_INTERVAL_MS,
+            min_rssi_dbm: crate::config::rx_filter::DEFAULT_MIN_RSSI_DBM,
+            min_snr_db: crate::config::rx_filter::DEFAULT_MIN_SNR_DB,
+            rx_duty_cycle: None,
+            config: LoraConfig::default(),
+            band: LoraBand::default(),
+            role: Role::default(),
+            repeater: false,
+            seen: SeenFrames::new(),
+            delivered: DeliveredMessages::new(),
+            duplicates: 0,
+            relays: Deque::new(),
+            reassembler: Reassembler::new(),
+            pending_ack: None,
+            ack_airtime_ms: 0,
+            pending_hop: None,
+            last_acked: None,
+            stream: None,
+            deferred: Deque::new(),
+            peer_max_payload: protocol::MAX_LORA_PAYLOAD,
+            tx_gap_ms: crate::config::tx_gap::DEFAULT_MS,
+            low_battery_policy: None,
+            battery_low: false,
+        }
+    }
+
+    /// Decode a received frame, returning an `RxPacket` response once a whole
+    /// message for this device has arrived
+    ///
+    /// Frames that do not decrypt (fail closed, counted), are addressed to
+    /// another device, are only part of a message, are ACKs or hop frames, or
+    /// repeat a message already delivered return `None`. If the message asked
+    /// for an ACK, one is left for `send_pending_ack`, and a hop frame is left
+    /// for `take_hop`.
+    pub fn accept_packet(&mut self, packet: &RxPacket, now_ms: u64) -> Option<Response> {
+        match self.receive_frame(packet, now_ms) {
+            Received::Message(response) => Some(response),
+            Received::Nothing | Received::Ack { .. } | Received::Hop(_) => None,
+        }
+    }
+
+    /// Transmit the ACK owed for the last message received, if any
+    ///
+    /// Returns its time on air, for duty-cycle accounting. A listener drops
+    /// the ACK unsent.
+    pub async fn send_pending_ack<R: LoraRadio>(&mut self, radio: &mut R) -> Option<u32> {
+        let (dest, msg_id) = self.pending_ack.take()?;
+        if self.role == Role::Listener {
+            return None;
+        }
+        let sealed = addressing::encode(dest, self.device_id, &fragment::encode_ack(msg_id))
+            .and_then(|frame| crypto::seal(&frame, &crypto::random_nonce()))?;
+        // Capped by the low-battery policy like any other transmission
+        let sent = match self.tx_power(None) {
+            Some(power_dbm) => radio.transmit_with_power(&sealed, power_dbm).await,
+            None => radio.transmit(&sealed).await,
+        };
+        if sent.is_err() {
+            crate::error!("LoRa TX: ACK failed");
+            return None;
+        }
+        Some(self.frame_airtime_ms(sealed.len()))
+    }
+
+    /// Channel hop frame heard since the last call, and who sent it
+    pub fn take_hop(&mut self) -> Option<(Address, Hop)> {
+        self.pending_hop.take()
+    }
+
+    /// Transmit a channel hop frame to `dest`
+    ///
+    /// Returns its time on air, for duty-cycle accounting. A listener sends
+    /// nothing.
+    pub async fn send_hop<R: LoraRadio>(&mut self, radio: &mut R, dest: Address, hop: Hop) -> Option<u32> {
+        if self.role == Role::Listener {
+            return None;
+        }
+        let sealed = addressing::encode(dest, self.device_id, &fragment::encode_hop(hop))
+            .and_then(|frame| crypto::seal(&frame, &crypto::random_nonce()))?;
+        let sent = match self.tx_power(None) {
+            Some(power_dbm) => radio.transmit_with_power(&sealed, power_dbm).await,
+            None => radio.transmit(&sealed).await,
+        };
+        if sent.is_err() {
+            crate::error!("LoRa TX: Hop frame failed");
+            return None;
+        }
+        Some(self.frame_airtime_ms(sealed.len()))
+    }
+
+    /// Listen for `CONFIRM_TIMEOUT_MS` for a peer to agree to move to hop
+    /// `position`: a confirmation, or its own announcement of the same hop
+    ///
+    /// Other traffic is handled as while waiting for an ACK.
+    pub async fn wait_for_hop_confirm<R: LoraRadio>(&mut self, radio: &mut R, position: u8) -> bool {
+        let deadline_ms = clock::now_ms() + crate::config::channel_hop::CONFIRM_TIMEOUT_MS as u64;
+        loop {
+            let now_ms = clock::now_ms();
+            if now_ms >= deadline_ms {
+                return false;
+            }
+            match radio.receive((deadline_ms - now_ms) as u32).await {
+                Ok(packet) => match self.receive_frame(&packet, now_ms) {
+                    Received::Hop(Hop::Confirm(p) | Hop::Announce(p)) if p == position => {
+                        self.pending_hop = None;
+                        return true;
+                    }
+                    Received::Message(response) => {
+                        if self.deferred.push_back(response).is_err() {
+                            crate::error!("LoRa RX: Deferred queue full, message dropped");
+                        }
+                    }
+                    Received::Ack { .. } | Received::Hop(_) | Received::Nothing => {}
+                },
+                Err(LoraError::Timeout) => return false,
+                // A CRC error or similar: keep listening
+                Err(_) => {}
+            }
+            self.send_ack_during_command(radio).await;
+        }
+    }
+
+    /// Transmit the ACK owed from inside a command, keeping its time on air
+    /// for `take_ack_airtime_ms`
+    async fn send_ack_during_command<R: LoraRadio>(&mut self, radio: &mut R) {
+        if let Some(airtime_ms) = self.send_pending_ack(radio).await {
+            self.ack_airtime_ms = self.ack_airtime_ms.saturating_add(airtime_ms);
+        }
+    }
+
+    /// Time on air of the ACKs sent by commands since the last call
+    ///
+    /// The runner charges only the command's own transmission up front, so it
+    /// takes this after each command and charges it too.
+    pub fn take_ack_airtime_ms(&mut self) -> u32 {
+        core::mem::take(&mut self.ack_airtime_ms)
+    }
+
+    /// Messages dropped as already delivered since the last call
+    pub fn take_duplicates(&mut self) -> u32 {
+        core::mem::take(&mut self.duplicates)
+    }
+
+    /// Next message received during a command, to publish as unsolicited
+    pub fn take_deferred(&mut self) -> Option<Response> {
+        self.deferred.pop_front()
+    }
+
+    /// Whether frames are waiting to be repeated
+    pub fn has_relays(&self) -> bool {
+        !self.relays.is_empty()
+    }
+
+    /// Next frame to repeat, sealed for transmission, and its time on air
+    pub fn take_relay(&mut self) -> Option<(Vec<u8, { protocol::MAX_LORA_PAYLOAD }>, u32)> {
+        let frame = self.relays.pop_front()?;
+        let airtime_ms = self.frame_airtime_ms(frame.len());
+        Some((frame, airtime_ms))
+    }
+
+    /// Queue another device's frame to be repeated with hop count `ttl`
+    ///
+    /// Re-sealed with a fresh nonce, since the hop count is inside the
+    /// encryption. Dropped if the queue is full.
+    fn queue_relay(&mut self, dest: Address, source: Address, ttl: u8, fragment: &[u8]) {
+        let sealed = addressing::encode_with_ttl(dest, source, ttl, fragment)
+            .and_then(|frame| crypto::seal(&frame, &crypto::random_nonce()))
+            .and_then(|sealed| Vec::from_slice(&sealed).ok());
+        let Some(sealed) = sealed else {
+            return;
+        };
+        if self.relays.push_back(sealed).is_err() {
+            crate::debug!("LoRa: Relay queue full, frame dropped");
+        }
+    }
+
+    /// Decode a received frame (see `accept_packet`)
+    fn receive_frame(&mut self, packet: &RxPacket, now_ms: u64) -> Received {
+        // GFSK packets come from legacy gear, not this firmware: they are
+        // passed up as they are, with no sender address
+        if self.config.is_fsk() {
+            let Ok(data) = Vec::from_slice(&packet.data) else {
+                return Received::Nothing;
+            };
+            let crc32 = Some(crc32::checksum(&data));
+            return Received::Message(Response::RxPacket {
+                source: addressing::UNADDRESSED,
+                data,
+                rssi: packet.rssi,
+                snr: packet.snr,
+                crc32,
+            });
+        }
+
+        let Some(frame) = crypto::open(&packet.data) else {
+            crate::trace!("LoRa RX: Dropped undecryptable frame ({} total)", crypto::decrypt_errors());
+            return Received::Nothing;
+        };
+
+        let Some((dest, source, ttl, fragment)) = addressing::decode(&frame) else {
+            return Received::Nothing;
+        };
+        // Our own frame, passed back by a repeater
+        if source == self.device_id {
+            return Received::Nothing;
+        }
+
+        // A repeater's copy of a frame already heard is dropped. A frame with
+        // the full hop count is an original and always taken, since a
+        // LoraTxReliable retry repeats its frames exactly.
+        let first_heard = repeater::frame_key(source, fragment).is_some_and(|key| self.seen.first_heard(key, now_ms));
+        if !first_heard && ttl < crate::config::repeater::DEFAULT_TTL {
+            crate::trace!("LoRa RX: Dropped relayed copy (TTL {})", ttl);
+            return Received::Nothing;
+        }
+        // Pass it on unless it was for this device alone or has used up its
+        // hops. A listener never transmits. Hop frames are only for the nodes
+        // that hear them directly.
+        let hop = fragment::decode_hop(fragment);
+        if self.repeater
+            && first_heard
+            && ttl > 0
+            && dest != self.device_id
+            && self.role != Role::Listener
+            && hop.is_none()
+        {
+            self.queue_relay(dest, source, ttl - 1, fragment);
+        }
+
+        // Ignore traffic addressed to other devices.
+        if !addressing::accepts(dest, self.device_id) {
+            return Received::Nothing;
+        }
+
+        if let Some(msg_id) = fragment::decode_ack(fragment) {
+            return Received::Ack { source, msg_id };
+        }
+        if let Some(hop) = hop {
+            self.pending_hop = Some((source, hop));
+            return Received::Hop(hop);
+        }
+
+        // Hold fragments back until the whole message has arrived.
+        let Some(data) = self.reassembler.push(source, fragment, now_ms) else {
+            return Received::Nothing;
+        };
+
+        // Only messages sent to us directly are ACKed, never broadcasts.
+        if dest == self.device_id && fragment::ack_requested(fragment) {
+            let key = (source, fragment[0]);
+            self.pending_ack = Some(key);
+            if self.last_acked == Some(key) {
+                crate::trace!("LoRa RX: Repeated message {} re-ACKed", key.1);
+                return Received::Nothing;
+            }
+            self.last_acked = Some(key);
+        }
+
+        // The same message can come in by more than one path, too far apart
+        // for the seen frames to catch
+        if !self.delivered.first_delivery((source, fragment[0]), now_ms) {
+            crate::trace!("LoRa RX: Dropped repeated message {}", fragment[0]);
+            self.duplicates = self.duplicates.wrapping_add(1);
+            return Received::Nothing;
+        }
+
+        // Log received message (show as string if valid UTF-8, else hex)
+        if let Ok(s) = core::str::from_utf8(&data) {
+            crate::trace!("LoRa RX: '{}' (RSSI: {}, SNR: {})", s, packet.rssi, packet.snr);
+        } else {
+            crate::trace!("LoRa RX: {} bytes (RSSI: {}, SNR: {})", data.len(), packet.rssi, packet.snr);
+        }
+
+        // Taken over the reassembled message here, so a host that enabled
+        // SetRxChecksum can check nothing was lost on the way to it
+        let crc32 = Some(crc32::checksum(&data));
+        Received::Message(Response::RxPacket {
+            source,
+            data,
+            rssi: packet.rssi,
+            snr: packet.snr,
+            crc32,
+        })
+    }
+
+    /// Drop partial messages whose remaining fragments never arrived
+    ///
+    /// Returns the number of messages discarded.
+    pub fn expire_partials(&mut self, now_ms: u64) -> usize {
+        self.reassembler.expire(now_ms)
+    }
+
+    /// Drop an open stream that has had no StreamData for
+    /// `IDLE_TIMEOUT_MS`, returning whether one was dropped
+    pub fn expire_stream(&mut self, now_ms: u64) -> bool {
+        let idle = self
+            .stream
+            .as_ref()
+            .is_some_and(|s| now_ms.saturating_sub(s.last_activity_ms) > crate::config::stream::IDLE_TIMEOUT_MS);
+        if idle {
+            self.stream = None;
+        }
+        idle
+    }
+
+    /// Bytes sent and announced for the open stream, if any
+    pub fn stream_progress(&self) -> Option<(u32, u32)> {
+        self.stream.as_ref().map(|s| (s.sent, s.total))
+    }
+
+    /// Time on air in ms that `command` will use, or `None` if it does not
+    /// transmit
+    ///
+    /// Counts every fragment of a LoRa message at its on-air size (address
+    /// header and encryption envelope included) under the applied config.
+    /// LoraTxReliable counts every attempt it may make. A listener refuses
+    /// every transmission, so nothing is charged to the duty-cycle budget.
+    pub fn tx_airtime_ms(&self, command: &Command) -> Option<u32> {
+        if self.role == Role::Listener {
+            return None;
+        }
+        let (data, attempts) = match command {
+            Command::LoraTx { data }
+            | Command::LoraTxAddressed { data, .. }
+            | Command::LoraTxPower { data, .. }
+            | Command::StreamData { data } => (data, 1),
+            Command::LoraTxReliable { data, .. } => (data, self.reliable_attempts(data.len()).max(1)),
+            Command::TxCarrier { duration_ms } => return Some(*duration_ms),
+            Command::LoraLoopback => return Some(self.frame_airtime_ms(loopback::PATTERN.len())),
+            _ => return None,
+        };
+
+        Some(self.message_airtime_ms(data.len()) * attempts)
+    }
+
+    /// Time on air in ms of one `len`-byte message under the applied config,
+    /// summed over its fragments
+    fn message_airtime_ms(&self, len: usize) -> u32 {
+        if self.config.is_fsk() {
+            return self.frame_airtime_ms(len);
+        }
+        let overhead = crypto::OVERHEAD + addressing::HEADER_LEN + fragment::HEADER_LEN;
+        let full = (len / fragment::MAX_CHUNK_LEN) as u32;
+        let rest = len % fragment::MAX_CHUNK_LEN;
+        let mut airtime = full * self.frame_airtime_ms(overhead + fragment::MAX_CHUNK_LEN);
+        if rest > 0 {
+            airtime += self.frame_airtime_ms(overhead + rest);
+        }
+        airtime
+    }
+
+    /// Time on air in ms of a `frame_len`-byte frame under the applied config
+    fn frame_airtime_ms(&self, frame_len: usize) -> u32 {
+        let c = &self.config;
+        let frame_len = if c.implicit_header { c.implicit_len as usize } else { frame_len };
+        lora_time_on_air_ms(c, frame_len)
+    }
+
+    /// Time on air in ms of a sealed channel hop frame
+    pub fn hop_airtime_ms(&self) -> u32 {
+        self.frame_airtime_ms(addressing::HEADER_LEN + fragment::HEADER_LEN + crypto::OVERHEAD)
+    }
+
+    /// Current background RX listen window, set by SetRxPollInterval
+    pub fn rx_poll_interval_ms(&self) -> u32 {
+        self.rx_poll_interval_ms
+    }
+
+    /// Whether `packet` is strong enough to pass the SetRxFilter thresholds
+    pub fn passes_rx_filter(&self, packet: &RxPacket) -> bool {
+        packet.rssi >= self.min_rssi_dbm as i16 && packet.snr >= self.min_snr_db
+    }
+
+    /// Least time between transmissions, set by SetTxGap
+    pub fn tx_gap_ms(&self) -> u32 {
+        self.tx_gap_ms
+    }
+
+    /// Receive windows to use while idle, if SetRxDutyCycle enabled them or
+    /// the low-battery policy calls for them
+    pub fn rx_duty_cycle(&self) -> Option<RxDutyCycle> {
+        use crate::config::battery::{LOW_RX_MS, LOW_SLEEP_MS};
+
+        let low_battery = self.low_battery().is_some_and(|policy| policy.rx_duty_cycle);
+        self.rx_duty_cycle.or(low_battery.then_some(RxDutyCycle {
+            rx_ms: LOW_RX_MS,
+            sleep_ms: LOW_SLEEP_MS,
+        }))
+    }
+
+    /// Check a battery sample against the SetLowBatteryPolicy threshold,
+    /// returning true when the battery has just run low
+    pub fn update_battery(&mut self, battery_mv: u32) -> bool {
+        let was_low = self.battery_low;
+        self.battery_low = self
+            .low_battery_policy
+            .is_some_and(|policy| policy.is_low(battery_mv, was_low));
+        self.battery_low && !was_low
+    }
+
+    /// The low-battery policy, while the battery is low
+    fn low_battery(&self) -> Option<LowBatteryPolicy> {
+        self.low_battery_policy.filter(|_| self.battery_low)
+    }
+
+    /// Power to transmit at when `requested` was asked for (None = the
+    /// configured power): capped by the low-battery policy while the battery
+    /// is low
+    pub fn tx_power(&self, requested: Option<i8>) -> Option<i8> {
+        match self.low_battery() {
+            Some(policy) => Some(requested.unwrap_or(self.config.tx_power_dbm).min(policy.tx_power_dbm)),
+            None => requested,
+        }
+    }
+
+    /// Regional band last selected with SetBand
+    pub fn band(&self) -> LoraBand {
+        self.band
+    }
+
+    /// Frequency in Hz of the applied config
+    pub fn frequency_hz(&self) -> u32 {
+        self.config.frequency_hz
+    }
+
+    /// Apply the last configuration again, after the radio was re-initialised
+    /// to its defaults
+    pub async fn restore_config<R: LoraRadio>(&mut self, radio: &mut R) -> Result<(), LoraError> {
+        radio.configure(&self.config).await
+    }
+
+    /// Retune to `frequency_hz`, keeping the rest of the applied config
+    ///
+    /// Used by channel agility; a later SetLoraConfig or SetBand overrides it.
+    pub async fn set_frequency<R: LoraRadio>(&mut self, radio: &mut R, frequency_hz: u32) -> Result<(), LoraError> {
+        let config = LoraConfig {
+            frequency_hz,
+            ..self.config.clone()
+        };
+        radio.configure(&config).await?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Role last selected with SetRole
+    pub fn role(&self) -> Role {
+        self.role
     }
 
     /// Dispatch a command and return the response
     pub async fn dispatch<R: LoraRadio>(
-        &self,
+        &mut self,
         radio: &mut R,
         command: Command,
     ) -> Response {
+        // A listener never keys the transmitter, whatever the host asks
+        if self.role == Role::Listener && transmits(&command) {
+            return Response::error(ResponseStatus::InvalidCommand, command.id());
+        }
+
         match command {
             Command::GetVersion => self.handle_get_version(),
             Command::Reboot => {
-                // Admin commands are handled by admin_task before reaching dispatcher
-                // For non-embedded (tests), return an error
+                // The interface readers send reboots to the admin task, and
+                // the runner hands off any that still arrive here
+                Response::error(ResponseStatus::InvalidCommand, command.id())
+            }
+            Command::GetStats
+            | Command::ClearStats
+            | Command::GetRecentPackets
+            | Command::SetSequenceEcho { .. }
+            | Command::SetRxChecksum { .. }
+            | Command::SetLed { .. } => {
+                // The link counters, recent packets, per-interface settings
+                // and LED channel are kept and answered by the LoRa task
+                Response::error(ResponseStatus::InvalidCommand, command.id())
+            }
+            Command::LoraTx { data } => {
+                self.handle_lora_tx(radio, addressing::BROADCAST, &data, None, CommandId::LoraTx)
+                    .await
+            }
+            Command::LoraTxAddressed { dest, data } => {
+                self.handle_lora_tx(radio, dest, &data, None, CommandId::LoraTxAddressed)
+                    .await
+            }
+            Command::LoraTxReliable { dest, data } => self.handle_lora_tx_reliable(radio, dest, &data).await,
+            Command::LoraTxPower { power_dbm, data } => {
+                self.handle_lora_tx(radio, addressing::BROADCAST, &data, Some(power_dbm), CommandId::LoraTxPower)
+                    .await
+            }
+            Command::LoraRx { timeout_ms } => self.handle_lora_rx(radio, timeout_ms).await,
+            Command::LoraLoopback => self.handle_lora_loopback(radio).await,
+            Command::StreamBegin { dest, total_len } => self.handle_stream_begin(dest, total_len),
+            Command::StreamData { data } => self.handle_stream_data(radio, &data).await,
+            Command::StreamEnd => self.handle_stream_end(),
+            Command::SetBleName { name } => self.handle_set_ble_name(&name),
+            Command::SetLabel { label } => self.handle_set_label(&label),
+            Command::GetLabel => Response::Label {
+                label: crate::label::get(),
+            },
+            Command::GetCrashLog { offset } => self.handle_get_crash_log(offset),
+            Command::Ping { data } => Response::Pong { data },
+            Command::GetDeviceId => Response::DeviceId { id: self.device_id },
+            Command::GetUptime => Response::Uptime {
+                millis: Instant::now().as_millis(),
+            },
+            Command::GetResetReason => Response::ResetReason {
+                code: crate::reset_reason::code(),
+            },
+            Command::Sync { nonce } => Response::SyncAck { nonce },
+            Command::GetProtocolVersions => Response::ProtocolVersions {
+                current: wt_protocol::PROTOCOL_VERSION,
+                min_supported: version::MIN_SUPPORTED_VERSION,
+            },
+            Command::GetCapabilities => Response::Capabilities {
+                bitmap: capabilities(),
+            },
+            Command::Sleep { duration_ms } => self.handle_sleep(radio, duration_ms).await,
+            Command::TxCarrier { duration_ms } => self.handle_tx_carrier(radio, duration_ms).await,
+            Command::SetRxPollInterval { interval_ms } => self.handle_set_rx_poll_interval(interval_ms),
+            Command::SetPeerMaxPayload { max_payload } => self.handle_set_peer_max_payload(max_payload),
+            Command::SetTxGap { gap_ms } => self.handle_set_tx_gap(gap_ms),
+            Command::SetLowBatteryPolicy {
+                threshold_mv,
+                tx_power_dbm,
+                rx_duty_cycle,
+            } => self.handle_set_low_battery_policy(threshold_mv, tx_power_dbm, rx_duty_cycle),
+            Command::SetModulation {
+                fsk,
+                bitrate_bps,
+                deviation_hz,
+                rx_bandwidth_hz,
+                gaussian_bt,
+            } => {
+                let modulation = if fsk {
+                    Modulation::Fsk(FskParams {
+                        bitrate_bps,
+                        deviation_hz,
+                        rx_bandwidth_hz,
+                        gaussian_bt,
+                    })
+                } else {
+                    Modulation::Lora
+                };
+                self.handle_set_modulation(radio, modulation).await
+            }
+            Command::SetRxFilter { min_rssi_dbm, min_snr_db } => {
+                self.min_rssi_dbm = min_rssi_dbm;
+                self.min_snr_db = min_snr_db;
+                Response::Ack {
+                    command: CommandId::SetRxFilter,
+                }
+            }
+            Command::SetRxDutyCycle { rx_ms, sleep_ms } => self.handle_set_rx_duty_cycle(rx_ms, sleep_ms),
+            Command::SetRepeater { enabled } => {
+                self.repeater = enabled;
+                Response::Ack {
+                    command: CommandId::SetRepeater,
+                }
+            }
+            Command::SetBeaconInterval { interval_secs } => self.handle_set_beacon_interval(interval_secs),
+            Command::SetHeartbeatInterval { interval_secs } => self.handle_set_heartbeat_interval(interval_secs),
+            Command::SetLogLevel { level } => self.handle_set_log_level(level),
+            Command::GetRssi => match radio.read_instant_rssi().await {
+                Ok(dbm) => Response::Rssi { dbm },
+                Err(e) => self.lora_error_to_response(e, CommandId::GetRssi),
+            },
+            Command::GetLastPacketStatus => match radio.last_packet_status() {
+                Some(status) => Response::LastPacketStatus {
+                    rssi: status.rssi,
+                    signal_rssi: status.signal_rssi,
+                    snr: status.snr,
+                    freq_error_hz: status.freq_error_hz,
+                },
+                // Nothing received since boot
+                None => Response::error(ResponseStatus::Timeout, CommandId::GetLastPacketStatus),
+            },
+            Command::SpectrumScan { start_hz, step_hz, count } => {
+                self.handle_spectrum_scan(radio, start_hz, step_hz, count).await
+            }
+            Command::SelfTest => match radio.self_test().await {
+                Ok(flags) => Response::SelfTest { flags },
+                Err(e) => self.lora_error_to_response(e, CommandId::SelfTest),
+            },
+            Command::GetRadioState => match radio.get_state().await {
+                Ok(state) => Response::RadioState { state: state as u8 },
+                Err(e) => self.lora_error_to_response(e, CommandId::GetRadioState),
+            },
+            Command::GetRandom => match radio.random_u32().await {
+                Ok(value) => Response::Random { value },
+                Err(e) => self.lora_error_to_response(e, CommandId::GetRandom),
+            },
+            Command::GetTemperature => match radio.read_temperature().await {
+                Ok(celsius) => Response::Temperature { celsius },
+                Err(e) => self.lora_error_to_response(e, CommandId::GetTemperature),
+            },
+            Command::SetBand { band } => self.handle_set_band(radio, band).await,
+            Command::SetRole { role } => self.handle_set_role(role),
+            Command::VerifyFirmware => self.handle_verify_firmware().await,
+            Command::EstimateAirtime { len } => self.handle_estimate_airtime(len),
+            #[cfg(feature = "debug-commands")]
+            Command::ReadReg { addr } => match radio.read_register(addr).await {
+                Ok(value) => Response::Register { value },
+                Err(e) => self.lora_error_to_response(e, CommandId::ReadReg),
+            },
+            #[cfg(feature = "debug-commands")]
+            Command::WriteReg { addr, value } => match radio.write_register(addr, value).await {
+                Ok(()) => Response::Ack {
+                    command: CommandId::WriteReg,
+                },
+                Err(e) => self.lora_error_to_response(e, CommandId::WriteReg),
+            },
+            #[cfg(not(feature = "debug-commands"))]
+            Command::ReadReg { .. } | Command::WriteReg { .. } => {
+                // Raw register access is only built with the debug-commands feature
                 Response::error(ResponseStatus::InvalidCommand, command.id())
             }
-            Command::LoraTx { data } => self.handle_lora_tx(radio, &data).await,
+            Command::SetLoraConfig {
+                frequency_hz,
+                spreading_factor,
+                bandwidth_khz,
+                coding_rate,
+                tx_power_dbm,
+                preamble_symbols,
+                sync_word,
+                crc_enabled,
+                iq_inverted,
+                ocp_ma,
+                ramp_time,
+                standby_xosc,
+            } => {
+                let config = LoraConfig {
+                    frequency_hz,
+                    spreading_factor,
+                    bandwidth_khz: bandwidth_khz.into(),
+                    coding_rate,
+                    tx_power_dbm,
+                    preamble_symbols,
+                    sync_word,
+                    crc_enabled,
+                    iq_inverted,
+                    ocp_ma,
+                    ramp_time,
+                    standby_mode: if standby_xosc { StandbyMode::Xosc } else { StandbyMode::Rc },
+                    // Header mode is a build-time setting, not part of the
+                    // command, and the command always configures LoRa
+                    ..LoraConfig::default()
+                };
+                self.handle_set_lora_config(radio, &config).await
+            }
         }
     }
 
     /// Handle GetVersion command
     fn handle_get_version(&self) -> Response {
-        crate::debug!("Version requested. Responding {}.{}.{}", protocol::VERSION_MAJOR, protocol::VERSION_MINOR, protocol::VERSION_PATCH);
+        crate::trace!("Version requested. Responding {}.{}.{}", protocol::VERSION_MAJOR, protocol::VERSION_MINOR, protocol::VERSION_PATCH);
         Response::Version {
             major: protocol::VERSION_MAJOR,
             minor: protocol::VERSION_MINOR,
             patch: protocol::VERSION_PATCH,
+            max_payload: protocol::MAX_LORA_PAYLOAD as u16,
+            // Short enough to always fit
+            build: Vec::from_slice(protocol::BUILD_ID.as_bytes()).unwrap_or_default(),
         }
     }
 
-    /// Handle LoraTx command
-    async fn handle_lora_tx<R: LoraRadio>(&self, radio: &mut R, data: &[u8]) -> Response {
-        match radio.transmit(data).await {
-            Ok(()) => Response::TxComplete,
-            Err(e) => self.lora_error_to_response(e, Command::LoraTx {
-                data: heapless::Vec::new(),
-            }),
+    /// Handle SetBleName command
+    ///
+    /// The BLE task re-advertises under the new name (dropping any current
+    /// connection). Saved in flash, so it survives a reboot.
+    fn handle_set_ble_name(&self, name: &[u8]) -> Response {
+        match crate::ble::name::set_custom_name(name) {
+            Ok(()) => Response::Ack {
+                command: CommandId::SetBleName,
+            },
+            Err(NameError::Invalid) => Response::error(ResponseStatus::InvalidLength, CommandId::SetBleName),
+            Err(NameError::Busy) => Response::error(ResponseStatus::Busy, CommandId::SetBleName),
+            // The flash is unavailable or the write failed
+            Err(NameError::Storage) => Response::error(ResponseStatus::InvalidCommand, CommandId::SetBleName),
         }
     }
 
-    /// Convert a LoRa error to a response
-    fn lora_error_to_response(&self, error: LoraError, command: Command) -> Response {
-        let status = match error {
-            LoraError::Timeout => ResponseStatus::Timeout,
-            _ => ResponseStatus::LoraError,
-        };
-        Response::error(status, command.id())
+    /// Handle GetCrashLog command: the next chunk of log text from `offset`,
+    /// empty once the host has read to the end
+    fn handle_get_crash_log(&self, offset: u32) -> Response {
+        let mut chunk = [0u8; crate::config::crash_log::CHUNK_LEN];
+        match crate::crash_log::read(offset, &mut chunk) {
+            Some(len) => Response::CrashLog {
+                offset,
+                data: Vec::from_slice(&chunk[..len]).unwrap_or_default(),
+            },
+            // The flash is lent to a running VerifyFirmware
+            None => Response::error(ResponseStatus::Busy, CommandId::GetCrashLog),
+        }
     }
-}
 
-impl Default for CommandDispatcher {
-    fn default() -> Self {
-        Self::new()
+    /// Handle SetLabel command
+    ///
+    /// Stored in flash, so the label survives a reboot. An empty label
+    /// clears it.
+    fn handle_set_label(&self, label: &[u8]) -> Response {
+        match crate::label::set(label) {
+            Ok(()) => Response::Ack {
+                command: CommandId::SetLabel,
+            },
+            Err(LabelError::Invalid) => Response::error(ResponseStatus::InvalidLength, CommandId::SetLabel),
+            Err(LabelError::Busy) => Response::error(ResponseStatus::Busy, CommandId::SetLabel),
+            // The flash is unavailable or the write failed
+            Err(LabelError::Storage) => Response::error(ResponseStatus::InvalidCommand, CommandId::SetLabel),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::lora::traits::mock::MockLoraRadio;
-    use heapless::Vec;
+    /// Handle SetLogLevel command
+    ///
+    /// Takes effect for the next message logged. Held in RAM only.
+    fn handle_set_log_level(&self, level: u8) -> Response {
+        match crate::debug::LogLevel::try_from(level) {
+            Ok(level) => {
+                crate::debug::set_log_level(level);
+                Response::Ack {
+                    command: CommandId::SetLogLevel,
+                }
+            }
+            Err(_) => Response::error(ResponseStatus::InvalidLength, CommandId::SetLogLevel),
+        }
+    }
 
-    #[test]
-    fn test_dispatch_get_version() {
-        let dispatcher = CommandDispatcher::new();
-        let mut radio = MockLoraRadio::new();
+    /// Handle SetLoraConfig command
+    ///
+    /// The new parameters apply from the next transmit/receive. Not persisted,
+    /// so the defaults return on reboot.
+    async fn handle_set_lora_config<R: LoraRadio>(&mut self, radio: &mut R, config: &LoraConfig) -> Response {
+        if let Err(e) = config.validate() {
+            return self.lora_error_to_response(e, CommandId::SetLoraConfig);
+        }
+        match radio.configure(config).await {
+            Ok(()) => {
+                self.config = config.clone();
+                Response::Ack {
+                    command: CommandId::SetLoraConfig,
+                }
+            }
+            Err(e) => self.lora_error_to_response(e, CommandId::SetLoraConfig),
+        }
+    }
 
-        futures::executor::block_on(async {
-            let response = dispatcher.dispatch(&mut radio, Command::GetVersion).await;
+    /// Handle SetModulation command
+    ///
+    /// Switches between LoRa and GFSK, keeping the frequency, power and the
+    /// rest of the applied config. In GFSK mode messages are sent and
+    /// received raw (see `send_message` and `receive_frame`). Held in RAM
+    /// only; SetLoraConfig also returns the radio to LoRa.
+    async fn handle_set_modulation<R: LoraRadio>(&mut self, radio: &mut R, modulation: Modulation) -> Response {
+        let config = LoraConfig {
+            modulation,
+            ..self.config.clone()
+        };
+        match radio.configure(&config).await {
+            Ok(()) => {
+                self.config = config;
+                Response::Ack {
+                    command: CommandId::SetModulation,
+                }
+            }
+            Err(e) => self.lora_error_to_response(e, CommandId::SetModulation),
+        }
+    }
 
-            match response {
-                Response::Version {
-                    major,
-                    minor,
-                    patch,
-                } => {
-                    assert_eq!(major, protocol::VERSION_MAJOR);
-                    assert_eq!(minor, protocol::VERSION_MINOR);
-                    assert_eq!(patch, protocol::VERSION_PATCH);
+    /// Handle SetBand command
+    ///
+    /// Applies the band's default channel and sync word on top of the current
+    /// modulation settings. The LoRa task then enforces the band's duty-cycle
+    /// limit. Held in RAM only, like SetLoraConfig.
+    async fn handle_set_band<R: LoraRadio>(&mut self, radio: &mut R, band: u8) -> Response {
+        let Ok(band) = LoraBand::try_from(band) else {
+            return self.lora_error_to_response(LoraError::InvalidConfig, CommandId::SetBand);
+        };
+        let config = LoraConfig {
+            frequency_hz: band.frequency_hz(),
+            sync_word: band.sync_word(),
+            ..self.config.clone()
+        };
+        match radio.configure(&config).await {
+            Ok(()) => {
+                self.config = config;
+                self.band = band;
+                Response::Ack {
+                    command: CommandId::SetBand,
                 }
-                _ => panic!("Expected Version response"),
             }
-        });
+            Err(e) => self.lora_error_to_response(e, CommandId::SetBand),
+        }
     }
 
-    #[test]
-    fn test_dispatch_lora_tx() {
-        let dispatcher = CommandDispatcher::new();
-        let mut radio = MockLoraRadio::new();
+    /// Handle SetRole command
+    ///
+    /// A listener refuses LoraTx, LoraTxReliable and TxCarrier and does not
+    /// ACK, but keeps receiving and forwarding everything addressed to it.
+    /// Held in RAM only, so a reboot returns to transceiver.
+    fn handle_set_role(&mut self, role: u8) -> Response {
+        let Ok(role) = Role::try_from(role) else {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetRole);
+        };
+        self.role = role;
+        Response::Ack {
+            command: CommandId::SetRole,
+        }
+    }
 
-        futures::executor::block_on(async {
-            radio.init().await.unwrap();
+    /// Handle VerifyFirmware command
+    ///
+    /// Read only, so safe on a live device. Reading the whole partition takes
+    /// a noticeable time, during which the LoRa task handles nothing else.
+    async fn handle_verify_firmware(&self) -> Response {
+        match crate::firmware::image_crc32().await {
+            Some(crc) => Response::FirmwareCrc { crc },
+            // The flash is unavailable or a read failed
+            None => Response::error(ResponseStatus::InvalidCommand, CommandId::VerifyFirmware),
+        }
+    }
 
-            let mut data = Vec::new();
-            data.extend_from_slice(&[0x48, 0x65, 0x6C, 0x6C, 0x6F])
-                .unwrap();
+    /// Handle EstimateAirtime command
+    ///
+    /// Estimates a LoraTx of `len` bytes under the applied config, counting
+    /// every fragment with its address header and encryption envelope, as the
+    /// duty-cycle budget does.
+    fn handle_estimate_airtime(&self, len: u16) -> Response {
+        let len = len as usize;
+        if len == 0 || len > protocol::MAX_MESSAGE_SIZE {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::EstimateAirtime);
+        }
+        Response::Airtime {
+            airtime_ms: self.message_airtime_ms(len),
+        }
+    }
 
-            let response = dispatcher
-                .dispatch(&mut radio, Command::LoraTx { data: data.clone() })
-                .await;
+    /// Handle SetRxPollInterval command
+    ///
+    /// Commands cancel the RX wait as soon as they arrive, so this does not
+    /// change command latency. It trades how often RX is re-armed against how
+    /// promptly stale partial messages are expired. Held in RAM only.
+    fn handle_set_rx_poll_interval(&mut self, interval_ms: u32) -> Response {
+        use crate::config::rx_poll::{MAX_INTERVAL_MS, MIN_INTERVAL_MS};
 
-            assert!(matches!(response, Response::TxComplete));
+        if !(MIN_INTERVAL_MS..=MAX_INTERVAL_MS).contains(&interval_ms) {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetRxPollInterval);
+        }
+        self.rx_poll_interval_ms = interval_ms;
+        Response::Ack {
+            command: CommandId::SetRxPollInterval,
+        }
+    }
 
-            // Verify the data was transmitted
-            let history = radio.get_tx_history();
-            assert_eq!(history.len(), 1);
-            assert_eq!(history[0].as_slice(), data.as_slice());
-        });
+    /// Handle SetPeerMaxPayload command
+    ///
+    /// `max_payload` is the peer's `MAX_LORA_PAYLOAD`, as its GetVersion
+    /// reports. It must be large enough for the shortest message and no more
+    /// than ours. Held in RAM only.
+    fn handle_set_peer_max_payload(&mut self, max_payload: u16) -> Response {
+        let max_payload = usize::from(max_payload);
+        if !(fragment::packet_len(1)..=protocol::MAX_LORA_PAYLOAD).contains(&max_payload) {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetPeerMaxPayload);
+        }
+        self.peer_max_payload = max_payload;
+        Response::Ack {
+            command: CommandId::SetPeerMaxPayload,
+        }
+    }
+
+    /// Handle SetTxGap command
+    ///
+    /// The LoRa task holds each transmitting command until `gap_ms` has
+    /// passed since the last transmission ended, so a slow receiver can
+    /// re-arm between packets. `0` sends back-to-back. Held in RAM only.
+    fn handle_set_tx_gap(&mut self, gap_ms: u32) -> Response {
+        if gap_ms > crate::config::tx_gap::MAX_MS {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetTxGap);
+        }
+        self.tx_gap_ms = gap_ms;
+        Response::Ack {
+            command: CommandId::SetTxGap,
+        }
+    }
+
+    /// Handle SetLowBatteryPolicy command
+    ///
+    /// Below `threshold_mv` the LoRa task announces a LowBattery event, every
+    /// message goes out at no more than `tx_power_dbm`, and with
+    /// `rx_duty_cycle` the radio drops to duty-cycled RX once idle. A
+    /// `threshold_mv` of 0 turns the policy off. Held in RAM only.
+    fn handle_set_low_battery_policy(&mut self, threshold_mv: u16, tx_power_dbm: i8, rx_duty_cycle: bool) -> Response {
+        if threshold_mv == 0 {
+            self.low_battery_policy = None;
+            self.battery_low = false;
+        } else {
+            if !(MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&tx_power_dbm) {
+                return Response::error(ResponseStatus::InvalidLength, CommandId::SetLowBatteryPolicy);
+            }
+            self.low_battery_policy = Some(LowBatteryPolicy {
+                threshold_mv,
+                tx_power_dbm,
+                rx_duty_cycle,
+            });
+        }
+        Response::Ack {
+            command: CommandId::SetLowBatteryPolicy,
+        }
+    }
+
+    /// Handle SetBeaconInterval command
+    ///
+    /// `0` stops beaconing. Beacons go through the normal TX path, so they are
+    /// charged against the duty-cycle budget. Held in RAM only, so beacons are
+    /// off again after a reboot.
+    fn handle_set_beacon_interval(&self, interval_secs: u32) -> Response {
+        use crate::config::beacon::{MAX_INTERVAL_SECS, MIN_INTERVAL_SECS};
+
+        if interval_secs != 0 && !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&interval_secs) {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetBeaconInterval);
+        }
+        BEACON_INTERVAL.signal(interval_secs);
+        Response::Ack {
+            command: CommandId::SetBeaconInterval,
+        }
+    }
+
+    /// Handle SetHeartbeatInterval command
+    ///
+    /// `0` stops the heartbeat. Held in RAM only, so it is off again after a
+    /// reboot.
+    fn handle_set_heartbeat_interval(&self, interval_secs: u32) -> Response {
+        use crate::config::heartbeat::{MAX_INTERVAL_SECS, MIN_INTERVAL_SECS};
+
+        if interval_secs != 0 && !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&interval_secs) {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SetHeartbeatInterval);
+        }
+        HEARTBEAT_INTERVAL.signal(interval_secs);
+        Response::Ack {
+            command: CommandId::SetHeartbeatInterval,
+        }
+    }
+
+    /// Handle SetRxDutyCycle command
+    ///
+    /// Only records the windows; the LoRa task applies them once the link
+    /// has been idle for `config::rx_duty_cycle::IDLE_SECS`. `rx_ms` of 0
+    /// keeps the radio in continuous RX.
+    fn handle_set_rx_duty_cycle(&mut self, rx_ms: u32, sleep_ms: u32) -> Response {
+        if rx_ms == 0 {
+            self.rx_duty_cycle = None;
+        } else {
+            let windows = RxDutyCycle { rx_ms, sleep_ms };
+            if windows.validate().is_err() {
+                return Response::error(ResponseStatus::InvalidLength, CommandId::SetRxDutyCycle);
+            }
+            self.rx_duty_cycle = Some(windows);
+        }
+        Response::Ack {
+            command: CommandId::SetRxDutyCycle,
+        }
+    }
+
+    /// Handle SpectrumScan command
+    ///
+    /// Reads the instantaneous RSSI on `count` channels from `start_hz`,
+    /// `step_hz` apart, for a site survey. Every channel must be one the
+    /// SX1262 can tune to. Background RX is off the configured channel for
+    /// the few ms the sweep takes, so a packet sent meanwhile is missed.
+    async fn handle_spectrum_scan<R: LoraRadio>(
+        &self,
+        radio: &mut R,
+        start_hz: u32,
+        step_hz: u32,
+        count: u8,
+    ) -> Response {
+        use crate::config::spectrum_scan::{MAX_FREQ_HZ, MAX_STEPS, MIN_FREQ_HZ};
+
+        let count = usize::from(count);
+        let end_hz = start_hz as u64 + step_hz as u64 * count.saturating_sub(1) as u64;
+        if !(1..=MAX_STEPS).contains(&count) || start_hz < MIN_FREQ_HZ || end_hz > MAX_FREQ_HZ as u64 {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::SpectrumScan);
+        }
+
+        let mut rssi = [0i16; MAX_STEPS];
+        match radio.scan_rssi(start_hz, step_hz, &mut rssi[..count]).await {
+            Ok(()) => Response::SpectrumScan {
+                start_hz,
+                step_hz,
+                rssi: Vec::from_slice(&rssi[..count]).unwrap_or_default(),
+            },
+            Err(e) => self.lora_error_to_response(e, CommandId::SpectrumScan),
+        }
+    }
+
+    /// Handle TxCarrier command
+    ///
+    /// Keys an unmodulated carrier for antenna tuning. Blocks the LoRa task
+    /// (and so other commands) for the duration, which is capped at
+    /// `MAX_CARRIER_MS`.
+    async fn handle_tx_carrier<R: LoraRadio>(&self, radio: &mut R, duration_ms: u32) -> Response {
+        if duration_ms == 0 || duration_ms > crate::config::lora_defaults::MAX_CARRIER_MS {
+            return self.lora_error_to_response(LoraError::InvalidConfig, CommandId::TxCarrier);
+        }
+        match radio.transmit_carrier(duration_ms).await {
+            Ok(()) => Response::Ack {
+                command: CommandId::TxCarrier,
+            },
+            Err(e) => self.lora_error_to_response(e, CommandId::TxCarrier),
+        }
+    }
+
+    /// Handle Sleep command
+    ///
+    /// Only the radio side: a timed sleep puts the SX1262 into cold-start sleep,
+    /// while `duration_ms == 0` (wake on LoRa) leaves it in continuous RX so a
+    /// packet can raise DIO1. The LoRa task then hands the MCU over to the
+    /// admin task for light sleep and wakes the radio afterwards.
+    async fn handle_sleep<R: LoraRadio>(&self, radio: &mut R, duration_ms: u32) -> Response {
+        if duration_ms > 0 {
+            if let Err(e) = radio.sleep(SleepMode::Cold).await {
+                return self.lora_error_to_response(e, CommandId::Sleep);
+            }
+        }
+        Response::Ack {
+            command: CommandId::Sleep,
+        }
+    }
+
+    /// Handle LoraTx / LoraTxAddressed commands
+    ///
+    /// The message is split into fragments (see `lora::fragment`) sent back to
+    /// back; a message that fits in one frame is sent as a single fragment.
+    /// Each fragment is addressed to `dest` and sealed with a fresh nonce
+    /// before transmission. The TxComplete echoes the message length; its
+    /// `seq` is stamped by the LoRa task, which holds the envelope.
+    async fn handle_lora_tx<R: LoraRadio>(
+        &mut self,
+        radio: &mut R,
+        dest: Address,
+        data: &[u8],
+        power_dbm: Option<i8>,
+        command_id: CommandId,
+    ) -> Response {
+        if data.is_empty()
+            || data.len() > protocol::MAX_MESSAGE_SIZE
+            || !self.fits_peer(data)
+            || !self.fits_budget(data)
+        {
+            return Response::error(ResponseStatus::InvalidLength, command_id);
+        }
+        // Reject before sending anything, so a message is never half-sent
+        if power_dbm.is_some_and(|p| !(MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&p)) {
+            return self.lora_error_to_response(LoraError::InvalidConfig, command_id);
+        }
+
+        let msg_id = self.take_msg_id();
+        match self.send_message(radio, dest, msg_id, data, power_dbm, 0).await {
+            Ok(()) => Response::TxComplete {
+                seq: 0,
+                len: data.len() as u16,
+            },
+            Err(e) => self.lora_error_to_response(e, command_id),
+        }
+    }
+
+    /// Handle StreamBegin command
+    ///
+    /// Opens a stream of `total_len` bytes to `dest`, replacing any stream
+    /// already open. Nothing is buffered: each StreamData is sent as it
+    /// arrives.
+    fn handle_stream_begin(&mut self, dest: Address, total_len: u32) -> Response {
+        if total_len == 0 {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::StreamBegin);
+        }
+        self.stream = Some(Stream {
+            dest,
+            total: total_len,
+            sent: 0,
+            last_activity_ms: clock::now_ms(),
+        });
+        Response::Ack {
+            command: CommandId::StreamBegin,
+        }
+    }
+
+    /// Handle StreamData command
+    ///
+    /// Sends the chunk as one LoRa message to the stream's addressee. A chunk
+    /// that fails (or is refused for duty cycle before reaching here) is not
+    /// counted, so the host can resend it.
+    async fn handle_stream_data<R: LoraRadio>(&mut self, radio: &mut R, data: &[u8]) -> Response {
+        let Some(stream) = self.stream.as_mut() else {
+            return Response::error(ResponseStatus::InvalidCommand, CommandId::StreamData);
+        };
+        stream.last_activity_ms = clock::now_ms();
+        if data.len() as u32 > stream.total - stream.sent {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::StreamData);
+        }
+        let dest = stream.dest;
+
+        let response = self.handle_lora_tx(radio, dest, data, None, CommandId::StreamData).await;
+        if let (Response::TxComplete { .. }, Some(stream)) = (&response, self.stream.as_mut()) {
+            stream.sent += data.len() as u32;
+        }
+        response
+    }
+
+    /// Handle StreamEnd command
+    ///
+    /// Closes the stream, answering `InvalidLength` if fewer bytes were sent
+    /// than StreamBegin announced.
+    fn handle_stream_end(&mut self) -> Response {
+        match self.stream.take() {
+            None => Response::error(ResponseStatus::InvalidCommand, CommandId::StreamEnd),
+            Some(stream) if stream.sent < stream.total => {
+                Response::error(ResponseStatus::InvalidLength, CommandId::StreamEnd)
+            }
+            Some(_) => Response::Ack {
+                command: CommandId::StreamEnd,
+            },
+        }
+    }
+
+    /// Handle LoraTxReliable command
+    ///
+    /// Sends the message with `ACK_REQUESTED` set and waits up to
+    /// `ACK_TIMEOUT_MS` for the addressee's ACK, retransmitting up to
+    /// `MAX_RETRIES` times, or fewer if a slow config would take them past
+    /// `COMMAND_BUDGET_MS`. Only a single addressee can ACK, so broadcast is
+    /// rejected. Messages for this device that arrive while waiting are
+    /// deferred, not lost.
+    async fn handle_lora_tx_reliable<R: LoraRadio>(&mut self, radio: &mut R, dest: Address, data: &[u8]) -> Response {
+        if data.is_empty() || data.len() > protocol::MAX_MESSAGE_SIZE {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::LoraTxReliable);
+        }
+        if dest == addressing::BROADCAST {
+            return self.lora_error_to_response(LoraError::InvalidConfig, CommandId::LoraTxReliable);
+        }
+        // Raw GFSK packets carry nothing to ACK
+        if self.config.is_fsk() {
+            return Response::error(ResponseStatus::InvalidCommand, CommandId::LoraTxReliable);
+        }
+        let attempts = self.reliable_attempts(data.len());
+        if !self.fits_peer(data) || attempts == 0 {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::LoraTxReliable);
+        }
+
+        let msg_id = self.take_msg_id();
+        for attempt in 0..attempts {
+            if let Err(e) = self
+                .send_message(radio, dest, msg_id, data, None, fragment::ACK_REQUESTED)
+                .await
+            {
+                return self.lora_error_to_response(e, CommandId::LoraTxReliable);
+            }
+            if self.wait_for_ack(radio, dest, msg_id).await {
+                return Response::TxComplete {
+                    seq: 0,
+                    len: data.len() as u16,
+                };
+            }
+            crate::trace!("LoRa TX: No ACK for message {} (attempt {})", msg_id, attempt + 1);
+        }
+        Response::error(ResponseStatus::Timeout, CommandId::LoraTxReliable)
+    }
+
+    /// Listen for `dest`'s ACK of `msg_id` until `ACK_TIMEOUT_MS` has passed
+    ///
+    /// Other traffic is still handled: messages are deferred and any ACK they
+    /// ask for is sent.
+    async fn wait_for_ack<R: LoraRadio>(&mut self, radio: &mut R, dest: Address, msg_id: u8) -> bool {
+        let deadline_ms = clock::now_ms() + crate::config::reliable::ACK_TIMEOUT_MS as u64;
+        loop {
+            let now_ms = clock::now_ms();
+            if now_ms >= deadline_ms {
+                return false;
+            }
+            match radio.receive((deadline_ms - now_ms) as u32).await {
+                Ok(packet) => match self.receive_frame(&packet, now_ms) {
+                    Received::Ack { source, msg_id: acked } if source == dest && acked == msg_id => return true,
+                    Received::Message(response) => {
+                        if self.deferred.push_back(response).is_err() {
+                            crate::error!("LoRa RX: Deferred queue full, message dropped");
+                        }
+                    }
+                    Received::Ack { .. } | Received::Hop(_) | Received::Nothing => {}
+                },
+                Err(LoraError::Timeout) => return false,
+                // A CRC error or similar: keep listening
+                Err(_) => {}
+            }
+            self.send_ack_during_command(radio).await;
+        }
+    }
+
+    /// Allocate the message id for the next outgoing message
+    fn take_msg_id(&mut self) -> u8 {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        msg_id
+    }
+
+    /// Fragment, address, seal and transmit a message
+    ///
+    /// `flags` is ORed into every fragment's `frag_total` (see
+    /// `fragment::ACK_REQUESTED`). In GFSK mode the message is sent raw.
+    async fn send_message<R: LoraRadio>(
+        &mut self,
+        radio: &mut R,
+        dest: Address,
+        msg_id: u8,
+        data: &[u8],
+        power_dbm: Option<i8>,
+        flags: u8,
+    ) -> Result<(), LoraError> {
+        let power_dbm = self.tx_power(power_dbm);
+        // Legacy GFSK gear knows nothing of our addresses, fragments or
+        // encryption, so the message goes out as it is, in one packet
+        if self.config.is_fsk() {
+            return match power_dbm {
+                Some(power_dbm) => radio.transmit_with_power(data, power_dbm).await,
+                None => radio.transmit(data).await,
+            };
+        }
+        let frag_total = fragment::fragment_count(data.len()) as u8 | flags;
+        for (frag_index, chunk) in data.chunks(fragment::MAX_CHUNK_LEN).enumerate() {
+            let fragment = fragment::encode_fragment(msg_id, frag_index as u8, frag_total, chunk);
+            // Fragments are sized to leave room for the address header and the
+            // encryption envelope, so this always fits.
+            let sealed = addressing::encode(dest, self.device_id, &fragment)
+                .and_then(|frame| crypto::seal(&frame, &crypto::random_nonce()))
+                .ok_or(LoraError::InvalidConfig)?;
+            match power_dbm {
+                Some(power_dbm) => radio.transmit_with_power(&sealed, power_dbm).await?,
+                None => radio.transmit(&sealed).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether every packet carrying `data` fits the peer's receive buffer
+    /// (see SetPeerMaxPayload)
+    fn fits_peer(&self, data: &[u8]) -> bool {
+        fragment::packet_len(data.len()) <= self.peer_max_payload
+    }
+
+    /// Whether `data` can be sent within `COMMAND_BUDGET_MS` under the applied
+    /// config. The LoRa task does not pulse the watchdog while it transmits.
+    fn fits_budget(&self, data: &[u8]) -> bool {
+        self.message_airtime_ms(data.len()) <= watchdog::COMMAND_BUDGET_MS
+    }
+
+    /// Attempts LoraTxReliable makes at a message of `len` bytes: the first
+    /// and `MAX_RETRIES` more, or only as many as fit in `COMMAND_BUDGET_MS`
+    /// with their ACK waits. 0 if not even one does.
+    fn reliable_attempts(&self, len: usize) -> u32 {
+        use crate::config::reliable::{ACK_TIMEOUT_MS, MAX_RETRIES};
+
+        let attempt_ms = self.message_airtime_ms(len) + ACK_TIMEOUT_MS;
+        (watchdog::COMMAND_BUDGET_MS / attempt_ms).min(1 + MAX_RETRIES as u32)
+    }
+
+    /// Handle LoraRx command
+    ///
+    /// A single bounded receive. The LoRa task runs commands in place of its
+    /// background listen, so the two never drain the radio at the same time; a
+    /// message completed here goes to the requester only, not to every
+    /// interface. Frames that are not a complete message for this device
+    /// (other addresses, or a fragment of a longer message, which is kept for
+    /// reassembly) are not returned and the command times out.
+    async fn handle_lora_rx<R: LoraRadio>(&mut self, radio: &mut R, timeout_ms: u32) -> Response {
+        if timeout_ms > crate::config::lora_defaults::MAX_RX_TIMEOUT_MS {
+            return Response::error(ResponseStatus::InvalidLength, CommandId::LoraRx);
+        }
+        let packet = match radio.receive(timeout_ms).await {
+            Ok(packet) => packet,
+            Err(e) => return self.lora_error_to_response(e, CommandId::LoraRx),
+        };
+        let response = self.accept_packet(&packet, clock::now_ms());
+        self.send_ack_during_command(radio).await;
+        response.unwrap_or_else(|| Response::error(ResponseStatus::Timeout, CommandId::LoraRx))
+    }
+
+    /// Handle LoraLoopback command
+    ///
+    /// Sends `loopback::PATTERN` as one raw packet, with no address header or
+    /// encryption, then listens for it to come back until `TIMEOUT_MS` has
+    /// passed. The radio cannot hear its own transmission, so a fixture has
+    /// to return it, e.g. a second radio echoing what it hears. Other traffic
+    /// is handled as while waiting for an ACK.
+    async fn handle_lora_loopback<R: LoraRadio>(&mut self, radio: &mut R) -> Response {
+        let sent = match self.tx_power(None) {
+            Some(power_dbm) => radio.transmit_with_power(&loopback::PATTERN, power_dbm).await,
+            None => radio.transmit(&loopback::PATTERN).await,
+        };
+        if let Err(e) = sent {
+            return self.lora_error_to_response(e, CommandId::LoraLoopback);
+        }
+
+        let deadline_ms = clock::now_ms() + loopback::TIMEOUT_MS as u64;
+        loop {
+            let now_ms = clock::now_ms();
+            if now_ms >= deadline_ms {
+                break;
+            }
+            match radio.receive((deadline_ms - now_ms) as u32).await {
+                Ok(packet) if packet.data == loopback::PATTERN => {
+                    return Response::Loopback {
+                        passed: true,
+                        rssi: packet.rssi,
+                        snr: packet.snr,
+                    };
+                }
+                Ok(packet) => {
+                    if let Received::Message(response) = self.receive_frame(&packet, now_ms) {
+                        if self.deferred.push_back(response).is_err() {
+                            crate::error!("LoRa RX: Deferred queue full, message dropped");
+                        }
+                    }
+                }
+                Err(LoraError::Timeout) => break,
+                // A CRC error or similar: keep listening
+                Err(_) => {}
+            }
+            self.send_ack_during_command(radio).await;
+        }
+        Response::Loopback {
+            passed: false,
+            rssi: 0,
+            snr: 0,
+        }
+    }
+
+    /// Convert a LoRa error to a response
+    ///
+    /// Each variant has its own status so a host can tell a wiring fault
+    /// (SPI, BUSY line) from a rejected parameter or a bad packet.
+    /// `LoraError` itself now only means an invalid configuration.
+    fn lora_error_to_response(&self, error: LoraError, command_id: CommandId) -> Response {
+        Response::error(lora_error_status(error), command_id)
+    }
+}
+
+/// Response status reporting `error`
+pub fn lora_error_status(error: LoraError) -> ResponseStatus {
+    match error {
+        LoraError::Timeout => ResponseStatus::Timeout,
+        LoraError::InvalidConfig => ResponseStatus::LoraError,
+        LoraError::CrcError => ResponseStatus::RadioCrcError,
+        LoraError::TransmitFailed => ResponseStatus::TransmitFailed,
+        LoraError::ReceiveFailed => ResponseStatus::ReceiveFailed,
+        LoraError::BusyTimeout => ResponseStatus::RadioBusy,
+        LoraError::SpiError => ResponseStatus::SpiError,
+        LoraError::NotInitialised => ResponseStatus::NotInitialised,
+        LoraError::Unsupported => ResponseStatus::Unsupported,
+    }
+}
+
+/// Bitmap of the command IDs this build accepts: bit `id % 8` of byte
+/// `id / 8` is set for each one
+///
+/// Derived from `CommandId::from_byte`, the same table the parser uses, so a
+/// new command shows up here without touching this function.
+pub fn capabilities() -> [u8; 32] {
+    let mut bitmap = [0u8; 32];
+    for byte in 0..=u8::MAX {
+        let Some(id) = CommandId::from_byte(byte) else {
+            continue;
+        };
+        // Raw register access parses everywhere but only runs in debug builds
+        if !cfg!(feature = "debug-commands") && matches!(id, CommandId::ReadReg | CommandId::WriteReg) {
+            continue;
+        }
+        bitmap[usize::from(byte / 8)] |= 1 << (byte % 8);
+    }
+    bitmap
+}
+
+/// Whether `command` keys the transmitter
+fn transmits(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::LoraTx { .. }
+            | Command::LoraTxAddressed { .. }
+            | Command::LoraTxReliable { .. }
+            | Command::LoraTxPower { .. }
+            | Command::StreamData { .. }
+            | Command::TxCarrier { .. }
+            | Command::LoraLoopback
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lora::airtime;
+    use crate::lora::traits::mock::MockLoraRadio;
+    use crate::lora::traits::{self_test, RadioState};
+    use heapless::Vec;
+
+    const DEVICE_ID: Address = [0x12, 0x34, 0x56];
+
+    /// Strip the encryption envelope and address header from a sent frame.
+    fn open_frame(sealed: &[u8]) -> (Address, Address, std::vec::Vec<u8>) {
+        let frame = crypto::open(sealed).expect("frame decrypts");
+        let (dest, source, _, fragment) = addressing::decode(&frame).expect("frame is addressed");
+        (dest, source, fragment.to_vec())
+    }
+
+    #[test]
+    fn test_dispatch_get_version() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::GetVersion).await;
+
+            match response {
+                Response::Version {
+                    major,
+                    minor,
+                    patch,
+                    max_payload,
+                    build,
+                } => {
+                    assert_eq!(major, protocol::VERSION_MAJOR);
+                    assert_eq!(minor, protocol::VERSION_MINOR);
+                    assert_eq!(patch, protocol::VERSION_PATCH);
+                    assert_eq!(usize::from(max_payload), protocol::MAX_LORA_PAYLOAD);
+                    assert_eq!(build.as_slice(), protocol::BUILD_ID.as_bytes());
+                }
+                _ => panic!("Expected Version response"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0x48, 0x65, 0x6C, 0x6C, 0x6F])
+                .unwrap();
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTx { data: data.clone() })
+                .await;
+
+            assert!(matches!(response, Response::TxComplete { len: 5, .. }));
+
+            // Verify the data was transmitted as a single sealed fragment
+            let history = radio.get_tx_history();
+            assert_eq!(history.len(), 1);
+            let (dest, source, frame) = open_frame(&history[0]);
+            assert_eq!(dest, addressing::BROADCAST);
+            assert_eq!(source, DEVICE_ID);
+            assert_eq!(&frame[..fragment::HEADER_LEN], &[0, 0, 1]);
+            assert_eq!(&frame[fragment::HEADER_LEN..], data.as_slice());
+
+            // Nothing to send is a length problem, not a radio one
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTx { data: Vec::new() })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(radio.get_tx_history().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_fragments_long_message() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            let mut data = Vec::new();
+            for i in 0..600u16 {
+                data.push(i as u8).unwrap();
+            }
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTx { data: data.clone() })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+
+            let history = radio.get_tx_history();
+            assert_eq!(history.len(), 3);
+
+            let mut reassembler = fragment::Reassembler::new();
+            let mut message = None;
+            for sealed in history.iter() {
+                let (_, source, frame) = open_frame(sealed);
+                message = reassembler.push(source, &frame, 0);
+            }
+            let message = message.expect("message complete");
+            assert_eq!(message.as_slice(), data.as_slice());
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_power() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"near").unwrap();
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxPower { power_dbm: 2, data: data.clone() })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTx { data: data.clone() })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+            assert_eq!(radio.get_tx_power_history().as_slice(), &[Some(2), None]);
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxPower { power_dbm: 23, data })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::LoraError, .. }));
+            assert_eq!(radio.get_tx_history().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_addressed() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            let dest = [0xAB, 0xCD, 0xEF];
+            let mut data = Vec::new();
+            data.extend_from_slice(b"Hi").unwrap();
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxAddressed { dest, data })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+
+            let history = radio.get_tx_history();
+            let (sent_dest, source, _) = open_frame(&history[0]);
+            assert_eq!(sent_dest, dest);
+            assert_eq!(source, DEVICE_ID);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_ble_name() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            // A valid name is only applied once it is saved, and the host
+            // tests have no flash
+            let mut name = Vec::new();
+            name.extend_from_slice(b"Base Camp").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBleName { name })
+                .await;
+            assert!(matches!(
+                response,
+                Response::Error { status: ResponseStatus::Busy, .. }
+            ));
+            assert_eq!(crate::ble::name::custom_name(), None);
+            assert_eq!(crate::ble::name::parse(b"Base Camp").as_deref(), Ok("Base Camp"));
+
+            let mut name = Vec::new();
+            name.extend_from_slice(b"bad\nname").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBleName { name })
+                .await;
+            match response {
+                Response::Error { status, .. } => {
+                    assert_eq!(status, ResponseStatus::InvalidLength);
+                }
+                _ => panic!("Expected Error response"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_ping_echoes_without_radio() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::Ping { data: data.clone() })
+                .await;
+
+            match response {
+                Response::Pong { data: echoed } => assert_eq!(echoed, data),
+                _ => panic!("Expected Pong response"),
+            }
+            assert!(radio.get_tx_history().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_dispatch_sleep_puts_radio_to_sleep() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::Sleep { duration_ms: 5_000 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::Sleep }));
+            assert_eq!(radio.sleep_mode(), Some(SleepMode::Cold));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_sleep_wake_on_lora_keeps_radio_listening() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::Sleep { duration_ms: 0 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::Sleep }));
+            assert_eq!(radio.sleep_mode(), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_lora_config() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(
+                    &mut radio,
+                    Command::SetLoraConfig {
+                        frequency_hz: 868_100_000,
+                        spreading_factor: 9,
+                        bandwidth_khz: 125,
+                        coding_rate: 5,
+                        tx_power_dbm: 14,
+                        preamble_symbols: 16,
+                        sync_word: 0x3444,
+                        crc_enabled: false,
+                        iq_inverted: true,
+                        ocp_ma: 100,
+                        ramp_time: 0x07,
+                        standby_xosc: true,
+                    },
+                )
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetLoraConfig }));
+
+            let config = radio.get_config().expect("radio configured");
+            assert_eq!(config.frequency_hz, 868_100_000);
+            assert_eq!(config.spreading_factor, 9);
+            assert_eq!(config.preamble_symbols, 16);
+            assert_eq!(config.sync_word, 0x3444);
+            assert!(!config.crc_enabled);
+            assert!(config.iq_inverted);
+            assert_eq!(config.ocp_ma, 100);
+            assert_eq!(config.ramp_time, 0x07);
+            assert_eq!(config.standby_mode, StandbyMode::Xosc);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_lora_config_rejects_invalid() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(
+                    &mut radio,
+                    Command::SetLoraConfig {
+                        frequency_hz: 868_100_000,
+                        spreading_factor: 13,
+                        bandwidth_khz: 125,
+                        coding_rate: 5,
+                        tx_power_dbm: 14,
+                        preamble_symbols: 8,
+                        sync_word: 0x1424,
+                        crc_enabled: true,
+                        iq_inverted: false,
+                        ocp_ma: 140,
+                        ramp_time: 0x04,
+                        standby_xosc: false,
+                    },
+                )
+                .await;
+            match response {
+                Response::Error { status, .. } => assert_eq!(status, ResponseStatus::LoraError),
+                _ => panic!("Expected Error response"),
+            }
+
+            // An over-current limit no PA setup needs
+            let response = dispatcher
+                .dispatch(
+                    &mut radio,
+                    Command::SetLoraConfig {
+                        frequency_hz: 868_100_000,
+                        spreading_factor: 9,
+                        bandwidth_khz: 125,
+                        coding_rate: 5,
+                        tx_power_dbm: 14,
+                        preamble_symbols: 8,
+                        sync_word: 0x1424,
+                        crc_enabled: true,
+                        iq_inverted: false,
+                        ocp_ma: 241,
+                        ramp_time: 0x04,
+                        standby_xosc: false,
+                    },
+                )
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::LoraError, .. }));
+            assert!(radio.get_config().is_none());
+        });
+    }
+
+    #[test]
+    fn test_tx_airtime_follows_applied_config() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"Hello").unwrap();
+        let command = Command::LoraTx { data };
+        let frame_len = crypto::OVERHEAD + addressing::HEADER_LEN + fragment::HEADER_LEN + 5;
+
+        let defaults = LoraConfig::default();
+        let expected = airtime::time_on_air_ms(
+            defaults.spreading_factor,
+            defaults.bandwidth_khz,
+            defaults.coding_rate,
+            defaults.preamble_symbols,
+            false,
+            true,
+            frame_len,
+        );
+        assert_eq!(dispatcher.tx_airtime_ms(&command), Some(expected));
+        assert_eq!(dispatcher.tx_airtime_ms(&Command::GetVersion), None);
+        assert_eq!(dispatcher.tx_airtime_ms(&Command::TxCarrier { duration_ms: 250 }), Some(250));
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(
+                    &mut radio,
+                    Command::SetLoraConfig {
+                        frequency_hz: 869_525_000,
+                        spreading_factor: 7,
+                        bandwidth_khz: 500,
+                        coding_rate: 5,
+                        tx_power_dbm: 14,
+                        preamble_symbols: 8,
+                        sync_word: 0x1424,
+                        crc_enabled: true,
+                        iq_inverted: false,
+                        ocp_ma: 140,
+                        ramp_time: 0x04,
+                        standby_xosc: false,
+                    },
+                )
+                .await;
+            assert!(matches!(response, Response::Ack { .. }));
+        });
+        let fast = airtime::time_on_air_ms(7, 500, 5, 8, false, true, frame_len);
+        assert_eq!(dispatcher.tx_airtime_ms(&command), Some(fast));
+        assert!(fast < expected);
+    }
+
+    #[test]
+    fn test_dispatch_estimate_airtime() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&[0x55; 300]).unwrap();
+        let expected = dispatcher.tx_airtime_ms(&Command::LoraTx { data }).unwrap();
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::EstimateAirtime { len: 300 }).await;
+            let Response::Airtime { airtime_ms } = response else {
+                panic!("Expected Airtime response");
+            };
+            assert_eq!(airtime_ms, expected);
+            assert!(radio.get_tx_history().is_empty());
+
+            for len in [0, protocol::MAX_MESSAGE_SIZE as u16 + 1] {
+                let response = dispatcher.dispatch(&mut radio, Command::EstimateAirtime { len }).await;
+                assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_device_id() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::GetDeviceId).await;
+            assert!(matches!(response, Response::DeviceId { id: DEVICE_ID }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_uptime() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let Response::Uptime { millis: first } = dispatcher.dispatch(&mut radio, Command::GetUptime).await else {
+                panic!("Expected Uptime response");
+            };
+            let Response::Uptime { millis: second } = dispatcher.dispatch(&mut radio, Command::GetUptime).await else {
+                panic!("Expected Uptime response");
+            };
+            assert!(second >= first);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_reset_reason() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        crate::reset_reason::set(crate::reset_reason::BROWNOUT);
+        let response = futures::executor::block_on(dispatcher.dispatch(&mut radio, Command::GetResetReason));
+        assert!(matches!(response, Response::ResetReason { code: crate::reset_reason::BROWNOUT }));
+    }
+
+    #[test]
+    fn test_dispatch_get_protocol_versions() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        let response = futures::executor::block_on(dispatcher.dispatch(&mut radio, Command::GetProtocolVersions));
+        let Response::ProtocolVersions { current, min_supported } = response else {
+            panic!("Expected ProtocolVersions response");
+        };
+        assert_eq!(current, wt_protocol::PROTOCOL_VERSION);
+        assert!(min_supported <= current);
+    }
+
+    #[test]
+    fn test_dispatch_get_capabilities() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        let response = futures::executor::block_on(dispatcher.dispatch(&mut radio, Command::GetCapabilities));
+        let Response::Capabilities { bitmap } = response else {
+            panic!("Expected Capabilities response");
+        };
+        let supported = |id: u8| bitmap[usize::from(id / 8)] & (1 << (id % 8)) != 0;
+
+        for byte in 0..=u8::MAX {
+            match CommandId::from_byte(byte) {
+                Some(CommandId::ReadReg | CommandId::WriteReg) => {
+                    assert_eq!(supported(byte), cfg!(feature = "debug-commands"), "{:#04x}", byte)
+                }
+                Some(id) => {
+                    assert_eq!(id as u8, byte);
+                    assert!(supported(byte), "{:?} missing from capabilities", id);
+                }
+                None => assert!(!supported(byte), "{:#04x} is not a command", byte),
+            }
+        }
+
+        // Every variant, so a command the parser forgets is caught here too
+        let all = [
+            CommandId::GetVersion,
+            CommandId::Reboot,
+            CommandId::GetStats,
+            CommandId::Ping,
+            CommandId::Sleep,
+            CommandId::GetDeviceId,
+            CommandId::GetUptime,
+            CommandId::Sync,
+            CommandId::GetProtocolVersions,
+            CommandId::GetCapabilities,
+            CommandId::LoraTx,
+            CommandId::LoraRx,
+            CommandId::LoraTxAddressed,
+            CommandId::LoraTxPower,
+            CommandId::LoraTxReliable,
+            CommandId::StreamBegin,
+            CommandId::StreamData,
+            CommandId::StreamEnd,
+            CommandId::SetLoraConfig,
+            CommandId::GetTemperature,
+            CommandId::TxCarrier,
+            CommandId::GetRssi,
+            CommandId::SetLogLevel,
+            CommandId::SelfTest,
+            CommandId::ClearStats,
+            CommandId::SetBand,
+            CommandId::EstimateAirtime,
+            CommandId::SetRole,
+            CommandId::VerifyFirmware,
+            CommandId::GetRandom,
+            CommandId::SetRxFilter,
+            CommandId::SetHeartbeatInterval,
+            CommandId::GetRecentPackets,
+            CommandId::SetLabel,
+            CommandId::GetLabel,
+            CommandId::SetLed,
+            CommandId::GetCrashLog,
+            CommandId::SetRepeater,
+            CommandId::SpectrumScan,
+            CommandId::SetBleName,
+            CommandId::SetRxPollInterval,
+            CommandId::SetBeaconInterval,
+            CommandId::SetRxDutyCycle,
+            CommandId::SetSequenceEcho,
+            CommandId::SetRxChecksum,
+            CommandId::GetLastPacketStatus,
+            CommandId::SetPeerMaxPayload,
+            CommandId::SetTxGap,
+            CommandId::GetResetReason,
+            CommandId::SetModulation,
+            CommandId::SetLowBatteryPolicy,
+            CommandId::GetRadioState,
+            CommandId::LoraLoopback,
+        ];
+        for id in all {
+            assert!(supported(id as u8), "{:?} missing from capabilities", id);
+        }
+    }
+
+    #[test]
+    fn test_response_subscribers_cover_every_interface() {
+        let channel = ResponseChannel::new();
+        let _serial = channel.subscriber().expect("a slot for the serial writer");
+        let mut ble: Vec<_, { crate::config::ble::MAX_CONNECTIONS }> = Vec::new();
+        for _ in 0..crate::config::ble::MAX_CONNECTIONS {
+            assert!(ble.push(channel.subscriber().expect("a slot for every BLE connection")).is_ok());
+        }
+        #[cfg(feature = "wifi")]
+        let _wifi = channel.subscriber().expect("a slot for the WiFi client");
+
+        // Every slot is taken, so one more connection would be refused
+        assert!(channel.subscriber().is_err());
+        // A disconnect gives its slot back for the next connection
+        ble.pop();
+        assert!(channel.subscriber().is_ok());
+    }
+
+    #[test]
+    fn test_response_messages_reach_only_their_interface() {
+        let reply = || ResponseMessage::Command {
+            source: CommandSource::WiFi,
+            sequence_id: 7,
+            host_sequence: None,
+            response: Response::Pong { data: Vec::new() },
+        };
+        assert!(matches!(reply().for_interface(CommandSource::WiFi), Some((Response::Pong { .. }, Trailer::None))));
+        assert!(reply().for_interface(CommandSource::Serial).is_none());
+        assert!(reply().for_interface(CommandSource::Ble(0)).is_none());
+
+        let event = ResponseMessage::Unsolicited(Response::Pong { data: Vec::new() });
+        assert!(matches!(event.for_interface(CommandSource::Serial), Some((_, Trailer::None))));
+    }
+
+    #[test]
+    fn test_ble_responses_reach_only_their_slot() {
+        let reply = || ResponseMessage::Command {
+            source: CommandSource::Ble(2),
+            sequence_id: 9,
+            host_sequence: None,
+            response: Response::Pong { data: Vec::new() },
+        };
+        set_sequence_echo(CommandSource::Ble(2), true);
+        assert!(matches!(
+            reply().for_interface(CommandSource::Ble(2)),
+            Some((Response::Pong { .. }, Trailer::Sequence(9)))
+        ));
+        assert!(reply().for_interface(CommandSource::Ble(0)).is_none());
+        assert!(reply().for_interface(CommandSource::Serial).is_none());
+
+        // Every slot gets unsolicited messages, without a sequence id
+        for slot in 0..3 {
+            let event = ResponseMessage::Unsolicited(Response::Pong { data: Vec::new() });
+            assert!(matches!(event.for_interface(CommandSource::Ble(slot)), Some((_, Trailer::None))));
+        }
+        reset_interface(CommandSource::Ble(2));
+        assert!(matches!(reply().for_interface(CommandSource::Ble(2)), Some((_, Trailer::None))));
+    }
+
+    #[test]
+    fn test_host_sequence_is_echoed_with_the_device_sequence() {
+        let envelope = CommandEnvelope {
+            command: Command::GetVersion,
+            source: CommandSource::Ble(3),
+            sequence_id: 41,
+            host_sequence: Some(0xBEEF),
+        };
+        let reply = || ResponseMessage::reply(&envelope, Response::Pong { data: Vec::new() });
+        let expected = Trailer::HostSequence {
+            host: 0xBEEF,
+            device: 41,
+        };
+        assert!(matches!(reply().for_interface(CommandSource::Ble(3)), Some((_, trailer)) if trailer == expected));
+        // It takes the place of the SetSequenceEcho trailer
+        set_sequence_echo(CommandSource::Ble(3), true);
+        assert!(matches!(reply().for_interface(CommandSource::Ble(3)), Some((_, trailer)) if trailer == expected));
+        assert!(reply().for_interface(CommandSource::Serial).is_none());
+        reset_interface(CommandSource::Ble(3));
+
+        // A command without one is answered as before
+        assert_eq!(response_trailer(CommandSource::Ble(3), 41, None), Trailer::None);
+    }
+
+    #[test]
+    fn test_rx_checksum_reaches_only_interfaces_that_enabled_it() {
+        let checksum = |source| {
+            let packet = ResponseMessage::Unsolicited(Response::RxPacket {
+                source: [0xAB, 0xCD, 0xEF],
+                data: Vec::from_slice(b"hi").unwrap(),
+                rssi: -60,
+                snr: 7,
+                crc32: Some(crc32::checksum(b"hi")),
+            });
+            match packet.for_interface(source) {
+                Some((Response::RxPacket { crc32, .. }, _)) => crc32,
+                other => panic!("Expected RxPacket, got {:?}", other),
+            }
+        };
+
+        set_rx_checksum(CommandSource::Ble(1), true);
+        assert_eq!(checksum(CommandSource::Ble(1)), Some(crc32::checksum(b"hi")));
+        assert_eq!(checksum(CommandSource::Ble(0)), None);
+
+        // A dropped client's setting does not carry over to the next one
+        reset_interface(CommandSource::Ble(1));
+        assert_eq!(checksum(CommandSource::Ble(1)), None);
+    }
+
+    #[test]
+    fn test_dispatch_sync_echoes_nonce() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::Sync { nonce: 0xDEAD_BEEF }).await;
+            assert!(matches!(response, Response::SyncAck { nonce: 0xDEAD_BEEF }));
+            assert!(radio.get_tx_history().is_empty());
+        });
+    }
+
+    #[cfg(feature = "debug-commands")]
+    #[test]
+    fn test_dispatch_register_access() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::WriteReg { addr: 0x08E7, value: 0x18 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::WriteReg }));
+            assert_eq!(radio.register(0x08E7), Some(0x18));
+
+            let response = dispatcher.dispatch(&mut radio, Command::ReadReg { addr: 0x08E7 }).await;
+            assert!(matches!(response, Response::Register { value: 0x18 }));
+        });
+    }
+
+    #[cfg(not(feature = "debug-commands"))]
+    #[test]
+    fn test_dispatch_register_access_disabled() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::WriteReg { addr: 0x08E7, value: 0x18 })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+            assert_eq!(radio.register(0x08E7), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_self_test() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let Response::SelfTest { flags } = dispatcher.dispatch(&mut radio, Command::SelfTest).await else {
+                panic!("Expected SelfTest response");
+            };
+            assert_eq!(flags, self_test::ALL);
+
+            // A radio with a stuck TCXO still answers, with its bit cleared
+            radio.set_self_test_flags(self_test::SPI_OK | self_test::RADIO_RESPONSIVE);
+            let Response::SelfTest { flags } = dispatcher.dispatch(&mut radio, Command::SelfTest).await else {
+                panic!("Expected SelfTest response");
+            };
+            assert_eq!(flags, self_test::SPI_OK | self_test::RADIO_RESPONSIVE);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_radio_state() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::GetRadioState).await;
+            assert!(matches!(response, Response::RadioState { state } if state == RadioState::Rx as u8));
+
+            radio.sleep(SleepMode::Warm).await.unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::GetRadioState).await;
+            assert!(matches!(response, Response::RadioState { state } if state == RadioState::Sleep as u8));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_loopback() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            // Nothing sends the pattern back
+            let response = dispatcher.dispatch(&mut radio, Command::LoraLoopback).await;
+            assert!(matches!(response, Response::Loopback { passed: false, .. }));
+            assert_eq!(radio.get_tx_history()[0].as_slice(), &loopback::PATTERN);
+
+            // A fixture returns it, and its link quality is reported
+            radio.set_loopback(Some((-42, 9)));
+            let response = dispatcher.dispatch(&mut radio, Command::LoraLoopback).await;
+            assert!(matches!(response, Response::Loopback { passed: true, rssi: -42, snr: 9 }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_random() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::GetRandom).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::NotInitialised, .. }));
+
+            radio.init().await.unwrap();
+            radio.set_random(0x0123_4567);
+            let response = dispatcher.dispatch(&mut radio, Command::GetRandom).await;
+            assert!(matches!(response, Response::Random { value: 0x0123_4567 }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_temperature() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        radio.set_temperature(-7);
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::GetTemperature).await;
+            assert!(matches!(response, Response::Temperature { celsius: -7 }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_rx_poll_interval() {
+        use crate::config::rx_poll::{DEFAULT_INTERVAL_MS, MAX_INTERVAL_MS, MIN_INTERVAL_MS};
+
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        assert_eq!(dispatcher.rx_poll_interval_ms(), DEFAULT_INTERVAL_MS);
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRxPollInterval { interval_ms: MIN_INTERVAL_MS })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetRxPollInterval }));
+            assert_eq!(dispatcher.rx_poll_interval_ms(), MIN_INTERVAL_MS);
+
+            for interval_ms in [MIN_INTERVAL_MS - 1, MAX_INTERVAL_MS + 1] {
+                let response = dispatcher
+                    .dispatch(&mut radio, Command::SetRxPollInterval { interval_ms })
+                    .await;
+                assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            }
+            assert_eq!(dispatcher.rx_poll_interval_ms(), MIN_INTERVAL_MS);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_beacon_interval() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBeaconInterval { interval_secs: 60 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetBeaconInterval }));
+            assert_eq!(BEACON_INTERVAL.try_take(), Some(60));
+
+            // 0 disables
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBeaconInterval { interval_secs: 0 })
+                .await;
+            assert!(matches!(response, Response::Ack { .. }));
+            assert_eq!(BEACON_INTERVAL.try_take(), Some(0));
+
+            // Too frequent
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBeaconInterval { interval_secs: 1 })
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+            assert_eq!(BEACON_INTERVAL.try_take(), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_heartbeat_interval() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetHeartbeatInterval { interval_secs: 5 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetHeartbeatInterval }));
+            assert_eq!(HEARTBEAT_INTERVAL.try_take(), Some(5));
+
+            // 0 disables
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetHeartbeatInterval { interval_secs: 0 })
+                .await;
+            assert!(matches!(response, Response::Ack { .. }));
+            assert_eq!(HEARTBEAT_INTERVAL.try_take(), Some(0));
+
+            // Longer than an hour
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetHeartbeatInterval { interval_secs: 3_601 })
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+            assert_eq!(HEARTBEAT_INTERVAL.try_take(), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_rx_duty_cycle() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRxDutyCycle { rx_ms: 10, sleep_ms: 40 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetRxDutyCycle }));
+            assert_eq!(dispatcher.rx_duty_cycle(), Some(RxDutyCycle { rx_ms: 10, sleep_ms: 40 }));
+
+            // A sleep window longer than the radio can time is refused
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRxDutyCycle { rx_ms: 10, sleep_ms: 300_000 })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(dispatcher.rx_duty_cycle(), Some(RxDutyCycle { rx_ms: 10, sleep_ms: 40 }));
+
+            // 0 ms listening turns it off
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRxDutyCycle { rx_ms: 0, sleep_ms: 0 })
+                .await;
+            assert!(matches!(response, Response::Ack { .. }));
+            assert_eq!(dispatcher.rx_duty_cycle(), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_log_level() {
+        use crate::debug::{self, LogLevel};
+
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetLogLevel { level: LogLevel::Error as u8 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetLogLevel }));
+            assert!(debug::enabled(LogLevel::Error));
+            assert!(!debug::enabled(LogLevel::Info));
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetLogLevel { level: 4 })
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+            assert!(!debug::enabled(LogLevel::Info));
+
+            debug::set_log_level(LogLevel::Info);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_rssi() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        radio.set_instant_rssi(-97);
+
+        futures::executor::block_on(async {
+            let response = dispatcher.dispatch(&mut radio, Command::GetRssi).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::NotInitialised, .. }));
+
+            radio.init().await.unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::GetRssi).await;
+            assert!(matches!(response, Response::Rssi { dbm: -97 }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_spectrum_scan() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            radio.set_instant_rssi(-100);
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SpectrumScan { start_hz: 868_000_000, step_hz: 200_000, count: 3 })
+                .await;
+            let Response::SpectrumScan { start_hz, step_hz, rssi } = response else {
+                panic!("Expected SpectrumScan response, got {:?}", response);
+            };
+            assert_eq!((start_hz, step_hz), (868_000_000, 200_000));
+            assert_eq!(rssi.as_slice(), &[-100, -101, -102]);
+
+            // No channels, too many, or a sweep that runs off the top of the band
+            for (start_hz, step_hz, count) in [
+                (868_000_000, 200_000, 0),
+                (868_000_000, 200_000, crate::config::spectrum_scan::MAX_STEPS as u8 + 1),
+                (959_000_000, 1_000_000, 3),
+            ] {
+                let response = dispatcher
+                    .dispatch(&mut radio, Command::SpectrumScan { start_hz, step_hz, count })
+                    .await;
+                assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_tx_carrier() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::TxCarrier { duration_ms: 2_000 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::TxCarrier }));
+            assert_eq!(radio.carrier_requested(), Some(2_000));
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::TxCarrier { duration_ms: u32::MAX })
+                .await;
+            assert!(matches!(response, Response::Error { .. }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_band() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetBand { band: LoraBand::Us915 as u8 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetBand }));
+            assert_eq!(radio.get_config().unwrap().frequency_hz, 915_000_000);
+            assert_eq!(dispatcher.band(), LoraBand::Us915);
+
+            // Unknown bands leave the radio on the last one
+            let response = dispatcher.dispatch(&mut radio, Command::SetBand { band: 9 }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::LoraError, .. }));
+            assert_eq!(dispatcher.band(), LoraBand::Us915);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_role_listener_never_transmits() {
+        const PEER: Address = [0xAB, 0xCD, 0xEF];
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRole { role: Role::Listener as u8 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetRole }));
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"hello").unwrap();
+            let command = Command::LoraTx { data };
+            assert_eq!(dispatcher.tx_airtime_ms(&command), None);
+            let response = dispatcher.dispatch(&mut radio, command).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+            let response = dispatcher
+                .dispatch(&mut radio, Command::TxCarrier { duration_ms: 100 })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+            assert_eq!(radio.carrier_requested(), None);
+
+            // Messages asking for an ACK are still delivered, unACKed
+            let packet = rx_packet(DEVICE_ID, PEER, &fragment::encode_fragment(0, 0, 1 | fragment::ACK_REQUESTED, b"hi"));
+            assert!(dispatcher.accept_packet(&packet, 0).is_some());
+            assert_eq!(dispatcher.send_pending_ack(&mut radio).await, None);
+            assert!(radio.get_tx_history().is_empty());
+
+            // Back to transceiver, and unknown roles are refused
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetRole { role: Role::Transceiver as u8 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetRole }));
+            let response = dispatcher.dispatch(&mut radio, Command::SetRole { role: 7 }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(dispatcher.role(), Role::Transceiver);
+        });
+    }
+
+    /// Seal a single-fragment message from `source` to `dest` as received
+    fn rx_frame(dest: Address, source: Address, data: &[u8]) -> RxPacket {
+        rx_packet(dest, source, &fragment::encode_fragment(0, 0, 1, data))
+    }
+
+    /// Seal a fragment (or ACK) from `source` to `dest` as received
+    fn rx_packet(dest: Address, source: Address, fragment: &[u8]) -> RxPacket {
+        rx_relayed(dest, source, crate::config::repeater::DEFAULT_TTL, fragment)
+    }
+
+    /// Seal a fragment from `source` to `dest` as received with hop count `ttl`
+    fn rx_relayed(dest: Address, source: Address, ttl: u8, fragment: &[u8]) -> RxPacket {
+        let frame = addressing::encode_with_ttl(dest, source, ttl, fragment).unwrap();
+        let sealed = crypto::seal(&frame, &[0x5A; crypto::NONCE_LEN]).unwrap();
+        let mut packet_data = Vec::new();
+        packet_data.extend_from_slice(&sealed).unwrap();
+        RxPacket {
+            data: packet_data,
+            rssi: -60,
+            snr: 7,
+        }
+    }
+
+    #[test]
+    fn test_dispatch_set_repeater() {
+        use crate::config::repeater::DEFAULT_TTL;
+        const PEER: Address = [0xAB, 0xCD, 0xEF];
+        const OTHER: Address = [0x99, 0x99, 0x99];
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let message = |msg_id| fragment::encode_fragment(msg_id, 0, 1, b"hi");
+
+        // Off by default
+        assert!(dispatcher.accept_packet(&rx_packet(OTHER, PEER, &message(0)), 0).is_none());
+        assert!(!dispatcher.has_relays());
+
+        let response = futures::executor::block_on(dispatcher.dispatch(&mut radio, Command::SetRepeater { enabled: true }));
+        assert!(matches!(response, Response::Ack { command: CommandId::SetRepeater }));
+
+        // Frames for another device or broadcast are passed on with one hop
+        // less; a broadcast is still delivered here
+        assert!(dispatcher.accept_packet(&rx_packet(OTHER, PEER, &message(1)), 0).is_none());
+        assert!(dispatcher.accept_packet(&rx_packet(addressing::BROADCAST, PEER, &message(2)), 0).is_some());
+        for (dest, msg_id) in [(OTHER, 1), (addressing::BROADCAST, 2)] {
+            let (sealed, airtime_ms) = dispatcher.take_relay().expect("frame queued for relay");
+            assert!(airtime_ms > 0);
+            let frame = crypto::open(&sealed).unwrap();
+            let (relay_dest, source, ttl, fragment) = addressing::decode(&frame).unwrap();
+            assert_eq!((relay_dest, source, ttl), (dest, PEER, DEFAULT_TTL - 1));
+            assert_eq!(fragment, message(msg_id).as_slice());
+        }
+
+        // Not frames for this device alone, with no hops left, or heard before
+        assert!(dispatcher.accept_packet(&rx_packet(DEVICE_ID, PEER, &message(3)), 0).is_some());
+        assert!(dispatcher.accept_packet(&rx_relayed(OTHER, PEER, 0, &message(4)), 0).is_none());
+        assert!(dispatcher.accept_packet(&rx_relayed(OTHER, PEER, 1, &message(1)), 10).is_none());
+        assert!(!dispatcher.has_relays());
+
+        // Another repeater's copy of a message already delivered is dropped,
+        // even once its frame is forgotten
+        use crate::config::repeater::SEEN_WINDOW_MS;
+        assert!(dispatcher.accept_packet(&rx_relayed(addressing::BROADCAST, PEER, 1, &message(2)), 10).is_none());
+        assert_eq!(dispatcher.take_duplicates(), 0);
+        let late = SEEN_WINDOW_MS + 10;
+        assert!(dispatcher.accept_packet(&rx_relayed(addressing::BROADCAST, PEER, 1, &message(2)), late).is_none());
+        assert_eq!(dispatcher.take_duplicates(), 1);
+        assert_eq!(dispatcher.take_duplicates(), 0);
+        assert!(dispatcher.take_relay().is_some());
+        // As are this device's own frames passed back
+        assert!(dispatcher.accept_packet(&rx_relayed(addressing::BROADCAST, DEVICE_ID, 1, &message(5)), 10).is_none());
+        assert!(!dispatcher.has_relays());
+    }
+
+    #[test]
+    fn test_dispatch_set_peer_max_payload() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            for max_payload in [fragment::packet_len(1) - 1, protocol::MAX_LORA_PAYLOAD + 1] {
+                let response = dispatcher
+                    .dispatch(&mut radio, Command::SetPeerMaxPayload { max_payload: max_payload as u16 })
+                    .await;
+                assert!(
+                    matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }),
+                    "{}",
+                    max_payload
+                );
+            }
+            let response = dispatcher
+                .dispatch(&mut radio, Command::SetPeerMaxPayload { max_payload: 64 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetPeerMaxPayload }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_tx_gap() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let gap_ms = crate::config::tx_gap::MAX_MS + 1;
+            let response = dispatcher.dispatch(&mut radio, Command::SetTxGap { gap_ms }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(dispatcher.tx_gap_ms(), 0);
+
+            let response = dispatcher.dispatch(&mut radio, Command::SetTxGap { gap_ms: 250 }).await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetTxGap }));
+            assert_eq!(dispatcher.tx_gap_ms(), 250);
+        });
+    }
+
+    #[test]
+    fn test_low_battery_policy_caps_tx_power_and_duty_cycles_rx() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let policy = |tx_power_dbm| Command::SetLowBatteryPolicy {
+            threshold_mv: 3500,
+            tx_power_dbm,
+            rx_duty_cycle: true,
+        };
+        let message = || Vec::from_slice(b"hi").unwrap();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            let response = dispatcher.dispatch(&mut radio, policy(MAX_TX_POWER_DBM + 1)).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            let response = dispatcher.dispatch(&mut radio, policy(5)).await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetLowBatteryPolicy }));
+
+            // Above the threshold nothing changes
+            assert!(!dispatcher.update_battery(3600));
+            dispatcher.dispatch(&mut radio, Command::LoraTx { data: message() }).await;
+            assert_eq!(dispatcher.rx_duty_cycle(), None);
+
+            // Crossing it is reported once
+            assert!(dispatcher.update_battery(3400));
+            assert!(!dispatcher.update_battery(3450));
+            dispatcher.dispatch(&mut radio, Command::LoraTx { data: message() }).await;
+            let quiet = Command::LoraTxPower {
+                power_dbm: 2,
+                data: message(),
+            };
+            dispatcher.dispatch(&mut radio, quiet).await;
+            assert_eq!(radio.get_tx_power_history().as_slice(), &[None, Some(5), Some(2)]);
+            assert!(dispatcher.rx_duty_cycle().is_some());
+
+            // ACKs are capped too
+            let wants_ack = fragment::encode_fragment(5, 0, 1 | fragment::ACK_REQUESTED, b"Hi");
+            dispatcher.accept_packet(&rx_packet(DEVICE_ID, [0xAB, 0xCD, 0xEF], &wants_ack), 0);
+            assert!(dispatcher.send_pending_ack(&mut radio).await.is_some());
+            assert_eq!(radio.get_tx_power_history().as_slice(), &[None, Some(5), Some(2), Some(5)]);
+
+            // Recovered, or the policy turned off
+            assert!(!dispatcher.update_battery(3700));
+            assert_eq!(dispatcher.tx_power(None), None);
+            dispatcher.update_battery(3400);
+            let off = Command::SetLowBatteryPolicy {
+                threshold_mv: 0,
+                tx_power_dbm: 0,
+                rx_duty_cycle: false,
+            };
+            dispatcher.dispatch(&mut radio, off).await;
+            assert_eq!(dispatcher.tx_power(None), None);
+            assert_eq!(dispatcher.rx_duty_cycle(), None);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_set_modulation_sends_and_receives_raw_fsk() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let set_fsk = |rx_bandwidth_hz| Command::SetModulation {
+            fsk: true,
+            bitrate_bps: 4_800,
+            deviation_hz: 5_000,
+            rx_bandwidth_hz,
+            gaussian_bt: 5,
+        };
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            // Not one of the radio's bandwidth steps
+            let response = dispatcher.dispatch(&mut radio, set_fsk(20_000)).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::LoraError, .. }));
+            assert!(!dispatcher.config.is_fsk());
+
+            let response = dispatcher.dispatch(&mut radio, set_fsk(19_500)).await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetModulation }));
+            assert!(radio.get_config().is_some_and(|c| c.is_fsk()));
+
+            // The message goes out without address, fragment or encryption
+            let data = Vec::from_slice(b"raw").unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::LoraTx { data }).await;
+            assert!(matches!(response, Response::TxComplete { len: 3, .. }));
+            assert_eq!(radio.get_tx_history()[0].as_slice(), b"raw");
+
+            // And a packet comes in as it was sent
+            radio.queue_rx_packet(RxPacket {
+                data: Vec::from_slice(b"sensor").unwrap(),
+                rssi: -70,
+                snr: 0,
+            });
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraRx { timeout_ms: 1_000 })
+                .await;
+            match response {
+                Response::RxPacket { source, data, .. } => {
+                    assert_eq!(source, addressing::UNADDRESSED);
+                    assert_eq!(&data[..], b"sensor");
+                }
+                _ => panic!("Expected RxPacket response"),
+            }
+
+            // Nothing can ACK a raw packet
+            let data = Vec::from_slice(b"raw").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: [0xAB, 0xCD, 0xEF], data })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+
+            let response = dispatcher
+                .dispatch(
+                    &mut radio,
+                    Command::SetModulation {
+                        fsk: false,
+                        bitrate_bps: 0,
+                        deviation_hz: 0,
+                        rx_bandwidth_hz: 0,
+                        gaussian_bt: 0,
+                    },
+                )
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::SetModulation }));
+            assert_eq!(radio.get_config().map(|c| c.modulation), Some(Modulation::Lora));
+        });
+    }
+
+    #[test]
+    fn test_lora_tx_is_held_to_the_peer_max_payload() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        // Room for exactly 40 bytes of message in one packet
+        let max_payload = fragment::packet_len(40);
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            dispatcher
+                .dispatch(&mut radio, Command::SetPeerMaxPayload { max_payload: max_payload as u16 })
+                .await;
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0x42; 40]).unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::LoraTx { data: data.clone() }).await;
+            assert!(matches!(response, Response::TxComplete { len: 40, .. }));
+            assert_eq!(radio.get_tx_history()[0].len(), max_payload);
+
+            data.push(0x42).unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::LoraTx { data: data.clone() }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: [0xAB, 0xCD, 0xEF], data })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(radio.get_tx_history().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_get_last_packet_status() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::GetLastPacketStatus).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::Timeout, .. }));
+
+            radio.queue_rx_packet(rx_frame(DEVICE_ID, [0xAB, 0xCD, 0xEF], b"hi"));
+            radio.set_freq_error(-1_250);
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraRx { timeout_ms: 1_000 })
+                .await;
+            assert!(matches!(response, Response::RxPacket { .. }));
+
+            let response = dispatcher.dispatch(&mut radio, Command::GetLastPacketStatus).await;
+            match response {
+                Response::LastPacketStatus { rssi, signal_rssi, snr, freq_error_hz } => {
+                    assert_eq!((rssi, signal_rssi, snr), (-60, -63, 7));
+                    assert_eq!(freq_error_hz, -1_250);
+                }
+                other => panic!("unexpected response: {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_rx() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+
+        futures::executor::block_on(async {
+            radio.queue_rx_packet(rx_frame(DEVICE_ID, peer, b"hi"));
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraRx { timeout_ms: 1_000 })
+                .await;
+            match response {
+                Response::RxPacket { source, data, rssi, snr, crc32 } => {
+                    assert_eq!(source, peer);
+                    assert_eq!(&data[..], b"hi");
+                    assert_eq!((rssi, snr), (-60, 7));
+                    assert_eq!(crc32, Some(crate::crc32::checksum(b"hi")));
+                }
+                _ => panic!("Expected RxPacket response"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_rx_timeout() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            // Nothing on air
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraRx { timeout_ms: 100 })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::Timeout, .. }));
+
+            // A frame for another device is not returned
+            radio.queue_rx_packet(rx_frame([0x99, 0x99, 0x99], [0xAB, 0xCD, 0xEF], b"hi"));
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraRx { timeout_ms: 100 })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::Timeout, .. }));
+
+            // A wait the watchdog would not survive is refused
+            let timeout_ms = crate::config::lora_defaults::MAX_RX_TIMEOUT_MS + 1;
+            let response = dispatcher.dispatch(&mut radio, Command::LoraRx { timeout_ms }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_stream() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer: Address = [0xAB, 0xCD, 0xEF];
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            // Data before StreamBegin has nowhere to go
+            let mut chunk = Vec::new();
+            chunk.extend_from_slice(b"abc").unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::StreamData { data: chunk.clone() }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::StreamBegin { dest: peer, total_len: 5 })
+                .await;
+            assert!(matches!(response, Response::Ack { command: CommandId::StreamBegin }));
+
+            let response = dispatcher.dispatch(&mut radio, Command::StreamData { data: chunk.clone() }).await;
+            assert!(matches!(response, Response::TxComplete { len: 3, .. }));
+            assert_eq!(dispatcher.stream_progress(), Some((3, 5)));
+            let (dest, _, frame) = open_frame(&radio.get_tx_history()[0]);
+            assert_eq!(dest, peer);
+            assert_eq!(&frame[fragment::HEADER_LEN..], b"abc");
+
+            // More than was announced is refused and not sent
+            let response = dispatcher.dispatch(&mut radio, Command::StreamData { data: chunk }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(radio.get_tx_history().len(), 1);
+
+            // Ending early reports the shortfall and still closes the stream
+            let response = dispatcher.dispatch(&mut radio, Command::StreamEnd).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(dispatcher.stream_progress(), None);
+
+            let response = dispatcher
+                .dispatch(&mut radio, Command::StreamBegin { dest: peer, total_len: 2 })
+                .await;
+            assert!(matches!(response, Response::Ack { .. }));
+            let mut rest = Vec::new();
+            rest.extend_from_slice(b"de").unwrap();
+            let response = dispatcher.dispatch(&mut radio, Command::StreamData { data: rest }).await;
+            assert!(matches!(response, Response::TxComplete { len: 2, .. }));
+            let response = dispatcher.dispatch(&mut radio, Command::StreamEnd).await;
+            assert!(matches!(response, Response::Ack { command: CommandId::StreamEnd }));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_reliable_acked() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            radio.queue_rx_packet(rx_packet(DEVICE_ID, peer, &fragment::encode_ack(0)));
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"Hi").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: peer, data })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+
+            let history = radio.get_tx_history();
+            assert_eq!(history.len(), 1);
+            let (sent_dest, _, fragment) = open_frame(&history[0]);
+            assert_eq!(sent_dest, peer);
+            assert!(fragment::ack_requested(&fragment));
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_reliable_retries_until_acked() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            // The first attempt hears nothing; the ACK follows the retry
+            radio.set_next_rx_error(LoraError::Timeout);
+            radio.queue_rx_packet(rx_packet(DEVICE_ID, peer, &fragment::encode_ack(0)));
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"Hi").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: peer, data })
+                .await;
+            assert!(matches!(response, Response::TxComplete { .. }));
+            assert_eq!(radio.get_tx_history().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_lora_tx_reliable_gives_up() {
+        use crate::config::reliable::MAX_RETRIES;
+
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            // An ACK for a different message does not count
+            radio.queue_rx_packet(rx_packet(DEVICE_ID, peer, &fragment::encode_ack(9)));
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"Hi").unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: peer, data: data.clone() })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::Timeout, .. }));
+            assert_eq!(radio.get_tx_history().len(), 1 + MAX_RETRIES as usize);
+
+            // Broadcast cannot be acknowledged
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: addressing::BROADCAST, data })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::LoraError, .. }));
+
+            // Nothing to send is a length problem, not a radio one
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: peer, data: Vec::new() })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(radio.get_tx_history().len(), 1 + MAX_RETRIES as usize);
+        });
+    }
+
+    #[test]
+    fn test_slow_config_keeps_transmits_inside_the_command_budget() {
+        use crate::config::reliable::MAX_RETRIES;
+
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+        let slow = |bandwidth_khz| Command::SetLoraConfig {
+            frequency_hz: 869_525_000,
+            spreading_factor: 12,
+            bandwidth_khz,
+            coding_rate: 8,
+            tx_power_dbm: 14,
+            preamble_symbols: 8,
+            sync_word: 0x1424,
+            crc_enabled: true,
+            iq_inverted: false,
+            ocp_ma: 140,
+            ramp_time: 0x04,
+            standby_xosc: false,
+        };
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            let response = dispatcher.dispatch(&mut radio, slow(125)).await;
+            assert!(matches!(response, Response::Ack { .. }));
+
+            // Four full fragments at SF12/125 kHz leave time for one attempt
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0x42; fragment::MAX_CHUNK_LEN * 4]).unwrap();
+            let attempts = dispatcher.reliable_attempts(data.len());
+            assert!(attempts >= 1 && attempts < 1 + MAX_RETRIES as u32);
+            let command = Command::LoraTxReliable { dest: peer, data };
+            assert_eq!(
+                dispatcher.tx_airtime_ms(&command),
+                Some(dispatcher.message_airtime_ms(fragment::MAX_CHUNK_LEN * 4) * attempts)
+            );
+            let response = dispatcher.dispatch(&mut radio, command).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::Timeout, .. }));
+            assert_eq!(radio.get_tx_history().len(), 4 * attempts as usize);
+
+            // At 7.8 kHz a single packet outlasts the budget, so nothing is sent
+            let response = dispatcher.dispatch(&mut radio, slow(7)).await;
+            assert!(matches!(response, Response::Ack { .. }));
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0x42; fragment::MAX_CHUNK_LEN]).unwrap();
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTx { data: data.clone() })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            let response = dispatcher
+                .dispatch(&mut radio, Command::LoraTxReliable { dest: peer, data })
+                .await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+            assert_eq!(radio.get_tx_history().len(), 4 * attempts as usize);
+        });
+    }
+
+    #[test]
+    fn test_reliable_message_is_acked_and_delivered_once() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+        let peer = [0xAB, 0xCD, 0xEF];
+        let packet = rx_packet(DEVICE_ID, peer, &fragment::encode_fragment(5, 0, 1 | fragment::ACK_REQUESTED, b"Hi"));
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+
+            assert!(matches!(dispatcher.accept_packet(&packet, 0), Some(Response::RxPacket { .. })));
+            assert!(dispatcher.send_pending_ack(&mut radio).await.is_some());
+
+            // A retransmission is ACKed again but not delivered twice
+            assert!(dispatcher.accept_packet(&packet, 10).is_none());
+            assert!(dispatcher.send_pending_ack(&mut radio).await.is_some());
+            assert!(dispatcher.send_pending_ack(&mut radio).await.is_none());
+
+            let history = radio.get_tx_history();
+            assert_eq!(history.len(), 2);
+            let (dest, _, ack) = open_frame(&history[0]);
+            assert_eq!(dest, peer);
+            assert_eq!(fragment::decode_ack(&ack), Some(5));
+        });
     }
 
     #[test]
     fn test_dispatch_lora_tx_error() {
-        let dispatcher = CommandDispatcher::new();
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
         let mut radio = MockLoraRadio::new();
 
         futures::executor::block_on(async {
@@ -212,11 +3407,45 @@ mod tests {
 
             match response {
                 Response::Error { status, .. } => {
-                    assert_eq!(status, ResponseStatus::LoraError);
+                    assert_eq!(status, ResponseStatus::TransmitFailed);
                 }
                 _ => panic!("Expected Error response"),
             }
         });
     }
 
+    #[test]
+    fn test_lora_errors_map_to_distinct_statuses() {
+        let mapping = [
+            (LoraError::Timeout, ResponseStatus::Timeout),
+            (LoraError::CrcError, ResponseStatus::RadioCrcError),
+            (LoraError::TransmitFailed, ResponseStatus::TransmitFailed),
+            (LoraError::ReceiveFailed, ResponseStatus::ReceiveFailed),
+            (LoraError::InvalidConfig, ResponseStatus::LoraError),
+            (LoraError::BusyTimeout, ResponseStatus::RadioBusy),
+            (LoraError::SpiError, ResponseStatus::SpiError),
+            (LoraError::NotInitialised, ResponseStatus::NotInitialised),
+            (LoraError::Unsupported, ResponseStatus::Unsupported),
+        ];
+        for (error, status) in mapping {
+            assert_eq!(lora_error_status(error), status, "{:?}", error);
+        }
+        for (i, (_, a)) in mapping.iter().enumerate() {
+            assert!(mapping[i + 1..].iter().all(|(_, b)| a != b), "{:?} reused", a);
+        }
+    }
+
+    #[test]
+    fn test_lora_rx_reports_the_radio_error() {
+        let mut dispatcher = CommandDispatcher::new(DEVICE_ID);
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.init().await.unwrap();
+            radio.set_next_rx_error(LoraError::CrcError);
+            let response = dispatcher.dispatch(&mut radio, Command::LoraRx { timeout_ms: 100 }).await;
+            assert!(matches!(response, Response::Error { status: ResponseStatus::RadioCrcError, .. }));
+        });
+    }
+
 }
@@ -1,6 +1,11 @@
 pub mod handler;
+pub mod reader;
+pub mod runner;
+pub mod version;
 
 pub use handler::{
-    CommandDispatcher, CommandEnvelope, CommandSource, ResponseMessage, ResponsePublisher,
-    COMMAND_CHANNEL, RESPONSE_CHANNEL,
+    reset_interface, response_trailer, sequence_echo, AdminCommand, CommandDispatcher, CommandEnvelope, CommandSource,
+    ResponseMessage, ResponseSubscriber, BEACON_INTERVAL, COMMAND_CHANNEL, HEARTBEAT_INTERVAL, RESPONSE_CHANNEL,
 };
+pub use reader::{decode_frame, BleReader, BleReply, FrameReader};
+pub use runner::{Handoff, LoraRunner};
@@ -0,0 +1,680 @@
+//! Body of the serial (and WiFi) reader tasks, and the command side of a BLE
+//! connection
+//!
+//! Splits the incoming byte stream into frames (see `framing`), parses each
+//! into a command and queues it for the LoRa task, answering malformed frames with an
+//! error and commands that find the queue full with `Busy`. Reboots skip the
+//! queue, go straight to the admin task and are acked here. Generic over the serial port and takes its channel handles as
+//! arguments, so `tasks::serial` is a thin loop around it and tests can drive
+//! it with a scripted port.
+//!
+//! `BleReader` does the same for the bytes a BLE client writes, but hands its
+//! own replies back to be notified on the connection instead of publishing
+//! them, so `tasks::ble` only has to deal with the GATT server.
+
+use embassy_sync::channel::{DynamicSender, TrySendError};
+use embassy_sync::pubsub::DynImmediatePublisher;
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::Read;
+
+use crate::config;
+use crate::framing::{Deframer, Push};
+use wt_protocol::{Command, Response, ResponseStatus};
+
+use super::handler::{AdminCommand, CommandEnvelope, CommandSource, ResponseMessage};
+use super::version;
+
+/// Frame reader for one serial port or network connection
+pub struct FrameReader<'a, R: Read> {
+    reader: R,
+    source: CommandSource,
+    deframer: Deframer,
+    sequence_counter: u16,
+    commands: DynamicSender<'a, CommandEnvelope>,
+    admin: DynamicSender<'a, AdminCommand>,
+    responses: DynImmediatePublisher<'a, ResponseMessage>,
+}
+
+impl<'a, R: Read> FrameReader<'a, R> {
+    /// Create a reader tagging commands with `source` and queueing them on
+    /// `commands`, sending admin commands to `admin` and publishing parse
+    /// errors on `responses`
+    pub fn new(
+        reader: R,
+        source: CommandSource,
+        commands: DynamicSender<'a, CommandEnvelope>,
+        admin: DynamicSender<'a, AdminCommand>,
+        responses: DynImmediatePublisher<'a, ResponseMessage>,
+    ) -> Self {
+        Self {
+            reader,
+            source,
+            deframer: Deframer::new(),
+            sequence_counter: 0,
+            commands,
+            admin,
+            responses,
+        }
+    }
+
+    /// Read and process one chunk of bytes
+    ///
+    /// Returns after at most `IDLE_PULSE_SECS` even if the port stays idle, so
+    /// the caller can pulse the watchdog. A read error is passed back so the
+    /// caller can back off before polling the port again.
+    pub async fn step(&mut self) -> Result<(), R::Error> {
+        // Read bytes from serial, waking periodically so an idle port still
+        // pulses the watchdog
+        let mut buf = [0u8; 64];
+        let idle = Duration::from_secs(config::watchdog::IDLE_PULSE_SECS);
+        let Ok(read) = with_timeout(idle, self.reader.read(&mut buf)).await else {
+            return Ok(());
+        };
+        match read {
+            Ok(n) => {
+                for &byte in &buf[..n] {
+                    self.push(byte);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // The host went away (e.g. the terminal closed the port). Drop
+                // any half-received frame so the next connection starts on a
+                // clean frame boundary rather than being glued onto it.
+                crate::debug!("{:?}: read error, resetting deframer", self.source);
+                self.deframer = Deframer::new();
+                Err(e)
+            }
+        }
+    }
+
+    /// Push one byte through the deframer, handling any frame it completes
+    fn push(&mut self, byte: u8) {
+        let pushed = self.deframer.push(byte);
+        if pushed == Push::Overflow {
+            crate::error!("{:?}: frame overflow, discarded", self.source);
+        }
+        // Nothing yet, or an empty frame, which gets no response and no
+        // sequence id
+        let Some(result) = decode_frame(pushed) else {
+            return;
+        };
+
+        let seq_id = self.sequence_counter;
+        self.sequence_counter = self.sequence_counter.wrapping_add(1);
+
+        match result {
+            Ok((cmd, host_sequence)) => {
+                // Acked here once the admin task has it, so the host knows
+                // the reboot is coming; the admin task waits for the writer
+                // to get the Ack out before resetting
+                if let Some(admin) = AdminCommand::from_command(&cmd) {
+                    let response = if self.admin.try_send(admin).is_ok() {
+                        Response::Ack { command: cmd.id() }
+                    } else {
+                        crate::error!("{:?}: admin queue full, answering busy", self.source);
+                        Response::error(ResponseStatus::Busy, cmd.id())
+                    };
+                    self.responses.publish_immediate(ResponseMessage::Command {
+                        source: self.source,
+                        sequence_id: seq_id,
+                        host_sequence,
+                        response,
+                    });
+                    return;
+                }
+
+                let envelope = CommandEnvelope {
+                    command: cmd,
+                    source: self.source,
+                    sequence_id: seq_id,
+                    host_sequence,
+                };
+                // Waiting for room would stall reading and back the port up;
+                // answer Busy so the host knows to slow down
+                if let Err(TrySendError::Full(envelope)) = self.commands.try_send(envelope) {
+                    crate::error!("{:?}: command queue full, answering busy", self.source);
+                    let response = Response::error(ResponseStatus::Busy, envelope.command.id());
+                    self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+                }
+            }
+            Err(response) => {
+                let msg = ResponseMessage::Command {
+                    source: self.source,
+                    sequence_id: seq_id,
+                    host_sequence: None,
+                    response,
+                };
+                self.responses.publish_immediate(msg);
+            }
+        }
+    }
+}
+
+/// A reply `BleReader` makes itself, to notify straight back to the client
+#[derive(Debug)]
+pub struct BleReply {
+    pub response: Response,
+    /// The command's sequence id, echoed if the client enabled SetSequenceEcho
+    pub sequence_id: u16,
+    /// The host sequence number the command frame carried, if any
+    pub host_sequence: Option<u16>,
+}
+
+/// Command reader for one BLE connection
+///
+/// Fed the bytes written to the NUS RX characteristic, which may split or
+/// join frames freely.
+pub struct BleReader<'a> {
+    slot: u8,
+    deframer: Deframer,
+    sequence_id: u16,
+    commands: DynamicSender<'a, CommandEnvelope>,
+    admin: DynamicSender<'a, AdminCommand>,
+}
+
+impl<'a> BleReader<'a> {
+    /// Create a reader for the client in `slot`, queueing commands on
+    /// `commands` and sending admin commands to `admin`
+    pub fn new(slot: u8, commands: DynamicSender<'a, CommandEnvelope>, admin: DynamicSender<'a, AdminCommand>) -> Self {
+        Self {
+            slot,
+            deframer: Deframer::new(),
+            sequence_id: 0,
+            commands,
+            admin,
+        }
+    }
+
+    /// Push one written byte, returning the reply to notify if it completed a
+    /// frame that is answered here: a parse error, `Busy` for a full queue, or
+    /// the Ack for a Reboot
+    pub fn push(&mut self, byte: u8) -> Option<BleReply> {
+        // An empty frame is skipped without using up a sequence id
+        let result = decode_frame(self.deframer.push(byte))?;
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+
+        let mut host_sequence = None;
+        let response = match result {
+            Ok((command, host)) => {
+                host_sequence = host;
+                if let Some(admin) = AdminCommand::from_command(&command) {
+                    // Straight to the admin task, ahead of any queued LoRa work
+                    if self.admin.try_send(admin).is_ok() {
+                        Response::Ack { command: command.id() }
+                    } else {
+                        crate::error!("BLE: admin queue full, slot {} told busy", self.slot);
+                        Response::error(ResponseStatus::Busy, command.id())
+                    }
+                } else {
+                    let envelope = CommandEnvelope {
+                        command,
+                        source: CommandSource::Ble(self.slot),
+                        sequence_id: self.sequence_id,
+                        host_sequence,
+                    };
+                    // A full queue would otherwise drop the command silently;
+                    // tell the client to slow down
+                    match self.commands.try_send(envelope) {
+                        Ok(()) => return None,
+                        Err(TrySendError::Full(envelope)) => {
+                            crate::error!("BLE: command queue full, slot {} told busy", self.slot);
+                            Response::error(ResponseStatus::Busy, envelope.command.id())
+                        }
+                    }
+                }
+            }
+            Err(response) => response,
+        };
+        Some(BleReply {
+            response,
+            sequence_id: self.sequence_id,
+            host_sequence,
+        })
+    }
+}
+
+/// Parse what a deframer produced into a command, with the host sequence
+/// number if the frame carried one, or the error response to send back
+///
+/// Shared by the serial, WiFi and BLE readers so they treat bad frames alike.
+/// None while a frame is incomplete, and for an empty frame (which hosts send
+/// to resynchronise), which is skipped without a response. Errors echo the
+/// frame's command id whenever it got that far, and 0x00 only when it is
+/// unreadable.
+///
+/// The host sequence is an optional 2-byte trailer between the data and the
+/// CRC, told apart by the frame being two bytes longer than its length field
+/// says.
+pub fn decode_frame(pushed: Push) -> Option<Result<(Command, Option<u16>), Response>> {
+    let mut decoded = match pushed {
+        Push::Pending => return None,
+        Push::Frame(payload) if payload.is_empty() => return None,
+        Push::Frame(payload) => payload,
+        // Discarded whole, so the command id was never seen intact
+        Push::Overflow => return Some(Err(Response::error_raw(ResponseStatus::InvalidLength, 0x00))),
+        // A bad COBS encoding is a framing problem, and the command id is
+        // unreadable
+        Push::Corrupt => return Some(Err(Response::error_raw(ResponseStatus::FramingError, 0x00))),
+    };
+
+    // Byte 1 is the command id (byte 0 is the protocol version); echoed back on error.
+    let command_id = decoded.get(1).copied().unwrap_or(0);
+
+    // A frame in an older supported version is parsed as the current one
+    if let Err(status) = version::negotiate(&mut decoded) {
+        return Some(Err(Response::error_raw(status, command_id)));
+    }
+    Some(wt_protocol::parse_command_with_sequence(&decoded).map_err(|status| Response::error_raw(status, command_id)))
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::crc16;
+    use crate::framing::Payload;
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::channel::Channel;
+    use embassy_sync::pubsub::PubSubChannel;
+    use embedded_io::ErrorKind;
+    use futures::executor::block_on;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+    use wt_protocol::CommandId;
+
+    /// Serial port replaying a script of reads
+    struct ScriptedPort {
+        reads: VecDeque<Result<Vec<u8>, ErrorKind>>,
+    }
+
+    impl embedded_io::ErrorType for ScriptedPort {
+        type Error = ErrorKind;
+    }
+
+    impl Read for ScriptedPort {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let chunk = self.reads.pop_front().expect("read past end of script")?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    /// COBS-encode `data` and append the frame delimiter
+    fn cobs_frame(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0];
+        let mut code_at = 0;
+        for &byte in data {
+            if byte == 0 {
+                out[code_at] = (out.len() - code_at) as u8;
+                code_at = out.len();
+                out.push(0);
+            } else {
+                out.push(byte);
+            }
+        }
+        out[code_at] = (out.len() - code_at) as u8;
+        out.push(0);
+        out
+    }
+
+    /// Wire frame for GetVersion: version 1, command 0x01, empty payload
+    fn get_version_frame() -> Vec<u8> {
+        let mut raw = vec![0x01, 0x01, 0x00, 0x00];
+        let crc = crc16::checksum(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        cobs_frame(&raw)
+    }
+
+    #[test]
+    fn test_frame_is_parsed_into_command() {
+        embassy_time::MockDriver::get().reset();
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4> = Channel::new();
+        let responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 4, 1, 0> = PubSubChannel::new();
+        let mut subscriber = responses.subscriber().unwrap();
+        let port = ScriptedPort {
+            reads: VecDeque::from([Ok(get_version_frame())]),
+        };
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = FrameReader::new(
+            port,
+            CommandSource::Serial,
+            commands.dyn_sender(),
+            admin.dyn_sender(),
+            responses.dyn_immediate_publisher(),
+        );
+
+        assert!(block_on(reader.step()).is_ok());
+
+        let envelope = commands.try_receive().unwrap();
+        assert!(matches!(envelope.command, Command::GetVersion));
+        assert!(subscriber.try_next_message_pure().is_none());
+    }
+
+    /// Encoded bytes of `response`
+    fn wire(response: &Response) -> Vec<u8> {
+        wt_protocol::encode_response(response).to_vec()
+    }
+
+    /// Feed `bytes` to a reader with a fresh set of channels, returning what
+    /// it queued and published
+    fn read_one(bytes: Vec<u8>) -> (Vec<CommandEnvelope>, Vec<ResponseMessage>) {
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4> = Channel::new();
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 4, 1, 0> = PubSubChannel::new();
+        let mut subscriber = responses.subscriber().unwrap();
+        let port = ScriptedPort {
+            reads: VecDeque::from([Ok(bytes)]),
+        };
+        let mut reader = FrameReader::new(
+            port,
+            CommandSource::Serial,
+            commands.dyn_sender(),
+            admin.dyn_sender(),
+            responses.dyn_immediate_publisher(),
+        );
+        assert!(block_on(reader.step()).is_ok());
+
+        let queued = std::iter::from_fn(|| commands.try_receive().ok()).collect();
+        let published = std::iter::from_fn(|| subscriber.try_next_message_pure()).collect();
+        (queued, published)
+    }
+
+    #[test]
+    fn test_empty_frame_is_skipped_without_a_sequence_id() {
+        embassy_time::MockDriver::get().reset();
+
+        // A bare delimiter, as hosts send to resynchronise, then a command
+        let mut bytes = vec![0x00];
+        bytes.extend(get_version_frame());
+        let (queued, published) = read_one(bytes);
+
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].sequence_id, 0);
+        assert!(published.is_empty());
+        // BLE shares the decoder, so it skips the frame too
+        assert!(decode_frame(Push::Frame(Payload::new())).is_none());
+    }
+
+    /// GetVersion frame carrying `host` as its host sequence trailer
+    fn get_version_frame_with_host_sequence(host: u16) -> Vec<u8> {
+        let mut raw = vec![0x01, 0x01, 0x00, 0x00];
+        raw.extend_from_slice(&host.to_le_bytes());
+        let crc = crc16::checksum(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        cobs_frame(&raw)
+    }
+
+    #[test]
+    fn test_host_sequence_is_carried_in_the_envelope() {
+        embassy_time::MockDriver::get().reset();
+
+        let mut bytes = get_version_frame_with_host_sequence(0x0102);
+        bytes.extend(get_version_frame());
+        let (queued, published) = read_one(bytes);
+
+        assert!(published.is_empty());
+        match queued.as_slice() {
+            [first, second] => {
+                assert!(matches!(first.command, Command::GetVersion));
+                assert_eq!((first.sequence_id, first.host_sequence), (0, Some(0x0102)));
+                assert!(matches!(second.command, Command::GetVersion));
+                assert_eq!((second.sequence_id, second.host_sequence), (1, None));
+            }
+            other => panic!("expected two commands, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_zero_length_lora_tx_reports_its_command_id() {
+        embassy_time::MockDriver::get().reset();
+
+        let mut raw = vec![0x01, 0x10, 0x00, 0x00];
+        let crc = crc16::checksum(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        let (queued, published) = read_one(cobs_frame(&raw));
+
+        // Compared on the wire, which carries the status and command id
+        let expected = wire(&Response::error_raw(ResponseStatus::InvalidLength, 0x10));
+        assert!(queued.is_empty());
+        match published.as_slice() {
+            [ResponseMessage::Command { response, .. }] => assert_eq!(wire(response), expected),
+            other => panic!("expected one error response, got {:?}", other),
+        }
+        match decode_frame(Push::Frame(Payload::from_slice(&raw).unwrap())) {
+            Some(Err(response)) => assert_eq!(wire(&response), expected),
+            other => panic!("expected an error for LoraTx, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_too_old_version_is_rejected_with_its_command_id() {
+        let mut raw = vec![version::MIN_SUPPORTED_VERSION - 1, 0x01, 0x00, 0x00];
+        let crc = crc16::checksum(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+
+        let expected = wire(&Response::error_raw(ResponseStatus::InvalidVersion, 0x01));
+        match decode_frame(Push::Frame(Payload::from_slice(&raw).unwrap())) {
+            Some(Err(response)) => assert_eq!(wire(&response), expected),
+            other => panic!("expected InvalidVersion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_disconnect_mid_frame_discards_partial_frame() {
+        embassy_time::MockDriver::get().reset();
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4> = Channel::new();
+        let responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 4, 1, 0> = PubSubChannel::new();
+        let mut subscriber = responses.subscriber().unwrap();
+
+        // Half a frame, then the host disconnects and a fresh connection
+        // sends a complete one
+        let frame = get_version_frame();
+        let port = ScriptedPort {
+            reads: VecDeque::from([
+                Ok(frame[..3].to_vec()),
+                Err(ErrorKind::NotConnected),
+                Ok(frame.clone()),
+            ]),
+        };
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = FrameReader::new(
+            port,
+            CommandSource::Serial,
+            commands.dyn_sender(),
+            admin.dyn_sender(),
+            responses.dyn_immediate_publisher(),
+        );
+
+        assert!(block_on(reader.step()).is_ok());
+        assert_eq!(block_on(reader.step()), Err(ErrorKind::NotConnected));
+        assert!(block_on(reader.step()).is_ok());
+
+        // The reconnect's frame parses on its own rather than being glued
+        // onto the stale bytes and failing as a framing or CRC error
+        let envelope = commands.try_receive().unwrap();
+        assert!(matches!(envelope.command, Command::GetVersion));
+        assert!(commands.try_receive().is_err());
+        assert!(subscriber.try_next_message_pure().is_none());
+    }
+
+    #[test]
+    fn test_reboot_bypasses_a_full_command_queue() {
+        embassy_time::MockDriver::get().reset();
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 1> = Channel::new();
+        let responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 4, 1, 0> = PubSubChannel::new();
+        let mut subscriber = responses.subscriber().unwrap();
+
+        // The queue fills with a GetVersion; the reboot behind it must still
+        // reach the admin task
+        let mut reboot = vec![0x01, 0x03, 0x00, 0x00];
+        let crc = crc16::checksum(&reboot);
+        reboot.extend_from_slice(&crc.to_le_bytes());
+        let mut burst = get_version_frame();
+        burst.extend(cobs_frame(&reboot));
+        let port = ScriptedPort {
+            reads: VecDeque::from([Ok(burst)]),
+        };
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = FrameReader::new(
+            port,
+            CommandSource::Serial,
+            commands.dyn_sender(),
+            admin.dyn_sender(),
+            responses.dyn_immediate_publisher(),
+        );
+
+        assert!(block_on(reader.step()).is_ok());
+
+        assert!(matches!(admin.try_receive(), Ok(AdminCommand::Reboot)));
+        assert!(matches!(commands.try_receive().unwrap().command, Command::GetVersion));
+        assert!(commands.try_receive().is_err());
+        // The reboot is acked to the port that sent it
+        match subscriber.try_next_message_pure() {
+            Some(ResponseMessage::Command {
+                source: CommandSource::Serial,
+                sequence_id: 1,
+                host_sequence: None,
+                response: Response::Ack { command: CommandId::Reboot },
+            }) => {}
+            _ => panic!("expected the reboot Ack"),
+        }
+        assert!(subscriber.try_next_message_pure().is_none());
+    }
+
+    #[test]
+    fn test_full_queue_answers_busy() {
+        embassy_time::MockDriver::get().reset();
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 1> = Channel::new();
+        let responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 4, 1, 0> = PubSubChannel::new();
+        let mut subscriber = responses.subscriber().unwrap();
+
+        // Two commands in one read with room for only one
+        let mut burst = get_version_frame();
+        burst.extend(get_version_frame());
+        let port = ScriptedPort {
+            reads: VecDeque::from([Ok(burst)]),
+        };
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = FrameReader::new(
+            port,
+            CommandSource::Serial,
+            commands.dyn_sender(),
+            admin.dyn_sender(),
+            responses.dyn_immediate_publisher(),
+        );
+
+        assert!(block_on(reader.step()).is_ok());
+
+        assert_eq!(commands.try_receive().unwrap().sequence_id, 0);
+        match subscriber.try_next_message_pure() {
+            Some(ResponseMessage::Command {
+                source: CommandSource::Serial,
+                sequence_id: 1,
+                host_sequence: None,
+                response: Response::Error { status, .. },
+            }) => assert_eq!(status, ResponseStatus::Busy),
+            _ => panic!("expected a Busy error for the second command"),
+        }
+    }
+
+    /// Every reply from pushing `bytes` through `reader`
+    fn feed(reader: &mut BleReader, bytes: &[u8]) -> Vec<BleReply> {
+        bytes.iter().filter_map(|&byte| reader.push(byte)).collect()
+    }
+
+    #[test]
+    fn test_ble_reader_joins_frames_split_across_writes() {
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4> = Channel::new();
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = BleReader::new(1, commands.dyn_sender(), admin.dyn_sender());
+
+        // One frame over two writes, then the next one glued to an empty frame
+        let frame = get_version_frame();
+        let (head, tail) = frame.split_at(3);
+        assert!(feed(&mut reader, head).is_empty());
+        assert!(feed(&mut reader, tail).is_empty());
+        let mut burst = vec![0x00];
+        burst.extend(get_version_frame());
+        assert!(feed(&mut reader, &burst).is_empty());
+
+        for sequence_id in [1, 2] {
+            let envelope = commands.try_receive().unwrap();
+            assert!(matches!(envelope.command, Command::GetVersion));
+            assert_eq!(envelope.source, CommandSource::Ble(1));
+            assert_eq!(envelope.sequence_id, sequence_id);
+        }
+        assert!(commands.try_receive().is_err());
+        assert!(admin.try_receive().is_err());
+    }
+
+    #[test]
+    fn test_ble_reader_answers_bad_frames_and_reboots_itself() {
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4> = Channel::new();
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = BleReader::new(0, commands.dyn_sender(), admin.dyn_sender());
+
+        // The code byte claims more bytes than arrive before the delimiter
+        match feed(&mut reader, &[0x05, 0x01, 0x00]).as_slice() {
+            [BleReply {
+                response: Response::Error { status, .. },
+                sequence_id: 1,
+                host_sequence: None,
+            }] => assert_eq!(*status, ResponseStatus::FramingError),
+            _ => panic!("expected a FramingError"),
+        }
+
+        let mut reboot = vec![0x01, 0x03, 0x00, 0x00];
+        let crc = crc16::checksum(&reboot);
+        reboot.extend_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            feed(&mut reader, &cobs_frame(&reboot)).as_slice(),
+            [BleReply {
+                response: Response::Ack { command: CommandId::Reboot },
+                sequence_id: 2,
+                host_sequence: None,
+            }]
+        ));
+        assert!(matches!(admin.try_receive(), Ok(AdminCommand::Reboot)));
+
+        // With the admin queue full the next reboot is refused
+        let _ = admin.try_send(AdminCommand::Reboot);
+        match feed(&mut reader, &cobs_frame(&reboot)).as_slice() {
+            [BleReply {
+                response: Response::Error { status, .. },
+                sequence_id: 3,
+                host_sequence: None,
+            }] => assert_eq!(*status, ResponseStatus::Busy),
+            _ => panic!("expected a Busy error for the second reboot"),
+        }
+        assert!(commands.try_receive().is_err());
+    }
+
+    #[test]
+    fn test_ble_reader_full_queue_answers_busy() {
+        let commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 1> = Channel::new();
+        let admin: Channel<CriticalSectionRawMutex, AdminCommand, 1> = Channel::new();
+        let mut reader = BleReader::new(0, commands.dyn_sender(), admin.dyn_sender());
+
+        let mut burst = get_version_frame();
+        burst.extend(get_version_frame());
+        match feed(&mut reader, &burst).as_slice() {
+            [BleReply {
+                response: Response::Error { status, .. },
+                sequence_id: 2,
+                host_sequence: None,
+            }] => assert_eq!(*status, ResponseStatus::Busy),
+            _ => panic!("expected a Busy error for the second command"),
+        }
+        assert_eq!(commands.try_receive().unwrap().sequence_id, 1);
+
+        // A refused command still gets its host sequence back
+        match feed(&mut reader, &get_version_frame_with_host_sequence(77)).as_slice() {
+            [BleReply {
+                response: Response::Error { status, .. },
+                sequence_id: 3,
+                host_sequence: Some(77),
+            }] => assert_eq!(*status, ResponseStatus::Busy),
+            _ => panic!("expected a Busy error carrying the host sequence"),
+        }
+    }
+}
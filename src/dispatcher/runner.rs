@@ -0,0 +1,1751 @@
+//! Body of the LoRa task
+//!
+//! Background listening, command dispatch, duty-cycle accounting and the link
+//! counters. Generic over the radio and takes its channel handles as
+//! arguments, so `tasks::lora` is a thin loop around it and tests can drive it
+//! with `MockLoraRadio` and local channels.
+
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{DynamicReceiver, DynamicSender};
+use embassy_sync::pubsub::DynImmediatePublisher;
+use embassy_time::{with_timeout, Duration, Timer};
+use heapless::{Deque, Vec};
+
+use crate::battery;
+use crate::clock;
+use crate::config;
+use crate::crc32;
+use crate::led::{error_code, LedPattern};
+use crate::lora::addressing::{self, Address};
+use crate::lora::duty_cycle::DutyCycle;
+use crate::lora::fragment::Hop;
+use crate::lora::hop::{CollisionMonitor, HopSequence};
+use crate::lora::traits::{LoraError, LoraRadio, RxDutyCycle};
+use wt_protocol::{Command, CommandId, Event, Response, ResponseStatus};
+
+use super::handler::{
+    lora_error_status, set_rx_checksum, set_sequence_echo, CommandDispatcher, CommandEnvelope, ResponseMessage,
+};
+
+/// Link counters reported by GetStats and zeroed by ClearStats
+#[derive(Debug, Clone, Copy, Default)]
+struct LinkStats {
+    /// LoRa messages sent (TxComplete)
+    tx_messages: u32,
+    /// LoRa transmissions that failed
+    tx_errors: u32,
+    /// Messages received, in the background or by LoraRx
+    rx_messages: u32,
+    /// Receive errors other than timeouts (e.g. CRC errors)
+    rx_errors: u32,
+    /// Transmissions refused by the duty-cycle limit
+    duty_cycle_rejections: u32,
+    /// Packets dropped for falling below the SetRxFilter thresholds
+    rx_filtered: u32,
+    /// Messages dropped for having been delivered already
+    rx_duplicates: u32,
+}
+
+/// Link counters since boot or the last ClearStats
+///
+/// Every access takes the lock, so a ClearStats can never interleave with
+/// the RX loop's read-modify-write of a counter.
+static STATS: Mutex<CriticalSectionRawMutex, Cell<LinkStats>> = Mutex::new(Cell::new(LinkStats {
+    tx_messages: 0,
+    tx_errors: 0,
+    rx_messages: 0,
+    rx_errors: 0,
+    duty_cycle_rejections: 0,
+    rx_filtered: 0,
+    rx_duplicates: 0,
+}));
+
+/// Update the link counters
+fn update_stats(f: impl FnOnce(&mut LinkStats)) {
+    STATS.lock(|stats| {
+        let mut value = stats.get();
+        f(&mut value);
+        stats.set(value);
+    });
+}
+
+/// Answer GetStats or ClearStats, which read and reset the counters the
+/// runner keeps; None for any other command
+fn handle_stats_command(command: &Command) -> Option<Response> {
+    match command {
+        Command::GetStats => {
+            let stats = STATS.lock(|stats| stats.get());
+            Some(Response::Stats {
+                tx_messages: stats.tx_messages,
+                tx_errors: stats.tx_errors,
+                rx_messages: stats.rx_messages,
+                rx_errors: stats.rx_errors,
+                duty_cycle_rejections: stats.duty_cycle_rejections,
+                rx_filtered: stats.rx_filtered,
+                rx_duplicates: stats.rx_duplicates,
+            })
+        }
+        Command::ClearStats => {
+            STATS.lock(|stats| stats.set(LinkStats::default()));
+            Some(Response::Ack {
+                command: CommandId::ClearStats,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A received message kept for GetRecentPackets
+struct RecentPacket {
+    source: Address,
+    /// Message data, truncated to `DATA_CAP`
+    data: Vec<u8, { config::recent_packets::DATA_CAP }>,
+    rssi: i16,
+    snr: i8,
+}
+
+/// Work a step leaves for the caller because another task owns it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handoff {
+    /// Nothing to do
+    None,
+    /// Reboot was requested; no response has been sent
+    Reboot,
+    /// Sleep was acknowledged and the radio is asleep; sleep the MCU for
+    /// `duration_ms` (0 = until DIO1), then wake the radio
+    Sleep { duration_ms: u32 },
+}
+
+/// State and channel handles of the LoRa task
+///
+/// Only frames addressed to the device or broadcast are surfaced to the host.
+pub struct LoraRunner<'a> {
+    dispatcher: CommandDispatcher,
+    duty_cycle: DutyCycle,
+    hops: HopSequence,
+    collisions: CollisionMonitor,
+    /// Whether enough link failures were seen to propose a channel hop
+    hop_due: bool,
+    /// Last messages received, oldest first
+    recent: Deque<RecentPacket, { config::recent_packets::COUNT }>,
+    /// When the last host command or received packet was seen
+    last_activity_ms: u64,
+    /// Receive windows the radio is using (None = continuous RX)
+    rx_windows: Option<RxDutyCycle>,
+    /// Why the radio failed to initialise, while it is unusable
+    radio_fault: Option<LoraError>,
+    /// When the radio fault was last reported
+    last_fault_ms: u64,
+    /// Receives in a row that failed in the radio itself
+    radio_errors: u32,
+    /// When the last transmitting command finished, for SetTxGap
+    last_tx_ms: Option<u64>,
+    commands: DynamicReceiver<'a, CommandEnvelope>,
+    responses: DynImmediatePublisher<'a, ResponseMessage>,
+    leds: DynamicSender<'a, LedPattern>,
+}
+
+impl<'a> LoraRunner<'a> {
+    /// Create a runner for `device_id` taking commands from `commands` and
+    /// publishing every response to `responses`
+    pub fn new(
+        device_id: Address,
+        commands: DynamicReceiver<'a, CommandEnvelope>,
+        responses: DynImmediatePublisher<'a, ResponseMessage>,
+        leds: DynamicSender<'a, LedPattern>,
+    ) -> Self {
+        Self {
+            dispatcher: CommandDispatcher::new(device_id),
+            duty_cycle: DutyCycle::new(),
+            hops: HopSequence::new(),
+            collisions: CollisionMonitor::new(),
+            hop_due: false,
+            recent: Deque::new(),
+            last_activity_ms: 0,
+            rx_windows: None,
+            radio_fault: None,
+            last_fault_ms: 0,
+            radio_errors: 0,
+            last_tx_ms: None,
+            commands,
+            responses,
+            leds,
+        }
+    }
+
+    /// Initialise the radio, retrying with backoff
+    ///
+    /// Each failure shows the error pattern and publishes a RadioFault event,
+    /// and a later success clears the pattern. If every attempt fails the
+    /// runner carries on without the radio: commands that need it are
+    /// answered `NotInitialised` and the fault is reported again every
+    /// `FAULT_REPORT_SECS`.
+    pub async fn init<R: LoraRadio>(&mut self, radio: &mut R) {
+        use config::radio_init::{BACKOFF_MS, RETRIES};
+
+        crate::debug!("LoRa: Initialising radio...");
+        for attempt in 0..=RETRIES {
+            match radio.init().await {
+                Ok(()) => {
+                    crate::debug!("LoRa: Radio initialised");
+                    // Take down the error pattern an earlier attempt put up
+                    if self.radio_fault.take().is_some() {
+                        let _ = self.leds.try_send(LedPattern::ClearError);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    crate::error!("LoRa: Radio init failed (attempt {})", attempt + 1);
+                    self.report_radio_fault(e);
+                }
+            }
+            if attempt < RETRIES {
+                Timer::after_millis(BACKOFF_MS << attempt).await;
+            }
+        }
+        crate::error!("LoRa: Radio unavailable, running without it");
+    }
+
+    /// Record that the radio is unusable and tell the LED and every interface
+    fn report_radio_fault(&mut self, error: LoraError) {
+        self.radio_fault = Some(error);
+        self.last_fault_ms = clock::now_ms();
+        let _ = self.leds.try_send(LedPattern::Error(error_code::RADIO_INIT));
+        self.responses
+            .publish_immediate(ResponseMessage::Unsolicited(Response::Event(Event::RadioFault {
+                status: lora_error_status(error),
+            })));
+    }
+
+    /// Back off after a receive that failed in the radio rather than on the
+    /// link, and re-initialise it after `REINIT_AFTER` such failures in a row
+    ///
+    /// A successful re-init puts back the configuration the host set. If
+    /// every attempt fails, the runner carries on without the radio, as after
+    /// a failed boot.
+    async fn recover_radio<R: LoraRadio>(&mut self, radio: &mut R, error: LoraError) {
+        use config::radio_recovery::{ERROR_BACKOFF_MS, REINIT_AFTER};
+
+        self.radio_errors += 1;
+        crate::error!("LoRa RX: Radio error {:?} ({} in a row)", error, self.radio_errors);
+        if self.radio_errors < REINIT_AFTER {
+            Timer::after_millis(ERROR_BACKOFF_MS).await;
+            return;
+        }
+
+        self.radio_errors = 0;
+        self.init(radio).await;
+        if self.radio_fault.is_some() {
+            return;
+        }
+        // init leaves the radio in continuous RX on the defaults
+        self.rx_windows = None;
+        if self.dispatcher.restore_config(radio).await.is_err() {
+            crate::error!("LoRa: Failed to restore the radio config");
+        }
+    }
+
+    /// Step while the radio is down: answer commands, repeating the fault
+    /// report every `FAULT_REPORT_SECS`
+    ///
+    /// Returns after at most `IDLE_PULSE_SECS` so the caller can pulse the
+    /// watchdog.
+    async fn step_without_radio<R: LoraRadio>(&mut self, radio: &mut R, error: LoraError) -> Handoff {
+        let idle = Duration::from_secs(config::watchdog::IDLE_PULSE_SECS);
+        if let Ok(envelope) = with_timeout(idle, self.commands.receive()).await {
+            return self.handle_command(radio, envelope).await;
+        }
+        let since_report_ms = clock::now_ms().saturating_sub(self.last_fault_ms);
+        if since_report_ms >= config::radio_init::FAULT_REPORT_SECS * 1000 {
+            self.report_radio_fault(error);
+        }
+        Handoff::None
+    }
+
+    /// Wait for a host command or a packet and handle whichever comes first
+    ///
+    /// The command channel is polled first, so a queued command is never held
+    /// up by a receive that completes immediately. select drops the losing
+    /// future, so when a command arrives the in-flight receive() is cancelled
+    /// (radio stays in RX; the next transmit/receive takes over). The listen
+    /// window (SetRxPollInterval) therefore only bounds how often RX is
+    /// re-armed and stale fragments expired when fully idle; it is not the
+    /// command-response latency.
+    pub async fn step<R: LoraRadio>(&mut self, radio: &mut R) -> Handoff {
+        if let Some(error) = self.radio_fault {
+            return self.step_without_radio(radio, error).await;
+        }
+
+        // Drop partial messages whose remaining fragments never arrived.
+        let dropped = self.dispatcher.expire_partials(clock::now_ms());
+        if dropped > 0 {
+            crate::trace!("LoRa RX: Dropped {} incomplete message(s)", dropped);
+        }
+        // Likewise a stream the host stopped feeding (e.g. it disconnected)
+        if self.dispatcher.expire_stream(clock::now_ms()) {
+            crate::debug!("LoRa TX: Stream timed out, dropped");
+        }
+
+        self.check_battery();
+        self.apply_idle_policy(radio).await;
+
+        // A step of its own, so the handshake never adds to a command's time
+        // between watchdog pulses
+        if core::mem::take(&mut self.hop_due) {
+            self.propose_hop(radio).await;
+            return Handoff::None;
+        }
+
+        // Frames to repeat go out once the channel has been quiet for a
+        // moment, not in the middle of a multi-fragment message
+        let mut listen_ms = self.dispatcher.rx_poll_interval_ms();
+        if self.dispatcher.has_relays() {
+            listen_ms = listen_ms.min(config::repeater::HOLDOFF_MS);
+        }
+        match select(self.commands.receive(), radio.receive(listen_ms)).await {
+            Either::First(envelope) => self.handle_command(radio, envelope).await,
+            Either::Second(rx_result) => {
+                if !matches!(rx_result, Err(LoraError::SpiError | LoraError::BusyTimeout)) {
+                    // The radio answered, whatever it had to say
+                    self.radio_errors = 0;
+                }
+                match rx_result {
+                    // Too weak to trust: counted, but neither surfaced nor ACKed
+                    Ok(packet) if !self.dispatcher.passes_rx_filter(&packet) => {
+                        crate::trace!("LoRa RX: Filtered packet (RSSI: {}, SNR: {})", packet.rssi, packet.snr);
+                        update_stats(|stats| stats.rx_filtered = stats.rx_filtered.wrapping_add(1));
+                    }
+                    Ok(packet) => {
+                        let now_ms = clock::now_ms();
+                        self.last_activity_ms = now_ms;
+                        let response = self.dispatcher.accept_packet(&packet, now_ms);
+                        self.count_duplicates();
+
+                        // Confirm a message that asked for it (even a repeat,
+                        // whose earlier ACK may have been lost).
+                        if let Some(airtime_ms) = self.dispatcher.send_pending_ack(radio).await {
+                            self.duty_cycle.record(now_ms, airtime_ms);
+                        }
+                        self.follow_hop(radio).await;
+
+                        // Only whole messages for this device are surfaced.
+                        if let Some(response) = response {
+                            update_stats(|stats| stats.rx_messages = stats.rx_messages.wrapping_add(1));
+                            self.remember(&response);
+
+                            // Signal LED flash for received message (non-blocking)
+                            let _ = self.leds.try_send(LedPattern::RxActive);
+
+                            // Broadcast unsolicited to all subscribers (serial, BLE)
+                            self.responses
+                                .publish_immediate(ResponseMessage::Unsolicited(response));
+                        }
+                    }
+                    // Timeout is the normal idle case; other errors are counted
+                    // and just re-loop.
+                    Err(LoraError::Timeout) => self.send_relays(radio).await,
+                    Err(error @ (LoraError::SpiError | LoraError::BusyTimeout)) => {
+                        update_stats(|stats| stats.rx_errors = stats.rx_errors.wrapping_add(1));
+                        self.recover_radio(radio, error).await;
+                    }
+                    Err(_) => {
+                        update_stats(|stats| stats.rx_errors = stats.rx_errors.wrapping_add(1));
+                        self.note_link_failure();
+                    }
+                }
+                Handoff::None
+            }
+        }
+    }
+
+    /// Wait out whatever is left of the SetTxGap since the last transmission
+    async fn wait_tx_gap(&self) {
+        let Some(last_tx_ms) = self.last_tx_ms else {
+            return;
+        };
+        let ready_ms = last_tx_ms + u64::from(self.dispatcher.tx_gap_ms());
+        let wait_ms = ready_ms.saturating_sub(clock::now_ms());
+        if wait_ms > 0 {
+            crate::trace!("LoRa TX: Holding {} ms for the TX gap", wait_ms);
+            Timer::after_millis(wait_ms).await;
+        }
+    }
+
+    /// Add the messages the dispatcher dropped as already delivered to the
+    /// link counters
+    fn count_duplicates(&mut self) {
+        let duplicates = self.dispatcher.take_duplicates();
+        if duplicates > 0 {
+            update_stats(|stats| stats.rx_duplicates = stats.rx_duplicates.wrapping_add(duplicates));
+        }
+    }
+
+    /// Check the latest battery sample against the SetLowBatteryPolicy,
+    /// telling every interface when the battery has just run low
+    fn check_battery(&mut self) {
+        let battery_mv = battery::millivolts();
+        if self.dispatcher.update_battery(battery_mv) {
+            crate::debug!("Battery: Low at {} mV", battery_mv);
+            self.responses
+                .publish_immediate(ResponseMessage::Unsolicited(Response::Event(Event::LowBattery {
+                    battery_mv: u16::try_from(battery_mv).unwrap_or(u16::MAX),
+                })));
+        }
+    }
+
+    /// Switch to the SetRxDutyCycle windows once the link has been idle for
+    /// `IDLE_SECS`, and back to continuous RX when there is activity
+    ///
+    /// A failure (e.g. the radio is asleep) leaves the current mode in place,
+    /// to be retried on the next step.
+    async fn apply_idle_policy<R: LoraRadio>(&mut self, radio: &mut R) {
+        if self.radio_fault.is_some() {
+            return;
+        }
+        let idle_ms = clock::now_ms().saturating_sub(self.last_activity_ms);
+        let wanted = self
+            .dispatcher
+            .rx_duty_cycle()
+            .filter(|_| idle_ms >= config::rx_duty_cycle::IDLE_SECS * 1000);
+        if wanted == self.rx_windows {
+            return;
+        }
+        match radio.set_rx_duty_cycle(wanted).await {
+            Ok(()) => {
+                crate::debug!("LoRa: {} RX", if wanted.is_some() { "Duty-cycled" } else { "Continuous" });
+                self.rx_windows = wanted;
+            }
+            Err(_) => crate::error!("LoRa: Failed to change RX mode"),
+        }
+    }
+
+    /// Dispatch a single host command and publish its response.
+    async fn handle_command<R: LoraRadio>(&mut self, radio: &mut R, envelope: CommandEnvelope) -> Handoff {
+        // Signal LED flash for command (non-blocking)
+        let _ = self.leds.try_send(LedPattern::TxActive);
+
+        // Any SPI access wakes the radio out of a sleep window anyway, so
+        // go back to continuous RX before the command touches it
+        self.last_activity_ms = clock::now_ms();
+        self.apply_idle_policy(radio).await;
+
+        // Admin commands are handled by the admin task; no response is sent here.
+        if let Command::Reboot = &envelope.command {
+            return Handoff::Reboot;
+        }
+
+        // The link counters live here
+        if let Some(response) = handle_stats_command(&envelope.command) {
+            self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+            return Handoff::None;
+        }
+
+        if let Command::GetRecentPackets = &envelope.command {
+            self.replay_recent(&envelope);
+            return Handoff::None;
+        }
+
+        // Only the runner knows which interface sent the command
+        let interface_setting = match envelope.command {
+            Command::SetSequenceEcho { enabled } => {
+                set_sequence_echo(envelope.source, enabled);
+                true
+            }
+            Command::SetRxChecksum { enabled } => {
+                set_rx_checksum(envelope.source, enabled);
+                true
+            }
+            _ => false,
+        };
+        if interface_setting {
+            let response = Response::Ack {
+                command: envelope.command.id(),
+            };
+            self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+            return Handoff::None;
+        }
+
+        // The runner holds the LED sender; the LED task keeps manual
+        // patterns until Auto
+        if let Command::SetLed { mode } = envelope.command {
+            let response = match LedPattern::from_mode(mode) {
+                Some(pattern) if self.leds.try_send(pattern).is_ok() => Response::Ack {
+                    command: CommandId::SetLed,
+                },
+                Some(_) => Response::error(ResponseStatus::Busy, CommandId::SetLed),
+                None => Response::error(ResponseStatus::InvalidLength, CommandId::SetLed),
+            };
+            self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+            return Handoff::None;
+        }
+
+        // Fail fast rather than let the command time out on a dead radio
+        if self.radio_fault.is_some() && needs_radio(&envelope.command) {
+            let response = Response::error(ResponseStatus::NotInitialised, envelope.command.id());
+            self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+            return Handoff::None;
+        }
+
+        // Log TX command if it's a LoraTx
+        if let Command::LoraTx { ref data }
+        | Command::LoraTxAddressed { ref data, .. }
+        | Command::LoraTxPower { ref data, .. }
+        | Command::LoraTxReliable { ref data, .. }
+        | Command::StreamData { ref data } = envelope.command
+        {
+            if let Ok(s) = core::str::from_utf8(data) {
+                crate::trace!("LoRa TX: '{}'", s);
+            } else {
+                crate::trace!("LoRa TX: {} bytes", data.len());
+            }
+        }
+
+        let is_lora_tx = matches!(
+            envelope.command,
+            Command::LoraTx { .. }
+                | Command::LoraTxAddressed { .. }
+                | Command::LoraTxPower { .. }
+                | Command::LoraTxReliable { .. }
+                | Command::StreamData { .. }
+        );
+        let is_stream_data = matches!(envelope.command, Command::StreamData { .. });
+        let is_lora_rx = matches!(envelope.command, Command::LoraRx { .. });
+        let is_reliable = matches!(envelope.command, Command::LoraTxReliable { .. });
+        let retunes = matches!(envelope.command, Command::SetBand { .. } | Command::SetLoraConfig { .. });
+        let sleep_ms = match envelope.command {
+            Command::Sleep { duration_ms } => Some(duration_ms),
+            _ => None,
+        };
+
+        // Hold a transmission back until SetTxGap has passed since the last
+        // one. The budget below is checked and charged from when it actually
+        // goes out, after the wait.
+        let airtime_ms = self.dispatcher.tx_airtime_ms(&envelope.command);
+        if airtime_ms.is_some() {
+            self.wait_tx_gap().await;
+        }
+
+        // Refuse transmissions that would exceed the duty-cycle budget
+        let now_ms = clock::now_ms();
+        let band_limit = self.dispatcher.band().duty_cycle_permille();
+        if let Some(airtime_ms) = airtime_ms.filter(|_| config::duty_cycle::ENABLED && band_limit.is_some()) {
+            if !self.duty_cycle.allows(now_ms, airtime_ms) {
+                crate::debug!(
+                    "LoRa TX: Duty cycle exceeded ({} ms used, {} ms requested)",
+                    self.duty_cycle.used_ms(now_ms),
+                    airtime_ms
+                );
+                update_stats(|stats| stats.duty_cycle_rejections = stats.duty_cycle_rejections.wrapping_add(1));
+                let response = Response::error(ResponseStatus::DutyCycleExceeded, envelope.command.id());
+                self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+                return Handoff::None;
+            }
+        }
+
+        let mut response = self.dispatcher.dispatch(radio, envelope.command).await;
+
+        // ACKs the command sent while it listened use the channel too
+        let ack_airtime_ms = self.dispatcher.take_ack_airtime_ms();
+        if ack_airtime_ms > 0 {
+            self.duty_cycle.record(clock::now_ms(), ack_airtime_ms);
+        }
+
+        // Echo the sequence id so a host pipelining transmissions can tell
+        // which one completed
+        if let Response::TxComplete { seq, .. } = &mut response {
+            *seq = envelope.sequence_id;
+        }
+
+        // Only completed transmissions are charged against the budget. A
+        // reliable send that timed out waiting for its ACK still used every
+        // attempt.
+        if let Some(airtime_ms) = airtime_ms {
+            let sent = matches!(response, Response::TxComplete { .. } | Response::Ack { .. } | Response::Loopback { .. })
+                || matches!(response, Response::Error { status: ResponseStatus::Timeout, .. });
+            if sent {
+                self.duty_cycle.record(now_ms, airtime_ms);
+                self.last_tx_ms = Some(clock::now_ms());
+            }
+        }
+        let sleep_ms = sleep_ms.filter(|_| matches!(response, Response::Ack { .. }));
+
+        // A channel the host picks replaces any hop, and its band brings its
+        // own duty-cycle limit (airtime already used still counts against it)
+        if retunes && matches!(response, Response::Ack { .. }) {
+            self.hops.set_position(0);
+            if let Some(limit_permille) = self.dispatcher.band().duty_cycle_permille() {
+                self.duty_cycle.set_limit(limit_permille);
+            }
+        }
+
+        // Count the outcome for GetStats
+        match &response {
+            Response::TxComplete { .. } => update_stats(|stats| stats.tx_messages = stats.tx_messages.wrapping_add(1)),
+            Response::RxPacket { .. } => {
+                update_stats(|stats| stats.rx_messages = stats.rx_messages.wrapping_add(1));
+                self.remember(&response);
+            }
+            Response::Error { .. } if is_lora_tx => update_stats(|stats| stats.tx_errors = stats.tx_errors.wrapping_add(1)),
+            Response::Error { status, .. } if is_lora_rx && *status != ResponseStatus::Timeout => {
+                update_stats(|stats| stats.rx_errors = stats.rx_errors.wrapping_add(1))
+            }
+            _ => {}
+        }
+
+        // A CRC error, or a reliable send whose every attempt went unACKed,
+        // points at a busy channel
+        let link_failed = match &response {
+            Response::Error { status: ResponseStatus::Timeout, .. } => is_reliable,
+            Response::Error { status, .. } => is_lora_rx && *status != ResponseStatus::Timeout,
+            _ => false,
+        };
+
+        // Log response
+        match &response {
+            Response::Version { major, minor, patch, .. } => {
+                crate::trace!("Version: {}.{}.{}", major, minor, patch);
+            }
+            Response::TxComplete { seq, len } => crate::trace!("LoRa TX: Complete (seq {}, {} bytes)", seq, len),
+            Response::Error { status, .. } => crate::error!("LoRa TX: Failed ({:?})", status),
+            _ => {}
+        }
+
+        let chunk_sent = is_stream_data && matches!(response, Response::TxComplete { .. });
+
+        // Publish command response (subscribers filter by source)
+        self.responses.publish_immediate(ResponseMessage::reply(&envelope, response));
+
+        if let Some((sent, total)) = self.dispatcher.stream_progress().filter(|_| chunk_sent) {
+            self.responses
+                .publish_immediate(ResponseMessage::Unsolicited(Response::Event(Event::StreamProgress { sent, total })));
+        }
+
+        self.publish_deferred();
+        self.follow_hop(radio).await;
+
+        if link_failed {
+            self.note_link_failure();
+        }
+
+        match sleep_ms {
+            Some(duration_ms) => Handoff::Sleep { duration_ms },
+            None => Handoff::None,
+        }
+    }
+
+    /// Publish the messages that arrived while a command was listening for
+    /// something else
+    fn publish_deferred(&mut self) {
+        while let Some(deferred) = self.dispatcher.take_deferred() {
+            self.remember(&deferred);
+            self.responses.publish_immediate(ResponseMessage::Unsolicited(deferred));
+        }
+    }
+
+    /// Repeat the frames SetRepeater queued, within the duty-cycle budget
+    ///
+    /// A frame the budget cannot cover is dropped rather than held, and
+    /// counted as a rejection.
+    async fn send_relays<R: LoraRadio>(&mut self, radio: &mut R) {
+        while let Some((frame, airtime_ms)) = self.dispatcher.take_relay() {
+            let now_ms = clock::now_ms();
+            let limited = config::duty_cycle::ENABLED && self.dispatcher.band().duty_cycle_permille().is_some();
+            if limited && !self.duty_cycle.allows(now_ms, airtime_ms) {
+                crate::debug!("LoRa TX: Duty cycle exceeded, relay dropped");
+                update_stats(|stats| stats.duty_cycle_rejections = stats.duty_cycle_rejections.wrapping_add(1));
+                continue;
+            }
+            let sent = match self.dispatcher.tx_power(None) {
+                Some(power_dbm) => radio.transmit_with_power(&frame, power_dbm).await,
+                None => radio.transmit(&frame).await,
+            };
+            match sent {
+                Ok(()) => self.duty_cycle.record(now_ms, airtime_ms),
+                Err(_) => crate::error!("LoRa TX: Relay failed"),
+            }
+        }
+    }
+
+    /// Keep a received message for GetRecentPackets, dropping the oldest
+    /// once the buffer is full
+    fn remember(&mut self, response: &Response) {
+        let Response::RxPacket { source, data, rssi, snr, .. } = response else {
+            return;
+        };
+        if self.recent.is_full() {
+            self.recent.pop_front();
+        }
+        let kept = &data[..data.len().min(config::recent_packets::DATA_CAP)];
+        let _ = self.recent.push_back(RecentPacket {
+            source: *source,
+            data: Vec::from_slice(kept).unwrap_or_default(),
+            rssi: *rssi,
+            snr: *snr,
+        });
+    }
+
+    /// Answer GetRecentPackets: each kept message as an RxPacket, oldest
+    /// first, then an Ack marking the end of the replay
+    fn replay_recent(&self, envelope: &CommandEnvelope) {
+        for packet in &self.recent {
+            let response = Response::RxPacket {
+                source: packet.source,
+                data: Vec::from_slice(&packet.data).unwrap_or_default(),
+                rssi: packet.rssi,
+                snr: packet.snr,
+                // Over the bytes kept, which may be fewer than received
+                crc32: Some(crc32::checksum(&packet.data)),
+            };
+            self.responses.publish_immediate(ResponseMessage::reply(envelope, response));
+        }
+        let done = Response::Ack {
+            command: CommandId::GetRecentPackets,
+        };
+        self.responses.publish_immediate(ResponseMessage::reply(envelope, done));
+    }
+
+    /// Count a link failure, proposing a channel hop on the next step once
+    /// there have been `ERROR_THRESHOLD` within the window (see `lora::hop`)
+    fn note_link_failure(&mut self) {
+        if config::channel_hop::ENABLED && self.collisions.record(clock::now_ms()) {
+            self.hop_due = true;
+        }
+    }
+
+    /// Move to the next channel of the hop sequence if a peer agrees
+    ///
+    /// The hop is announced on the current channel and, if no peer confirms
+    /// it there, again on the new channel, where a peer that heard an earlier
+    /// announcement may already be. With no confirmation on either, the
+    /// device goes back rather than strand itself alone.
+    async fn propose_hop<R: LoraRadio>(&mut self, radio: &mut R) {
+        let position = self.hops.next(self.dispatcher.frequency_hz());
+        let Some(channel) = self.hops.channel(position) else {
+            return;
+        };
+        if self.announce_hop(radio, position).await {
+            if self.tune(radio, position, channel.freq_hz, Some(channel.duty_cycle_permille)).await {
+                crate::debug!("LoRa: Repeated collisions, hopped to {} Hz", channel.freq_hz);
+            }
+            return;
+        }
+
+        let (old_position, old_hz) = (self.hops.position(), self.dispatcher.frequency_hz());
+        let old_limit = match self.hops.channel(old_position) {
+            Some(old) => Some(old.duty_cycle_permille),
+            None => self.dispatcher.band().duty_cycle_permille(),
+        };
+        if !self.tune(radio, position, channel.freq_hz, Some(channel.duty_cycle_permille)).await {
+            return;
+        }
+        if self.announce_hop(radio, position).await {
+            crate::debug!("LoRa: Repeated collisions, hopped to {} Hz", channel.freq_hz);
+            return;
+        }
+        crate::debug!("LoRa: Hop to {} Hz not confirmed, staying on {} Hz", channel.freq_hz, old_hz);
+        self.tune(radio, old_position, old_hz, old_limit).await;
+    }
+
+    /// Broadcast an announcement of the hop to `position` and wait for a
+    /// peer to confirm it, within the duty-cycle budget
+    async fn announce_hop<R: LoraRadio>(&mut self, radio: &mut R, position: u8) -> bool {
+        let limited = config::duty_cycle::ENABLED && self.dispatcher.band().duty_cycle_permille().is_some();
+        let now_ms = clock::now_ms();
+        if limited && !self.duty_cycle.allows(now_ms, self.dispatcher.hop_airtime_ms()) {
+            crate::debug!("LoRa TX: Duty cycle exceeded, hop not announced");
+            return false;
+        }
+        let Some(airtime_ms) = self
+            .dispatcher
+            .send_hop(radio, addressing::BROADCAST, Hop::Announce(position))
+            .await
+        else {
+            return false;
+        };
+        self.duty_cycle.record(now_ms, airtime_ms);
+        let confirmed = self.dispatcher.wait_for_hop_confirm(radio, position).await;
+        let ack_airtime_ms = self.dispatcher.take_ack_airtime_ms();
+        if ack_airtime_ms > 0 {
+            self.duty_cycle.record(clock::now_ms(), ack_airtime_ms);
+        }
+        self.publish_deferred();
+        confirmed
+    }
+
+    /// Act on a hop a peer announced, if channel agility is enabled
+    async fn follow_hop<R: LoraRadio>(&mut self, radio: &mut R) {
+        if let Some((source, Hop::Announce(position))) = self.dispatcher.take_hop() {
+            if config::channel_hop::ENABLED {
+                self.follow(radio, source, position).await;
+            }
+        }
+    }
+
+    /// Confirm the hop to `position` that `source` announced, and follow it
+    ///
+    /// The confirmation goes out on the channel the announcement came in on,
+    /// before moving, so the peer hears it. A listener follows without
+    /// confirming.
+    async fn follow<R: LoraRadio>(&mut self, radio: &mut R, source: Address, position: u8) {
+        let Some(channel) = self.hops.channel(position) else {
+            return;
+        };
+        if let Some(airtime_ms) = self.dispatcher.send_hop(radio, source, Hop::Confirm(position)).await {
+            self.duty_cycle.record(clock::now_ms(), airtime_ms);
+        }
+        if position != self.hops.position()
+            && self.tune(radio, position, channel.freq_hz, Some(channel.duty_cycle_permille)).await
+        {
+            crate::debug!("LoRa: Followed hop to {} Hz", channel.freq_hz);
+        }
+    }
+
+    /// Retune to `freq_hz` as hop `position`, applying the sub-band's
+    /// duty-cycle limit and telling every interface
+    async fn tune<R: LoraRadio>(
+        &mut self,
+        radio: &mut R,
+        position: u8,
+        freq_hz: u32,
+        limit_permille: Option<u32>,
+    ) -> bool {
+        if self.dispatcher.set_frequency(radio, freq_hz).await.is_err() {
+            crate::error!("LoRa: Channel hop to {} Hz failed", freq_hz);
+            return false;
+        }
+        self.hops.set_position(position);
+        if let Some(limit_permille) = limit_permille {
+            self.duty_cycle.set_limit(limit_permille);
+        }
+        self.responses
+            .publish_immediate(ResponseMessage::Unsolicited(Response::Event(Event::ChannelChanged { freq_hz })));
+        true
+    }
+}
+
+/// Whether `command` can only be served by a working radio
+///
+/// SelfTest and GetRadioState are left out on purpose: they are how a host
+/// diagnoses a radio that failed to initialise.
+fn needs_radio(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::LoraTx { .. }
+            | Command::LoraTxAddressed { .. }
+            | Command::LoraTxPower { .. }
+            | Command::LoraTxReliable { .. }
+            | Command::StreamData { .. }
+            | Command::LoraRx { .. }
+            | Command::TxCarrier { .. }
+            | Command::SetLoraConfig { .. }
+            | Command::SetModulation { .. }
+            | Command::SetBand { .. }
+            | Command::GetRssi
+            | Command::SpectrumScan { .. }
+            | Command::GetRandom
+            | Command::GetTemperature
+            | Command::Sleep { .. }
+            | Command::LoraLoopback
+    )
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::crypto;
+    use crate::dispatcher::{sequence_echo, CommandSource};
+    use crate::led::led_mode;
+    use crate::lora::addressing::{self, BROADCAST};
+    use crate::lora::fragment;
+    use crate::lora::traits::mock::MockLoraRadio;
+    use crate::lora::traits::RxPacket;
+    use embassy_sync::channel::Channel;
+    use embassy_sync::pubsub::{PubSubChannel, WaitResult};
+    use heapless::Vec;
+
+    const DEVICE_ID: Address = [0x12, 0x34, 0x56];
+    const PEER: Address = [0xAB, 0xCD, 0xEF];
+
+    /// Channels a runner under test is wired to
+    struct Harness {
+        commands: Channel<CriticalSectionRawMutex, CommandEnvelope, 4>,
+        responses: PubSubChannel<CriticalSectionRawMutex, ResponseMessage, 8, 1, 1>,
+        leds: Channel<CriticalSectionRawMutex, LedPattern, 4>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            embassy_time::MockDriver::get().reset();
+            clock::set_ms(0);
+            Self {
+                commands: Channel::new(),
+                responses: PubSubChannel::new(),
+                leds: Channel::new(),
+            }
+        }
+
+        fn runner(&self) -> LoraRunner<'_> {
+            LoraRunner::new(
+                DEVICE_ID,
+                self.commands.dyn_receiver(),
+                self.responses.dyn_immediate_publisher(),
+                self.leds.dyn_sender(),
+            )
+        }
+
+        /// Queue a host command from the serial interface
+        fn send(&self, command: Command, sequence_id: u16) {
+            self.commands
+                .try_send(CommandEnvelope {
+                    command,
+                    source: CommandSource::Serial,
+                    sequence_id,
+                    host_sequence: None,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Seal a single-fragment message from `PEER` to `dest` as received
+    fn rx_frame(dest: Address, data: &[u8]) -> RxPacket {
+        rx_message(dest, 0, data)
+    }
+
+    /// Seal single-fragment message `msg_id` from `PEER` to `dest` as received
+    fn rx_message(dest: Address, msg_id: u8, data: &[u8]) -> RxPacket {
+        let frame = addressing::encode(dest, PEER, &fragment::encode_fragment(msg_id, 0, 1, data)).unwrap();
+        let sealed = crypto::seal(&frame, &[0x5A; crypto::NONCE_LEN]).unwrap();
+        let mut packet_data = Vec::new();
+        packet_data.extend_from_slice(&sealed).unwrap();
+        RxPacket {
+            data: packet_data,
+            rssi: -60,
+            snr: 7,
+        }
+    }
+
+    /// Next published response, which must already be waiting
+    fn next_response(
+        subscriber: &mut embassy_sync::pubsub::DynSubscriber<'_, ResponseMessage>,
+    ) -> ResponseMessage {
+        match subscriber.try_next_message() {
+            Some(WaitResult::Message(message)) => message,
+            other => panic!("Expected a published response, got {:?}", other),
+        }
+    }
+
+    /// Run `fut` to completion, moving the mock time driver on whenever it
+    /// is waiting on a timer
+    fn run_with_timers<F: core::future::Future>(fut: F) -> F::Output {
+        let waker = futures::task::noop_waker();
+        let mut cx = core::task::Context::from_waker(&waker);
+        let mut fut = core::pin::pin!(fut);
+        loop {
+            if let core::task::Poll::Ready(value) = fut.as_mut().poll(&mut cx) {
+                return value;
+            }
+            embassy_time::MockDriver::get().advance(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn received_packet_is_published_unsolicited() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.queue_rx_packet(rx_frame(DEVICE_ID, b"hello"));
+            assert_eq!(runner.step(&mut radio).await, Handoff::None);
+        });
+
+        let ResponseMessage::Unsolicited(Response::RxPacket { source, data, .. }) = next_response(&mut subscriber) else {
+            panic!("Expected unsolicited RxPacket");
+        };
+        assert_eq!(source, PEER);
+        assert_eq!(&data[..], b"hello");
+        assert_eq!(harness.leds.try_receive(), Ok(LedPattern::RxActive));
+        assert!(subscriber.try_next_message().is_none());
+    }
+
+    #[test]
+    fn packet_for_another_device_is_not_published() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            radio.queue_rx_packet(rx_frame([0x01, 0x02, 0x03], b"not ours"));
+            runner.step(&mut radio).await;
+        });
+
+        assert!(subscriber.try_next_message().is_none());
+        assert!(harness.leds.try_receive().is_err());
+    }
+
+    #[test]
+    fn repeater_passes_frames_on_once_the_channel_is_quiet() {
+        let harness = Harness::new();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            harness.send(Command::SetRepeater { enabled: true }, 1);
+            runner.step(&mut radio).await;
+
+            radio.queue_rx_packet(rx_frame([0x01, 0x02, 0x03], b"not ours"));
+            runner.step(&mut radio).await;
+            // Held while the rest of a message might still be on its way
+            assert!(radio.get_tx_history().is_empty());
+
+            // The next listen times out and the frame goes out
+            runner.step(&mut radio).await;
+        });
+
+        let history = radio.get_tx_history();
+        assert_eq!(history.len(), 1);
+        let frame = crypto::open(&history[0]).unwrap();
+        let (dest, source, ttl, _) = addressing::decode(&frame).unwrap();
+        assert_eq!((dest, source, ttl), ([0x01, 0x02, 0x03], PEER, config::repeater::DEFAULT_TTL - 1));
+    }
+
+    #[test]
+    fn packet_below_rx_filter_is_counted_not_published() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            harness.send(
+                Command::SetRxFilter {
+                    min_rssi_dbm: -80,
+                    min_snr_db: 0,
+                },
+                1,
+            );
+            runner.step(&mut radio).await;
+            assert!(matches!(next_response(&mut subscriber), ResponseMessage::Command { .. }));
+
+            let weak = RxPacket {
+                rssi: -100,
+                ..rx_frame(DEVICE_ID, b"weak")
+            };
+            radio.queue_rx_packet(weak);
+            runner.step(&mut radio).await;
+            assert!(subscriber.try_next_message().is_none());
+
+            radio.queue_rx_packet(rx_frame(DEVICE_ID, b"strong"));
+            runner.step(&mut radio).await;
+            let ResponseMessage::Unsolicited(Response::RxPacket { data, .. }) = next_response(&mut subscriber) else {
+                panic!("Expected unsolicited RxPacket");
+            };
+            assert_eq!(&data[..], b"strong");
+
+            harness.send(Command::GetStats, 2);
+            runner.step(&mut radio).await;
+        });
+
+        let ResponseMessage::Command {
+            response: Response::Stats { rx_filtered, .. },
+            ..
+        } = next_response(&mut subscriber)
+        else {
+            panic!("Expected Stats");
+        };
+        assert!(rx_filtered >= 1);
+    }
+
+    #[test]
+    fn message_heard_twice_is_published_once_and_counted() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            // The same message by two paths, then the sender's next one
+            radio.queue_rx_packet(rx_message(DEVICE_ID, 7, b"twice"));
+            runner.step(&mut radio).await;
+            clock::advance_ms(config::repeater::SEEN_WINDOW_MS);
+            radio.queue_rx_packet(rx_message(DEVICE_ID, 7, b"twice"));
+            runner.step(&mut radio).await;
+            radio.queue_rx_packet(rx_message(DEVICE_ID, 8, b"next"));
+            runner.step(&mut radio).await;
+
+            for expected in [&b"twice"[..], b"next"] {
+                let ResponseMessage::Unsolicited(Response::RxPacket { data, .. }) = next_response(&mut subscriber)
+                else {
+                    panic!("Expected unsolicited RxPacket");
+                };
+                assert_eq!(&data[..], expected);
+            }
+            assert!(subscriber.try_next_message().is_none());
+
+            harness.send(Command::GetStats, 1);
+            runner.step(&mut radio).await;
+        });
+        let ResponseMessage::Command {
+            response: Response::Stats { rx_duplicates, .. },
+            ..
+        } = next_response(&mut subscriber)
+        else {
+            panic!("Expected Stats");
+        };
+        assert!(rx_duplicates >= 1);
+    }
+
+    #[test]
+    fn recent_packets_are_replayed_to_the_requester() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            let long = [b'x'; config::recent_packets::DATA_CAP + 10];
+            radio.queue_rx_packet(rx_frame(DEVICE_ID, b"first"));
+            runner.step(&mut radio).await;
+            radio.queue_rx_packet(rx_message(DEVICE_ID, 1, &long));
+            runner.step(&mut radio).await;
+            next_response(&mut subscriber);
+            next_response(&mut subscriber);
+
+            harness.send(Command::GetRecentPackets, 3);
+            runner.step(&mut radio).await;
+        });
+
+        let mut replayed = std::vec::Vec::new();
+        while let Some(WaitResult::Message(message)) = subscriber.try_next_message() {
+            let ResponseMessage::Command { sequence_id: 3, response, .. } = message else {
+                panic!("Expected replies to GetRecentPackets");
+            };
+            replayed.push(response);
+        }
+        assert_eq!(replayed.len(), 3);
+        let Response::RxPacket { source, data, .. } = &replayed[0] else {
+            panic!("Expected RxPacket");
+        };
+        assert_eq!((*source, &data[..]), (PEER, &b"first"[..]));
+        let Response::RxPacket { data, crc32, .. } = &replayed[1] else {
+            panic!("Expected RxPacket");
+        };
+        assert_eq!(data.len(), config::recent_packets::DATA_CAP);
+        // The checksum covers what is replayed, not the longer original
+        assert_eq!(*crc32, Some(crc32::checksum(data)));
+        assert!(matches!(replayed[2], Response::Ack { command: CommandId::GetRecentPackets }));
+    }
+
+    #[test]
+    fn sequence_echo_is_set_for_the_requesting_interface_only() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            harness.send(Command::SetSequenceEcho { enabled: true }, 9);
+            runner.step(&mut radio).await;
+        });
+        let ResponseMessage::Command { sequence_id: 9, response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Ack { command: CommandId::SetSequenceEcho }));
+        assert!(sequence_echo(CommandSource::Serial));
+        assert!(!sequence_echo(CommandSource::Ble(0)));
+        assert!(!sequence_echo(CommandSource::Internal));
+
+        futures::executor::block_on(async {
+            harness.send(Command::SetSequenceEcho { enabled: false }, 10);
+            runner.step(&mut radio).await;
+        });
+        next_response(&mut subscriber);
+        assert!(!sequence_echo(CommandSource::Serial));
+    }
+
+    #[test]
+    fn host_sequence_is_returned_with_the_response() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            for (command, host_sequence) in [(Command::GetVersion, Some(1000)), (Command::GetStats, Some(1002))] {
+                harness
+                    .commands
+                    .try_send(CommandEnvelope {
+                        command,
+                        source: CommandSource::Serial,
+                        sequence_id: 4,
+                        host_sequence,
+                    })
+                    .unwrap();
+                runner.step(&mut radio).await;
+            }
+        });
+
+        // Both from the dispatcher and from the runner's own replies
+        for expected in [1000, 1002] {
+            let ResponseMessage::Command {
+                sequence_id: 4,
+                host_sequence,
+                ..
+            } = next_response(&mut subscriber)
+            else {
+                panic!("Expected a command response");
+            };
+            assert_eq!(host_sequence, Some(expected));
+        }
+    }
+
+    #[test]
+    fn set_led_hands_the_pattern_to_the_led_task() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            harness.send(Command::SetLed { mode: led_mode::BLINK }, 1);
+            runner.step(&mut radio).await;
+            harness.send(Command::SetLed { mode: 9 }, 2);
+            runner.step(&mut radio).await;
+        });
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Ack { command: CommandId::SetLed }));
+        assert_eq!(harness.leds.try_receive(), Ok(LedPattern::Blink));
+
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidLength, .. }));
+        assert!(harness.leds.try_receive().is_err());
+    }
+
+    #[test]
+    fn stream_chunks_report_progress_and_idle_streams_expire() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            harness.send(Command::StreamBegin { dest: PEER, total_len: 4 }, 1);
+            runner.step(&mut radio).await;
+            next_response(&mut subscriber);
+
+            let mut data = Vec::new();
+            data.extend_from_slice(b"ab").unwrap();
+            harness.send(Command::StreamData { data }, 2);
+            runner.step(&mut radio).await;
+            assert!(matches!(
+                next_response(&mut subscriber),
+                ResponseMessage::Command { sequence_id: 2, response: Response::TxComplete { .. }, .. }
+            ));
+            assert!(matches!(
+                next_response(&mut subscriber),
+                ResponseMessage::Unsolicited(Response::Event(Event::StreamProgress { sent: 2, total: 4 }))
+            ));
+
+            // The host goes quiet: the stream is dropped on a later step
+            clock::advance_ms(config::stream::IDLE_TIMEOUT_MS + 1);
+            runner.step(&mut radio).await;
+            harness.send(Command::StreamEnd, 3);
+            runner.step(&mut radio).await;
+        });
+
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Error { status: ResponseStatus::InvalidCommand, .. }));
+    }
+
+    #[test]
+    fn duty_cycle_budget_frees_up_as_airtime_ages_out() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let carrier_ms = config::lora_defaults::MAX_CARRIER_MS;
+        let budget_ms = config::duty_cycle::WINDOW_SECS as u32 * config::duty_cycle::LIMIT_PERMILLE;
+
+        let mut carrier = |runner: &mut LoraRunner<'_>, radio: &mut MockLoraRadio| {
+            harness.send(Command::TxCarrier { duration_ms: carrier_ms }, 1);
+            futures::executor::block_on(runner.step(radio));
+            let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+                panic!("Expected a command response");
+            };
+            response
+        };
+
+        futures::executor::block_on(runner.init(&mut radio));
+        for _ in 0..budget_ms / carrier_ms {
+            assert!(matches!(carrier(&mut runner, &mut radio), Response::Ack { .. }));
+        }
+        assert!(matches!(
+            carrier(&mut runner, &mut radio),
+            Response::Error { status: ResponseStatus::DutyCycleExceeded, .. }
+        ));
+
+        // A window later the airtime has aged out
+        clock::advance_ms(config::duty_cycle::WINDOW_SECS * 1000);
+        assert!(matches!(carrier(&mut runner, &mut radio), Response::Ack { .. }));
+    }
+
+    #[test]
+    fn acks_sent_by_a_command_count_against_the_duty_cycle() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let fragment = fragment::encode_fragment(4, 0, 1 | fragment::ACK_REQUESTED, b"hi");
+        let frame = addressing::encode(DEVICE_ID, PEER, &fragment).unwrap();
+        let sealed = crypto::seal(&frame, &[0x5A; crypto::NONCE_LEN]).unwrap();
+        let mut data = Vec::new();
+        data.extend_from_slice(&sealed).unwrap();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            radio.queue_rx_packet(RxPacket { data, rssi: -60, snr: 7 });
+            harness.send(Command::LoraRx { timeout_ms: 1_000 }, 1);
+            runner.step(&mut radio).await;
+        });
+
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::RxPacket { .. }));
+        // LoraRx transmits nothing itself; the ACK it sent is what is charged
+        assert_eq!(radio.get_tx_history().len(), 1);
+        assert!(runner.duty_cycle.used_ms(clock::now_ms()) > 0);
+    }
+
+    /// Seal a channel hop frame from `PEER` to `dest` as received
+    fn rx_hop(dest: Address, hop: Hop) -> RxPacket {
+        let frame = addressing::encode(dest, PEER, &fragment::encode_hop(hop)).unwrap();
+        let sealed = crypto::seal(&frame, &[0x5A; crypto::NONCE_LEN]).unwrap();
+        let mut data = Vec::new();
+        data.extend_from_slice(&sealed).unwrap();
+        RxPacket { data, rssi: -60, snr: 7 }
+    }
+
+    /// Destination and hop frame of a sealed transmission
+    fn sent_hop(sealed: &[u8]) -> (Address, Option<Hop>) {
+        let frame = crypto::open(sealed).unwrap();
+        let (dest, _, _, payload) = addressing::decode(&frame).unwrap();
+        (dest, fragment::decode_hop(payload))
+    }
+
+    #[test]
+    fn announced_hop_is_confirmed_then_followed() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let channel = runner.hops.channel(1).unwrap();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            // Ignored while channel agility is off
+            radio.queue_rx_packet(rx_hop(BROADCAST, Hop::Announce(1)));
+            runner.step(&mut radio).await;
+            assert!(radio.get_tx_history().is_empty());
+            runner.follow(&mut radio, PEER, 1).await;
+        });
+
+        // Confirmed to the announcer, then retuned
+        let sent = radio.get_tx_history();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent_hop(&sent[0]), (PEER, Some(Hop::Confirm(1))));
+        assert_eq!(radio.get_config().unwrap().frequency_hz, channel.freq_hz);
+        assert_eq!(runner.hops.position(), 1);
+        assert!(matches!(
+            next_response(&mut subscriber),
+            ResponseMessage::Unsolicited(Response::Event(Event::ChannelChanged { freq_hz })) if freq_hz == channel.freq_hz
+        ));
+
+        // The new sub-band's limit applies
+        let budget_ms = config::duty_cycle::WINDOW_SECS as u32 * channel.duty_cycle_permille;
+        let used_ms = runner.duty_cycle.used_ms(clock::now_ms());
+        assert!(runner.duty_cycle.allows(clock::now_ms(), budget_ms - used_ms));
+        assert!(!runner.duty_cycle.allows(clock::now_ms(), budget_ms - used_ms + 1));
+    }
+
+    #[test]
+    fn proposed_hop_moves_once_a_peer_confirms() {
+        let harness = Harness::new();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            let position = runner.hops.next(runner.dispatcher.frequency_hz());
+            radio.queue_rx_packet(rx_hop(DEVICE_ID, Hop::Confirm(position)));
+            runner.propose_hop(&mut radio).await;
+            assert_eq!(runner.hops.position(), position);
+            let channel = runner.hops.channel(position).unwrap();
+            assert_eq!(radio.get_config().unwrap().frequency_hz, channel.freq_hz);
+        });
+
+        // Announced once, to every node, on the old channel
+        let sent = radio.get_tx_history();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent_hop(&sent[0]), (BROADCAST, Some(Hop::Announce(runner.hops.position()))));
+    }
+
+    #[test]
+    fn unconfirmed_hop_goes_back_to_the_old_channel() {
+        let harness = Harness::new();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        let home_hz = run_with_timers(async {
+            runner.init(&mut radio).await;
+            let home_hz = runner.dispatcher.frequency_hz();
+            runner.propose_hop(&mut radio).await;
+            home_hz
+        });
+
+        // Announced on the old channel and again on the new one
+        assert_eq!(radio.get_tx_history().len(), 2);
+        assert_eq!(runner.hops.position(), 0);
+        assert_eq!(runner.dispatcher.frequency_hz(), home_hz);
+        assert_eq!(radio.get_config().unwrap().frequency_hz, home_hz);
+    }
+
+    #[test]
+    fn radio_init_is_retried_after_a_failure() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        radio.fail_next_inits(1);
+
+        run_with_timers(runner.init(&mut radio));
+        assert!(matches!(
+            next_response(&mut subscriber),
+            ResponseMessage::Unsolicited(Response::Event(Event::RadioFault { status: ResponseStatus::SpiError }))
+        ));
+        assert!(subscriber.try_next_message().is_none());
+
+        // The error pattern goes up for the failure and comes down with the
+        // success
+        assert_eq!(harness.leds.try_receive(), Ok(LedPattern::Error(error_code::RADIO_INIT)));
+        assert_eq!(harness.leds.try_receive(), Ok(LedPattern::ClearError));
+        assert!(harness.leds.try_receive().is_err());
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"hi").unwrap();
+        harness.send(Command::LoraTx { data }, 1);
+        futures::executor::block_on(runner.step(&mut radio));
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::TxComplete { .. }));
+    }
+
+    #[test]
+    fn dead_radio_answers_not_initialised_and_keeps_reporting() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        radio.fail_next_inits(u32::MAX);
+
+        run_with_timers(runner.init(&mut radio));
+        for _ in 0..=config::radio_init::RETRIES {
+            assert!(matches!(
+                next_response(&mut subscriber),
+                ResponseMessage::Unsolicited(Response::Event(Event::RadioFault { .. }))
+            ));
+        }
+
+        // Commands that need the radio fail at once; others still work
+        let mut data = Vec::new();
+        data.extend_from_slice(b"hi").unwrap();
+        harness.send(Command::LoraTx { data }, 1);
+        futures::executor::block_on(runner.step(&mut radio));
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Error { status: ResponseStatus::NotInitialised, .. }));
+        assert!(radio.get_tx_history().is_empty());
+
+        harness.send(Command::GetVersion, 2);
+        futures::executor::block_on(runner.step(&mut radio));
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Version { .. }));
+
+        // An idle step after the report interval repeats the fault
+        clock::advance_ms(config::radio_init::FAULT_REPORT_SECS * 1000);
+        run_with_timers(runner.step(&mut radio));
+        assert!(matches!(
+            next_response(&mut subscriber),
+            ResponseMessage::Unsolicited(Response::Event(Event::RadioFault { .. }))
+        ));
+    }
+
+    #[test]
+    fn transmissions_are_spaced_by_the_tx_gap() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let lora_tx = || {
+            let mut data = Vec::new();
+            data.extend_from_slice(b"hi").unwrap();
+            Command::LoraTx { data }
+        };
+        let tx_complete = |msg: ResponseMessage| {
+            matches!(msg, ResponseMessage::Command { response: Response::TxComplete { .. }, .. })
+        };
+
+        harness.send(Command::SetTxGap { gap_ms: 300 }, 1);
+        futures::executor::block_on(runner.step(&mut radio));
+        let _ = next_response(&mut subscriber);
+
+        // Nothing has been sent yet, so the first goes straight out
+        harness.send(lora_tx(), 2);
+        futures::executor::block_on(runner.step(&mut radio));
+        assert!(tx_complete(next_response(&mut subscriber)));
+
+        // The next one right behind it waits out the gap
+        let started = embassy_time::Instant::now();
+        harness.send(lora_tx(), 3);
+        run_with_timers(runner.step(&mut radio));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(tx_complete(next_response(&mut subscriber)));
+
+        // Once the gap has passed there is nothing to wait for
+        clock::advance_ms(300);
+        harness.send(lora_tx(), 4);
+        futures::executor::block_on(runner.step(&mut radio));
+        assert!(tx_complete(next_response(&mut subscriber)));
+    }
+
+    #[test]
+    fn repeated_spi_errors_reinitialise_the_radio() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(runner.init(&mut radio));
+        radio.fail_next_receives(config::radio_recovery::REINIT_AFTER);
+
+        // Each failure backs off; none of them is reported until re-init
+        let started = embassy_time::Instant::now();
+        for _ in 1..config::radio_recovery::REINIT_AFTER {
+            run_with_timers(runner.step(&mut radio));
+        }
+        let backoff_ms = config::radio_recovery::ERROR_BACKOFF_MS * u64::from(config::radio_recovery::REINIT_AFTER - 1);
+        assert!(started.elapsed() >= Duration::from_millis(backoff_ms));
+        assert!(radio.get_config().is_none());
+
+        // The last one re-initialises, and the configuration is put back
+        run_with_timers(runner.step(&mut radio));
+        assert!(radio.get_config().is_some());
+        assert!(subscriber.try_next_message().is_none());
+
+        // The radio has recovered and carries on as normal
+        let mut data = Vec::new();
+        data.extend_from_slice(b"hi").unwrap();
+        harness.send(Command::LoraTx { data }, 1);
+        futures::executor::block_on(runner.step(&mut radio));
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::TxComplete { .. }));
+    }
+
+    #[test]
+    fn radio_that_fails_to_reinitialise_is_reported() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(runner.init(&mut radio));
+        radio.fail_next_receives(config::radio_recovery::REINIT_AFTER);
+        radio.fail_next_inits(u32::MAX);
+        for _ in 0..config::radio_recovery::REINIT_AFTER {
+            run_with_timers(runner.step(&mut radio));
+        }
+        for _ in 0..=config::radio_init::RETRIES {
+            assert!(matches!(
+                next_response(&mut subscriber),
+                ResponseMessage::Unsolicited(Response::Event(Event::RadioFault { status: ResponseStatus::SpiError }))
+            ));
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"hi").unwrap();
+        harness.send(Command::LoraTx { data }, 1);
+        futures::executor::block_on(runner.step(&mut radio));
+        let ResponseMessage::Command { response, .. } = next_response(&mut subscriber) else {
+            panic!("Expected a command response");
+        };
+        assert!(matches!(response, Response::Error { status: ResponseStatus::NotInitialised, .. }));
+    }
+
+    #[test]
+    fn idle_link_switches_to_rx_duty_cycle_until_the_next_command() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let windows = RxDutyCycle { rx_ms: 10, sleep_ms: 40 };
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            harness.send(Command::SetRxDutyCycle { rx_ms: 10, sleep_ms: 40 }, 1);
+            runner.step(&mut radio).await;
+            next_response(&mut subscriber);
+            assert_eq!(radio.rx_duty_cycle(), None);
+
+            // Idle long enough: the next listen uses the windows
+            clock::advance_ms(config::rx_duty_cycle::IDLE_SECS * 1000);
+            runner.step(&mut radio).await;
+            assert_eq!(radio.rx_duty_cycle(), Some(windows));
+
+            // A transmission brings back continuous RX
+            let mut data = Vec::new();
+            data.extend_from_slice(b"hi").unwrap();
+            harness.send(Command::LoraTx { data }, 2);
+            runner.step(&mut radio).await;
+            assert_eq!(radio.rx_duty_cycle(), None);
+        });
+    }
+
+    #[test]
+    fn low_battery_is_announced_once_and_duty_cycles_the_idle_link() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+        let policy = Command::SetLowBatteryPolicy {
+            threshold_mv: 3500,
+            tx_power_dbm: 5,
+            rx_duty_cycle: true,
+        };
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            battery::set_millivolts(3400);
+            harness.send(policy, 1);
+            runner.step(&mut radio).await;
+            next_response(&mut subscriber);
+
+            // Checked on the next step, and only reported the once
+            runner.step(&mut radio).await;
+            assert!(matches!(
+                next_response(&mut subscriber),
+                ResponseMessage::Unsolicited(Response::Event(Event::LowBattery { battery_mv: 3400 }))
+            ));
+            runner.step(&mut radio).await;
+            assert!(subscriber.try_next_message().is_none());
+
+            // No SetRxDutyCycle, so the policy's own windows
+            clock::advance_ms(config::rx_duty_cycle::IDLE_SECS * 1000);
+            runner.step(&mut radio).await;
+            assert_eq!(
+                radio.rx_duty_cycle(),
+                Some(RxDutyCycle {
+                    rx_ms: config::battery::LOW_RX_MS,
+                    sleep_ms: config::battery::LOW_SLEEP_MS,
+                })
+            );
+        });
+        battery::set_millivolts(0);
+    }
+
+    #[test]
+    fn lora_tx_is_answered_to_its_source() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            let mut data = Vec::new();
+            data.extend_from_slice(b"hi").unwrap();
+            harness.send(Command::LoraTx { data }, 7);
+
+            assert_eq!(runner.step(&mut radio).await, Handoff::None);
+        });
+
+        let ResponseMessage::Command {
+            source,
+            sequence_id,
+            response,
+            ..
+        } = next_response(&mut subscriber)
+        else {
+            panic!("Expected a command response");
+        };
+        assert_eq!(source, CommandSource::Serial);
+        assert_eq!(sequence_id, 7);
+        assert!(matches!(response, Response::TxComplete { seq: 7, len: 2 }));
+        assert_eq!(radio.get_tx_history().len(), 1);
+        assert_eq!(harness.leds.try_receive(), Ok(LedPattern::TxActive));
+        let (dest, _, _, _) = addressing::decode(&crypto::open(&radio.get_tx_history()[0]).unwrap()).unwrap();
+        assert_eq!(dest, BROADCAST);
+    }
+
+    #[test]
+    fn tx_error_is_answered_to_its_source() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            runner.init(&mut radio).await;
+            radio.set_next_tx_error(LoraError::TransmitFailed);
+            let mut data = Vec::new();
+            data.extend_from_slice(b"hi").unwrap();
+            harness.send(Command::LoraTx { data }, 9);
+
+            runner.step(&mut radio).await;
+        });
+
+        let ResponseMessage::Command {
+            sequence_id, response, ..
+        } = next_response(&mut subscriber)
+        else {
+            panic!("Expected a command response");
+        };
+        assert_eq!(sequence_id, 9);
+        assert!(matches!(response, Response::Error { status: ResponseStatus::TransmitFailed, .. }));
+        assert!(radio.get_tx_history().is_empty());
+    }
+
+    #[test]
+    fn reboot_is_handed_off_without_a_response() {
+        let harness = Harness::new();
+        let mut subscriber = harness.responses.dyn_subscriber().unwrap();
+        let mut runner = harness.runner();
+        let mut radio = MockLoraRadio::new();
+
+        futures::executor::block_on(async {
+            harness.send(Command::Reboot, 1);
+            assert_eq!(runner.step(&mut radio).await, Handoff::Reboot);
+        });
+
+        assert!(subscriber.try_next_message().is_none());
+    }
+}
@@ -0,0 +1,97 @@
+//! Protocol version negotiation
+//!
+//! A host may frame its commands in any protocol version from
+//! `MIN_SUPPORTED_VERSION` to `PROTOCOL_VERSION`, so an older host keeps
+//! working after a firmware update. The frame layout is the same in every
+//! version, so an accepted older frame is restamped with the current version
+//! for wt-protocol's parser.
+//!
+//! While both are 1 this is scaffolding: every accepted frame is already
+//! current. The version bump that first raises `PROTOCOL_VERSION` also has to
+//! refuse, in an older frame, the commands that version did not define.
+
+use core::ops::RangeInclusive;
+
+use wt_protocol::{ResponseStatus, PROTOCOL_VERSION};
+
+use crate::crc16;
+
+/// Oldest protocol version still accepted
+pub const MIN_SUPPORTED_VERSION: u8 = 1;
+
+/// Check a decoded frame's version against the versions this firmware
+/// speaks, restamping an accepted older frame with the current version
+///
+/// The frame is `[version][cmd_id][length: u16 LE][data][crc16: u16 LE]`.
+/// A frame too short to hold a CRC is left for the parser to reject.
+pub fn negotiate(frame: &mut [u8]) -> Result<(), ResponseStatus> {
+    negotiate_within(frame, MIN_SUPPORTED_VERSION..=PROTOCOL_VERSION)
+}
+
+fn negotiate_within(frame: &mut [u8], supported: RangeInclusive<u8>) -> Result<(), ResponseStatus> {
+    let version = match *frame {
+        [version, _, _, _, _, _, ..] => version,
+        _ => return Ok(()),
+    };
+    if version == *supported.end() {
+        return Ok(());
+    }
+    if !supported.contains(&version) {
+        return Err(ResponseStatus::InvalidVersion);
+    }
+
+    // Only a frame that arrived intact is restamped, so the new CRC never
+    // covers a corrupted one
+    let body_len = frame.len() - 2;
+    if crc16::checksum(&frame[..body_len]).to_le_bytes() != frame[body_len..] {
+        return Err(ResponseStatus::CrcError);
+    }
+
+    frame[0] = *supported.end();
+    let crc = crc16::checksum(&frame[..body_len]);
+    frame[body_len..].copy_from_slice(&crc.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Versions 1 to 3, as after two bumps
+    const SUPPORTED: RangeInclusive<u8> = 1..=3;
+
+    fn frame(version: u8, command_id: u8) -> std::vec::Vec<u8> {
+        let mut frame = std::vec![version, command_id, 0x00, 0x00];
+        let crc = crc16::checksum(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn older_version_is_accepted_and_restamped() {
+        let mut older = frame(1, 0x01);
+        assert_eq!(negotiate_within(&mut older, SUPPORTED), Ok(()));
+        assert_eq!(older, frame(3, 0x01));
+
+        let mut current = frame(3, 0x01);
+        assert_eq!(negotiate_within(&mut current, SUPPORTED), Ok(()));
+        assert_eq!(current, frame(3, 0x01));
+    }
+
+    #[test]
+    fn version_outside_the_range_is_rejected() {
+        for version in [0, 4] {
+            let mut frame = frame(version, 0x01);
+            assert_eq!(negotiate_within(&mut frame, SUPPORTED), Err(ResponseStatus::InvalidVersion));
+        }
+    }
+
+    #[test]
+    fn corrupted_older_frame_is_not_restamped() {
+        let mut frame = frame(1, 0x01);
+        frame[2] ^= 0x01;
+        let sent = frame.clone();
+        assert_eq!(negotiate_within(&mut frame, SUPPORTED), Err(ResponseStatus::CrcError));
+        assert_eq!(frame, sent);
+    }
+}
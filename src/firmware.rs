@@ -0,0 +1,187 @@
+//! Firmware image integrity check
+//!
+//! VerifyFirmware answers with a CRC32 over the application image in flash
+//! (see `config::firmware`), so host tooling can spot a corrupt flash by
+//! comparing it with the value computed over the build's `.bin`. Only the
+//! image is checksummed, not the rest of its partition: its length comes
+//! from the ESP app image header (`image_len`). The flash handle installed
+//! here is also lent to `label` and `crash_log`, which get `None` from
+//! `with_flash` while a check is reading it.
+
+/// ESP app image header: the common 8 bytes and the 16-byte extension
+const HEADER_LEN: usize = 24;
+/// First byte of every app image
+const MAGIC: u8 = 0xE9;
+/// Header byte set to 1 when a SHA-256 digest follows the checksum byte
+const HASH_APPENDED: usize = 23;
+/// Each segment's header: load address and data length, u32 LE each
+const SEGMENT_HEADER_LEN: u32 = 8;
+/// Length of the appended SHA-256 digest
+const DIGEST_LEN: u32 = 32;
+
+/// Length in bytes of the app image at the start of a partition of
+/// `partition_len` bytes, read through `read(offset, buf)`
+///
+/// The image is its header and segments, zero padding up to a 16-byte
+/// boundary of which the last byte is the checksum, then the SHA-256 digest
+/// if the header says one is appended. Returns `None` if there is no image
+/// (wrong magic byte), it runs past the partition or a read fails.
+pub fn image_len<E>(partition_len: u32, mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>) -> Option<u32> {
+    let mut header = [0u8; HEADER_LEN];
+    read(0, &mut header).ok()?;
+    if header[0] != MAGIC {
+        return None;
+    }
+
+    let mut end = HEADER_LEN as u32;
+    for _ in 0..header[1] {
+        let mut segment = [0u8; SEGMENT_HEADER_LEN as usize];
+        read(end, &mut segment).ok()?;
+        let data_len = u32::from_le_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        end = end.checked_add(SEGMENT_HEADER_LEN)?.checked_add(data_len)?;
+        if end > partition_len {
+            return None;
+        }
+    }
+    let mut len = (end + 1).checked_next_multiple_of(16)?;
+    if header[HASH_APPENDED] == 1 {
+        len += DIGEST_LEN;
+    }
+    (len <= partition_len).then_some(len)
+}
+
+#[cfg(feature = "embedded")]
+mod flash {
+    use core::cell::RefCell;
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embedded_storage::ReadStorage;
+    use esp_storage::FlashStorage;
+
+    use crate::crc32::Crc32;
+    use crate::config::firmware::{APP_OFFSET, APP_SIZE};
+
+    /// Bytes read from flash between yields to the executor
+    const CHUNK_LEN: usize = 4096;
+
+    /// Flash handle, installed at boot
+    static FLASH: Mutex<CriticalSectionRawMutex, RefCell<Option<FlashStorage<'static>>>> =
+        Mutex::new(RefCell::new(None));
+
+    /// Hand the flash to the integrity check
+    pub fn init(flash: FlashStorage<'static>) {
+        FLASH.lock(|cell| *cell.borrow_mut() = Some(flash));
+    }
+
+    /// Run `f` with the flash, or return `None` if it is not available (not
+    /// installed yet, or lent out to a running integrity check)
+    pub fn with_flash<T>(f: impl FnOnce(&mut FlashStorage<'static>) -> T) -> Option<T> {
+        let mut flash = FLASH.lock(|cell| cell.borrow_mut().take())?;
+        let result = f(&mut flash);
+        FLASH.lock(|cell| *cell.borrow_mut() = Some(flash));
+        Some(result)
+    }
+
+    /// CRC32 of the application image, or `None` if the flash is not
+    /// available, holds no valid image or a read fails
+    ///
+    /// Read only. Yields between chunks so other tasks keep running while
+    /// the image is read; the flash is lent out until it finishes.
+    pub async fn image_crc32() -> Option<u32> {
+        // Taken out of the lock so reads do not run inside a critical section
+        let mut flash = FLASH.lock(|cell| cell.borrow_mut().take())?;
+        let crc = checksum_image(&mut flash).await;
+        FLASH.lock(|cell| *cell.borrow_mut() = Some(flash));
+        crc
+    }
+
+    async fn checksum_image(flash: &mut FlashStorage<'static>) -> Option<u32> {
+        let Some(image_len) = super::image_len(APP_SIZE, |offset, buf| flash.read(APP_OFFSET + offset, buf)) else {
+            crate::error!("Firmware: no valid image at {:#x}", APP_OFFSET);
+            return None;
+        };
+        let mut crc = Crc32::new();
+        let mut buf = [0u8; CHUNK_LEN];
+        let mut offset = APP_OFFSET;
+        let end = APP_OFFSET + image_len;
+        while offset < end {
+            let len = CHUNK_LEN.min((end - offset) as usize);
+            if flash.read(offset, &mut buf[..len]).is_err() {
+                crate::error!("Firmware: flash read failed at {:#x}", offset);
+                return None;
+            }
+            crc.update(&buf[..len]);
+            offset += len as u32;
+            embassy_futures::yield_now().await;
+        }
+        Some(crc.finish())
+    }
+}
+
+#[cfg(feature = "embedded")]
+pub use flash::{image_crc32, init, with_flash};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An app image with segments of `segment_lens` bytes, followed by
+    /// erased flash up to `partition_len`
+    fn partition(segment_lens: &[u32], hash_appended: bool, partition_len: usize) -> std::vec::Vec<u8> {
+        let mut image = std::vec![0u8; HEADER_LEN];
+        image[0] = MAGIC;
+        image[1] = segment_lens.len() as u8;
+        image[HASH_APPENDED] = hash_appended as u8;
+        for &len in segment_lens {
+            image.extend_from_slice(&0x4037_0000u32.to_le_bytes());
+            image.extend_from_slice(&len.to_le_bytes());
+            image.resize(image.len() + len as usize, 0xA5);
+        }
+        while image.len() % 16 != 15 {
+            image.push(0);
+        }
+        image.push(0xEF);
+        if hash_appended {
+            image.extend_from_slice(&[0x11; DIGEST_LEN as usize]);
+        }
+        image.resize(partition_len, 0xFF);
+        image
+    }
+
+    fn len_of(partition: &[u8]) -> Option<u32> {
+        image_len(partition.len() as u32, |offset, buf: &mut [u8]| {
+            let offset = offset as usize;
+            match partition.get(offset..offset + buf.len()) {
+                Some(bytes) => {
+                    buf.copy_from_slice(bytes);
+                    Ok(())
+                }
+                None => Err(()),
+            }
+        })
+    }
+
+    #[test]
+    fn image_ends_after_its_checksum_byte() {
+        // 24 + 8 + 16 + 8 + 20 = 76 bytes of segments, checksum at 79
+        assert_eq!(len_of(&partition(&[16, 20], false, 4096)), Some(80));
+        // A segment that already ends one short of a boundary needs no padding
+        assert_eq!(len_of(&partition(&[15], false, 4096)), Some(48));
+    }
+
+    #[test]
+    fn appended_digest_is_part_of_the_image() {
+        assert_eq!(len_of(&partition(&[16, 20], true, 4096)), Some(112));
+    }
+
+    #[test]
+    fn missing_or_oversized_image_has_no_length() {
+        // Erased flash
+        assert_eq!(len_of(&[0xFF; 4096]), None);
+        // A segment claiming more than the partition holds
+        let mut oversized = partition(&[16], false, 4096);
+        oversized[HEADER_LEN + 4..HEADER_LEN + 8].copy_from_slice(&8192u32.to_le_bytes());
+        assert_eq!(len_of(&oversized), None);
+    }
+}
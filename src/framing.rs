@@ -0,0 +1,326 @@
+//! Frame format on the host link
+//!
+//! Frames are COBS-encoded and end in a 0x00 delimiter by default. With the
+//! `length-prefixed` feature every interface instead sends and expects
+//! `[len: u16 LE][payload]` with no byte stuffing, for hosts that already
+//! speak a length-prefixed protocol or for reading a capture by eye. The
+//! payload (`[version][id][length][data][crc16]`) is the same either way.
+//!
+//! COBS resynchronises on the next delimiter after line noise. A
+//! length-prefixed stream cannot: a corrupt length byte throws every later
+//! frame off until the host reconnects.
+//!
+//! Either end can put a short trailer between a frame's data and its CRC
+//! (see `Trailer`); the frame's length field still covers only the data.
+//!
+//! Both formats are always compiled; `MODE` picks the one the interfaces use.
+
+use heapless::Vec;
+use wt_protocol::{FrameAccumulator, PushResult, Response};
+
+use crate::config::protocol::MAX_FRAME_SIZE;
+
+/// How frames are delimited on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// COBS with a 0x00 delimiter (the default)
+    Cobs,
+    /// `[len: u16 LE][payload]`
+    LengthPrefixed,
+}
+
+/// The format this build speaks, set by the `length-prefixed` feature
+pub const MODE: Mode = if cfg!(feature = "length-prefixed") {
+    Mode::LengthPrefixed
+} else {
+    Mode::Cobs
+};
+
+/// Bytes of the length prefix
+const PREFIX_LEN: usize = 2;
+
+/// Longest frame on the wire in either format: COBS adds a byte per 254 plus
+/// the delimiter, the length prefix adds two
+pub const MAX_ENCODED: usize = MAX_FRAME_SIZE + MAX_FRAME_SIZE / 254 + 2;
+
+/// A frame's payload, with the framing removed
+pub type Payload = Vec<u8, MAX_FRAME_SIZE>;
+
+/// A frame ready to write
+pub type Frame = Vec<u8, MAX_ENCODED>;
+
+/// Result of pushing one byte into a `Deframer`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Push {
+    /// More bytes are needed
+    Pending,
+    /// A whole frame's payload. Empty for a bare COBS delimiter or a zero
+    /// length, which hosts send to resynchronise.
+    Frame(Payload),
+    /// The frame was longer than `MAX_FRAME_SIZE` and has been discarded
+    Overflow,
+    /// The frame was not valid COBS
+    Corrupt,
+}
+
+/// Splits a byte stream into frame payloads
+pub struct Deframer(Inner);
+
+enum Inner {
+    Cobs(FrameAccumulator),
+    LengthPrefixed(LengthPrefixed),
+}
+
+impl Deframer {
+    /// Deframer for the format this build speaks
+    pub fn new() -> Self {
+        Self::with_mode(MODE)
+    }
+
+    /// Deframer for `mode`
+    pub fn with_mode(mode: Mode) -> Self {
+        Self(match mode {
+            Mode::Cobs => Inner::Cobs(FrameAccumulator::new()),
+            Mode::LengthPrefixed => Inner::LengthPrefixed(LengthPrefixed::default()),
+        })
+    }
+
+    /// Push the next byte, returning a payload once a frame is complete
+    pub fn push(&mut self, byte: u8) -> Push {
+        match &mut self.0 {
+            Inner::Cobs(accumulator) => match accumulator.push(byte) {
+                PushResult::Pending => Push::Pending,
+                PushResult::Frame(frame) => match wt_protocol::cobs_decode(&frame) {
+                    Ok(decoded) => Payload::from_slice(&decoded).map_or(Push::Overflow, Push::Frame),
+                    Err(_) => Push::Corrupt,
+                },
+                PushResult::Overflow => Push::Overflow,
+            },
+            Inner::LengthPrefixed(frame) => frame.push(byte),
+        }
+    }
+}
+
+impl Default for Deframer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// State of a length-prefixed frame being received
+#[derive(Default)]
+struct LengthPrefixed {
+    prefix: [u8; PREFIX_LEN],
+    prefix_len: usize,
+    /// Payload bytes still to come
+    remaining: usize,
+    /// Set when the length is over the limit; the bytes are counted off
+    /// but not kept
+    oversized: bool,
+    payload: Payload,
+}
+
+impl LengthPrefixed {
+    fn push(&mut self, byte: u8) -> Push {
+        if self.prefix_len < PREFIX_LEN {
+            self.prefix[self.prefix_len] = byte;
+            self.prefix_len += 1;
+            if self.prefix_len < PREFIX_LEN {
+                return Push::Pending;
+            }
+            self.remaining = u16::from_le_bytes(self.prefix) as usize;
+            self.oversized = self.remaining > MAX_FRAME_SIZE;
+            self.payload.clear();
+        } else {
+            self.remaining -= 1;
+            if !self.oversized {
+                // Cannot fail: the length was checked against the capacity
+                let _ = self.payload.push(byte);
+            }
+        }
+
+        if self.remaining > 0 {
+            return Push::Pending;
+        }
+        self.prefix_len = 0;
+        if self.oversized {
+            Push::Overflow
+        } else {
+            Push::Frame(core::mem::take(&mut self.payload))
+        }
+    }
+}
+
+/// What goes between a response's data and its CRC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trailer {
+    /// Nothing
+    None,
+    /// The command's sequence id (SetSequenceEcho)
+    Sequence(u16),
+    /// The host sequence number the command carried, then the device's own
+    /// sequence id for it, so a host can spot commands that never arrived
+    HostSequence { host: u16, device: u16 },
+}
+
+/// Encode `response` for the wire, with `trailer`
+pub fn encode_response(response: &Response, trailer: Trailer) -> Frame {
+    encode_response_as(MODE, response, trailer)
+}
+
+/// Encode `response` in `mode`
+///
+/// wt-protocol produces the COBS frame; the length-prefixed one is built
+/// from its decoded payload. Both fit in `MAX_ENCODED` for any payload up to
+/// `MAX_FRAME_SIZE`.
+pub fn encode_response_as(mode: Mode, response: &Response, trailer: Trailer) -> Frame {
+    let cobs = match trailer {
+        Trailer::None => wt_protocol::encode_response_with_sequence(response, None),
+        Trailer::Sequence(sequence) => wt_protocol::encode_response_with_sequence(response, Some(sequence)),
+        Trailer::HostSequence { host, device } => {
+            wt_protocol::encode_response_with_host_sequence(response, host, device)
+        }
+    };
+    let mut frame = Frame::new();
+    match mode {
+        Mode::Cobs => {
+            let _ = frame.extend_from_slice(&cobs);
+        }
+        Mode::LengthPrefixed => {
+            if let Ok(payload) = wt_protocol::cobs_decode(&cobs) {
+                let _ = frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+                let _ = frame.extend_from_slice(&payload);
+            }
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wt_protocol::Command;
+
+    /// GetVersion: version 1, command 0x01, empty payload, CRC-16/XMODEM
+    const GET_VERSION: [u8; 6] = [0x01, 0x01, 0x00, 0x00, 0x84, 0x41];
+
+    /// Frame `payload` as a host would in `mode`
+    fn host_frame(mode: Mode, payload: &[u8]) -> std::vec::Vec<u8> {
+        match mode {
+            Mode::Cobs => {
+                let mut out = std::vec![0];
+                let mut code_at = 0;
+                for &byte in payload {
+                    if byte == 0 {
+                        out[code_at] = (out.len() - code_at) as u8;
+                        code_at = out.len();
+                        out.push(0);
+                    } else {
+                        out.push(byte);
+                    }
+                }
+                out[code_at] = (out.len() - code_at) as u8;
+                out.push(0);
+                out
+            }
+            Mode::LengthPrefixed => {
+                let mut out = (payload.len() as u16).to_le_bytes().to_vec();
+                out.extend_from_slice(payload);
+                out
+            }
+        }
+    }
+
+    /// Every non-pending result of feeding `bytes` to `deframer`
+    fn feed(deframer: &mut Deframer, bytes: &[u8]) -> std::vec::Vec<Push> {
+        bytes
+            .iter()
+            .map(|&byte| deframer.push(byte))
+            .filter(|push| *push != Push::Pending)
+            .collect()
+    }
+
+    #[test]
+    fn commands_round_trip_in_both_modes() {
+        for mode in [Mode::Cobs, Mode::LengthPrefixed] {
+            let mut deframer = Deframer::with_mode(mode);
+            let mut stream = host_frame(mode, &GET_VERSION);
+            stream.extend(host_frame(mode, &GET_VERSION));
+
+            let pushed = feed(&mut deframer, &stream);
+            assert_eq!(pushed.len(), 2, "{:?}", mode);
+            for push in pushed {
+                let Push::Frame(payload) = push else {
+                    panic!("{:?}: expected a frame, got {:?}", mode, push);
+                };
+                assert!(matches!(wt_protocol::parse_command(&payload), Ok(Command::GetVersion)));
+            }
+        }
+    }
+
+    #[test]
+    fn responses_round_trip_in_both_modes() {
+        let response = Response::SyncAck { nonce: 0x0000_00FF };
+        let expected = wt_protocol::cobs_decode(&wt_protocol::encode_response_with_sequence(&response, Some(7)))
+            .unwrap()
+            .to_vec();
+        for mode in [Mode::Cobs, Mode::LengthPrefixed] {
+            let frame = encode_response_as(mode, &response, Trailer::Sequence(7));
+            let pushed = feed(&mut Deframer::with_mode(mode), &frame);
+            assert_eq!(pushed, [Push::Frame(Payload::from_slice(&expected).unwrap())], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn host_sequence_trailer_follows_the_data() {
+        let response = Response::SyncAck { nonce: 0x0000_00FF };
+        let frame = encode_response_as(
+            Mode::LengthPrefixed,
+            &response,
+            Trailer::HostSequence {
+                host: 0x1234,
+                device: 0x0005,
+            },
+        );
+        let payload = &frame[PREFIX_LEN..];
+        // [version][id][length: 4][nonce][host][device][crc16]
+        assert_eq!(payload.len(), 4 + 4 + 4 + 2);
+        assert_eq!(u16::from_le_bytes([payload[2], payload[3]]), 4);
+        assert_eq!(&payload[8..12], &[0x34, 0x12, 0x05, 0x00]);
+    }
+
+    #[test]
+    fn length_prefixed_frames_are_not_stuffed() {
+        let frame = encode_response_as(Mode::LengthPrefixed, &Response::SyncAck { nonce: 0 }, Trailer::None);
+        let payload = &frame[PREFIX_LEN..];
+        assert_eq!(u16::from_le_bytes([frame[0], frame[1]]) as usize, payload.len());
+        // The zero nonce goes out as zero bytes, where COBS would replace them
+        assert!(payload.contains(&0));
+    }
+
+    #[test]
+    fn empty_frames_give_no_payload() {
+        // A bare delimiter, or a zero length
+        for (mode, empty) in [(Mode::Cobs, &[0x00][..]), (Mode::LengthPrefixed, &[0x00, 0x00][..])] {
+            let pushed = feed(&mut Deframer::with_mode(mode), empty);
+            assert!(pushed.iter().all(|push| *push == Push::Frame(Payload::new())), "{:?}: {:?}", mode, pushed);
+        }
+    }
+
+    #[test]
+    fn oversized_length_prefixed_frame_is_skipped_whole() {
+        let mut deframer = Deframer::with_mode(Mode::LengthPrefixed);
+        let mut stream = host_frame(Mode::LengthPrefixed, &[0x55; MAX_FRAME_SIZE + 1]);
+        stream.extend(host_frame(Mode::LengthPrefixed, &GET_VERSION));
+
+        let pushed = feed(&mut deframer, &stream);
+        assert_eq!(pushed[0], Push::Overflow);
+        assert_eq!(pushed[1], Push::Frame(Payload::from_slice(&GET_VERSION).unwrap()));
+    }
+
+    #[test]
+    fn bad_cobs_is_corrupt() {
+        // The code byte claims more bytes than arrive before the delimiter
+        assert_eq!(feed(&mut Deframer::with_mode(Mode::Cobs), &[0x05, 0x01, 0x00]), [Push::Corrupt]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Operator-set device label
+//!
+//! A free-text label (e.g. "North gate repeater") set with SetLabel and read
+//! back with GetLabel over any interface, for telling deployed units apart.
+//! It is kept in flash, at the start of the `settings` data partition
+//! (`config::label::FLASH_OFFSET`) next to the BLE name, so it survives
+//! reboots and reflashing.
+
+use heapless::Vec;
+
+use crate::record;
+
+/// Longest label in bytes
+pub const MAX_LEN: usize = 32;
+
+/// A device label: up to `MAX_LEN` bytes of UTF-8 (empty = no label)
+pub type Label = Vec<u8, MAX_LEN>;
+
+/// Marks a label record (see `record`)
+const MAGIC: [u8; 2] = *b"WL";
+
+/// Stored record: the label padded to `MAX_LEN`, framed by `record`
+pub const RECORD_LEN: usize = MAX_LEN + record::OVERHEAD;
+
+const _: () = assert!(RECORD_LEN as u32 <= crate::config::partitions::SETTINGS_SIZE);
+
+/// Why a label could not be set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelError {
+    /// Longer than `MAX_LEN` or not UTF-8
+    Invalid,
+    /// The flash is lent to a running VerifyFirmware
+    Busy,
+    /// The flash is unavailable or the write failed
+    Storage,
+}
+
+/// Check `label` and copy it into a `Label`
+pub fn parse(label: &[u8]) -> Result<Label, LabelError> {
+    if core::str::from_utf8(label).is_err() {
+        return Err(LabelError::Invalid);
+    }
+    Vec::from_slice(label).map_err(|_| LabelError::Invalid)
+}
+
+/// Flash record holding `label`
+pub fn encode(label: &Label) -> [u8; RECORD_LEN] {
+    let mut stored = [0u8; RECORD_LEN];
+    record::encode(MAGIC, label, &mut stored);
+    stored
+}
+
+/// Label held in a flash record, or `None` if it was never written or is
+/// corrupt
+pub fn decode(stored: &[u8; RECORD_LEN]) -> Option<Label> {
+    parse(record::decode(MAGIC, stored)?).ok()
+}
+
+#[cfg(feature = "embedded")]
+mod flash {
+    use core::cell::RefCell;
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embedded_storage::{ReadStorage, Storage};
+
+    use super::{decode, encode, parse, Label, LabelError, RECORD_LEN};
+    use crate::config::label::FLASH_OFFSET;
+    use crate::firmware::with_flash;
+
+    /// Label last loaded or set, so GetLabel does not touch the flash
+    static LABEL: Mutex<CriticalSectionRawMutex, RefCell<Label>> = Mutex::new(RefCell::new(Label::new()));
+
+    /// Read the stored label into RAM; call once at boot after
+    /// `firmware::init`
+    pub fn load() {
+        let mut record = [0u8; RECORD_LEN];
+        let read = with_flash(|flash| flash.read(FLASH_OFFSET, &mut record).is_ok()).unwrap_or(false);
+        if !read {
+            crate::error!("Label: flash read failed");
+            return;
+        }
+        if let Some(label) = decode(&record) {
+            LABEL.lock(|cell| *cell.borrow_mut() = label);
+        }
+    }
+
+    /// The current label (empty if none was set)
+    pub fn get() -> Label {
+        LABEL.lock(|cell| cell.borrow().clone())
+    }
+
+    /// Check, store and apply a new label (empty clears it)
+    ///
+    /// Erases and rewrites the label sector, which stalls the CPU for a few
+    /// tens of ms. A flash failure, or the flash being lent to VerifyFirmware,
+    /// leaves the previous label in place.
+    pub fn set(label: &[u8]) -> Result<(), LabelError> {
+        let label = parse(label)?;
+        let record = encode(&label);
+        let written = with_flash(|flash| flash.write(FLASH_OFFSET, &record).is_ok()).ok_or(LabelError::Busy)?;
+        if !written {
+            crate::error!("Label: flash write failed");
+            return Err(LabelError::Storage);
+        }
+        LABEL.lock(|cell| *cell.borrow_mut() = label);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded")]
+pub use flash::{get, load, set};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trips() {
+        for text in ["", "North gate repeater", "ünïcode ✓", "0123456789abcdef0123456789abcdef"] {
+            let label = parse(text.as_bytes()).unwrap();
+            assert_eq!(decode(&encode(&label)), Some(label));
+        }
+    }
+
+    #[test]
+    fn oversized_or_invalid_labels_are_rejected() {
+        assert_eq!(parse(&[b'x'; MAX_LEN + 1]), Err(LabelError::Invalid));
+        assert_eq!(parse(&[0xC3, 0x28]), Err(LabelError::Invalid));
+    }
+
+    #[test]
+    fn erased_or_corrupt_flash_reads_as_no_label() {
+        assert_eq!(decode(&[0xFF; RECORD_LEN]), None);
+        let mut record = encode(&parse(b"gate").unwrap());
+        record[4] ^= 0x01;
+        assert_eq!(decode(&record), None);
+    }
+}
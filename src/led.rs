@@ -0,0 +1,111 @@
+//! LED status patterns
+//!
+//! The patterns shown on the status LED and their blink timing. The LED task
+//! plays them; other tasks request them over the LED channel.
+
+/// Duration of an activity flash in milliseconds
+const LED_FLASH_MS: u64 = 50;
+
+/// Error codes shown by `LedPattern::Error` (number of blinks)
+pub mod error_code {
+    /// The LoRa radio failed to initialise
+    pub const RADIO_INIT: u8 = 1;
+}
+
+/// Modes accepted by the SetLed command
+pub mod led_mode {
+    /// Hand the LED back to the automatic patterns
+    pub const AUTO: u8 = 0;
+    /// Dark
+    pub const OFF: u8 = 1;
+    /// Lit
+    pub const ON: u8 = 2;
+    /// Fast blink, to pick the device out among many
+    pub const BLINK: u8 = 3;
+}
+
+/// LED pattern requested by a task
+///
+/// `Idle`, `BleConnected` and `Error` set the background pattern, which repeats
+/// until replaced. `TxActive` and `RxActive` play once over the background.
+/// An `Error` is sticky: it is kept (and activity flashes suppressed) so the
+/// blink count stays readable, until `ClearError` reports the fault over.
+/// Background changes requested meanwhile are kept for then.
+///
+/// `Off`, `Solid` and `Blink` are set by the host and take manual control:
+/// they repeat, overriding even an error, and every automatic request is held
+/// back until `Auto` hands the LED back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedPattern {
+    /// Solid on
+    Idle,
+    /// Solid on with a short off blip every two seconds
+    BleConnected,
+    /// Single short off flash
+    TxActive,
+    /// Double short off flash
+    RxActive,
+    /// `code` blinks after a dark pause, repeated
+    Error(u8),
+    /// Manual: dark
+    Off,
+    /// Manual: lit
+    Solid,
+    /// Manual: fast blink
+    Blink,
+    /// End manual control and return to the background pattern
+    Auto,
+    /// The fault behind `Error` is over: return to `Idle` or `BleConnected`
+    ClearError,
+}
+
+impl LedPattern {
+    /// Pattern for a SetLed `mode` byte (see `led_mode`), or None if unknown
+    pub fn from_mode(mode: u8) -> Option<Self> {
+        match mode {
+            led_mode::AUTO => Some(LedPattern::Auto),
+            led_mode::OFF => Some(LedPattern::Off),
+            led_mode::ON => Some(LedPattern::Solid),
+            led_mode::BLINK => Some(LedPattern::Blink),
+            _ => None,
+        }
+    }
+
+    /// Whether the pattern was set by the host and holds off automatic ones
+    pub fn is_manual(self) -> bool {
+        matches!(self, LedPattern::Off | LedPattern::Solid | LedPattern::Blink)
+    }
+
+    /// Whether the pattern plays once over the background pattern
+    pub fn is_transient(self) -> bool {
+        matches!(self, LedPattern::TxActive | LedPattern::RxActive)
+    }
+
+    /// Number of steps in one cycle of the pattern
+    pub fn len(self) -> usize {
+        match self {
+            LedPattern::Idle | LedPattern::Off | LedPattern::Solid | LedPattern::Auto | LedPattern::ClearError => 1,
+            LedPattern::BleConnected | LedPattern::Blink => 2,
+            LedPattern::TxActive => 1,
+            LedPattern::RxActive => 3,
+            LedPattern::Error(code) => 1 + 2 * code.max(1) as usize,
+        }
+    }
+
+    /// Step `index` of the pattern: whether the LED is lit and for how long
+    pub fn step(self, index: usize) -> (bool, u64) {
+        match self {
+            LedPattern::Idle | LedPattern::Solid | LedPattern::Auto | LedPattern::ClearError => (true, 1000),
+            LedPattern::Off => (false, 1000),
+            LedPattern::Blink => [(true, 150), (false, 150)][index],
+            LedPattern::BleConnected => [(false, 100), (true, 1900)][index],
+            LedPattern::TxActive => (false, LED_FLASH_MS),
+            LedPattern::RxActive => [(false, LED_FLASH_MS), (true, LED_FLASH_MS), (false, LED_FLASH_MS)][index],
+            LedPattern::Error(_) => match index {
+                0 => (false, 1500),
+                i if i % 2 == 1 => (true, 200),
+                _ => (false, 200),
+            },
+        }
+    }
+}
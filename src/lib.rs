@@ -1,6 +1,17 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod battery;
 pub mod config;
+pub mod crash_log;
+pub mod crc16;
+pub mod crc32;
+pub mod crypto;
+pub mod firmware;
+pub mod framing;
+pub mod label;
+pub mod led;
+pub mod record;
+pub mod reset_reason;
 
 // Wire protocol (command/response codec and COBS framing) shared with the app.
 pub use wt_protocol;
@@ -9,6 +20,10 @@ pub use wt_protocol;
 // can be unit-tested on the host; the hardware driver/traits are gated inside it.
 pub mod lora;
 
+// Reads embassy_time, except in unit tests which get a settable clock
+#[cfg(any(test, feature = "embedded", feature = "host-test"))]
+pub mod clock;
+
 // These modules depend on embassy/async features only available with embedded feature
 #[cfg(feature = "embedded")]
 pub mod ble;
@@ -26,3 +41,17 @@ macro_rules! debug {
         // No-op in non-embedded builds
     };
 }
+
+/// No-op error macro for non-embedded builds (tests).
+#[cfg(not(feature = "embedded"))]
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {};
+}
+
+/// No-op trace macro for non-embedded builds (tests).
+#[cfg(not(feature = "embedded"))]
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
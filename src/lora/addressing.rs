@@ -0,0 +1,110 @@
+//! LoRa frame addressing
+//!
+//! Every frame starts with a destination and source address, each the 3-byte
+//! device id derived from the eFuse MAC, and a hop count:
+//!
+//! ```text
+//! [dest: 3 bytes][source: 3 bytes][ttl: u8][fragment...]
+//! ```
+//!
+//! A receiver only surfaces frames addressed to itself or to `BROADCAST`.
+//! Repeaters retransmit a frame with `ttl` one lower (see `lora::repeater`).
+
+use heapless::Vec;
+
+use crate::config::repeater::DEFAULT_TTL;
+
+/// A device address (the device id).
+pub type Address = [u8; 3];
+
+/// Destination that every device accepts.
+pub const BROADCAST: Address = [0xFF, 0xFF, 0xFF];
+
+/// Source reported for packets that carry no address (raw GFSK).
+pub const UNADDRESSED: Address = [0x00, 0x00, 0x00];
+
+/// Size of the address header in bytes.
+pub const HEADER_LEN: usize = 7;
+
+/// Largest addressed frame (the radio length field is one byte).
+const MAX_FRAME_LEN: usize = 255;
+
+/// Prefix `payload` with the destination and source addresses and the
+/// starting hop count.
+///
+/// Returns `None` if the addressed frame would exceed a radio frame.
+pub fn encode(dest: Address, source: Address, payload: &[u8]) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+    encode_with_ttl(dest, source, DEFAULT_TTL, payload)
+}
+
+/// Prefix `payload` with the addresses and hop count `ttl`, as a repeater
+/// does when passing on another device's frame.
+pub fn encode_with_ttl(dest: Address, source: Address, ttl: u8, payload: &[u8]) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&dest).ok()?;
+    frame.extend_from_slice(&source).ok()?;
+    frame.push(ttl).ok()?;
+    frame.extend_from_slice(payload).ok()?;
+    Some(frame)
+}
+
+/// Split an addressed frame into `(dest, source, ttl, payload)`.
+pub fn decode(frame: &[u8]) -> Option<(Address, Address, u8, &[u8])> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let mut dest = [0u8; 3];
+    let mut source = [0u8; 3];
+    dest.copy_from_slice(&frame[..3]);
+    source.copy_from_slice(&frame[3..6]);
+    Some((dest, source, frame[6], &frame[HEADER_LEN..]))
+}
+
+/// Whether a frame sent to `dest` should be surfaced by the device `own`.
+pub fn accepts(dest: Address, own: Address) -> bool {
+    dest == own || dest == BROADCAST
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ME: Address = [0x12, 0x34, 0x56];
+    const PEER: Address = [0xAB, 0xCD, 0xEF];
+
+    #[test]
+    fn encode_then_decode_round_trips() {
+        let frame = encode(PEER, ME, b"Hi").unwrap();
+        assert_eq!(frame.as_slice(), &[0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56, DEFAULT_TTL, b'H', b'i']);
+
+        let (dest, source, ttl, payload) = decode(&frame).unwrap();
+        assert_eq!(dest, PEER);
+        assert_eq!(source, ME);
+        assert_eq!(ttl, DEFAULT_TTL);
+        assert_eq!(payload, b"Hi");
+    }
+
+    #[test]
+    fn relayed_frame_carries_its_ttl() {
+        let frame = encode_with_ttl(PEER, ME, 0, b"Hi").unwrap();
+        assert_eq!(decode(&frame).unwrap().2, 0);
+    }
+
+    #[test]
+    fn short_frame_does_not_decode() {
+        assert!(decode(&[0xFF; HEADER_LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn oversized_payload_does_not_encode() {
+        assert!(encode(PEER, ME, &[0u8; MAX_FRAME_LEN - HEADER_LEN]).is_some());
+        assert!(encode(PEER, ME, &[0u8; MAX_FRAME_LEN - HEADER_LEN + 1]).is_none());
+    }
+
+    #[test]
+    fn accepts_own_and_broadcast_only() {
+        assert!(accepts(ME, ME));
+        assert!(accepts(BROADCAST, ME));
+        assert!(!accepts(PEER, ME));
+    }
+}
@@ -0,0 +1,201 @@
+//! LoRa time-on-air calculation
+//!
+//! Implements the SX126x packet duration formula (datasheet section 6.1.4) for
+//! the packet formats the driver uses: CRC on or off, explicit or implicit
+//! header. GFSK packets (`fsk_time_on_air_us`) are just their bytes at the
+//! bit rate.
+
+/// Exact bandwidth in Hz for a nominal kHz value as used in `LoraConfig`.
+///
+/// Accepts the same values (and roundings) as the driver's modulation params;
+/// anything else falls back to 125 kHz, as the driver does.
+pub fn bandwidth_hz(bandwidth_khz: u32) -> u32 {
+    match bandwidth_khz {
+        7 | 8 => 7_810,
+        10 => 10_420,
+        15 | 16 => 15_630,
+        20 | 21 => 20_830,
+        31 => 31_250,
+        41 | 42 => 41_670,
+        62 | 63 => 62_500,
+        250 => 250_000,
+        500 => 500_000,
+        _ => 125_000,
+    }
+}
+
+/// Symbol length above which low data rate optimisation is required, per
+/// Semtech's guidance
+pub const LDRO_SYMBOL_US: u32 = 16_380;
+
+/// Duration of one LoRa symbol (2^SF / BW) in microseconds.
+pub fn symbol_duration_us(spreading_factor: u8, bandwidth_khz: u32) -> u32 {
+    ((1u64 << spreading_factor) * 1_000_000 / bandwidth_hz(bandwidth_khz) as u64) as u32
+}
+
+/// Whether low data rate optimisation is enabled for these settings.
+///
+/// Required once a symbol lasts longer than 16.38 ms: SF11 and up at 125 kHz,
+/// SF12 at 250 kHz, and correspondingly lower SFs at narrower bandwidths.
+pub fn low_data_rate_optimise(spreading_factor: u8, bandwidth_khz: u32) -> bool {
+    symbol_duration_us(spreading_factor, bandwidth_khz) > LDRO_SYMBOL_US
+}
+
+/// Time on air in microseconds for a packet of `payload_len` bytes.
+///
+/// `coding_rate` is the 4/x denominator (5-8).
+pub fn time_on_air_us(
+    spreading_factor: u8,
+    bandwidth_khz: u32,
+    coding_rate: u8,
+    preamble_symbols: u16,
+    implicit_header: bool,
+    crc_enabled: bool,
+    payload_len: usize,
+) -> u32 {
+    let sf = spreading_factor as i64;
+    let crc_bits = if crc_enabled { 16 } else { 0 };
+    let header_bits = if implicit_header { 0 } else { 20 };
+
+    // SF5/SF6 use a longer sync sequence and no extra 8-bit header term.
+    let (preamble_extra_quarters, payload_bits, symbol_bits) = if spreading_factor <= 6 {
+        (25, 8 * payload_len as i64 + crc_bits - 4 * sf + header_bits, 4 * sf)
+    } else {
+        let de = low_data_rate_optimise(spreading_factor, bandwidth_khz) as i64;
+        (17, 8 * payload_len as i64 + crc_bits - 4 * sf + 8 + header_bits, 4 * (sf - 2 * de))
+    };
+    let payload_symbols = 8 + (payload_bits.max(0) as u64).div_ceil(symbol_bits as u64) * coding_rate as u64;
+
+    // Work in quarter symbols so the 4.25/6.25 preamble terms stay integral.
+    let quarter_symbols = 4 * preamble_symbols as u64 + preamble_extra_quarters + 4 * payload_symbols;
+    let symbol_us_x4 = 4 * bandwidth_hz(bandwidth_khz) as u64;
+    (quarter_symbols * (1u64 << spreading_factor) * 1_000_000 / symbol_us_x4) as u32
+}
+
+/// Time on air in milliseconds, rounded up.
+pub fn time_on_air_ms(
+    spreading_factor: u8,
+    bandwidth_khz: u32,
+    coding_rate: u8,
+    preamble_symbols: u16,
+    implicit_header: bool,
+    crc_enabled: bool,
+    payload_len: usize,
+) -> u32 {
+    time_on_air_us(
+        spreading_factor,
+        bandwidth_khz,
+        coding_rate,
+        preamble_symbols,
+        implicit_header,
+        crc_enabled,
+        payload_len,
+    )
+    .div_ceil(1000)
+}
+
+/// Time on air in microseconds of a GFSK packet as the driver frames it:
+/// `preamble_bytes` of preamble, the 2-byte sync word, a length byte unless
+/// the length is fixed (implicit header), the payload and a 2-byte CRC if
+/// enabled.
+pub fn fsk_time_on_air_us(
+    bitrate_bps: u32,
+    preamble_bytes: u16,
+    implicit_header: bool,
+    crc_enabled: bool,
+    payload_len: usize,
+) -> u32 {
+    let length_byte = if implicit_header { 0 } else { 1 };
+    let crc_bytes = if crc_enabled { 2 } else { 0 };
+    let bytes = preamble_bytes as u64 + 2 + length_byte + payload_len as u64 + crc_bytes;
+    (bytes * 8 * 1_000_000 / bitrate_bps.max(1) as u64) as u32
+}
+
+/// GFSK time on air in milliseconds, rounded up.
+pub fn fsk_time_on_air_ms(
+    bitrate_bps: u32,
+    preamble_bytes: u16,
+    implicit_header: bool,
+    crc_enabled: bool,
+    payload_len: usize,
+) -> u32 {
+    fsk_time_on_air_us(bitrate_bps, preamble_bytes, implicit_header, crc_enabled, payload_len).div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference values from the Semtech LoRa airtime calculator (CRC on,
+    // explicit header, 8-symbol preamble).
+
+    #[test]
+    fn sf7_125khz_matches_reference() {
+        assert_eq!(time_on_air_us(7, 125, 5, 8, false, true, 10), 41_216);
+    }
+
+    #[test]
+    fn sf10_125khz_matches_reference() {
+        assert_eq!(time_on_air_us(10, 125, 5, 8, false, true, 51), 616_448);
+    }
+
+    #[test]
+    fn sf12_uses_low_data_rate_optimisation() {
+        assert!(low_data_rate_optimise(12, 125));
+        assert_eq!(time_on_air_us(12, 125, 5, 8, false, true, 10), 991_232);
+    }
+
+    #[test]
+    fn low_data_rate_optimisation_follows_symbol_duration() {
+        // Symbols of 16.384 ms: on
+        assert!(low_data_rate_optimise(11, 125));
+        assert!(low_data_rate_optimise(12, 250));
+        assert!(low_data_rate_optimise(10, 62));
+        assert!(low_data_rate_optimise(9, 31));
+        // Symbols of 8.192 ms or less: off
+        assert!(!low_data_rate_optimise(10, 125));
+        assert!(!low_data_rate_optimise(11, 250));
+        assert!(!low_data_rate_optimise(12, 500));
+        assert!(!low_data_rate_optimise(7, 125));
+    }
+
+    #[test]
+    fn sf12_250khz_matches_reference() {
+        // LDRO on, as the Semtech calculator has it
+        assert_eq!(time_on_air_us(12, 250, 5, 8, false, true, 10), 495_616);
+    }
+
+    #[test]
+    fn implicit_header_is_shorter() {
+        let explicit = time_on_air_us(9, 125, 5, 8, false, true, 32);
+        let implicit = time_on_air_us(9, 125, 5, 8, true, true, 32);
+        assert!(implicit < explicit);
+    }
+
+    #[test]
+    fn crc_adds_two_bytes() {
+        let with_crc = time_on_air_us(7, 125, 5, 8, false, true, 10);
+        assert_eq!(time_on_air_us(7, 125, 5, 8, false, false, 12), with_crc);
+        assert!(time_on_air_us(7, 125, 5, 8, false, false, 10) < with_crc);
+    }
+
+    #[test]
+    fn higher_coding_rate_and_bandwidth_scale_as_expected() {
+        assert!(time_on_air_us(7, 125, 8, 8, false, true, 50) > time_on_air_us(7, 125, 5, 8, false, true, 50));
+        assert_eq!(time_on_air_us(7, 250, 5, 8, false, true, 10) * 2, time_on_air_us(7, 125, 5, 8, false, true, 10));
+    }
+
+    #[test]
+    fn milliseconds_round_up() {
+        assert_eq!(time_on_air_ms(7, 125, 5, 8, false, true, 10), 42);
+    }
+
+    #[test]
+    fn fsk_counts_every_byte_at_the_bit_rate() {
+        // 8 preamble + 2 sync + 1 length + 10 payload + 2 CRC = 184 bits
+        assert_eq!(fsk_time_on_air_us(4_800, 8, false, true, 10), 38_333);
+        assert_eq!(fsk_time_on_air_ms(4_800, 8, false, true, 10), 39);
+        // Fixed length and no CRC drop 3 bytes
+        assert_eq!(fsk_time_on_air_us(50_000, 8, true, false, 10), 3_200);
+    }
+}
@@ -0,0 +1,93 @@
+//! Regional frequency-band presets
+//!
+//! Maps the band selected with SetBand to a default channel, sync word and
+//! duty-cycle limit, so users need not work out a legal frequency by hand.
+
+use crate::config::lora_defaults;
+
+/// Regional band preset, as selected by the SetBand byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum LoraBand {
+    /// Europe 863-870 MHz (the firmware default)
+    #[default]
+    Eu868 = 0,
+    /// Americas 902-928 MHz
+    Us915 = 1,
+    /// Asia-Pacific 915-928 MHz
+    As923 = 2,
+}
+
+impl TryFrom<u8> for LoraBand {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LoraBand::Eu868),
+            1 => Ok(LoraBand::Us915),
+            2 => Ok(LoraBand::As923),
+            _ => Err(value),
+        }
+    }
+}
+
+impl LoraBand {
+    /// Default channel in Hz
+    pub const fn frequency_hz(self) -> u32 {
+        match self {
+            // The 869.40-869.65 MHz sub-band, which allows the most airtime
+            LoraBand::Eu868 => 869_525_000,
+            LoraBand::Us915 => 915_000_000,
+            // First AS923 channel
+            LoraBand::As923 => 923_200_000,
+        }
+    }
+
+    /// Sync word applied with the preset
+    ///
+    /// The private-network value in every band, so a preset also undoes a
+    /// public sync word set with SetLoraConfig.
+    pub const fn sync_word(self) -> u16 {
+        lora_defaults::SYNC_WORD
+    }
+
+    /// Duty-cycle limit in tenths of a percent, or `None` where the band has
+    /// no duty-cycle rule
+    pub const fn duty_cycle_permille(self) -> Option<u32> {
+        match self {
+            // 10% in the 869.40-869.65 MHz sub-band
+            LoraBand::Eu868 => Some(100),
+            // FCC Part 15 limits dwell time per channel, not duty cycle
+            LoraBand::Us915 => None,
+            // 1%, the strictest of the AS923 national rules (e.g. Japan)
+            LoraBand::As923 => Some(10),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_band_matches_build_defaults() {
+        let band = LoraBand::default();
+        assert_eq!(band.frequency_hz(), lora_defaults::FREQUENCY_HZ);
+        assert_eq!(band.duty_cycle_permille(), Some(crate::config::duty_cycle::LIMIT_PERMILLE));
+    }
+
+    #[test]
+    fn selector_byte_round_trips() {
+        for band in [LoraBand::Eu868, LoraBand::Us915, LoraBand::As923] {
+            assert_eq!(LoraBand::try_from(band as u8), Ok(band));
+        }
+        assert_eq!(LoraBand::try_from(3), Err(3));
+    }
+
+    #[test]
+    fn channels_sit_inside_their_bands() {
+        assert!((863_000_000..=870_000_000).contains(&LoraBand::Eu868.frequency_hz()));
+        assert!((902_000_000..=928_000_000).contains(&LoraBand::Us915.frequency_hz()));
+        assert!((915_000_000..=928_000_000).contains(&LoraBand::As923.frequency_hz()));
+    }
+}
@@ -0,0 +1,106 @@
+//! Dropping messages delivered more than once
+//!
+//! With repeaters about, or several receivers bridged to one host, the same
+//! message can reach a device along more than one path, further apart than
+//! `repeater::SeenFrames` remembers single frames. Each whole message is
+//! remembered by sender and message id, and a repeat within `WINDOW_MS` is
+//! dropped instead of being passed to the host again. A sender's message ids
+//! wrap after 256 messages and start from 0 again when it reboots, so the
+//! window is kept short.
+
+use heapless::Vec;
+
+use crate::config::dedup::{MESSAGES, WINDOW_MS};
+use crate::lora::addressing::Address;
+
+/// Identifies a message: its sender and message id
+pub type MessageKey = (Address, u8);
+
+/// Messages delivered in the last `WINDOW_MS`
+pub struct DeliveredMessages {
+    /// Least recently heard first
+    messages: Vec<(MessageKey, u64), MESSAGES>,
+}
+
+impl DeliveredMessages {
+    pub const fn new() -> Self {
+        Self { messages: Vec::new() }
+    }
+
+    /// Record the message `key` completed at `now_ms`, returning true unless
+    /// it was already delivered within the window
+    ///
+    /// A repeat counts as hearing the message again, so it is remembered for
+    /// longer. Once full, the least recently heard message is forgotten to
+    /// make room.
+    pub fn first_delivery(&mut self, key: MessageKey, now_ms: u64) -> bool {
+        self.messages.retain(|(_, heard_ms)| now_ms.saturating_sub(*heard_ms) < WINDOW_MS);
+        let repeat = match self.messages.iter().position(|(seen, _)| *seen == key) {
+            Some(index) => {
+                self.messages.remove(index);
+                true
+            }
+            None => {
+                if self.messages.is_full() {
+                    self.messages.remove(0);
+                }
+                false
+            }
+        };
+        let _ = self.messages.push((key, now_ms));
+        !repeat
+    }
+}
+
+impl Default for DeliveredMessages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: Address = [0xAB, 0xCD, 0xEF];
+
+    #[test]
+    fn repeated_message_is_dropped() {
+        let mut delivered = DeliveredMessages::new();
+        assert!(delivered.first_delivery((PEER, 1), 0));
+        assert!(!delivered.first_delivery((PEER, 1), 10));
+        assert!(!delivered.first_delivery((PEER, 1), 20));
+    }
+
+    #[test]
+    fn distinct_ids_and_senders_are_delivered() {
+        let mut delivered = DeliveredMessages::new();
+        assert!(delivered.first_delivery((PEER, 1), 0));
+        assert!(delivered.first_delivery((PEER, 2), 0));
+        assert!(delivered.first_delivery(([0x01, 0x02, 0x03], 1), 0));
+        assert!(!delivered.first_delivery((PEER, 2), 0));
+    }
+
+    #[test]
+    fn message_is_forgotten_after_the_window() {
+        let mut delivered = DeliveredMessages::new();
+        assert!(delivered.first_delivery((PEER, 1), 0));
+        // Hearing it again keeps it remembered from then on
+        assert!(!delivered.first_delivery((PEER, 1), WINDOW_MS - 1));
+        assert!(!delivered.first_delivery((PEER, 1), 2 * WINDOW_MS - 2));
+        assert!(delivered.first_delivery((PEER, 1), 3 * WINDOW_MS));
+    }
+
+    #[test]
+    fn least_recently_heard_is_forgotten_when_full() {
+        let mut delivered = DeliveredMessages::new();
+        for msg_id in 0..MESSAGES as u8 {
+            assert!(delivered.first_delivery((PEER, msg_id), 0));
+        }
+        // Message 0 heard again, so message 1 is now the least recent
+        assert!(!delivered.first_delivery((PEER, 0), 0));
+        assert!(delivered.first_delivery((PEER, MESSAGES as u8), 0));
+        assert!(delivered.first_delivery((PEER, 1), 0));
+        assert!(!delivered.first_delivery((PEER, 0), 0));
+    }
+}
@@ -3,9 +3,13 @@
 //! Wraps the sx1262 crate to implement the LoraRadio trait for use with Embassy.
 
 use crate::config::protocol::MAX_LORA_PAYLOAD;
-use crate::config::tcxo;
+use crate::config::{lora_defaults, spectrum_scan, spi, tcxo};
+use crate::lora::airtime;
 use crate::lora::calibration::{image_cal_params, CALIBRATE_ALL};
-use crate::lora::traits::{LoraConfig, LoraError, LoraRadio, RxPacket};
+use crate::lora::traits::{
+    self_test, FskParams, LoraConfig, LoraError, LoraRadio, Modulation, PacketStatus, RadioState,
+    RxDutyCycle, RxPacket, SleepMode, StandbyMode, MAX_TX_POWER_DBM, MIN_TX_POWER_DBM,
+};
 use embassy_time::{Duration, Timer};
 use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_async::spi::SpiBus;
@@ -13,9 +17,12 @@ use heapless::Vec;
 
 /// SX1262 command opcodes
 mod cmd {
+    pub const SET_SLEEP: u8 = 0x84;
     pub const SET_STANDBY: u8 = 0x80;
     pub const SET_TX: u8 = 0x83;
+    pub const SET_TX_CONTINUOUS_WAVE: u8 = 0xD1;
     pub const SET_RX: u8 = 0x82;
+    pub const SET_RX_DUTY_CYCLE: u8 = 0x94;
     pub const SET_RF_FREQUENCY: u8 = 0x86;
     pub const SET_PACKET_TYPE: u8 = 0x8A;
     pub const SET_MODULATION_PARAMS: u8 = 0x8B;
@@ -30,17 +37,41 @@ mod cmd {
     pub const WRITE_BUFFER: u8 = 0x0E;
     pub const READ_BUFFER: u8 = 0x1E;
     pub const WRITE_REGISTER: u8 = 0x0D;
+    pub const READ_REGISTER: u8 = 0x1D;
     pub const GET_RX_BUFFER_STATUS: u8 = 0x13;
     pub const GET_PACKET_STATUS: u8 = 0x14;
+    pub const GET_RSSI_INST: u8 = 0x15;
     pub const GET_IRQ_STATUS: u8 = 0x12;
     pub const CLEAR_IRQ_STATUS: u8 = 0x02;
     pub const SET_DIO_IRQ_PARAMS: u8 = 0x08;
+    pub const GET_STATUS: u8 = 0xC0;
+    pub const GET_DEVICE_ERRORS: u8 = 0x17;
+    pub const SET_RX_TX_FALLBACK_MODE: u8 = 0x93;
 }
 
 /// SX1262 register addresses
 mod reg {
     /// Over-current protection register
     pub const OCP_CONFIGURATION: u16 = 0x08E7;
+    /// LoRa sync word, most significant byte
+    pub const LORA_SYNC_WORD_MSB: u16 = 0x0740;
+    /// LoRa sync word, least significant byte
+    pub const LORA_SYNC_WORD_LSB: u16 = 0x0741;
+    /// IQ polarity setup (datasheet errata 15.4); bit 2 must be cleared for
+    /// inverted IQ and set for standard IQ
+    pub const IQ_POLARITY: u16 = 0x0736;
+    /// First of the four random number generator registers (0x0819-0x081C)
+    pub const RANDOM_NUMBER_GEN: u16 = 0x0819;
+    /// LNA control; bit 0 connects the LNA to the antenna
+    pub const ANA_LNA: u16 = 0x08E2;
+    /// Mixer control; bit 7 enables the mixer
+    pub const ANA_MIXER: u16 = 0x08E5;
+    /// First of the three frequency error registers (0x076B-0x076D): a
+    /// 20-bit two's complement estimate, valid after a LoRa reception
+    pub const FREQ_ERROR: u16 = 0x076B;
+    /// GFSK sync word, first of eight bytes (0x06C0-0x06C7); the driver
+    /// uses the first two
+    pub const FSK_SYNC_WORD: u16 = 0x06C0;
 }
 
 /// Maximum RX payload length advertised to the modem.
@@ -53,16 +84,124 @@ const RX_MAX_PAYLOAD_LEN: u8 = if MAX_LORA_PAYLOAD > 255 {
     MAX_LORA_PAYLOAD as u8
 };
 
+/// Frequency error in Hz from the raw `reg::FREQ_ERROR` bytes
+///
+/// The register counts in steps of 1.55 Hz x BW / 1.6 MHz (the conversion
+/// Semtech gives for LoRa); only the low 20 bits are the estimate.
+fn freq_error_hz(raw: [u8; 3], bandwidth_khz: u32) -> i32 {
+    let value = ((raw[0] as i32 & 0x0F) << 16) | ((raw[1] as i32) << 8) | raw[2] as i32;
+    // Sign-extend from bit 19
+    let value = (value << 12) >> 12;
+    (value as i64 * 155 * bandwidth_khz as i64 / 160_000) as i32
+}
+
+/// GFSK SetModulationParams bytes for `fsk`, or `None` for a bandwidth or
+/// Gaussian filter the SX1262 does not have
+///
+/// The bit rate goes as 32 x Fxtal / bit rate and the deviation in steps of
+/// Fxtal / 2^25, both 24 bits big-endian, around the pulse shape and the
+/// receiver bandwidth code.
+fn fsk_modulation_params(fsk: &FskParams) -> Option<[u8; 8]> {
+    let bitrate = (32 * 32_000_000u64).checked_div(fsk.bitrate_bps as u64)? as u32;
+    let deviation = ((fsk.deviation_hz as u64 * (1 << 25)) / 32_000_000) as u32;
+    let pulse_shape = match fsk.gaussian_bt {
+        0 => 0x00,  // No filter
+        3 => 0x08,  // BT 0.3
+        5 => 0x09,  // BT 0.5
+        7 => 0x0A,  // BT 0.7
+        10 => 0x0B, // BT 1.0
+        _ => return None,
+    };
+    let bandwidth = match fsk.rx_bandwidth_hz {
+        4_800 => 0x1F,
+        5_800 => 0x17,
+        7_300 => 0x0F,
+        9_700 => 0x1E,
+        11_700 => 0x16,
+        14_600 => 0x0E,
+        19_500 => 0x1D,
+        23_400 => 0x15,
+        29_300 => 0x0D,
+        39_000 => 0x1C,
+        46_900 => 0x14,
+        58_600 => 0x0C,
+        78_200 => 0x1B,
+        93_800 => 0x13,
+        117_300 => 0x0B,
+        156_200 => 0x1A,
+        187_200 => 0x12,
+        234_300 => 0x0A,
+        312_000 => 0x19,
+        373_600 => 0x11,
+        467_000 => 0x09,
+        _ => return None,
+    };
+    Some([
+        (bitrate >> 16) as u8,
+        (bitrate >> 8) as u8,
+        bitrate as u8,
+        pulse_shape,
+        bandwidth,
+        (deviation >> 16) as u8,
+        (deviation >> 8) as u8,
+        deviation as u8,
+    ])
+}
+
+/// How often `wait_for_irq` reads the IRQ status register as well as DIO1
+const IRQ_STATUS_POLL_MS: u64 = 10;
+
+/// Packet-params header type byte
+mod header_type {
+    pub const EXPLICIT: u8 = 0x00;
+    pub const IMPLICIT: u8 = 0x01;
+}
+
 /// Standby modes
 mod standby {
     pub const STDBY_RC: u8 = 0x00;
+    pub const STDBY_XOSC: u8 = 0x01;
+}
+
+/// Modes the radio returns to after TX or RX (SetRxTxFallbackMode)
+mod fallback {
+    pub const STDBY_RC: u8 = 0x20;
+    pub const STDBY_XOSC: u8 = 0x30;
+}
+
+/// SetSleep configuration (RTC wake-up disabled)
+mod sleep_config {
+    /// Cold start: configuration is lost, lowest current
+    pub const COLD_START: u8 = 0x00;
+    /// Warm start: configuration is retained
+    pub const WARM_START: u8 = 0x04;
 }
 
 /// Packet types
 mod packet_type {
+    pub const GFSK: u8 = 0x00;
     pub const LORA: u8 = 0x01;
 }
 
+/// GFSK packet-params fields
+mod fsk_packet {
+    /// Preamble detector length: 16 bits
+    pub const PREAMBLE_DETECTOR_16_BITS: u8 = 0x05;
+    /// Sync word length in bits (the 2-byte `LoraConfig::sync_word`)
+    pub const SYNC_WORD_BITS: u8 = 16;
+    /// No node or broadcast address filtering
+    pub const ADDR_COMP_OFF: u8 = 0x00;
+    /// Fixed length: no length byte on air
+    pub const FIXED_LENGTH: u8 = 0x00;
+    /// Variable length: a length byte follows the sync word
+    pub const VARIABLE_LENGTH: u8 = 0x01;
+    pub const CRC_OFF: u8 = 0x01;
+    /// 2-byte inverted CRC; with the reset polynomial and seed this is the
+    /// CRC-16/CCITT most GFSK gear uses
+    pub const CRC_2_BYTE_INV: u8 = 0x06;
+    pub const WHITENING_OFF: u8 = 0x00;
+}
+
 /// IRQ masks
 mod irq {
     pub const TX_DONE: u16 = 0x0001;
@@ -71,56 +210,117 @@ mod irq {
     pub const CRC_ERR: u16 = 0x0040;
 }
 
+/// Status byte fields (GetStatus)
+mod status {
+    use super::RadioState;
+
+    /// Chip mode field: bits 6:4
+    pub const fn chip_mode(status: u8) -> u8 {
+        (status >> 4) & 0x07
+    }
+    /// Chip modes a powered radio reports (STBY_RC through TX); 0 and 7 are
+    /// unused, so a floating or shorted MISO reads as invalid
+    pub const VALID_MODES: core::ops::RangeInclusive<u8> = 2..=6;
+
+    /// What a powered radio reports it is doing, or None for an unused mode
+    pub const fn radio_state(status: u8) -> Option<RadioState> {
+        match chip_mode(status) {
+            0x2 => Some(RadioState::StandbyRc),
+            0x3 => Some(RadioState::StandbyXosc),
+            0x4 => Some(RadioState::Fs),
+            0x5 => Some(RadioState::Rx),
+            0x6 => Some(RadioState::Tx),
+            _ => None,
+        }
+    }
+}
+
+/// GetDeviceErrors bits (low byte)
+mod device_errors {
+    /// The crystal or TCXO failed to start
+    pub const XOSC_START_ERR: u8 = 0x20;
+}
+
 /// Control pins for SX1262
-pub struct Sx1262Pins<Nss, Dio1, Nrst, Busy> {
+pub struct Sx1262Pins<Nss, Dio1, Nrst, Busy, RfSwitch> {
     pub nss: Nss,
     pub dio1: Dio1,
     pub nrst: Nrst,
     pub busy: Busy,
+    /// External TX/RX switch control, high for TX; None if the radio drives
+    /// the switch from DIO2
+    pub rf_switch: Option<RfSwitch>,
 }
 
 /// SX1262 LoRa driver
 ///
 /// Implements the LoraRadio trait using dependency injection for SPI and GPIO pins.
 /// Uses SpiBus trait with manual NSS control.
-pub struct Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy>
+pub struct Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy, RfSwitch>
 where
     Spi: SpiBus,
     Nss: OutputPin,
     Dio1: InputPin,
     Nrst: OutputPin,
     Busy: InputPin,
+    RfSwitch: OutputPin,
 {
     spi: Spi,
     nss: Nss,
     dio1: Dio1,
     nrst: Nrst,
     busy: Busy,
+    rf_switch: Option<RfSwitch>,
     initialised: bool,
     config: Option<LoraConfig>,
+    /// Set while the radio is asleep
+    sleep_mode: Option<SleepMode>,
+    /// Set while the radio is in continuous (or duty-cycled) RX
+    rx_active: bool,
+    /// Receive windows, or None for continuous RX
+    rx_duty_cycle: Option<RxDutyCycle>,
+    /// Time NSS is held high after each command
+    nss_high: Duration,
+    /// Link quality of the last packet read out
+    last_packet: Option<PacketStatus>,
 }
 
-impl<Spi, Nss, Dio1, Nrst, Busy> Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy>
+impl<Spi, Nss, Dio1, Nrst, Busy, RfSwitch> Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy, RfSwitch>
 where
     Spi: SpiBus,
     Nss: OutputPin,
     Dio1: InputPin,
     Nrst: OutputPin,
     Busy: InputPin,
+    RfSwitch: OutputPin,
 {
     /// Create a new SX1262 driver
-    pub fn new(spi: Spi, pins: Sx1262Pins<Nss, Dio1, Nrst, Busy>) -> Self {
+    pub fn new(spi: Spi, pins: Sx1262Pins<Nss, Dio1, Nrst, Busy, RfSwitch>) -> Self {
         Self {
             spi,
             nss: pins.nss,
             dio1: pins.dio1,
             nrst: pins.nrst,
             busy: pins.busy,
+            rf_switch: pins.rf_switch,
             initialised: false,
             config: None,
+            sleep_mode: None,
+            rx_active: false,
+            rx_duty_cycle: None,
+            nss_high: Duration::from_micros(spi::NSS_HIGH_US),
+            last_packet: None,
         }
     }
 
+    /// Hold NSS high for `time` after each command instead of
+    /// `config::spi::NSS_HIGH_US`. Zero skips the hold, for radios on a slow
+    /// enough clock that BUSY is always up before the next command.
+    pub fn with_nss_high(mut self, time: Duration) -> Self {
+        self.nss_high = time;
+        self
+    }
+
     /// Reset the radio
     async fn reset(&mut self) -> Result<(), LoraError> {
         let _ = self.nrst.set_low();
@@ -142,6 +342,20 @@ where
         Err(LoraError::BusyTimeout)
     }
 
+    /// End an SPI transaction: raise NSS and hold it high
+    ///
+    /// BUSY only rises up to 600 ns after NSS does (T_SW in the datasheet).
+    /// Without the hold, a back-to-back command at a fast SPI clock can poll
+    /// BUSY before it rises, find it still low from the last command and be
+    /// sent while the radio is busy, which drops it. NSS is raised even when
+    /// the transfer failed, so the next command still starts cleanly.
+    async fn release_nss(&mut self) {
+        let _ = self.nss.set_high();
+        if self.nss_high > Duration::from_ticks(0) {
+            Timer::after(self.nss_high).await;
+        }
+    }
+
     /// Write a command to the radio
     async fn write_command(&mut self, cmd: u8, data: &[u8]) -> Result<(), LoraError> {
         self.wait_not_busy().await?;
@@ -153,12 +367,9 @@ where
         let len = 1 + data.len().min(15);
         buf[1..len].copy_from_slice(&data[..len - 1]);
 
-        self.spi
-            .write(&buf[..len])
-            .await
-            .map_err(|_| LoraError::SpiError)?;
-
-        let _ = self.nss.set_high();
+        let done = self.spi.write(&buf[..len]).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
 
         Ok(())
     }
@@ -176,12 +387,9 @@ where
         tx_buf[1] = 0x00; // NOP
 
         let total_len = 2 + len;
-        self.spi
-            .transfer(&mut rx_buf[..total_len], &tx_buf[..total_len])
-            .await
-            .map_err(|_| LoraError::SpiError)?;
-
-        let _ = self.nss.set_high();
+        let done = self.spi.transfer(&mut rx_buf[..total_len], &tx_buf[..total_len]).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
 
         // Response starts after status byte (index 2)
         let mut result = [0u8; 16];
@@ -190,6 +398,20 @@ where
         Ok(result)
     }
 
+    /// Read the status byte (GetStatus), returned while the NOP is clocked out
+    async fn get_status(&mut self) -> Result<u8, LoraError> {
+        self.wait_not_busy().await?;
+
+        let _ = self.nss.set_low();
+
+        let mut rx_buf = [0u8; 2];
+        let done = self.spi.transfer(&mut rx_buf, &[cmd::GET_STATUS, 0x00]).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
+
+        Ok(rx_buf[1])
+    }
+
     /// Configure DIO3 as TCXO control
     async fn configure_tcxo(&mut self) -> Result<(), LoraError> {
         // SetDIO3AsTcxoCtrl: voltage code + timeout (24-bit)
@@ -203,12 +425,22 @@ where
         self.write_command(cmd::SET_DIO3_AS_TCXO_CTRL, &data).await
     }
 
-    /// Configure DIO2 as RF switch control
+    /// Configure DIO2 as RF switch control, unless an external switch pin
+    /// was given, in which case DIO2 is left alone
     async fn configure_dio2_rf_switch(&mut self) -> Result<(), LoraError> {
-        self.write_command(cmd::SET_DIO2_AS_RF_SWITCH_CTRL, &[0x01])
+        let enable = self.rf_switch.is_none() as u8;
+        self.write_command(cmd::SET_DIO2_AS_RF_SWITCH_CTRL, &[enable])
             .await
     }
 
+    /// Point the external RF switch at the PA (`tx`) or the LNA; nothing
+    /// when DIO2 drives it
+    fn set_rf_switch(&mut self, tx: bool) {
+        if let Some(pin) = self.rf_switch.as_mut() {
+            let _ = if tx { pin.set_high() } else { pin.set_low() };
+        }
+    }
+
     /// Recalibrate all blocks.
     ///
     /// Required after enabling the TCXO: the power-on calibration ran from the
@@ -237,8 +469,34 @@ where
         self.write_command(cmd::WRITE_REGISTER, &data).await
     }
 
+    /// Read a register
+    async fn read_register(&mut self, addr: u16) -> Result<u8, LoraError> {
+        self.wait_not_busy().await?;
+
+        let _ = self.nss.set_low();
+
+        // Opcode, address, then a NOP status byte before the value
+        let tx_buf = [cmd::READ_REGISTER, (addr >> 8) as u8, (addr & 0xFF) as u8, 0x00, 0x00];
+        let mut rx_buf = [0u8; 5];
+        let done = self.spi.transfer(&mut rx_buf, &tx_buf).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
+
+        Ok(rx_buf[4])
+    }
+
+    /// Apply the IQ polarity register fix from the SX1262 errata (15.4).
+    ///
+    /// Without it, a radio using inverted IQ loses packets. The register
+    /// holds other bits, so it is read-modify-written.
+    async fn set_iq_polarity(&mut self, inverted: bool) -> Result<(), LoraError> {
+        let value = self.read_register(reg::IQ_POLARITY).await?;
+        let value = if inverted { value & !0x04 } else { value | 0x04 };
+        self.write_register(reg::IQ_POLARITY, value).await
+    }
+
     /// Set current limit (OCP - Over Current Protection)
-    /// current_ma: Current limit in mA (default 140mA for SX1262)
+    /// current_ma: Current limit in mA (`LoraConfig::ocp_ma`, default 140mA)
     async fn set_current_limit(&mut self, current_ma: u16) -> Result<(), LoraError> {
         // OCP register value = current_ma / 2.5
         // Clamped to valid range
@@ -246,18 +504,56 @@ where
         self.write_register(reg::OCP_CONFIGURATION, ocp_value).await
     }
 
-    /// Set standby mode
-    async fn set_standby_internal(&mut self) -> Result<(), LoraError> {
-        self.write_command(cmd::SET_STANDBY, &[standby::STDBY_RC])
+    /// Set the LoRa sync word (both register bytes)
+    async fn set_sync_word(&mut self, sync_word: u16) -> Result<(), LoraError> {
+        self.write_register(reg::LORA_SYNC_WORD_MSB, (sync_word >> 8) as u8)
+            .await?;
+        self.write_register(reg::LORA_SYNC_WORD_LSB, (sync_word & 0xFF) as u8)
             .await
     }
 
-    /// Set packet type to LoRa
-    async fn set_packet_type_lora(&mut self) -> Result<(), LoraError> {
-        self.write_command(cmd::SET_PACKET_TYPE, &[packet_type::LORA])
+    /// Set the first two bytes of the GFSK sync word
+    async fn set_fsk_sync_word(&mut self, sync_word: u16) -> Result<(), LoraError> {
+        self.write_register(reg::FSK_SYNC_WORD, (sync_word >> 8) as u8)
+            .await?;
+        self.write_register(reg::FSK_SYNC_WORD + 1, (sync_word & 0xFF) as u8)
             .await
     }
 
+    /// Set standby mode on the configured oscillator
+    async fn set_standby_internal(&mut self) -> Result<(), LoraError> {
+        let mode = self.config.as_ref().map_or(StandbyMode::Rc, |c| c.standby_mode);
+        self.set_standby_on(mode).await
+    }
+
+    /// Set standby mode on `mode`'s oscillator
+    async fn set_standby_on(&mut self, mode: StandbyMode) -> Result<(), LoraError> {
+        self.rx_active = false;
+        let oscillator = match mode {
+            StandbyMode::Rc => standby::STDBY_RC,
+            StandbyMode::Xosc => standby::STDBY_XOSC,
+        };
+        self.write_command(cmd::SET_STANDBY, &[oscillator]).await
+    }
+
+    /// Set the mode the radio drops to after TX or RX
+    async fn set_fallback_mode(&mut self, mode: StandbyMode) -> Result<(), LoraError> {
+        let fallback = match mode {
+            StandbyMode::Rc => fallback::STDBY_RC,
+            StandbyMode::Xosc => fallback::STDBY_XOSC,
+        };
+        self.write_command(cmd::SET_RX_TX_FALLBACK_MODE, &[fallback]).await
+    }
+
+    /// Set the packet type for `modulation`
+    async fn set_packet_type(&mut self, modulation: Modulation) -> Result<(), LoraError> {
+        let packet_type = match modulation {
+            Modulation::Lora => packet_type::LORA,
+            Modulation::Fsk(_) => packet_type::GFSK,
+        };
+        self.write_command(cmd::SET_PACKET_TYPE, &[packet_type]).await
+    }
+
     /// Set RF frequency
     async fn set_frequency(&mut self, freq_hz: u32) -> Result<(), LoraError> {
         // Frequency = (freq_rf * 2^25) / 32MHz
@@ -273,6 +569,11 @@ where
 
     /// Set modulation parameters
     async fn set_modulation_params(&mut self, config: &LoraConfig) -> Result<(), LoraError> {
+        if let Modulation::Fsk(fsk) = config.modulation {
+            let data = fsk_modulation_params(&fsk).ok_or(LoraError::InvalidConfig)?;
+            return self.write_command(cmd::SET_MODULATION_PARAMS, &data).await;
+        }
+
         let bw = match config.bandwidth_khz {
             7 | 8 => 0x00,   // 7.8 kHz
             10 => 0x08,      // 10.4 kHz
@@ -284,7 +585,7 @@ where
             125 => 0x04,     // 125 kHz
             250 => 0x05,     // 250 kHz
             500 => 0x06,     // 500 kHz
-            _ => 0x04,       // Default to 125 kHz
+            _ => return Err(LoraError::InvalidConfig),
         };
 
         let cr = match config.coding_rate {
@@ -292,11 +593,11 @@ where
             6 => 0x02, // 4/6
             7 => 0x03, // 4/7
             8 => 0x04, // 4/8
-            _ => 0x01, // Default to 4/5
+            _ => return Err(LoraError::InvalidConfig),
         };
 
-        // Low data rate optimisation: required for SF11/SF12 at 125kHz
-        let ldro = if config.spreading_factor >= 11 && config.bandwidth_khz <= 125 {
+        // Low data rate optimisation: required once a symbol exceeds 16.38 ms
+        let ldro = if airtime::low_data_rate_optimise(config.spreading_factor, config.bandwidth_khz) {
             0x01
         } else {
             0x00
@@ -306,14 +607,83 @@ where
         self.write_command(cmd::SET_MODULATION_PARAMS, &data).await
     }
 
+    /// Whether the applied configuration uses implicit header mode, and if so
+    /// its fixed frame length
+    fn implicit_len(&self) -> Option<u8> {
+        self.config
+            .as_ref()
+            .filter(|c| c.implicit_header)
+            .map(|c| c.implicit_len)
+    }
+
+    /// Whether the applied configuration is GFSK
+    fn is_fsk(&self) -> bool {
+        self.config.as_ref().is_some_and(LoraConfig::is_fsk)
+    }
+
     /// Set packet parameters
+    ///
+    /// The preamble length, header mode, CRC and IQ come from the applied
+    /// configuration. In implicit header mode the fixed frame length replaces
+    /// `payload_len`, since the receiver has no header to learn it from.
     async fn set_packet_params(&mut self, payload_len: u8) -> Result<(), LoraError> {
+        if self.is_fsk() {
+            return self.set_fsk_packet_params(payload_len).await;
+        }
+        let (preamble, crc_enabled, iq_inverted) = self.config.as_ref().map_or(
+            (
+                lora_defaults::PREAMBLE_SYMBOLS,
+                lora_defaults::CRC_ENABLED,
+                lora_defaults::IQ_INVERTED,
+            ),
+            |c| (c.preamble_symbols, c.crc_enabled, c.iq_inverted),
+        );
+        let (header, payload_len) = match self.implicit_len() {
+            Some(len) => (header_type::IMPLICIT, len),
+            None => (header_type::EXPLICIT, payload_len),
+        };
+        let data = [
+            (preamble >> 8) as u8, // Preamble length (symbols, big-endian)
+            (preamble & 0xFF) as u8,
+            header,
+            payload_len,
+            crc_enabled as u8, // CRC on/off
+            iq_inverted as u8, // Standard/inverted IQ
+        ];
+        self.write_command(cmd::SET_PACKET_PARAMS, &data).await
+    }
+
+    /// Set GFSK packet parameters
+    ///
+    /// The plainest framing legacy gear uses: the preamble (`preamble_symbols`
+    /// bytes), the 2-byte sync word, a length byte unless implicit header
+    /// mode fixes the length, then the payload and CRC-16/CCITT if enabled.
+    /// No address filtering and no whitening.
+    async fn set_fsk_packet_params(&mut self, payload_len: u8) -> Result<(), LoraError> {
+        let (preamble, crc_enabled) = self.config.as_ref().map_or(
+            (lora_defaults::PREAMBLE_SYMBOLS, lora_defaults::CRC_ENABLED),
+            |c| (c.preamble_symbols, c.crc_enabled),
+        );
+        let preamble_bits = preamble.saturating_mul(8);
+        let (length_type, payload_len) = match self.implicit_len() {
+            Some(len) => (fsk_packet::FIXED_LENGTH, len),
+            None => (fsk_packet::VARIABLE_LENGTH, payload_len),
+        };
+        let crc_type = if crc_enabled {
+            fsk_packet::CRC_2_BYTE_INV
+        } else {
+            fsk_packet::CRC_OFF
+        };
         let data = [
-            0x00, 0x08, // Preamble length: 8 symbols
-            0x00, // Explicit header
+            (preamble_bits >> 8) as u8, // Preamble length (bits, big-endian)
+            (preamble_bits & 0xFF) as u8,
+            fsk_packet::PREAMBLE_DETECTOR_16_BITS,
+            fsk_packet::SYNC_WORD_BITS,
+            fsk_packet::ADDR_COMP_OFF,
+            length_type,
             payload_len,
-            0x01, // CRC on
-            0x00, // Standard IQ
+            crc_type,
+            fsk_packet::WHITENING_OFF,
         ];
         self.write_command(cmd::SET_PACKET_PARAMS, &data).await
     }
@@ -328,7 +698,7 @@ where
     }
 
     /// Set TX power
-    async fn set_tx_power(&mut self, power_dbm: i8) -> Result<(), LoraError> {
+    async fn set_tx_power(&mut self, power_dbm: i8, ramp_time: u8) -> Result<(), LoraError> {
         // For SX1262 with HP PA after SetPaConfig(0x04, 0x07, 0x00, 0x01):
         // Power register value maps directly to dBm for range -9 to +22
         // Negative values need to be converted to two's complement
@@ -337,7 +707,7 @@ where
         } else {
             power_dbm as u8
         };
-        let data = [power, 0x04]; // Power, ramp time 200us
+        let data = [power, ramp_time];
         self.write_command(cmd::SET_TX_PARAMS, &data).await
     }
 
@@ -387,12 +757,9 @@ where
         let len = data.len().min(256);
         buf[2..2 + len].copy_from_slice(&data[..len]);
 
-        self.spi
-            .write(&buf[..2 + len])
-            .await
-            .map_err(|_| LoraError::SpiError)?;
-
-        let _ = self.nss.set_high();
+        let done = self.spi.write(&buf[..2 + len]).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
 
         Ok(())
     }
@@ -411,12 +778,9 @@ where
         tx_buf[2] = 0x00; // NOP
 
         let total_len = 3 + len;
-        self.spi
-            .transfer(&mut rx_buf[..total_len], &tx_buf[..total_len])
-            .await
-            .map_err(|_| LoraError::SpiError)?;
-
-        let _ = self.nss.set_high();
+        let done = self.spi.transfer(&mut rx_buf[..total_len], &tx_buf[..total_len]).await;
+        self.release_nss().await;
+        done.map_err(|_| LoraError::SpiError)?;
 
         let mut result = Vec::new();
         result
@@ -432,22 +796,80 @@ where
         Ok((result[0], result[1])) // (payload_length, buffer_offset)
     }
 
-    /// Get packet status
-    async fn get_packet_status(&mut self) -> Result<(i16, i8), LoraError> {
+    /// Read a received packet out of the RX buffer with its signal quality
+    ///
+    /// In explicit header mode the length comes from the decoded header (via
+    /// the RX buffer status); in implicit mode there is no header, so exactly
+    /// the configured fixed length is read.
+    async fn read_received_packet(&mut self) -> Result<RxPacket, LoraError> {
+        let (reported_len, buffer_offset) = self.get_rx_buffer_status().await?;
+        let payload_len = self.implicit_len().unwrap_or(reported_len);
+        let data = self.read_buffer(buffer_offset, payload_len as usize).await?;
+        let status = self.get_packet_status().await?;
+        self.last_packet = Some(status);
+        Ok(RxPacket {
+            data,
+            rssi: status.rssi,
+            snr: status.snr,
+        })
+    }
+
+    /// Get packet status, with the frequency error estimate
+    ///
+    /// Must be read before the next reception overwrites it.
+    async fn get_packet_status(&mut self) -> Result<PacketStatus, LoraError> {
         let result = self.read_command(cmd::GET_PACKET_STATUS, 3).await?;
 
+        if self.is_fsk() {
+            // GFSK reports RX status, RSSI at the sync word and RSSI averaged
+            // over the packet; SNR and frequency error are LoRa only
+            return Ok(PacketStatus {
+                rssi: -(result[2] as i16) / 2,
+                signal_rssi: -(result[1] as i16) / 2,
+                snr: 0,
+                freq_error_hz: 0,
+            });
+        }
+
         // RSSI: -result[0]/2
         let rssi = -(result[0] as i16) / 2;
 
         // SNR: result[1] as signed / 4
         let snr = (result[1] as i8) / 4;
 
-        Ok((rssi, snr))
+        // Signal RSSI: -result[2]/2
+        let signal_rssi = -(result[2] as i16) / 2;
+
+        let mut raw = [0u8; 3];
+        for (offset, byte) in raw.iter_mut().enumerate() {
+            *byte = self.read_register(reg::FREQ_ERROR + offset as u16).await?;
+        }
+        let bandwidth_khz = self
+            .config
+            .as_ref()
+            .map_or(lora_defaults::BANDWIDTH_KHZ, |c| c.bandwidth_khz);
+
+        Ok(PacketStatus {
+            rssi,
+            signal_rssi,
+            snr,
+            freq_error_hz: freq_error_hz(raw, bandwidth_khz),
+        })
     }
 
-    /// Wait for DIO1 interrupt with timeout
+    /// Wait for an interrupt with timeout
+    ///
+    /// Watches DIO1, and also reads the IRQ status on entry and every
+    /// `IRQ_STATUS_POLL_MS`: an IRQ latched before the wait began (e.g. a fast
+    /// TX at SF7) may never be seen as a DIO1 edge.
     async fn wait_for_irq(&mut self, timeout_ms: u32) -> Result<u16, LoraError> {
+        // The deadline is kept in u64 ticks, so even u32::MAX ms cannot wrap
+        // round to an early timeout.
         let deadline = embassy_time::Instant::now() + Duration::from_millis(timeout_ms as u64);
+        let mut next_status_poll = embassy_time::Instant::now();
+        // Any SPI access wakes the radio from a duty-cycle sleep window, so
+        // duty-cycled RX relies on DIO1 alone (a level, so it cannot be missed)
+        let poll_status = !(self.rx_active && self.rx_duty_cycle.is_some());
 
         loop {
             // Check if DIO1 is high (interrupt pending)
@@ -455,6 +877,16 @@ where
                 return self.get_irq_status().await;
             }
 
+            // Only IRQs enabled by configure_irq are latched, so any set bit
+            // is one the caller is waiting for.
+            if poll_status && embassy_time::Instant::now() >= next_status_poll {
+                let irq_status = self.get_irq_status().await?;
+                if irq_status != 0 {
+                    return Ok(irq_status);
+                }
+                next_status_poll = embassy_time::Instant::now() + Duration::from_millis(IRQ_STATUS_POLL_MS);
+            }
+
             if embassy_time::Instant::now() >= deadline {
                 return Err(LoraError::Timeout);
             }
@@ -463,6 +895,19 @@ where
         }
     }
 
+    /// Tune to `freq_hz`, listen for `DWELL_MS` and read the instantaneous
+    /// RSSI, leaving the radio in RX there
+    async fn sample_rssi_at(&mut self, freq_hz: u32) -> Result<i16, LoraError> {
+        self.set_standby_internal().await?;
+        self.set_frequency(freq_hz).await?;
+        self.write_command(cmd::SET_RX, &[0xFF, 0xFF, 0xFF]).await?;
+        Timer::after(Duration::from_millis(spectrum_scan::DWELL_MS)).await;
+
+        let result = self.read_command(cmd::GET_RSSI_INST, 1).await?;
+        // RSSI: -result[0]/2
+        Ok(-(result[0] as i16) / 2)
+    }
+
     /// Start continuous receive mode (like Arduino's startReceive)
     /// Puts the radio into RX mode with no timeout
     async fn start_receive_mode(&mut self) -> Result<(), LoraError> {
@@ -477,29 +922,44 @@ where
             .await?;
         self.clear_irq(0xFFFF).await?;
 
-        // Start continuous RX (timeout = 0xFFFFFF means continuous)
-        let timeout_bytes = [0xFF, 0xFF, 0xFF];
-        self.write_command(cmd::SET_RX, &timeout_bytes).await?;
+        self.set_rf_switch(false);
+        match self.rx_duty_cycle {
+            // Alternate listen and sleep windows, counted in 15.625 us steps
+            Some(RxDutyCycle { rx_ms, sleep_ms }) => {
+                let [_, rx @ ..] = (rx_ms * 64).to_be_bytes();
+                let [_, sleep @ ..] = (sleep_ms * 64).to_be_bytes();
+                let data = [rx[0], rx[1], rx[2], sleep[0], sleep[1], sleep[2]];
+                self.write_command(cmd::SET_RX_DUTY_CYCLE, &data).await?;
+            }
+            // Start continuous RX (timeout = 0xFFFFFF means continuous)
+            None => {
+                let timeout_bytes = [0xFF, 0xFF, 0xFF];
+                self.write_command(cmd::SET_RX, &timeout_bytes).await?;
+            }
+        }
 
+        self.rx_active = true;
         Ok(())
     }
 }
 
-impl<Spi, Nss, Dio1, Nrst, Busy> LoraRadio for Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy>
+impl<Spi, Nss, Dio1, Nrst, Busy, RfSwitch> LoraRadio for Sx1262Driver<Spi, Nss, Dio1, Nrst, Busy, RfSwitch>
 where
     Spi: SpiBus,
     Nss: OutputPin,
     Dio1: InputPin,
     Nrst: OutputPin,
     Busy: InputPin,
+    RfSwitch: OutputPin,
 {
     async fn init(&mut self) -> Result<(), LoraError> {
         // Reset the radio
         self.reset().await?;
         self.wait_not_busy().await?;
 
-        // Set standby mode
-        self.set_standby_internal().await?;
+        // Set standby mode. Always RC here: the TCXO is not configured yet
+        // and calibration must run in STDBY_RC.
+        self.set_standby_on(StandbyMode::Rc).await?;
 
         // Configure TCXO (1.8V)
         self.configure_tcxo().await?;
@@ -510,14 +970,11 @@ where
         // calibration and the first packet is silently lost.
         self.calibrate_all().await?;
 
-        // Configure DIO2 as RF switch control
+        // Configure DIO2 as RF switch control (or not, for an external one)
         self.configure_dio2_rf_switch().await?;
 
-        // Set current limit (140mA as per Arduino config)
-        self.set_current_limit(140).await?;
-
-        // Set packet type to LoRa
-        self.set_packet_type_lora().await?;
+        // Set packet type to LoRa (configure switches it for GFSK)
+        self.set_packet_type(Modulation::Lora).await?;
 
         // Set buffer base addresses
         self.set_buffer_base_address(0x00, 0x80).await?;
@@ -540,6 +997,10 @@ where
         if data.is_empty() || data.len() > MAX_LORA_PAYLOAD {
             return Err(LoraError::InvalidConfig);
         }
+        // Implicit header frames have no length on air, so must be exact.
+        if self.implicit_len().is_some_and(|len| data.len() != len as usize) {
+            return Err(LoraError::InvalidConfig);
+        }
 
         // Set to standby
         self.set_standby_internal().await?;
@@ -555,16 +1016,22 @@ where
         self.clear_irq(0xFFFF).await?;
 
         // Start transmission (timeout 0 = no timeout)
-        self.write_command(cmd::SET_TX, &[0x00, 0x00, 0x00]).await?;
+        self.set_rf_switch(true);
+        if let Err(e) = self.write_command(cmd::SET_TX, &[0x00, 0x00, 0x00]).await {
+            self.set_rf_switch(false);
+            return Err(e);
+        }
 
         // Wait for TX done (10 second timeout)
-        let irq_status = self.wait_for_irq(10000).await?;
+        let irq_status = self.wait_for_irq(10000).await;
+        self.set_rf_switch(false);
+        let irq_status = irq_status?;
 
         // Clear IRQ after reading (Semtech pattern)
         self.clear_irq(irq_status).await?;
 
-        // NOTE: Don't call start_receive_mode() here.
-        // The lora_task will call receive() which handles RX mode re-entry.
+        // NOTE: Don't call start_receive_mode() here. TX done leaves the radio
+        // in standby, and the next receive() re-enters continuous RX.
 
         if irq_status & irq::TX_DONE != 0 {
             Ok(())
@@ -573,107 +1040,83 @@ where
         }
     }
 
-    async fn receive(&mut self, timeout_ms: u32) -> Result<RxPacket, LoraError> {
+    async fn transmit_with_power(&mut self, data: &[u8], power_dbm: i8) -> Result<(), LoraError> {
         if !self.initialised {
             return Err(LoraError::NotInitialised);
         }
-
-        // === Check for pending packet (Semtech pattern) ===
-        // If radio was in continuous RX and packet arrived, DIO1 will be high
-        if self.dio1.is_high().unwrap_or(false) {
-            let irq_status = self.get_irq_status().await?;
-
-            // Clear IRQ after reading (Semtech pattern: read -> clear -> process)
-            self.clear_irq(irq_status).await?;
-
-            if irq_status & irq::RX_DONE != 0 {
-                // Check for CRC error
-                if irq_status & irq::CRC_ERR != 0 {
-                    // Re-enter RX mode before returning error
-                    self.start_receive_mode().await?;
-                    return Err(LoraError::CrcError);
-                }
-
-                // Read the pending packet
-                let (payload_len, buffer_offset) = self.get_rx_buffer_status().await?;
-                let data = self.read_buffer(buffer_offset, payload_len as usize).await?;
-                let (rssi, snr) = self.get_packet_status().await?;
-
-                // Re-enter continuous RX mode for background listening
-                self.start_receive_mode().await?;
-
-                return Ok(RxPacket { data, rssi, snr });
-            }
-            // Other IRQ (timeout from previous op, etc) - continue to fresh RX
+        if !(MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&power_dbm) {
+            return Err(LoraError::InvalidConfig);
         }
 
-        // === No pending packet, do normal RX with timeout ===
+        let (configured, ramp_time) = self
+            .config
+            .as_ref()
+            .map_or((lora_defaults::TX_POWER_DBM, lora_defaults::RAMP_TIME), |c| {
+                (c.tx_power_dbm, c.ramp_time)
+            });
+
         self.set_standby_internal().await?;
-        self.set_packet_params(RX_MAX_PAYLOAD_LEN).await?;
+        self.set_tx_power(power_dbm, ramp_time).await?;
+        let result = self.transmit(data).await;
 
-        // Configure and clear IRQ
-        self.configure_irq(irq::RX_DONE | irq::TIMEOUT | irq::CRC_ERR)
-            .await?;
-        self.clear_irq(0xFFFF).await?;
+        // Restore the configured power even if the transmission failed
+        self.set_tx_power(configured, ramp_time).await?;
 
-        // Calculate SX1262 timeout value
-        // Timeout = timeout_value * 15.625 us
-        let timeout_val = if timeout_ms == 0 {
-            0x000000 // No timeout (continuous RX)
-        } else {
-            let us = timeout_ms * 1000;
-            let val = us / 16; // Approximate 15.625us
-            val.min(0xFFFFFF)
-        };
+        result
+    }
 
-        let timeout_bytes = [
-            ((timeout_val >> 16) & 0xFF) as u8,
-            ((timeout_val >> 8) & 0xFF) as u8,
-            (timeout_val & 0xFF) as u8,
-        ];
+    async fn receive(&mut self, timeout_ms: u32) -> Result<RxPacket, LoraError> {
+        if !self.initialised {
+            return Err(LoraError::NotInitialised);
+        }
 
-        // Start reception
-        self.write_command(cmd::SET_RX, &timeout_bytes).await?;
+        // The radio stays in continuous RX between calls; only (re-)enter it
+        // if something else (TX, carrier, standby) took it out. Re-arming on
+        // every poll would open a standby window in which packets are missed.
+        if !self.rx_active {
+            self.start_receive_mode().await?;
+        }
 
-        // Wait for RX done or timeout
-        let irq_status = self.wait_for_irq(timeout_ms + 1000).await?;
+        // Just wait for DIO1. A timeout leaves the radio listening, so a
+        // packet arriving between polls is picked up on the next call.
+        let irq_status = self.wait_for_irq(timeout_ms).await?;
 
-        // Clear IRQ after reading (Semtech pattern)
+        // Clear IRQ after reading (Semtech pattern: read -> clear -> process)
         self.clear_irq(irq_status).await?;
 
-        // Check for timeout
-        if irq_status & irq::TIMEOUT != 0 {
-            // Re-enter continuous RX for background listening
+        if irq_status & irq::RX_DONE == 0 {
+            // Stale or unexpected IRQ: re-arm to get back to a known state
             self.start_receive_mode().await?;
-            return Err(LoraError::Timeout);
+            return Err(LoraError::ReceiveFailed);
         }
 
-        // Check for CRC error
+        // CRC_ERR is only raised for frames that carry a CRC (the explicit
+        // header says whether they do; in implicit mode our own setting
+        // decides). A frame without one is accepted on RX_DONE alone, so its
+        // integrity rests on the decrypt check above the driver.
         if irq_status & irq::CRC_ERR != 0 {
             self.start_receive_mode().await?;
             return Err(LoraError::CrcError);
         }
 
-        // Verify RX done
-        if irq_status & irq::RX_DONE == 0 {
-            self.start_receive_mode().await?;
-            return Err(LoraError::ReceiveFailed);
-        }
-
-        // Read packet
-        let (payload_len, buffer_offset) = self.get_rx_buffer_status().await?;
-        let data = self.read_buffer(buffer_offset, payload_len as usize).await?;
-        let (rssi, snr) = self.get_packet_status().await?;
+        let packet = self.read_received_packet().await?;
 
-        // Re-enter continuous RX mode
+        // Re-arm continuous RX so the next packet starts from a clean buffer
         self.start_receive_mode().await?;
 
-        Ok(RxPacket { data, rssi, snr })
+        Ok(packet)
     }
 
     async fn configure(&mut self, config: &LoraConfig) -> Result<(), LoraError> {
-        // Set to standby before configuration
-        self.set_standby_internal().await?;
+        // Reject the whole configuration before touching the radio
+        config.validate()?;
+
+        // Set to standby before configuration, on RC because image
+        // calibration must run in STDBY_RC
+        self.set_standby_on(StandbyMode::Rc).await?;
+
+        // The packet type must be set before the modulation and packet params
+        self.set_packet_type(config.modulation).await?;
 
         // Set frequency
         self.set_frequency(config.frequency_hz).await?;
@@ -684,11 +1127,30 @@ where
         // Set modulation parameters
         self.set_modulation_params(config).await?;
 
+        if config.is_fsk() {
+            self.set_fsk_sync_word(config.sync_word).await?;
+        } else {
+            // Set sync word (private vs public network)
+            self.set_sync_word(config.sync_word).await?;
+
+            // IQ polarity errata fix; the IQ mode itself goes in the packet params
+            self.set_iq_polarity(config.iq_inverted).await?;
+        }
+
         // Configure Power Amplifier (must be called before SetTxParams)
         self.configure_pa().await?;
 
+        // Set current limit (after SetPaConfig, which resets it)
+        self.set_current_limit(config.ocp_ma).await?;
+
         // Set TX power
-        self.set_tx_power(config.tx_power_dbm).await?;
+        self.set_tx_power(config.tx_power_dbm, config.ramp_time).await?;
+
+        // Stay on the chosen oscillator between and after packets
+        self.set_fallback_mode(config.standby_mode).await?;
+        if config.standby_mode == StandbyMode::Xosc {
+            self.set_standby_on(StandbyMode::Xosc).await?;
+        }
 
         self.config = Some(config.clone());
 
@@ -698,86 +1160,364 @@ where
     async fn set_standby(&mut self) -> Result<(), LoraError> {
         self.set_standby_internal().await
     }
-}
-
-#[cfg(all(test, feature = "host-test"))]
-mod host_tests {
-    //! Host-side tests that drive the real driver against a recording SPI bus.
-    //!
-    //! Run with: `cargo test --target x86_64-unknown-linux-gnu --features host-test`
 
-    use super::*;
-    use crate::lora::traits::LoraRadio;
-    use core::cell::RefCell;
-    use core::future::Future;
-    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
-    use std::rc::Rc;
-    use std::vec::Vec as StdVec;
+    async fn sleep(&mut self, mode: SleepMode) -> Result<(), LoraError> {
+        let sleep_config = match mode {
+            SleepMode::Warm => sleep_config::WARM_START,
+            SleepMode::Cold => sleep_config::COLD_START,
+        };
+        self.set_standby_internal().await?;
+        self.write_command(cmd::SET_SLEEP, &[sleep_config]).await?;
+        // The radio ignores commands for 500 us after SetSleep.
+        Timer::after(Duration::from_micros(500)).await;
 
-    /// Error type for the mocks (never actually returned).
-    #[derive(Debug)]
-    struct MockError;
+        self.sleep_mode = Some(mode);
+        Ok(())
+    }
 
-    impl embedded_hal::spi::Error for MockError {
-        fn kind(&self) -> embedded_hal::spi::ErrorKind {
-            embedded_hal::spi::ErrorKind::Other
+    async fn wake(&mut self) -> Result<(), LoraError> {
+        match self.sleep_mode.take() {
+            None => Ok(()),
+            Some(SleepMode::Warm) => {
+                // A falling edge on NSS wakes the radio; BUSY stays high until
+                // it reaches STDBY_RC.
+                let _ = self.nss.set_low();
+                Timer::after(Duration::from_micros(100)).await;
+                let _ = self.nss.set_high();
+                self.wait_not_busy().await?;
+                self.start_receive_mode().await
+            }
+            Some(SleepMode::Cold) => {
+                // Configuration was lost: re-run init (which resets the radio),
+                // then restore the last applied configuration.
+                let config = self.config.clone();
+                self.init().await?;
+                if let Some(config) = config {
+                    self.configure(&config).await?;
+                    self.start_receive_mode().await?;
+                }
+                Ok(())
+            }
         }
     }
 
-    impl embedded_hal::digital::Error for MockError {
-        fn kind(&self) -> embedded_hal::digital::ErrorKind {
-            embedded_hal::digital::ErrorKind::Other
+    async fn transmit_carrier(&mut self, duration_ms: u32) -> Result<(), LoraError> {
+        if !self.initialised {
+            return Err(LoraError::NotInitialised);
         }
-    }
 
-    /// SPI bus that records every outbound buffer (first byte is the opcode).
-    #[derive(Clone)]
-    struct RecordingSpi {
-        writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
-    }
+        // Frequency, PA config and TX power were applied by configure() and
+        // are used as-is by SetTxContinuousWave.
+        self.set_standby_internal().await?;
+        self.set_rf_switch(true);
+        if let Err(e) = self.write_command(cmd::SET_TX_CONTINUOUS_WAVE, &[]).await {
+            self.set_rf_switch(false);
+            return Err(e);
+        }
 
-    impl embedded_hal::spi::ErrorType for RecordingSpi {
-        type Error = MockError;
+        Timer::after(Duration::from_millis(duration_ms as u64)).await;
+
+        // Unkey and resume background listening
+        self.set_standby_internal().await?;
+        self.set_rf_switch(false);
+        self.start_receive_mode().await
     }
 
-    impl embedded_hal_async::spi::SpiBus<u8> for RecordingSpi {
-        async fn read(&mut self, _words: &mut [u8]) -> Result<(), MockError> {
-            Ok(())
+    async fn read_instant_rssi(&mut self) -> Result<i16, LoraError> {
+        if !self.initialised || self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
         }
 
-        async fn write(&mut self, words: &[u8]) -> Result<(), MockError> {
-            self.writes.borrow_mut().push(words.to_vec());
-            Ok(())
+        // GetRssiInst is only meaningful in RX (e.g. not straight after a TX,
+        // which leaves the radio in standby).
+        if !self.rx_active {
+            self.start_receive_mode().await?;
+            // Give the RSSI estimate time to settle after entering RX
+            Timer::after(Duration::from_millis(1)).await;
         }
 
-        async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), MockError> {
-            self.writes.borrow_mut().push(write.to_vec());
-            read.iter_mut().for_each(|b| *b = 0);
-            Ok(())
+        let result = self.read_command(cmd::GET_RSSI_INST, 1).await?;
+
+        // RSSI: -result[0]/2
+        Ok(-(result[0] as i16) / 2)
+    }
+
+    async fn scan_rssi(&mut self, start_hz: u32, step_hz: u32, rssi: &mut [i16]) -> Result<(), LoraError> {
+        if !self.initialised || self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
         }
+        let resume_rx = self.rx_active;
 
-        async fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), MockError> {
-            Ok(())
+        // Nothing heard on the way is a packet for us, so mask every IRQ
+        self.set_standby_internal().await?;
+        self.configure_irq(0).await?;
+        let mut swept = Ok(());
+        for (step, sample) in (0..).zip(rssi.iter_mut()) {
+            match self.sample_rssi_at(start_hz + step * step_hz).await {
+                Ok(dbm) => *sample = dbm,
+                Err(e) => {
+                    swept = Err(e);
+                    break;
+                }
+            }
         }
 
-        async fn flush(&mut self) -> Result<(), MockError> {
-            Ok(())
+        // Back to the configured channel whether or not the sweep finished
+        self.set_standby_internal().await?;
+        let freq_hz = self.config.as_ref().map_or(lora_defaults::FREQUENCY_HZ, |c| c.frequency_hz);
+        self.set_frequency(freq_hz).await?;
+        // Re-entering RX restores its IRQ mask; TX sets its own
+        if resume_rx {
+            self.start_receive_mode().await?;
         }
+        swept
     }
 
-    /// Output pin that ignores writes.
-    struct NoopOut;
-    impl embedded_hal::digital::ErrorType for NoopOut {
-        type Error = MockError;
-    }
-    impl embedded_hal::digital::OutputPin for NoopOut {
-        fn set_low(&mut self) -> Result<(), MockError> {
-            Ok(())
+    async fn self_test(&mut self) -> Result<u8, LoraError> {
+        if self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
         }
-        fn set_high(&mut self) -> Result<(), MockError> {
-            Ok(())
+
+        let mut flags = 0;
+        let resume_rx = self.rx_active;
+
+        // A dead bus reads all zeros or all ones, so check a register holds
+        // the value configure() (or init, with the defaults) wrote to it.
+        let sync_word = self.config.as_ref().map_or(lora_defaults::SYNC_WORD, |c| c.sync_word);
+        if matches!(self.read_register(reg::LORA_SYNC_WORD_MSB).await, Ok(msb) if msb == (sync_word >> 8) as u8) {
+            flags |= self_test::SPI_OK;
         }
-    }
+
+        // BUSY goes high as NSS rises at the end of a command and is released
+        // once the command has been processed.
+        let status_valid = matches!(
+            self.get_status().await,
+            Ok(byte) if status::VALID_MODES.contains(&status::chip_mode(byte))
+        );
+        let busy_toggled = self.set_standby_internal().await.is_ok()
+            && self.busy.is_high().unwrap_or(false)
+            && self.wait_not_busy().await.is_ok();
+        if status_valid && busy_toggled {
+            flags |= self_test::RADIO_RESPONSIVE;
+        }
+
+        if matches!(
+            self.read_command(cmd::GET_DEVICE_ERRORS, 2).await,
+            Ok(errors) if errors[1] & device_errors::XOSC_START_ERR == 0
+        ) {
+            flags |= self_test::TCXO_READY;
+        }
+
+        // Best effort: if the radio cannot re-enter RX the flags already say so
+        if resume_rx {
+            let _ = self.start_receive_mode().await;
+        }
+
+        Ok(flags)
+    }
+
+    /// Semtech's procedure: with the LNA and mixer detached the receiver
+    /// only amplifies its own noise, which the RNG registers sample while in
+    /// continuous RX.
+    async fn random_u32(&mut self) -> Result<u32, LoraError> {
+        if !self.initialised || self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
+        }
+        let resume_rx = self.rx_active;
+
+        // Nothing received in this state is a packet, so mask every IRQ
+        self.set_standby_internal().await?;
+        self.configure_irq(0).await?;
+        let lna = self.read_register(reg::ANA_LNA).await?;
+        let mixer = self.read_register(reg::ANA_MIXER).await?;
+        self.write_register(reg::ANA_LNA, lna & !0x01).await?;
+        self.write_register(reg::ANA_MIXER, mixer & !0x80).await?;
+
+        self.write_command(cmd::SET_RX, &[0xFF, 0xFF, 0xFF]).await?;
+        // Let the generator accumulate fresh noise
+        Timer::after(Duration::from_millis(1)).await;
+        let mut bytes = [0u8; 4];
+        for (offset, byte) in (0..).zip(bytes.iter_mut()) {
+            *byte = self.read_register(reg::RANDOM_NUMBER_GEN + offset).await?;
+        }
+
+        self.set_standby_internal().await?;
+        self.write_register(reg::ANA_LNA, lna).await?;
+        self.write_register(reg::ANA_MIXER, mixer).await?;
+        // Re-entering RX restores its IRQ mask; TX sets its own
+        if resume_rx {
+            self.start_receive_mode().await?;
+        }
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    async fn set_rx_duty_cycle(&mut self, windows: Option<RxDutyCycle>) -> Result<(), LoraError> {
+        if !self.initialised || self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
+        }
+        if let Some(windows) = &windows {
+            windows.validate()?;
+        }
+        self.rx_duty_cycle = windows;
+        if self.rx_active {
+            self.start_receive_mode().await?;
+        }
+        Ok(())
+    }
+
+    async fn read_register(&mut self, addr: u16) -> Result<u8, LoraError> {
+        if self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
+        }
+        // The inherent register helper, not this trait method
+        Sx1262Driver::read_register(self, addr).await
+    }
+
+    async fn write_register(&mut self, addr: u16, value: u8) -> Result<(), LoraError> {
+        if self.sleep_mode.is_some() {
+            return Err(LoraError::NotInitialised);
+        }
+        Sx1262Driver::write_register(self, addr, value).await
+    }
+
+    fn last_packet_status(&self) -> Option<PacketStatus> {
+        self.last_packet
+    }
+
+    async fn get_state(&mut self) -> Result<RadioState, LoraError> {
+        if self.sleep_mode.is_some() {
+            return Ok(RadioState::Sleep);
+        }
+        // An unused mode means the status byte did not come from the radio
+        let byte = self.get_status().await?;
+        status::radio_state(byte).ok_or(LoraError::SpiError)
+    }
+
+    /// Not available on the SX1262.
+    ///
+    /// Unlike the LR11xx family (which has GetTemp), the SX1261/2 datasheet
+    /// documents no temperature sensor, command or register, so there is no
+    /// reading to convert. Returns `Unsupported` rather than guessing at
+    /// undocumented registers; boards needing thermal monitoring during
+    /// sustained TX should use an external sensor.
+    async fn read_temperature(&mut self) -> Result<i16, LoraError> {
+        Err(LoraError::Unsupported)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod host_tests {
+    //! Host-side tests that drive the real driver against a recording SPI bus.
+    //!
+    //! Run with: `cargo test --target x86_64-unknown-linux-gnu --features host-test`
+
+    use super::*;
+    use crate::lora::traits::LoraRadio;
+    use core::cell::RefCell;
+    use core::future::Future;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    use std::rc::Rc;
+    use std::vec::Vec as StdVec;
+
+    /// Error type for the mocks (never actually returned).
+    #[derive(Debug)]
+    struct MockError;
+
+    impl embedded_hal::spi::Error for MockError {
+        fn kind(&self) -> embedded_hal::spi::ErrorKind {
+            embedded_hal::spi::ErrorKind::Other
+        }
+    }
+
+    impl embedded_hal::digital::Error for MockError {
+        fn kind(&self) -> embedded_hal::digital::ErrorKind {
+            embedded_hal::digital::ErrorKind::Other
+        }
+    }
+
+    /// SPI bus that records every outbound buffer (first byte is the opcode).
+    ///
+    /// Reads return zeros unless a canned reply is registered for the opcode,
+    /// in which case it is placed after the opcode and status bytes.
+    #[derive(Clone)]
+    struct RecordingSpi {
+        writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
+        replies: StdVec<(u8, StdVec<u8>)>,
+    }
+
+    impl embedded_hal::spi::ErrorType for RecordingSpi {
+        type Error = MockError;
+    }
+
+    impl embedded_hal_async::spi::SpiBus<u8> for RecordingSpi {
+        async fn read(&mut self, _words: &mut [u8]) -> Result<(), MockError> {
+            Ok(())
+        }
+
+        async fn write(&mut self, words: &[u8]) -> Result<(), MockError> {
+            self.writes.borrow_mut().push(words.to_vec());
+            Ok(())
+        }
+
+        async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), MockError> {
+            self.writes.borrow_mut().push(write.to_vec());
+            read.iter_mut().for_each(|b| *b = 0);
+            if let Some((_, reply)) = self.replies.iter().find(|(op, _)| write.first() == Some(op)) {
+                let len = reply.len().min(read.len().saturating_sub(2));
+                read[2..2 + len].copy_from_slice(&reply[..len]);
+            }
+            Ok(())
+        }
+
+        async fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), MockError> {
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<(), MockError> {
+            Ok(())
+        }
+    }
+
+    /// Output pin that ignores writes.
+    struct NoopOut;
+    impl embedded_hal::digital::ErrorType for NoopOut {
+        type Error = MockError;
+    }
+    impl embedded_hal::digital::OutputPin for NoopOut {
+        fn set_low(&mut self) -> Result<(), MockError> {
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), MockError> {
+            Ok(())
+        }
+    }
+
+    /// Output pin (NSS, or the RF switch) that logs each edge with the number
+    /// of SPI transfers made before it and the time it happened.
+    struct EdgeLog {
+        writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
+        edges: Rc<RefCell<StdVec<(bool, usize, embassy_time::Instant)>>>,
+    }
+    impl EdgeLog {
+        fn log(&mut self, high: bool) {
+            let transfers = self.writes.borrow().len();
+            self.edges.borrow_mut().push((high, transfers, embassy_time::Instant::now()));
+        }
+    }
+    impl embedded_hal::digital::ErrorType for EdgeLog {
+        type Error = MockError;
+    }
+    impl embedded_hal::digital::OutputPin for EdgeLog {
+        fn set_low(&mut self) -> Result<(), MockError> {
+            self.log(false);
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), MockError> {
+            self.log(true);
+            Ok(())
+        }
+    }
 
     /// Input pin that always reads low (BUSY released, no IRQ pending).
     struct LowPin;
@@ -793,6 +1533,21 @@ mod host_tests {
         }
     }
 
+    /// Input pin whose level the test controls (used for DIO1).
+    #[derive(Clone)]
+    struct FlagPin(Rc<core::cell::Cell<bool>>);
+    impl embedded_hal::digital::ErrorType for FlagPin {
+        type Error = MockError;
+    }
+    impl embedded_hal::digital::InputPin for FlagPin {
+        fn is_high(&mut self) -> Result<bool, MockError> {
+            Ok(self.0.get())
+        }
+        fn is_low(&mut self) -> Result<bool, MockError> {
+            Ok(!self.0.get())
+        }
+    }
+
     /// Minimal no-op waker built from core only (avoids extra deps).
     fn noop_waker() -> Waker {
         fn clone(_: *const ()) -> RawWaker {
@@ -818,10 +1573,20 @@ mod host_tests {
         }
     }
 
+    // The builders below skip the NSS hold so a command never yields and
+    // the timings the tests check stay exact; `nss_is_held_high_between_commands`
+    // covers the hold itself.
     fn build_driver(
         writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
-    ) -> Sx1262Driver<RecordingSpi, NoopOut, LowPin, NoopOut, LowPin> {
-        let spi = RecordingSpi { writes };
+    ) -> Sx1262Driver<RecordingSpi, NoopOut, LowPin, NoopOut, LowPin, NoopOut> {
+        build_driver_with_replies(writes, StdVec::new())
+    }
+
+    fn build_driver_with_replies(
+        writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
+        replies: StdVec<(u8, StdVec<u8>)>,
+    ) -> Sx1262Driver<RecordingSpi, NoopOut, LowPin, NoopOut, LowPin, NoopOut> {
+        let spi = RecordingSpi { writes, replies };
         Sx1262Driver::new(
             spi,
             Sx1262Pins {
@@ -829,8 +1594,29 @@ mod host_tests {
                 dio1: LowPin,
                 nrst: NoopOut,
                 busy: LowPin,
+                rf_switch: None,
             },
         )
+        .with_nss_high(Duration::from_ticks(0))
+    }
+
+    fn build_driver_with_dio1(
+        writes: Rc<RefCell<StdVec<StdVec<u8>>>>,
+        replies: StdVec<(u8, StdVec<u8>)>,
+        dio1: FlagPin,
+    ) -> Sx1262Driver<RecordingSpi, NoopOut, FlagPin, NoopOut, LowPin, NoopOut> {
+        let spi = RecordingSpi { writes, replies };
+        Sx1262Driver::new(
+            spi,
+            Sx1262Pins {
+                nss: NoopOut,
+                dio1,
+                nrst: NoopOut,
+                busy: LowPin,
+                rf_switch: None,
+            },
+        )
+        .with_nss_high(Duration::from_ticks(0))
     }
 
     fn first_index(writes: &[StdVec<u8>], opcode: u8) -> Option<usize> {
@@ -876,6 +1662,645 @@ mod host_tests {
         );
     }
 
+    #[test]
+    fn standby_uses_the_configured_oscillator() {
+        for (mode, oscillator, fallback_mode) in [
+            (StandbyMode::Rc, standby::STDBY_RC, fallback::STDBY_RC),
+            (StandbyMode::Xosc, standby::STDBY_XOSC, fallback::STDBY_XOSC),
+        ] {
+            embassy_time::MockDriver::get().reset();
+            let writes = Rc::new(RefCell::new(StdVec::new()));
+            let mut driver = build_driver(writes.clone());
+            run(driver.init()).expect("init should succeed");
+
+            let config = LoraConfig {
+                standby_mode: mode,
+                ..LoraConfig::default()
+            };
+            run(driver.configure(&config)).expect("configure should succeed");
+            let configured = writes.borrow().len();
+            run(driver.set_standby()).expect("standby should succeed");
+
+            let writes = writes.borrow();
+            // Calibration in init and configure always runs on RC
+            assert_eq!(
+                writes[first_index(&writes, cmd::SET_STANDBY).unwrap()].as_slice(),
+                &[cmd::SET_STANDBY, standby::STDBY_RC]
+            );
+            let fallback = first_index(&writes, cmd::SET_RX_TX_FALLBACK_MODE)
+                .expect("configure should set the fallback mode");
+            assert_eq!(writes[fallback].as_slice(), &[cmd::SET_RX_TX_FALLBACK_MODE, fallback_mode]);
+            assert_eq!(
+                writes[configured].as_slice(),
+                &[cmd::SET_STANDBY, oscillator],
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn sleep_emits_set_sleep_with_start_mode() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.sleep(SleepMode::Warm)).expect("warm sleep should succeed");
+        run(driver.sleep(SleepMode::Cold)).expect("cold sleep should succeed");
+
+        let writes = writes.borrow();
+        let sleeps: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_SLEEP))
+            .collect();
+        assert_eq!(sleeps.len(), 2);
+        assert_eq!(sleeps[0].as_slice(), &[cmd::SET_SLEEP, sleep_config::WARM_START]);
+        assert_eq!(sleeps[1].as_slice(), &[cmd::SET_SLEEP, sleep_config::COLD_START]);
+    }
+
+    #[test]
+    fn packet_params_use_configured_preamble() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            preamble_symbols: 0x0120,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+        run(driver.set_packet_params(42)).expect("set_packet_params should succeed");
+
+        let writes = writes.borrow();
+        let params = first_index(&writes, cmd::SET_PACKET_PARAMS)
+            .expect("SetPacketParams should be recorded");
+        assert_eq!(
+            writes[params].as_slice(),
+            &[cmd::SET_PACKET_PARAMS, 0x01, 0x20, 0x00, 42, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn modulation_params_enable_ldro_for_long_symbols() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        // SF12 at 250 kHz has 16.384 ms symbols, so LDRO must be on
+        let config = LoraConfig {
+            spreading_factor: 12,
+            bandwidth_khz: 250,
+            coding_rate: 5,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let params = first_index(&writes, cmd::SET_MODULATION_PARAMS)
+            .expect("SetModulationParams should be recorded");
+        assert_eq!(writes[params].as_slice(), &[cmd::SET_MODULATION_PARAMS, 12, 0x05, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn fsk_modulation_params_encode_bitrate_deviation_and_bandwidth() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            modulation: Modulation::Fsk(FskParams {
+                bitrate_bps: 4_800,
+                deviation_hz: 5_000,
+                rx_bandwidth_hz: 19_500,
+                gaussian_bt: 5,
+            }),
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let set_type =
+            first_index(&writes, cmd::SET_PACKET_TYPE).expect("SetPacketType should be recorded");
+        let params = first_index(&writes, cmd::SET_MODULATION_PARAMS)
+            .expect("SetModulationParams should be recorded");
+        assert!(set_type < params, "the packet type must be set before the modulation params");
+        assert_eq!(writes[set_type].as_slice(), &[cmd::SET_PACKET_TYPE, packet_type::GFSK]);
+        // 32 x 32 MHz / 4800 = 0x034155, BT 0.5, 19.5 kHz, 5 kHz in 0.95 Hz
+        // steps = 0x00147A
+        assert_eq!(
+            writes[params].as_slice(),
+            &[cmd::SET_MODULATION_PARAMS, 0x03, 0x41, 0x55, 0x09, 0x1D, 0x00, 0x14, 0x7A]
+        );
+
+        let fast = FskParams {
+            bitrate_bps: 50_000,
+            deviation_hz: 25_000,
+            rx_bandwidth_hz: 117_300,
+            gaussian_bt: 10,
+        };
+        assert_eq!(
+            fsk_modulation_params(&fast),
+            Some([0x00, 0x50, 0x00, 0x0B, 0x0B, 0x00, 0x66, 0x66])
+        );
+        assert_eq!(fsk_modulation_params(&FskParams { rx_bandwidth_hz: 20_000, ..fast }), None);
+        assert_eq!(fsk_modulation_params(&FskParams { gaussian_bt: 4, ..fast }), None);
+    }
+
+    #[test]
+    fn fsk_packet_params_and_sync_word_follow_config() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            preamble_symbols: 4,
+            sync_word: 0x2DD4,
+            modulation: Modulation::Fsk(FskParams {
+                bitrate_bps: 4_800,
+                deviation_hz: 5_000,
+                rx_bandwidth_hz: 19_500,
+                gaussian_bt: 5,
+            }),
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+        run(driver.set_packet_params(42)).expect("set_packet_params should succeed");
+
+        let writes = writes.borrow();
+        let params = first_index(&writes, cmd::SET_PACKET_PARAMS)
+            .expect("SetPacketParams should be recorded");
+        // 32-bit preamble, 16-bit detector, 16-bit sync word, no address
+        // filter, variable length, CRC-16/CCITT, no whitening
+        assert_eq!(
+            writes[params].as_slice(),
+            &[cmd::SET_PACKET_PARAMS, 0x00, 0x20, 0x05, 0x10, 0x00, 0x01, 42, 0x06, 0x00]
+        );
+
+        let registers: StdVec<&[u8]> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::WRITE_REGISTER))
+            .map(|w| &w[1..])
+            .collect();
+        assert!(registers.contains(&[0x06, 0xC0, 0x2D].as_slice()));
+        assert!(registers.contains(&[0x06, 0xC1, 0xD4].as_slice()));
+        // The LoRa sync word and IQ errata registers are left alone
+        assert!(registers.iter().all(|r| r[0] != 0x07));
+    }
+
+    #[test]
+    fn packet_params_follow_crc_and_iq_settings() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        for (crc_enabled, iq_inverted) in [(false, false), (true, true)] {
+            let config = LoraConfig {
+                crc_enabled,
+                iq_inverted,
+                ..LoraConfig::default()
+            };
+            run(driver.configure(&config)).expect("configure should succeed");
+            run(driver.set_packet_params(42)).expect("set_packet_params should succeed");
+        }
+
+        let writes = writes.borrow();
+        let params: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_PACKET_PARAMS))
+            .collect();
+        assert_eq!(params.len(), 2);
+        // [..., CRC type, invert IQ]
+        assert_eq!(&params[0][5..], &[0x00, 0x00]);
+        assert_eq!(&params[1][5..], &[0x01, 0x01]);
+    }
+
+    #[test]
+    fn configure_applies_iq_polarity_fix() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // The register reads back 0x0D (its reset value)
+        let mut driver =
+            build_driver_with_replies(writes.clone(), vec![(cmd::READ_REGISTER, vec![0x00, 0x00, 0x0D])]);
+
+        let inverted = LoraConfig {
+            iq_inverted: true,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&inverted)).expect("configure should succeed");
+        run(driver.configure(&LoraConfig::default())).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let iq_writes: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::WRITE_REGISTER) && w[1..3] == [0x07, 0x36])
+            .collect();
+        assert_eq!(iq_writes.len(), 2);
+        assert_eq!(iq_writes[0][3], 0x09, "inverted IQ clears bit 2");
+        assert_eq!(iq_writes[1][3], 0x0D, "standard IQ sets bit 2");
+    }
+
+    #[test]
+    fn configure_writes_sync_word_registers() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            sync_word: 0x3444,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let sync_writes: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::WRITE_REGISTER) && w.get(1) == Some(&0x07))
+            .collect();
+        assert_eq!(sync_writes.len(), 2);
+        assert_eq!(sync_writes[0].as_slice(), &[cmd::WRITE_REGISTER, 0x07, 0x40, 0x34]);
+        assert_eq!(sync_writes[1].as_slice(), &[cmd::WRITE_REGISTER, 0x07, 0x41, 0x44]);
+    }
+
+    #[test]
+    fn configure_rejects_invalid_config_untouched() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            bandwidth_khz: 300,
+            ..LoraConfig::default()
+        };
+        assert_eq!(run(driver.configure(&config)), Err(LoraError::InvalidConfig));
+        assert!(writes.borrow().is_empty(), "nothing is sent for a rejected config");
+    }
+
+    #[test]
+    fn configure_writes_ocp_register() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        let config = LoraConfig {
+            ocp_ma: 100,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+        // Above 157.5 mA the register clamps at 63
+        let config = LoraConfig {
+            ocp_ma: 240,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let ocp_writes: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::WRITE_REGISTER) && w[1..3] == [0x08, 0xE7])
+            .collect();
+        assert_eq!(ocp_writes.len(), 2);
+        assert_eq!(ocp_writes[0][3], 40, "100 mA / 2.5");
+        assert_eq!(ocp_writes[1][3], 63);
+    }
+
+    #[test]
+    fn configure_writes_ramp_time_to_tx_params() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.configure(&LoraConfig::default())).expect("configure should succeed");
+        let config = LoraConfig {
+            tx_power_dbm: 14,
+            ramp_time: 0x07,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&config)).expect("configure should succeed");
+
+        let writes = writes.borrow();
+        let tx_params: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_TX_PARAMS))
+            .collect();
+        assert_eq!(tx_params.len(), 2);
+        assert_eq!(tx_params[0][1..], [22, 0x04], "default ramp is 200 us");
+        assert_eq!(tx_params[1][1..], [14, 0x07]);
+    }
+
+    #[test]
+    fn packet_params_header_type_follows_mode() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.configure(&LoraConfig::default())).expect("configure should succeed");
+        run(driver.set_packet_params(42)).expect("explicit params should succeed");
+
+        let implicit = LoraConfig {
+            implicit_header: true,
+            implicit_len: 20,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&implicit)).expect("configure should succeed");
+        run(driver.set_packet_params(42)).expect("implicit params should succeed");
+
+        let writes = writes.borrow();
+        let params: StdVec<&StdVec<u8>> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_PACKET_PARAMS))
+            .collect();
+        assert_eq!(params.len(), 2);
+        // [opcode, preamble MSB, preamble LSB, header type, length, ...]
+        assert_eq!(&params[0][3..5], &[header_type::EXPLICIT, 42]);
+        assert_eq!(&params[1][3..5], &[header_type::IMPLICIT, 20]);
+    }
+
+    #[test]
+    fn received_length_follows_header_mode() {
+        embassy_time::MockDriver::get().reset();
+        // The RX buffer status reports a 5-byte payload at offset 0x80.
+        let replies = vec![(cmd::GET_RX_BUFFER_STATUS, vec![5, 0x80])];
+
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver_with_replies(writes.clone(), replies.clone());
+        run(driver.configure(&LoraConfig::default())).expect("configure should succeed");
+        let packet = run(driver.read_received_packet()).expect("explicit read should succeed");
+        assert_eq!(packet.data.len(), 5, "explicit mode reads the header-reported length");
+
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+        let implicit = LoraConfig {
+            implicit_header: true,
+            implicit_len: 12,
+            ..LoraConfig::default()
+        };
+        run(driver.configure(&implicit)).expect("configure should succeed");
+        let packet = run(driver.read_received_packet()).expect("implicit read should succeed");
+        assert_eq!(packet.data.len(), 12, "implicit mode reads the fixed length");
+
+        let writes = writes.borrow();
+        let read = first_index(&writes, cmd::READ_BUFFER).expect("ReadBuffer should be recorded");
+        assert_eq!(writes[read][1], 0x80, "read starts at the reported buffer offset");
+    }
+
+    #[test]
+    fn packet_status_includes_signal_rssi_and_frequency_error() {
+        embassy_time::MockDriver::get().reset();
+        // Packet RSSI -80 dBm, SNR 6 dB, signal RSSI -85 dBm; every register
+        // read (so each frequency error byte) returns 0x21
+        let replies = vec![
+            (cmd::GET_RX_BUFFER_STATUS, vec![4, 0x00]),
+            (cmd::GET_PACKET_STATUS, vec![160, 24, 170]),
+            (cmd::READ_REGISTER, vec![0x00, 0x00, 0x21]),
+        ];
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+        run(driver.configure(&LoraConfig::default())).expect("configure should succeed");
+        assert_eq!(driver.last_packet_status(), None);
+
+        let packet = run(driver.read_received_packet()).expect("read should succeed");
+        assert_eq!((packet.rssi, packet.snr), (-80, 6));
+        assert_eq!(
+            driver.last_packet_status(),
+            Some(PacketStatus {
+                rssi: -80,
+                signal_rssi: -85,
+                snr: 6,
+                freq_error_hz: freq_error_hz([0x21, 0x21, 0x21], 250),
+            })
+        );
+
+        let writes = writes.borrow();
+        let status =
+            first_index(&writes, cmd::GET_PACKET_STATUS).expect("GetPacketStatus should be issued");
+        let registers: StdVec<u16> = writes[status..]
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::READ_REGISTER))
+            .map(|w| u16::from_be_bytes([w[1], w[2]]))
+            .collect();
+        assert_eq!(registers, [reg::FREQ_ERROR, reg::FREQ_ERROR + 1, reg::FREQ_ERROR + 2]);
+    }
+
+    #[test]
+    fn frequency_error_is_signed_and_scaled_by_bandwidth() {
+        assert_eq!(freq_error_hz([0x00, 0x00, 0x00], 125), 0);
+        // 0x01000 steps of 1.55 x 125 / 1600 Hz
+        assert_eq!(freq_error_hz([0x00, 0x10, 0x00], 125), 496);
+        assert_eq!(freq_error_hz([0x00, 0x10, 0x00], 250), 992);
+        // Bit 19 set is negative; the top nibble of the first byte is ignored
+        assert_eq!(freq_error_hz([0xFF, 0xF0, 0x00], 125), -496);
+        assert_eq!(freq_error_hz([0x08, 0x00, 0x00], 500), -253_952);
+    }
+
+    #[test]
+    fn carrier_wave_is_keyed_then_returns_to_rx() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.init()).expect("init should succeed");
+        writes.borrow_mut().clear();
+        run(driver.transmit_carrier(100)).expect("carrier should succeed");
+
+        let writes = writes.borrow();
+        let carrier = first_index(&writes, cmd::SET_TX_CONTINUOUS_WAVE)
+            .expect("SetTxContinuousWave should be issued");
+        assert_eq!(writes[carrier].as_slice(), &[cmd::SET_TX_CONTINUOUS_WAVE]);
+        assert!(
+            first_index(&writes, cmd::SET_PA_CONFIG).is_none(),
+            "the existing PA configuration must be left alone"
+        );
+        let rx = writes
+            .iter()
+            .rposition(|w| w.first() == Some(&cmd::SET_RX))
+            .expect("RX should be re-armed");
+        assert!(rx > carrier);
+    }
+
+    #[test]
+    fn instant_rssi_enters_rx_first() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let replies = vec![(cmd::GET_RSSI_INST, vec![0xB4])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+
+        assert!(matches!(run(driver.read_instant_rssi()), Err(LoraError::NotInitialised)));
+
+        run(driver.init()).expect("init should succeed");
+        writes.borrow_mut().clear();
+        assert_eq!(run(driver.read_instant_rssi()).expect("read should succeed"), -90);
+        assert!(
+            first_index(&writes.borrow(), cmd::SET_RX).is_none(),
+            "already listening, so RX is not re-entered"
+        );
+
+        run(driver.set_standby()).expect("standby should succeed");
+        writes.borrow_mut().clear();
+        run(driver.read_instant_rssi()).expect("read should succeed");
+        let writes = writes.borrow();
+        let rx = first_index(&writes, cmd::SET_RX).expect("RX should be entered");
+        let rssi = first_index(&writes, cmd::GET_RSSI_INST).expect("GetRssiInst should be issued");
+        assert!(rx < rssi);
+    }
+
+    #[test]
+    fn rssi_scan_visits_each_channel_then_retunes() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let replies = vec![(cmd::GET_RSSI_INST, vec![0xB4])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+        let mut rssi = [0i16; 3];
+
+        assert!(matches!(run(driver.scan_rssi(868_000_000, 100_000, &mut rssi)), Err(LoraError::NotInitialised)));
+
+        run(driver.init()).expect("init should succeed");
+        writes.borrow_mut().clear();
+        run(driver.scan_rssi(868_000_000, 100_000, &mut rssi)).expect("scan should succeed");
+        assert_eq!(rssi, [-90; 3]);
+
+        let writes = writes.borrow();
+        let register = |freq_hz: u32| (((freq_hz as u64) << 25) / 32_000_000) as u32;
+        let tuned: StdVec<u32> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_RF_FREQUENCY))
+            .map(|w| u32::from_be_bytes([w[1], w[2], w[3], w[4]]))
+            .collect();
+        assert_eq!(
+            tuned,
+            [
+                register(868_000_000),
+                register(868_100_000),
+                register(868_200_000),
+                register(lora_defaults::FREQUENCY_HZ)
+            ]
+        );
+        let last_read = writes.iter().rposition(|w| w.first() == Some(&cmd::GET_RSSI_INST)).unwrap();
+        let rx = writes
+            .iter()
+            .rposition(|w| w.first() == Some(&cmd::SET_RX))
+            .expect("RX should be re-armed");
+        assert!(rx > last_read, "background RX resumes after the sweep");
+    }
+
+    #[test]
+    fn power_override_applies_then_restores_configured_power() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.init()).expect("init should succeed");
+        assert!(matches!(
+            run(driver.transmit_with_power(b"hi", MAX_TX_POWER_DBM + 1)),
+            Err(LoraError::InvalidConfig)
+        ));
+
+        writes.borrow_mut().clear();
+        // LowPin never raises DIO1, so the TX itself times out; the power must
+        // still be restored.
+        let _ = run(driver.transmit_with_power(b"hi", -5));
+
+        let writes = writes.borrow();
+        let params: StdVec<_> = writes
+            .iter()
+            .filter(|w| w.first() == Some(&cmd::SET_TX_PARAMS))
+            .collect();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0][1], (-5i8) as u8);
+        assert_eq!(params[1][1], lora_defaults::TX_POWER_DBM as u8);
+        let tx = first_index(&writes, cmd::SET_TX).expect("SetTx should be issued");
+        let override_at = first_index(&writes, cmd::SET_TX_PARAMS).unwrap();
+        assert!(override_at < tx);
+    }
+
+    #[test]
+    fn receive_stays_in_continuous_rx_across_polls() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let dio1 = FlagPin(Rc::new(core::cell::Cell::new(false)));
+        let replies = vec![(cmd::GET_RX_BUFFER_STATUS, vec![3, 0x80])];
+        let mut driver = build_driver_with_dio1(writes.clone(), replies, dio1.clone());
+        run(driver.init()).expect("init should succeed");
+
+        // An idle poll times out without leaving RX.
+        writes.borrow_mut().clear();
+        assert!(matches!(run(driver.receive(100)), Err(LoraError::Timeout)));
+        for opcode in [cmd::SET_STANDBY, cmd::SET_PACKET_PARAMS, cmd::SET_RX] {
+            assert!(
+                first_index(&writes.borrow(), opcode).is_none(),
+                "idle poll must not re-arm RX (opcode {:#04x})",
+                opcode
+            );
+        }
+
+        // A packet that lands between polls is still there on the next one.
+        dio1.0.set(true);
+        driver.spi.replies.push((cmd::GET_IRQ_STATUS, vec![0x00, irq::RX_DONE as u8]));
+        writes.borrow_mut().clear();
+        let packet = run(driver.receive(100)).expect("pending packet should be read");
+        assert_eq!(packet.data.len(), 3);
+
+        let writes = writes.borrow();
+        let read = first_index(&writes, cmd::READ_BUFFER).expect("packet should be read");
+        let rearm = writes
+            .iter()
+            .rposition(|w| w.first() == Some(&cmd::SET_RX))
+            .expect("RX should be re-armed after the packet");
+        assert!(read < rearm);
+    }
+
+    #[test]
+    fn rx_duty_cycle_arms_windows_and_stops_status_polling() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+        run(driver.init()).expect("init should succeed");
+
+        // 10 ms listening, 40 ms asleep, in 15.625 us steps
+        let windows = RxDutyCycle { rx_ms: 10, sleep_ms: 40 };
+        run(driver.set_rx_duty_cycle(Some(windows))).expect("windows should be accepted");
+        writes.borrow_mut().clear();
+        assert!(matches!(run(driver.receive(100)), Err(LoraError::Timeout)));
+        {
+            let writes = writes.borrow();
+            let armed = first_index(&writes, cmd::SET_RX_DUTY_CYCLE).expect("SetRxDutyCycle should be issued");
+            assert_eq!(
+                writes[armed].as_slice(),
+                &[cmd::SET_RX_DUTY_CYCLE, 0x00, 0x02, 0x80, 0x00, 0x0A, 0x00]
+            );
+            assert!(first_index(&writes, cmd::SET_RX).is_none());
+            // Polling the IRQ status would wake the radio from its sleep window
+            assert!(first_index(&writes, cmd::GET_IRQ_STATUS).is_none());
+        }
+
+        // Going back to continuous RX re-arms the active receiver at once
+        writes.borrow_mut().clear();
+        run(driver.set_rx_duty_cycle(None)).expect("continuous RX should be restored");
+        assert!(first_index(&writes.borrow(), cmd::SET_RX).is_some());
+
+        let too_long = RxDutyCycle {
+            rx_ms: 10,
+            sleep_ms: RxDutyCycle::MAX_WINDOW_MS + 1,
+        };
+        assert!(matches!(
+            run(driver.set_rx_duty_cycle(Some(too_long))),
+            Err(LoraError::InvalidConfig)
+        ));
+    }
+
+    #[test]
+    fn receive_re_enters_rx_after_transmit() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+        run(driver.init()).expect("init should succeed");
+
+        // TX leaves the radio in standby (DIO1 never rises here, so it times out).
+        let _ = run(driver.transmit(b"hi"));
+        writes.borrow_mut().clear();
+        let _ = run(driver.receive(100));
+        assert!(first_index(&writes.borrow(), cmd::SET_RX).is_some());
+    }
+
     #[test]
     fn calibrate_image_emits_band_bytes() {
         let writes = Rc::new(RefCell::new(StdVec::new()));
@@ -889,4 +2314,232 @@ mod host_tests {
             first_index(&writes, cmd::CALIBRATE_IMAGE).expect("CalibrateImage should be recorded");
         assert_eq!(&writes[image][1..3], &[0xE1, 0xE9]);
     }
+
+    #[test]
+    fn self_test_reports_each_check() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // Sync word MSB reads back as configured; no device errors latched.
+        let replies = vec![(cmd::READ_REGISTER, vec![0, 0, 0x14]), (cmd::GET_DEVICE_ERRORS, vec![0, 0])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+
+        // BUSY never rises on the mock and its status byte is 0, so the radio
+        // does not count as responsive.
+        let flags = run(driver.self_test()).expect("self test should run");
+        assert_eq!(flags, self_test::SPI_OK | self_test::TCXO_READY);
+        assert!(first_index(&writes.borrow(), cmd::GET_STATUS).is_some());
+    }
+
+    #[test]
+    fn self_test_flags_dead_bus_and_tcxo_error() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // Register reads return 0 and the XOSC start error is latched.
+        let replies = vec![(cmd::GET_DEVICE_ERRORS, vec![0, device_errors::XOSC_START_ERR])];
+        let mut driver = build_driver_with_replies(writes, replies);
+
+        assert_eq!(run(driver.self_test()), Ok(0));
+    }
+
+    #[test]
+    fn raw_register_access_framing() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // Opcode, address and status echo, then the value
+        let replies = vec![(cmd::READ_REGISTER, vec![0, 0, 0x5A])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+
+        assert_eq!(run(LoraRadio::read_register(&mut driver, 0x0740)), Ok(0x5A));
+        run(LoraRadio::write_register(&mut driver, 0x08E7, 0x18)).expect("write should succeed");
+
+        let writes = writes.borrow();
+        assert_eq!(writes[0].as_slice(), &[cmd::READ_REGISTER, 0x07, 0x40, 0x00, 0x00]);
+        assert_eq!(writes[1].as_slice(), &[cmd::WRITE_REGISTER, 0x08, 0xE7, 0x18]);
+    }
+
+    #[test]
+    fn status_byte_decodes_to_radio_state() {
+        // Chip mode in bits 6:4; the command status in bits 3:1 is ignored
+        assert_eq!(status::radio_state(0x22), Some(RadioState::StandbyRc));
+        assert_eq!(status::radio_state(0x30), Some(RadioState::StandbyXosc));
+        assert_eq!(status::radio_state(0x40), Some(RadioState::Fs));
+        assert_eq!(status::radio_state(0x54), Some(RadioState::Rx));
+        assert_eq!(status::radio_state(0x6C), Some(RadioState::Tx));
+        // A floating or shorted MISO
+        assert_eq!(status::radio_state(0x00), None);
+        assert_eq!(status::radio_state(0xFF), None);
+    }
+
+    #[test]
+    fn radio_state_while_asleep_does_not_wake_the_radio() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.sleep(SleepMode::Warm)).expect("sleep should succeed");
+        let before = writes.borrow().len();
+        assert_eq!(run(driver.get_state()), Ok(RadioState::Sleep));
+        assert_eq!(writes.borrow().len(), before, "a sleeping radio is not touched");
+    }
+
+    #[test]
+    fn raw_register_access_refused_while_asleep() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+
+        run(driver.sleep(SleepMode::Warm)).expect("sleep should succeed");
+        let before = writes.borrow().len();
+        assert_eq!(run(LoraRadio::read_register(&mut driver, 0x0740)), Err(LoraError::NotInitialised));
+        assert_eq!(
+            run(LoraRadio::write_register(&mut driver, 0x0740, 0x00)),
+            Err(LoraError::NotInitialised)
+        );
+        assert_eq!(writes.borrow().len(), before, "a sleeping radio is not touched");
+    }
+
+    #[test]
+    fn random_reads_rng_registers_with_lna_detached() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // Every register read (LNA, mixer and the four RNG bytes) returns 0xA5
+        let replies = vec![(cmd::READ_REGISTER, vec![0, 0, 0xA5])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+
+        assert_eq!(run(driver.random_u32()), Err(LoraError::NotInitialised));
+
+        run(driver.init()).expect("init should succeed");
+        writes.borrow_mut().clear();
+        assert_eq!(run(driver.random_u32()), Ok(0xA5A5_A5A5));
+
+        let writes = writes.borrow();
+        let position = |frame: &[u8]| writes.iter().position(|w| w.as_slice() == frame);
+        let irq_off = position(&[cmd::SET_DIO_IRQ_PARAMS, 0, 0, 0, 0, 0, 0, 0, 0]).expect("IRQs should be masked");
+        let lna_off = position(&[cmd::WRITE_REGISTER, 0x08, 0xE2, 0xA4]).expect("LNA should be detached");
+        let mixer_off = position(&[cmd::WRITE_REGISTER, 0x08, 0xE5, 0x25]).expect("mixer should be disabled");
+        let rx = first_index(&writes, cmd::SET_RX).expect("RX should be entered");
+        let rng = position(&[cmd::READ_REGISTER, 0x08, 0x19, 0x00, 0x00]).expect("RNG should be read");
+        let last_rng = position(&[cmd::READ_REGISTER, 0x08, 0x1C, 0x00, 0x00]).expect("all four bytes read");
+        let lna_on = position(&[cmd::WRITE_REGISTER, 0x08, 0xE2, 0xA5]).expect("LNA should be restored");
+        let mixer_on = position(&[cmd::WRITE_REGISTER, 0x08, 0xE5, 0xA5]).expect("mixer should be restored");
+        assert!(irq_off < rx && lna_off < rx && mixer_off < rx);
+        assert!(rx < rng && rng < last_rng);
+        assert!(last_rng < lna_on && last_rng < mixer_on);
+        // Background listening was active after init, so it resumes
+        assert!(writes[mixer_on..].iter().any(|w| w.first() == Some(&cmd::SET_RX)));
+    }
+
+    #[test]
+    fn nss_is_held_high_between_commands() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let edges = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = Sx1262Driver::new(
+            RecordingSpi {
+                writes: writes.clone(),
+                replies: StdVec::new(),
+            },
+            Sx1262Pins {
+                nss: EdgeLog {
+                    writes: writes.clone(),
+                    edges: edges.clone(),
+                },
+                dio1: LowPin,
+                nrst: NoopOut,
+                busy: LowPin,
+                rf_switch: None::<NoopOut>,
+            },
+        );
+
+        run(async {
+            driver.write_command(cmd::SET_STANDBY, &[0x00]).await?;
+            driver.write_command(cmd::SET_STANDBY, &[0x00]).await
+        })
+        .expect("standby should succeed");
+
+        let edges = edges.borrow();
+        let levels: StdVec<(bool, usize)> =
+            edges.iter().map(|&(high, transfers, _)| (high, transfers)).collect();
+        // Each command is framed by NSS low before its transfer and high after
+        assert_eq!(levels, [(false, 0), (true, 1), (false, 1), (true, 2)]);
+        assert!(
+            edges[2].2 - edges[1].2 >= Duration::from_micros(spi::NSS_HIGH_US),
+            "the next command must wait out the NSS hold"
+        );
+    }
+
+    #[test]
+    fn external_rf_switch_is_driven_around_transmit() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let edges = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = Sx1262Driver::new(
+            RecordingSpi {
+                writes: writes.clone(),
+                replies: vec![(cmd::GET_IRQ_STATUS, vec![0x00, irq::TX_DONE as u8])],
+            },
+            Sx1262Pins {
+                nss: NoopOut,
+                dio1: LowPin,
+                nrst: NoopOut,
+                busy: LowPin,
+                rf_switch: Some(EdgeLog {
+                    writes: writes.clone(),
+                    edges: edges.clone(),
+                }),
+            },
+        )
+        .with_nss_high(Duration::from_ticks(0));
+        run(driver.init()).expect("init should succeed");
+        run(driver.transmit(b"hi")).expect("transmit should succeed");
+
+        let writes = writes.borrow();
+        // DIO2 is not the switch
+        let dio2 = first_index(&writes, cmd::SET_DIO2_AS_RF_SWITCH_CTRL).expect("DIO2 should be configured");
+        assert_eq!(writes[dio2].as_slice(), &[cmd::SET_DIO2_AS_RF_SWITCH_CTRL, 0x00]);
+
+        // Switched to TX just before SetTx, and back once TX is done
+        let tx = first_index(&writes, cmd::SET_TX).expect("SetTx should be issued");
+        let edges = edges.borrow();
+        let high = edges.iter().position(|&(high, _, _)| high).expect("switch should go to TX");
+        assert!(edges[..high].iter().all(|&(high, _, _)| !high), "RX until the transmit");
+        assert_eq!(edges[high].1, tx);
+        assert!(!edges[high + 1].0 && edges[high + 1].1 > tx);
+    }
+
+    #[test]
+    fn irq_latched_before_wait_is_not_missed() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        // TX_DONE is already latched, but DIO1 (LowPin) never shows an edge.
+        let replies = vec![(cmd::GET_IRQ_STATUS, vec![0x00, irq::TX_DONE as u8])];
+        let mut driver = build_driver_with_replies(writes.clone(), replies);
+        run(driver.init()).expect("init should succeed");
+
+        let start = embassy_time::Instant::now();
+        assert_eq!(run(driver.transmit(b"hi")), Ok(()));
+        // Returned on the entry check rather than after the 10 s TX timeout
+        assert!(embassy_time::Instant::now() - start < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn very_long_receive_timeout_does_not_wrap() {
+        embassy_time::MockDriver::get().reset();
+        let writes = Rc::new(RefCell::new(StdVec::new()));
+        let mut driver = build_driver(writes.clone());
+        run(driver.init()).expect("init should succeed");
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut rx = core::pin::pin!(driver.receive(u32::MAX));
+        assert!(rx.as_mut().poll(&mut cx).is_pending());
+
+        // Well past the ~71 minutes at which `timeout_ms * 1000` overflows a u32
+        embassy_time::MockDriver::get().advance(Duration::from_secs(2 * 3600));
+        assert!(rx.as_mut().poll(&mut cx).is_pending(), "must not time out early");
+
+        // The full timeout (about 49.7 days) still ends the wait
+        embassy_time::MockDriver::get().advance(Duration::from_millis(u32::MAX as u64));
+        assert!(matches!(rx.as_mut().poll(&mut cx), Poll::Ready(Err(LoraError::Timeout))));
+    }
 }
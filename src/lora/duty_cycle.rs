@@ -0,0 +1,138 @@
+//! Transmit duty-cycle accounting
+//!
+//! Tracks time on air over a rolling window so the firmware can refuse
+//! transmissions that would exceed a regulatory duty-cycle limit (e.g. the EU868
+//! sub-band limits). The window is split into fixed buckets, so memory use is
+//! constant however many packets are sent.
+
+use crate::config::duty_cycle::{LIMIT_PERMILLE, WINDOW_SECS};
+
+/// Number of buckets the window is split into
+const BUCKETS: usize = 60;
+
+/// Rolling-window time-on-air budget
+pub struct DutyCycle {
+    /// Airtime in ms used per bucket
+    used_ms: [u32; BUCKETS],
+    /// Which bucket period (now / bucket length) each slot currently holds
+    periods: [u64; BUCKETS],
+    /// Length of one bucket in ms
+    bucket_ms: u64,
+    /// Airtime allowed per window in ms
+    budget_ms: u32,
+}
+
+impl DutyCycle {
+    /// Create a tracker for the configured limit and window
+    pub const fn new() -> Self {
+        Self::with_limit(LIMIT_PERMILLE, WINDOW_SECS)
+    }
+
+    /// Create a tracker allowing `limit_permille` tenths of a percent of
+    /// `window_secs` on air
+    pub const fn with_limit(limit_permille: u32, window_secs: u64) -> Self {
+        let window_ms = window_secs * 1000;
+        Self {
+            used_ms: [0; BUCKETS],
+            periods: [0; BUCKETS],
+            bucket_ms: window_ms / BUCKETS as u64,
+            budget_ms: (window_ms * limit_permille as u64 / 1000) as u32,
+        }
+    }
+
+    /// Change the limit to `limit_permille` tenths of a percent of the window,
+    /// keeping the airtime already used
+    pub fn set_limit(&mut self, limit_permille: u32) {
+        let window_ms = self.bucket_ms * BUCKETS as u64;
+        self.budget_ms = (window_ms * limit_permille as u64 / 1000) as u32;
+    }
+
+    /// Airtime used within the window ending at `now_ms`
+    pub fn used_ms(&self, now_ms: u64) -> u32 {
+        let current = now_ms / self.bucket_ms;
+        self.used_ms
+            .iter()
+            .zip(self.periods.iter())
+            .filter(|(_, &period)| current.saturating_sub(period) < BUCKETS as u64)
+            .map(|(&used, _)| used)
+            .sum()
+    }
+
+    /// Whether `airtime_ms` more can be sent at `now_ms` within the budget
+    pub fn allows(&self, now_ms: u64, airtime_ms: u32) -> bool {
+        self.used_ms(now_ms).saturating_add(airtime_ms) <= self.budget_ms
+    }
+
+    /// Record `airtime_ms` sent at `now_ms`
+    pub fn record(&mut self, now_ms: u64, airtime_ms: u32) {
+        let period = now_ms / self.bucket_ms;
+        let slot = (period % BUCKETS as u64) as usize;
+        if self.periods[slot] != period {
+            // The slot still holds a bucket from a previous window
+            self.periods[slot] = period;
+            self.used_ms[slot] = 0;
+        }
+        self.used_ms[slot] = self.used_ms[slot].saturating_add(airtime_ms);
+    }
+}
+
+impl Default for DutyCycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1% of a 100 s window: a 1000 ms budget
+    fn one_percent() -> DutyCycle {
+        DutyCycle::with_limit(10, 100)
+    }
+
+    #[test]
+    fn budget_is_enforced() {
+        let mut duty = one_percent();
+        assert!(duty.allows(0, 1000));
+        assert!(!duty.allows(0, 1001));
+
+        duty.record(0, 600);
+        assert!(duty.allows(10, 400));
+        assert!(!duty.allows(10, 401));
+    }
+
+    #[test]
+    fn airtime_leaves_the_window() {
+        let mut duty = one_percent();
+        duty.record(0, 1000);
+        assert_eq!(duty.used_ms(99_000), 1000);
+        assert!(!duty.allows(99_000, 1));
+
+        // One full window later the old bucket no longer counts.
+        assert_eq!(duty.used_ms(100_000), 0);
+        assert!(duty.allows(100_000, 1000));
+    }
+
+    #[test]
+    fn new_limit_keeps_used_airtime() {
+        let mut duty = one_percent();
+        duty.record(0, 600);
+
+        // 0.1% of 100 s is 100 ms, already exceeded
+        duty.set_limit(1);
+        assert!(!duty.allows(10, 1));
+
+        duty.set_limit(100);
+        assert!(duty.allows(10, 9_400));
+        assert!(!duty.allows(10, 9_401));
+    }
+
+    #[test]
+    fn reused_slot_is_cleared() {
+        let mut duty = one_percent();
+        duty.record(0, 700);
+        duty.record(100_000, 200);
+        assert_eq!(duty.used_ms(100_000), 200);
+    }
+}
@@ -0,0 +1,441 @@
+//! LoRa message fragmentation and reassembly
+//!
+//! A single SX1262 frame carries at most 255 bytes, so host messages longer
+//! than that are split into fragments, each prefixed with a small header:
+//!
+//! ```text
+//! [msg_id: u8][frag_index: u8][frag_total: u8][chunk...]
+//! ```
+//!
+//! Every frame carries the header (a short message is one fragment of one), so
+//! the receiver never has to guess whether a frame is part of a larger message.
+//!
+//! The top bit of `frag_total` (`ACK_REQUESTED`) asks the addressee to confirm
+//! the whole message with an ACK frame: a bare header with `frag_total` 0,
+//! echoing the `msg_id`.
+//!
+//! Channel hops are agreed with the same kind of bare header, carrying the
+//! hop position in place of the `msg_id` and `HOP_ANNOUNCE` or `HOP_CONFIRM`
+//! as the `frag_index` (see `lora::hop`).
+
+use crate::config::protocol::MAX_MESSAGE_SIZE;
+use crate::crypto;
+use crate::lora::addressing::{self, Address};
+use heapless::Vec;
+
+/// Size of the fragment header in bytes.
+pub const HEADER_LEN: usize = 3;
+
+/// Largest fragment frame, leaving room for the address header and encryption
+/// envelope within the radio's 255-byte limit (the packet-params length field
+/// is one byte).
+pub const MAX_FRAME_LEN: usize = 255 - crypto::OVERHEAD - addressing::HEADER_LEN;
+
+/// Largest chunk of message data carried by a single fragment.
+pub const MAX_CHUNK_LEN: usize = MAX_FRAME_LEN - HEADER_LEN;
+
+/// Most fragments a message of `MAX_MESSAGE_SIZE` bytes can be split into.
+pub const MAX_FRAGMENTS: usize = MAX_MESSAGE_SIZE.div_ceil(MAX_CHUNK_LEN);
+
+const _: () = assert!(MAX_FRAGMENTS < 32, "Partial::received has one bit per fragment");
+
+/// Flag in `frag_total` asking the addressee to ACK the message.
+pub const ACK_REQUESTED: u8 = 0x80;
+
+/// `frag_index` of a frame announcing a channel hop
+pub const HOP_ANNOUNCE: u8 = 0xFE;
+/// `frag_index` of a frame confirming a hop announcement
+pub const HOP_CONFIRM: u8 = 0xFF;
+
+/// Partial messages still missing fragments after this long are discarded.
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 5_000;
+
+/// Number of messages that can be reassembled concurrently.
+const MAX_PENDING: usize = 2;
+
+/// Number of fragments needed to carry `len` bytes of message data.
+pub fn fragment_count(len: usize) -> usize {
+    len.div_ceil(MAX_CHUNK_LEN).max(1)
+}
+
+/// Length on air of the largest packet carrying `len` bytes of message data:
+/// the first fragment, with its address header and encryption envelope.
+pub fn packet_len(len: usize) -> usize {
+    crypto::OVERHEAD + addressing::HEADER_LEN + HEADER_LEN + len.min(MAX_CHUNK_LEN)
+}
+
+/// Build a single fragment frame: header followed by the chunk.
+pub fn encode_fragment(
+    msg_id: u8,
+    frag_index: u8,
+    frag_total: u8,
+    chunk: &[u8],
+) -> Vec<u8, MAX_FRAME_LEN> {
+    let mut frame = Vec::new();
+    let _ = frame.extend_from_slice(&[msg_id, frag_index, frag_total]);
+    let len = chunk.len().min(MAX_CHUNK_LEN);
+    let _ = frame.extend_from_slice(&chunk[..len]);
+    frame
+}
+
+/// Build an ACK frame confirming message `msg_id`.
+pub fn encode_ack(msg_id: u8) -> Vec<u8, MAX_FRAME_LEN> {
+    let mut frame = Vec::new();
+    let _ = frame.extend_from_slice(&[msg_id, 0, 0]);
+    frame
+}
+
+/// The `msg_id` confirmed by `frame`, if it is an ACK frame.
+pub fn decode_ack(frame: &[u8]) -> Option<u8> {
+    match frame {
+        [msg_id, 0, 0] => Some(*msg_id),
+        _ => None,
+    }
+}
+
+/// A channel hop frame, with the hop position it is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hop {
+    /// The sender is moving to this position and asks others to follow
+    Announce(u8),
+    /// The sender has heard the announcement and follows
+    Confirm(u8),
+}
+
+/// Build a channel hop frame.
+pub fn encode_hop(hop: Hop) -> Vec<u8, MAX_FRAME_LEN> {
+    let (position, kind) = match hop {
+        Hop::Announce(position) => (position, HOP_ANNOUNCE),
+        Hop::Confirm(position) => (position, HOP_CONFIRM),
+    };
+    let mut frame = Vec::new();
+    let _ = frame.extend_from_slice(&[position, kind, 0]);
+    frame
+}
+
+/// The hop carried by `frame`, if it is a channel hop frame.
+pub fn decode_hop(frame: &[u8]) -> Option<Hop> {
+    match *frame {
+        [position, HOP_ANNOUNCE, 0] => Some(Hop::Announce(position)),
+        [position, HOP_CONFIRM, 0] => Some(Hop::Confirm(position)),
+        _ => None,
+    }
+}
+
+/// Whether the sender of fragment `frame` asked for an ACK.
+pub fn ack_requested(frame: &[u8]) -> bool {
+    frame.len() >= HEADER_LEN && frame[2] & ACK_REQUESTED != 0
+}
+
+/// A message that has received some but not all of its fragments.
+struct Partial {
+    source: Address,
+    msg_id: u8,
+    frag_total: u8,
+    /// Bit N set once fragment N has been stored.
+    received: u32,
+    /// Length of the final fragment's chunk, once it has arrived.
+    last_len: usize,
+    started_ms: u64,
+    buf: [u8; MAX_MESSAGE_SIZE],
+}
+
+impl Partial {
+    fn is_complete(&self) -> bool {
+        self.received == (1u32 << self.frag_total) - 1
+    }
+
+    fn message_len(&self) -> usize {
+        (self.frag_total as usize - 1) * MAX_CHUNK_LEN + self.last_len
+    }
+}
+
+/// Reassembles fragments back into complete messages.
+///
+/// Fragments are keyed by sender and `msg_id`; a message is emitted once every
+/// fragment has arrived. Partial messages older than `REASSEMBLY_TIMEOUT_MS` are dropped
+/// by [`Reassembler::expire`], so a lost fragment cannot pin a slot forever.
+pub struct Reassembler {
+    slots: [Option<Partial>; MAX_PENDING],
+}
+
+impl Reassembler {
+    /// Create an empty reassembler
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { None }; MAX_PENDING],
+        }
+    }
+
+    /// Feed a received frame, returning the full message once it is complete.
+    ///
+    /// Malformed frames (short header, index out of range, oversized chunk)
+    /// are dropped.
+    pub fn push(
+        &mut self,
+        source: Address,
+        frame: &[u8],
+        now_ms: u64,
+    ) -> Option<Vec<u8, MAX_MESSAGE_SIZE>> {
+        if frame.len() < HEADER_LEN {
+            return None;
+        }
+        let (msg_id, frag_index, frag_total) = (frame[0], frame[1], frame[2] & !ACK_REQUESTED);
+        let chunk = &frame[HEADER_LEN..];
+
+        if frag_total == 0
+            || frag_index >= frag_total
+            || frag_total as usize > MAX_FRAGMENTS
+            || chunk.len() > MAX_CHUNK_LEN
+        {
+            return None;
+        }
+        // Every fragment but the last must be full, or offsets would not line up.
+        let is_last = frag_index + 1 == frag_total;
+        if !is_last && chunk.len() != MAX_CHUNK_LEN {
+            return None;
+        }
+
+        // Single-fragment messages never need a slot.
+        if frag_total == 1 {
+            let mut message = Vec::new();
+            message.extend_from_slice(chunk).ok()?;
+            return Some(message);
+        }
+
+        let slot = self.slot_for(source, msg_id, frag_total, now_ms);
+        let partial = slot.as_mut()?;
+
+        let offset = frag_index as usize * MAX_CHUNK_LEN;
+        if offset + chunk.len() > MAX_MESSAGE_SIZE {
+            *slot = None;
+            return None;
+        }
+        partial.buf[offset..offset + chunk.len()].copy_from_slice(chunk);
+        partial.received |= 1 << frag_index;
+        if is_last {
+            partial.last_len = chunk.len();
+        }
+
+        if !partial.is_complete() {
+            return None;
+        }
+
+        let partial = slot.take()?;
+        let mut message = Vec::new();
+        message
+            .extend_from_slice(&partial.buf[..partial.message_len()])
+            .ok()?;
+        Some(message)
+    }
+
+    /// Drop partial messages that have been waiting longer than the timeout.
+    ///
+    /// Returns the number of messages discarded.
+    pub fn expire(&mut self, now_ms: u64) -> usize {
+        let mut dropped = 0;
+        for slot in self.slots.iter_mut() {
+            if let Some(partial) = slot {
+                if now_ms.saturating_sub(partial.started_ms) >= REASSEMBLY_TIMEOUT_MS {
+                    *slot = None;
+                    dropped += 1;
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Find the slot for `(source, msg_id)`, starting a new partial if none matches.
+    ///
+    /// A matching id with a different fragment count is a new message reusing
+    /// the id, so the stale partial is replaced. When every slot is busy the
+    /// oldest partial is evicted.
+    fn slot_for(
+        &mut self,
+        source: Address,
+        msg_id: u8,
+        frag_total: u8,
+        now_ms: u64,
+    ) -> &mut Option<Partial> {
+        let is_match = |s: &Option<Partial>| {
+            matches!(s, Some(p) if p.source == source && p.msg_id == msg_id && p.frag_total == frag_total)
+        };
+        let index = self
+            .slots
+            .iter()
+            .position(is_match)
+            .or_else(|| self.slots.iter().position(|s| s.is_none()))
+            .unwrap_or_else(|| {
+                self.slots
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, s)| s.as_ref().map_or(0, |p| p.started_ms))
+                    .map_or(0, |(i, _)| i)
+            });
+
+        let slot = &mut self.slots[index];
+        if !is_match(slot) {
+            *slot = Some(Partial {
+                source,
+                msg_id,
+                frag_total,
+                received: 0,
+                last_len: 0,
+                started_ms: now_ms,
+                buf: [0u8; MAX_MESSAGE_SIZE],
+            });
+        }
+        slot
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: Address = [0xAB, 0xCD, 0xEF];
+
+    fn split(msg_id: u8, data: &[u8]) -> std::vec::Vec<Vec<u8, MAX_FRAME_LEN>> {
+        let total = fragment_count(data.len()) as u8;
+        data.chunks(MAX_CHUNK_LEN)
+            .enumerate()
+            .map(|(i, chunk)| encode_fragment(msg_id, i as u8, total, chunk))
+            .collect()
+    }
+
+    #[test]
+    fn short_message_is_a_single_fragment() {
+        let frames = split(7, b"Hello");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_slice(), &[7, 0, 1, b'H', b'e', b'l', b'l', b'o']);
+
+        let mut reassembler = Reassembler::new();
+        let message = reassembler.push(PEER, &frames[0], 0).expect("single fragment completes");
+        assert_eq!(message.as_slice(), b"Hello");
+    }
+
+    #[test]
+    fn packet_len_counts_the_largest_fragment() {
+        let sealed = addressing::encode(PEER, PEER, &encode_fragment(0, 0, 1, b"Hello"))
+            .and_then(|frame| crypto::seal(&frame, &[0; crypto::NONCE_LEN]))
+            .unwrap();
+        assert_eq!(packet_len(5), sealed.len());
+        // Longer messages are split, so no packet exceeds the radio's limit
+        assert_eq!(packet_len(MAX_CHUNK_LEN), 255);
+        assert_eq!(packet_len(MAX_MESSAGE_SIZE), 255);
+    }
+
+    #[test]
+    fn long_message_round_trips() {
+        let data: std::vec::Vec<u8> = (0..600u16).map(|i| i as u8).collect();
+        let frames = split(3, &data);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.len() <= MAX_FRAME_LEN));
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &frames[0], 0).is_none());
+        assert!(reassembler.push(PEER, &frames[1], 10).is_none());
+        let message = reassembler.push(PEER, &frames[2], 20).expect("last fragment completes");
+        assert_eq!(message.as_slice(), data.as_slice());
+    }
+
+    #[test]
+    fn out_of_order_fragments_reassemble() {
+        let data: std::vec::Vec<u8> = (0..400u16).map(|i| (i * 3) as u8).collect();
+        let frames = split(9, &data);
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &frames[1], 0).is_none());
+        let message = reassembler.push(PEER, &frames[0], 5).expect("both fragments present");
+        assert_eq!(message.as_slice(), data.as_slice());
+    }
+
+    #[test]
+    fn interleaved_messages_are_kept_apart() {
+        let a = [0xAAu8; 300];
+        let b = [0xBBu8; 300];
+        let frames_a = split(1, &a);
+        let frames_b = split(2, &b);
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &frames_a[0], 0).is_none());
+        assert!(reassembler.push(PEER, &frames_b[0], 0).is_none());
+        assert_eq!(reassembler.push(PEER, &frames_b[1], 0).unwrap().as_slice(), &b[..]);
+        assert_eq!(reassembler.push(PEER, &frames_a[1], 0).unwrap().as_slice(), &a[..]);
+    }
+
+    #[test]
+    fn partial_message_times_out() {
+        let data = [0x55u8; 300];
+        let frames = split(4, &data);
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &frames[0], 0).is_none());
+        assert_eq!(reassembler.expire(REASSEMBLY_TIMEOUT_MS - 1), 0);
+        assert_eq!(reassembler.expire(REASSEMBLY_TIMEOUT_MS), 1);
+
+        // The surviving fragment alone can no longer complete the message.
+        assert!(reassembler.push(PEER, &frames[1], REASSEMBLY_TIMEOUT_MS).is_none());
+    }
+
+    #[test]
+    fn same_msg_id_from_different_senders_is_kept_apart() {
+        let a = [0xAAu8; 300];
+        let b = [0xBBu8; 300];
+        let frames_a = split(5, &a);
+        let frames_b = split(5, &b);
+        let other = [0x01, 0x02, 0x03];
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &frames_a[0], 0).is_none());
+        assert!(reassembler.push(other, &frames_b[0], 0).is_none());
+        assert_eq!(reassembler.push(PEER, &frames_a[1], 0).unwrap().as_slice(), &a[..]);
+        assert_eq!(reassembler.push(other, &frames_b[1], 0).unwrap().as_slice(), &b[..]);
+    }
+
+    #[test]
+    fn ack_requested_flag_is_ignored_by_reassembly() {
+        let frame = encode_fragment(6, 0, 1 | ACK_REQUESTED, b"Hi");
+        assert!(ack_requested(&frame));
+        assert!(!ack_requested(&split(6, b"Hi")[0]));
+
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(PEER, &frame, 0).unwrap().as_slice(), b"Hi");
+    }
+
+    #[test]
+    fn ack_frames_round_trip() {
+        assert_eq!(decode_ack(&encode_ack(42)), Some(42));
+        assert_eq!(decode_ack(&split(42, b"x")[0]), None);
+        // ACKs are never mistaken for message fragments
+        assert!(Reassembler::new().push(PEER, &encode_ack(42), 0).is_none());
+    }
+
+    #[test]
+    fn hop_frames_round_trip() {
+        for hop in [Hop::Announce(3), Hop::Confirm(3)] {
+            let frame = encode_hop(hop);
+            assert_eq!(decode_hop(&frame), Some(hop));
+            // Hop frames are neither ACKs nor message fragments
+            assert_eq!(decode_ack(&frame), None);
+            assert!(Reassembler::new().push(PEER, &frame, 0).is_none());
+        }
+        assert_eq!(decode_hop(&encode_ack(3)), None);
+    }
+
+    #[test]
+    fn malformed_frames_are_dropped() {
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(PEER, &[1, 0], 0).is_none());
+        assert!(reassembler.push(PEER, &[1, 0, 0, 0xAA], 0).is_none());
+        assert!(reassembler.push(PEER, &[1, 2, 2, 0xAA], 0).is_none());
+        // A non-final fragment must be full-size.
+        assert!(reassembler.push(PEER, &[1, 0, 2, 0xAA], 0).is_none());
+    }
+}
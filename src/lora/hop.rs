@@ -0,0 +1,197 @@
+//! Channel agility after repeated collisions
+//!
+//! When the link keeps failing (CRC errors, reliable sends that get no ACK)
+//! the LoRa task proposes the next channel of `config::channel_hop`. Every
+//! node derives the same channel order from the shared seed, and a hop names
+//! its position in that order, so a peer that hears the announcement lands on
+//! the same channel whatever hops it missed. Position 0 is the configured
+//! home frequency.
+
+use crate::config::channel_hop::{HopChannel, CHANNELS as TABLE, ERROR_THRESHOLD, SEED, WINDOW_MS};
+
+/// Number of candidate channels
+pub const CHANNELS: usize = TABLE.len();
+
+/// Shuffle `0..N` with a xorshift32 generator seeded by `seed`
+///
+/// The same seed always gives the same order, on every node.
+pub fn hop_order<const N: usize>(seed: u32) -> [u8; N] {
+    let mut order = [0u8; N];
+    for (i, slot) in order.iter_mut().enumerate() {
+        *slot = i as u8;
+    }
+    // xorshift32 never leaves zero, so a zero seed needs a substitute
+    let mut state = if seed == 0 { 0x9E37_79B9 } else { seed };
+    for i in (1..N).rev() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let j = (state % (i as u32 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+/// Position in the shared hop sequence
+pub struct HopSequence {
+    order: [u8; CHANNELS],
+    position: u8,
+}
+
+impl HopSequence {
+    /// Sequence for the configured seed, on the home channel
+    pub fn new() -> Self {
+        Self::with_seed(SEED)
+    }
+
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            order: hop_order(seed),
+            position: 0,
+        }
+    }
+
+    /// Current position, 0 while on the home channel
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position;
+    }
+
+    /// Channel at `position`, or None for the home channel or a position
+    /// past the end of the table
+    pub fn channel(&self, position: u8) -> Option<HopChannel> {
+        let index = usize::from(position).checked_sub(1)?;
+        self.order.get(index).map(|&c| TABLE[c as usize])
+    }
+
+    /// Position to propose after the current one, skipping any channel on
+    /// `current_hz`. Wraps past the last channel to the first, never home.
+    pub fn next(&self, current_hz: u32) -> u8 {
+        let mut position = self.position;
+        for _ in 0..CHANNELS {
+            position = position % CHANNELS as u8 + 1;
+            if self.channel(position).is_some_and(|c| c.freq_hz != current_hz) {
+                break;
+            }
+        }
+        position
+    }
+}
+
+impl Default for HopSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts link failures and reports when there have been enough within the
+/// window to warrant a hop
+pub struct CollisionMonitor {
+    /// Failures in the current window
+    count: u32,
+    /// When the current window opened
+    window_start_ms: u64,
+}
+
+impl CollisionMonitor {
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            window_start_ms: 0,
+        }
+    }
+
+    /// Record a failure at `now_ms`, returning true when it reaches
+    /// `ERROR_THRESHOLD` within `WINDOW_MS`; the count then starts over
+    pub fn record(&mut self, now_ms: u64) -> bool {
+        if self.count == 0 || now_ms.saturating_sub(self.window_start_ms) > WINDOW_MS {
+            self.count = 0;
+            self.window_start_ms = now_ms;
+        }
+        self.count += 1;
+        if self.count >= ERROR_THRESHOLD {
+            self.count = 0;
+            return true;
+        }
+        false
+    }
+}
+
+impl Default for CollisionMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_is_a_permutation() {
+        for seed in [0, 1, 0xDEAD_BEEF, SEED] {
+            let mut order = hop_order::<16>(seed);
+            order.sort_unstable();
+            let expected: [u8; 16] = core::array::from_fn(|i| i as u8);
+            assert_eq!(order, expected);
+        }
+    }
+
+    #[test]
+    fn order_is_deterministic_per_seed() {
+        assert_eq!(hop_order::<16>(1234), hop_order::<16>(1234));
+        assert_ne!(hop_order::<16>(1234), hop_order::<16>(4321));
+    }
+
+    #[test]
+    fn positions_map_to_the_same_channel_on_every_node() {
+        let a = HopSequence::with_seed(42);
+        let b = HopSequence::with_seed(42);
+        assert_eq!(a.channel(0), None);
+        assert_eq!(a.channel(CHANNELS as u8 + 1), None);
+        for p in 1..=CHANNELS as u8 {
+            let channel = a.channel(p).unwrap();
+            assert_eq!(Some(channel), b.channel(p));
+            assert!(TABLE.contains(&channel));
+        }
+    }
+
+    #[test]
+    fn next_visits_every_channel_and_never_returns_home() {
+        let mut hops = HopSequence::with_seed(7);
+        let mut seen = std::vec::Vec::new();
+        for _ in 0..CHANNELS {
+            let p = hops.next(0);
+            assert_ne!(p, 0);
+            hops.set_position(p);
+            seen.push(hops.channel(p).unwrap().freq_hz);
+        }
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen.len(), CHANNELS);
+    }
+
+    #[test]
+    fn next_skips_the_channel_already_in_use() {
+        let hops = HopSequence::with_seed(7);
+        let first = hops.channel(1).unwrap().freq_hz;
+        assert_eq!(hops.next(first), 2);
+    }
+
+    #[test]
+    fn monitor_needs_threshold_within_window() {
+        let mut monitor = CollisionMonitor::new();
+        for i in 1..ERROR_THRESHOLD {
+            assert!(!monitor.record(i as u64));
+        }
+        assert!(monitor.record(ERROR_THRESHOLD as u64));
+
+        // Failures spread wider than the window never add up
+        for i in 0..2 * ERROR_THRESHOLD as u64 {
+            assert!(!monitor.record(100 + i * (WINDOW_MS + 1)));
+        }
+    }
+}
@@ -1,4 +1,12 @@
+pub mod addressing;
+pub mod airtime;
+pub mod band;
 pub mod calibration;
+pub mod dedup;
+pub mod duty_cycle;
+pub mod fragment;
+pub mod hop;
+pub mod repeater;
 #[cfg(any(feature = "embedded", feature = "host-test"))]
 pub mod driver;
 #[cfg(any(feature = "embedded", feature = "host-test"))]
@@ -0,0 +1,113 @@
+//! Repeater mode: passing on other devices' frames to extend range
+//!
+//! A device with SetRepeater on retransmits every frame it hears from
+//! another device that is not addressed to it alone, with the address
+//! header's `ttl` one lower; a frame that arrives with `ttl` 0 goes no
+//! further. Frames are told apart by sender and fragment header, and a frame
+//! heard again within `SEEN_WINDOW_MS` is not repeated again, so two
+//! repeaters in range of each other do not bounce it back and forth. Every
+//! device also drops a relayed copy of a frame it has already heard, so a
+//! message is not delivered twice.
+
+use heapless::Deque;
+
+use crate::config::repeater::{SEEN_FRAMES, SEEN_WINDOW_MS};
+use crate::lora::addressing::Address;
+use crate::lora::fragment;
+
+/// Identifies a frame: its sender and fragment header
+pub type FrameKey = (Address, [u8; fragment::HEADER_LEN]);
+
+/// Key of the frame from `source` carrying `fragment`, if it has a whole
+/// fragment header
+pub fn frame_key(source: Address, fragment: &[u8]) -> Option<FrameKey> {
+    let header = fragment.get(..fragment::HEADER_LEN)?;
+    let mut key = [0u8; fragment::HEADER_LEN];
+    key.copy_from_slice(header);
+    Some((source, key))
+}
+
+/// Frames heard in the last `SEEN_WINDOW_MS`
+pub struct SeenFrames {
+    /// Oldest first
+    frames: Deque<(FrameKey, u64), SEEN_FRAMES>,
+}
+
+impl SeenFrames {
+    pub const fn new() -> Self {
+        Self { frames: Deque::new() }
+    }
+
+    /// Record the frame `key` heard at `now_ms`, returning true unless it was
+    /// already heard within the window
+    ///
+    /// Once full, the oldest frame is forgotten to make room.
+    pub fn first_heard(&mut self, key: FrameKey, now_ms: u64) -> bool {
+        while self
+            .frames
+            .front()
+            .is_some_and(|(_, heard_ms)| now_ms.saturating_sub(*heard_ms) >= SEEN_WINDOW_MS)
+        {
+            self.frames.pop_front();
+        }
+        if self.frames.iter().any(|(seen, _)| *seen == key) {
+            return false;
+        }
+        if self.frames.is_full() {
+            self.frames.pop_front();
+        }
+        let _ = self.frames.push_back((key, now_ms));
+        true
+    }
+}
+
+impl Default for SeenFrames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: Address = [0xAB, 0xCD, 0xEF];
+
+    fn key(msg_id: u8, frag_index: u8) -> FrameKey {
+        frame_key(PEER, &fragment::encode_fragment(msg_id, frag_index, 2, b"x")).unwrap()
+    }
+
+    #[test]
+    fn frame_is_only_heard_first_once_per_window() {
+        let mut seen = SeenFrames::new();
+        assert!(seen.first_heard(key(1, 0), 0));
+        assert!(!seen.first_heard(key(1, 0), SEEN_WINDOW_MS - 1));
+        // A retransmission after the window counts as new
+        assert!(seen.first_heard(key(1, 0), SEEN_WINDOW_MS));
+    }
+
+    #[test]
+    fn fragments_and_senders_are_kept_apart() {
+        let mut seen = SeenFrames::new();
+        assert!(seen.first_heard(key(1, 0), 0));
+        assert!(seen.first_heard(key(1, 1), 0));
+        assert!(seen.first_heard(frame_key([0x01, 0x02, 0x03], &fragment::encode_fragment(1, 0, 2, b"x")).unwrap(), 0));
+        // An ACK for message 1 is not its first fragment
+        assert!(seen.first_heard(frame_key(PEER, &fragment::encode_ack(1)).unwrap(), 0));
+    }
+
+    #[test]
+    fn oldest_frame_is_forgotten_when_full() {
+        let mut seen = SeenFrames::new();
+        for msg_id in 0..=SEEN_FRAMES as u8 {
+            assert!(seen.first_heard(key(msg_id, 0), 0));
+        }
+        assert!(seen.first_heard(key(0, 0), 0));
+        assert!(!seen.first_heard(key(SEEN_FRAMES as u8, 0), 0));
+    }
+
+    #[test]
+    fn short_fragment_has_no_key() {
+        assert!(frame_key(PEER, &[1, 0]).is_none());
+    }
+}
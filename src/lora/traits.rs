@@ -7,6 +7,23 @@ use crate::config::protocol::MAX_LORA_PAYLOAD;
 use core::future::Future;
 use heapless::Vec;
 
+/// Lowest TX power the SX1262 high-power PA supports, in dBm
+pub const MIN_TX_POWER_DBM: i8 = -9;
+/// Highest TX power the SX1262 high-power PA supports, in dBm
+pub const MAX_TX_POWER_DBM: i8 = 22;
+
+/// Result bits reported by `LoraRadio::self_test`
+pub mod self_test {
+    /// A register read back the value the driver wrote to it
+    pub const SPI_OK: u8 = 0x01;
+    /// The status byte was valid and BUSY toggled around a standby command
+    pub const RADIO_RESPONSIVE: u8 = 0x02;
+    /// The TCXO started (no oscillator start error latched)
+    pub const TCXO_READY: u8 = 0x04;
+    /// Every check passed
+    pub const ALL: u8 = SPI_OK | RADIO_RESPONSIVE | TCXO_READY;
+}
+
 /// Errors that can occur during LoRa operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LoraError {
@@ -26,6 +43,8 @@ pub enum LoraError {
     SpiError,
     /// Radio not initialised
     NotInitialised,
+    /// Operation not supported by this radio
+    Unsupported,
 }
 
 /// Configuration for LoRa modulation
@@ -41,6 +60,89 @@ pub struct LoraConfig {
     pub coding_rate: u8,
     /// Transmit power in dBm
     pub tx_power_dbm: i8,
+    /// Preamble length in symbols
+    pub preamble_symbols: u16,
+    /// Sync word (0x1424 private, 0x3444 public)
+    pub sync_word: u16,
+    /// Implicit (fixed-length) header mode instead of explicit
+    pub implicit_header: bool,
+    /// Frame length in implicit header mode (ignored in explicit mode)
+    pub implicit_len: u8,
+    /// Payload CRC on transmitted frames (and expected on received frames in
+    /// implicit header mode)
+    pub crc_enabled: bool,
+    /// Inverted IQ instead of standard
+    pub iq_inverted: bool,
+    /// PA over-current protection limit in mA (up to 240)
+    pub ocp_ma: u16,
+    /// PA ramp time code for SetTxParams (0x00 = 10 us up to 0x07 = 3400 us)
+    pub ramp_time: u8,
+    /// Oscillator the radio idles on in standby and falls back to after TX/RX
+    pub standby_mode: StandbyMode,
+    /// LoRa, or GFSK for legacy gear. In GFSK mode the spreading factor,
+    /// bandwidth, coding rate and IQ are unused, the preamble is counted in
+    /// bytes and the sync word is the 2-byte GFSK one.
+    pub modulation: Modulation,
+}
+
+/// Modulation the radio transmits and receives with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Modulation {
+    /// LoRa, shaped by the spreading factor, bandwidth and coding rate
+    #[default]
+    Lora,
+    /// GFSK, for remotes and sensors that do not speak LoRa
+    Fsk(FskParams),
+}
+
+/// GFSK modulation parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FskParams {
+    /// Bit rate in bits/s (600-300000)
+    pub bitrate_bps: u32,
+    /// Frequency deviation in Hz (600-200000)
+    pub deviation_hz: u32,
+    /// Receiver bandwidth in Hz, one of the SX1262's steps (4800, 5800,
+    /// 7300, 9700, 11700, 14600, 19500, 23400, 29300, 39000, 46900, 58600,
+    /// 78200, 93800, 117300, 156200, 187200, 234300, 312000, 373600, 467000)
+    pub rx_bandwidth_hz: u32,
+    /// Gaussian filter BT x 10: 0 (no filter), 3, 5, 7 or 10
+    pub gaussian_bt: u8,
+}
+
+impl FskParams {
+    /// Whether the SX1262 supports these parameters
+    fn is_valid(&self) -> bool {
+        use crate::config::fsk;
+
+        (fsk::MIN_BITRATE_BPS..=fsk::MAX_BITRATE_BPS).contains(&self.bitrate_bps)
+            && (fsk::MIN_DEVIATION_HZ..=fsk::MAX_DEVIATION_HZ).contains(&self.deviation_hz)
+            && matches!(
+                self.rx_bandwidth_hz,
+                4_800
+                    | 5_800
+                    | 7_300
+                    | 9_700
+                    | 11_700
+                    | 14_600
+                    | 19_500
+                    | 23_400
+                    | 29_300
+                    | 39_000
+                    | 46_900
+                    | 58_600
+                    | 78_200
+                    | 93_800
+                    | 117_300
+                    | 156_200
+                    | 187_200
+                    | 234_300
+                    | 312_000
+                    | 373_600
+                    | 467_000
+            )
+            && matches!(self.gaussian_bt, 0 | 3 | 5 | 7 | 10)
+    }
 }
 
 impl Default for LoraConfig {
@@ -53,10 +155,132 @@ impl Default for LoraConfig {
             bandwidth_khz: lora_defaults::BANDWIDTH_KHZ,
             coding_rate: lora_defaults::CODING_RATE,
             tx_power_dbm: lora_defaults::TX_POWER_DBM,
+            preamble_symbols: lora_defaults::PREAMBLE_SYMBOLS,
+            sync_word: lora_defaults::SYNC_WORD,
+            implicit_header: lora_defaults::IMPLICIT_HEADER,
+            implicit_len: lora_defaults::IMPLICIT_LEN,
+            crc_enabled: lora_defaults::CRC_ENABLED,
+            iq_inverted: lora_defaults::IQ_INVERTED,
+            ocp_ma: lora_defaults::OCP_MA,
+            ramp_time: lora_defaults::RAMP_TIME,
+            standby_mode: if lora_defaults::STANDBY_XOSC {
+                StandbyMode::Xosc
+            } else {
+                StandbyMode::Rc
+            },
+            modulation: Modulation::Lora,
+        }
+    }
+}
+
+impl LoraConfig {
+    /// Check every parameter is one the SX1262 (and this driver) supports
+    ///
+    /// Returns `InvalidConfig` rather than letting the driver substitute a
+    /// default, so a mistyped bandwidth or coding rate is reported instead of
+    /// silently changing the link.
+    pub fn validate(&self) -> Result<(), LoraError> {
+        let valid = (7..=12).contains(&self.spreading_factor)
+            && matches!(
+                self.bandwidth_khz,
+                7 | 8 | 10 | 15 | 16 | 20 | 21 | 31 | 41 | 42 | 62 | 63 | 125 | 250 | 500
+            )
+            && (5..=8).contains(&self.coding_rate)
+            && (MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&self.tx_power_dbm)
+            && self.preamble_symbols > 0
+            && (!self.implicit_header || self.implicit_len > 0)
+            && self.ocp_ma <= crate::config::lora_defaults::MAX_OCP_MA
+            && self.ramp_time <= crate::config::lora_defaults::MAX_RAMP_TIME
+            && match self.modulation {
+                Modulation::Lora => true,
+                // The preamble goes to the radio in bits, a 16-bit field
+                Modulation::Fsk(fsk) => fsk.is_valid() && self.preamble_symbols <= crate::config::fsk::MAX_PREAMBLE_BYTES,
+            };
+        if valid {
+            Ok(())
+        } else {
+            Err(LoraError::InvalidConfig)
+        }
+    }
+
+    /// Whether the radio runs GFSK rather than LoRa
+    pub fn is_fsk(&self) -> bool {
+        matches!(self.modulation, Modulation::Fsk(_))
+    }
+}
+
+/// Time on air in ms of a `payload_len`-byte packet sent with `config`,
+/// rounded up
+///
+/// `payload_len` is the whole radio payload (for this firmware, including the
+/// address header and encryption envelope). Low data rate optimisation follows
+/// the config as the driver applies it. See `airtime` for the formula.
+pub fn lora_time_on_air_ms(config: &LoraConfig, payload_len: usize) -> u32 {
+    match config.modulation {
+        Modulation::Lora => super::airtime::time_on_air_ms(
+            config.spreading_factor,
+            config.bandwidth_khz,
+            config.coding_rate,
+            config.preamble_symbols,
+            config.implicit_header,
+            config.crc_enabled,
+            payload_len,
+        ),
+        Modulation::Fsk(fsk) => super::airtime::fsk_time_on_air_ms(
+            fsk.bitrate_bps,
+            config.preamble_symbols,
+            config.implicit_header,
+            config.crc_enabled,
+            payload_len,
+        ),
+    }
+}
+
+/// Duty-cycled receive: listen for `rx_ms`, sleep for `sleep_ms`, repeat
+///
+/// The radio keeps listening once it detects a preamble, so a packet is
+/// caught as long as the sender's preamble spans a sleep window plus a
+/// listen window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RxDutyCycle {
+    pub rx_ms: u32,
+    pub sleep_ms: u32,
+}
+
+impl RxDutyCycle {
+    /// Longest window the radio's 24-bit, 15.625 us timers can count
+    pub const MAX_WINDOW_MS: u32 = 0xFF_FFFF / 64;
+
+    /// Check both windows are non-zero and fit the radio's timers
+    pub fn validate(&self) -> Result<(), LoraError> {
+        let window = 1..=Self::MAX_WINDOW_MS;
+        if window.contains(&self.rx_ms) && window.contains(&self.sleep_ms) {
+            Ok(())
+        } else {
+            Err(LoraError::InvalidConfig)
         }
     }
 }
 
+/// SX1262 standby oscillator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandbyMode {
+    /// 13 MHz RC oscillator (~0.6 mA); TX and RX wait for the TCXO to start
+    Rc,
+    /// TCXO kept running (~1.2 mA); TX and RX start without that wait
+    Xosc,
+}
+
+/// SX1262 sleep depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepMode {
+    /// Configuration retained; waking only re-arms RX (~600 nA asleep)
+    Warm,
+    /// Lowest current (~160 nA); configuration is lost, so the radio is
+    /// re-initialised on wake
+    Cold,
+}
+
 /// Received packet with metadata
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RxPacket {
@@ -68,6 +292,39 @@ pub struct RxPacket {
     pub snr: i8,
 }
 
+/// Link quality of a received packet, as the radio measured it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketStatus {
+    /// RSSI averaged over the packet in dBm (the RSSI in `RxPacket`)
+    pub rssi: i16,
+    /// RSSI of the despread LoRa signal in dBm, which can be below the noise
+    /// floor
+    pub signal_rssi: i16,
+    /// Signal-to-Noise Ratio in dB
+    pub snr: i8,
+    /// Estimated carrier frequency offset between the sender and this radio
+    /// in Hz
+    pub freq_error_hz: i32,
+}
+
+/// What the radio is doing, as GetRadioState reports it
+///
+/// The codes are the SX1262's chip-mode field; sleep, which the radio cannot
+/// report without being woken, takes the unused 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RadioState {
+    Sleep = 0x00,
+    /// Standby on the RC oscillator
+    StandbyRc = 0x02,
+    /// Standby on the crystal or TCXO
+    StandbyXosc = 0x03,
+    /// Frequency synthesis, on the way to TX or RX
+    Fs = 0x04,
+    Rx = 0x05,
+    Tx = 0x06,
+}
+
 /// Abstract LoRa radio interface for testability
 ///
 /// This trait allows the dispatcher to work with either the real SX1262
@@ -81,9 +338,17 @@ pub trait LoraRadio {
     /// Blocks until transmission is complete or an error occurs.
     fn transmit(&mut self, data: &[u8]) -> impl Future<Output = Result<(), LoraError>>;
 
+    /// Transmit data at `power_dbm` instead of the configured TX power
+    ///
+    /// The configured power is restored afterwards, whether or not the
+    /// transmission succeeds. Out-of-range power returns `InvalidConfig`.
+    fn transmit_with_power(&mut self, data: &[u8], power_dbm: i8) -> impl Future<Output = Result<(), LoraError>>;
+
     /// Receive data with timeout
     ///
     /// Listens for incoming packets until one is received or the timeout expires.
+    /// The radio stays in continuous RX across calls, so a packet arriving
+    /// between calls is returned by the next one.
     fn receive(&mut self, timeout_ms: u32) -> impl Future<Output = Result<RxPacket, LoraError>>;
 
     /// Configure the radio parameters
@@ -92,6 +357,90 @@ pub trait LoraRadio {
     /// Set the radio to standby mode
     #[allow(dead_code)]
     fn set_standby(&mut self) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Put the radio into sleep mode
+    ///
+    /// The radio must be woken with `wake` before it is used again.
+    fn sleep(&mut self, mode: SleepMode) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Wake the radio from sleep and resume continuous RX
+    ///
+    /// Does nothing if the radio is not asleep.
+    fn wake(&mut self) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Read the radio's junction temperature in degrees C
+    fn read_temperature(&mut self) -> impl Future<Output = Result<i16, LoraError>>;
+
+    /// Transmit an unmodulated carrier for `duration_ms`, then return to RX
+    ///
+    /// For antenna tuning and RF measurements. Uses the configured frequency,
+    /// PA configuration and TX power.
+    fn transmit_carrier(&mut self, duration_ms: u32) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Read the instantaneous RSSI on the configured channel in dBm
+    ///
+    /// Enters RX first if the radio is not already listening.
+    fn read_instant_rssi(&mut self) -> impl Future<Output = Result<i16, LoraError>>;
+
+    /// Sweep from `start_hz` in steps of `step_hz`, filling `rssi` with the
+    /// instantaneous RSSI in dBm of one channel each
+    ///
+    /// The radio is retuned to the configured frequency afterwards, and
+    /// continuous RX resumed if it was active, whether or not the sweep
+    /// finished. Image calibration is left as configured, so channels far
+    /// outside the configured band read less accurately.
+    fn scan_rssi(
+        &mut self,
+        start_hz: u32,
+        step_hz: u32,
+        rssi: &mut [i16],
+    ) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Check the radio is alive, returning `self_test` bits for the checks
+    /// that passed
+    ///
+    /// A failed check clears its bit rather than returning an error, so a dead
+    /// radio reports 0. Continuous RX is resumed afterwards if it was active.
+    fn self_test(&mut self) -> impl Future<Output = Result<u8, LoraError>>;
+
+    /// Read 32 bits from the radio's hardware random number generator
+    ///
+    /// Samples receiver noise, so it needs no other entropy source. Continuous
+    /// RX is resumed afterwards if it was active. Fails with `NotInitialised`
+    /// before init or while asleep.
+    fn random_u32(&mut self) -> impl Future<Output = Result<u32, LoraError>>;
+
+    /// Receive in duty-cycled windows (`Some`) or continuously (`None`)
+    ///
+    /// Applies to every later entry into RX, and switches an active receiver
+    /// over straight away. Fails with `InvalidConfig` for windows the radio
+    /// cannot time and `NotInitialised` before init or while asleep.
+    fn set_rx_duty_cycle(&mut self, windows: Option<RxDutyCycle>) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Read one radio register, for field debugging
+    ///
+    /// Fails with `NotInitialised` while the radio is asleep, since any SPI
+    /// access would wake it.
+    fn read_register(&mut self, addr: u16) -> impl Future<Output = Result<u8, LoraError>>;
+
+    /// Write one radio register, for field debugging
+    ///
+    /// Bypasses the driver's own configuration, so the next `configure` may
+    /// overwrite the value. Fails with `NotInitialised` while asleep.
+    fn write_register(&mut self, addr: u16, value: u8) -> impl Future<Output = Result<(), LoraError>>;
+
+    /// Link quality of the most recent packet received, or None before the
+    /// first
+    ///
+    /// Captured as each packet is read out, so it survives later TX, sleep
+    /// and reconfiguration.
+    fn last_packet_status(&self) -> Option<PacketStatus>;
+
+    /// Read what the radio is doing right now, for field debugging
+    ///
+    /// A sleeping radio reports `Sleep` without being touched, since any SPI
+    /// access would wake it.
+    fn get_state(&mut self) -> impl Future<Output = Result<RadioState, LoraError>>;
 }
 
 #[cfg(test)]
@@ -107,6 +456,8 @@ pub mod mock {
         rx_queue: RefCell<Vec<RxPacket, 8>>,
         /// Record of transmitted packets
         tx_history: RefCell<Vec<Vec<u8, MAX_LORA_PAYLOAD>, 8>>,
+        /// TX power override used for each transmitted packet (None = configured)
+        tx_power_history: RefCell<Vec<Option<i8>, 8>>,
         /// Current configuration
         config: RefCell<Option<LoraConfig>>,
         /// Error to return on next transmit
@@ -115,6 +466,33 @@ pub mod mock {
         next_rx_error: RefCell<Option<LoraError>>,
         /// Whether init has been called
         initialised: RefCell<bool>,
+        /// Sleep mode, while asleep
+        sleep_mode: RefCell<Option<SleepMode>>,
+        /// Value returned by read_temperature()
+        temperature: RefCell<i16>,
+        /// Duration of the last carrier-wave request
+        carrier_ms: RefCell<Option<u32>>,
+        /// Value returned by read_instant_rssi()
+        rssi: RefCell<i16>,
+        /// Value returned by self_test()
+        self_test_flags: RefCell<u8>,
+        /// Registers written with write_register(), read back by read_register()
+        registers: RefCell<Vec<(u16, u8), 16>>,
+        /// Value returned by random_u32()
+        random: RefCell<u32>,
+        /// Receive windows last set with set_rx_duty_cycle()
+        rx_duty_cycle: RefCell<Option<RxDutyCycle>>,
+        /// Number of upcoming init() calls that fail
+        init_failures: RefCell<u32>,
+        /// Number of upcoming receive() calls that fail
+        rx_failures: RefCell<u32>,
+        /// Frequency error reported for each received packet
+        freq_error_hz: RefCell<i32>,
+        /// Status of the last packet receive() returned
+        last_packet: RefCell<Option<PacketStatus>>,
+        /// RSSI and SNR each transmitted packet is heard back with, if looped
+        /// back
+        loopback: RefCell<Option<(i16, i8)>>,
     }
 
     impl MockLoraRadio {
@@ -123,10 +501,24 @@ pub mod mock {
             Self {
                 rx_queue: RefCell::new(Vec::new()),
                 tx_history: RefCell::new(Vec::new()),
+                tx_power_history: RefCell::new(Vec::new()),
                 config: RefCell::new(None),
                 next_tx_error: RefCell::new(None),
                 next_rx_error: RefCell::new(None),
                 initialised: RefCell::new(false),
+                sleep_mode: RefCell::new(None),
+                temperature: RefCell::new(25),
+                carrier_ms: RefCell::new(None),
+                rssi: RefCell::new(-120),
+                self_test_flags: RefCell::new(self_test::ALL),
+                registers: RefCell::new(Vec::new()),
+                random: RefCell::new(0x5EED_F00D),
+                rx_duty_cycle: RefCell::new(None),
+                init_failures: RefCell::new(0),
+                rx_failures: RefCell::new(0),
+                freq_error_hz: RefCell::new(0),
+                last_packet: RefCell::new(None),
+                loopback: RefCell::new(None),
             }
         }
 
@@ -150,6 +542,11 @@ pub mod mock {
             self.tx_history.borrow().clone()
         }
 
+        /// Get the TX power override used for each transmitted packet
+        pub fn get_tx_power_history(&self) -> Vec<Option<i8>, 8> {
+            self.tx_power_history.borrow().clone()
+        }
+
         /// Check if the radio has been initialised
         pub fn is_initialised(&self) -> bool {
             *self.initialised.borrow()
@@ -159,6 +556,71 @@ pub mod mock {
         pub fn get_config(&self) -> Option<LoraConfig> {
             self.config.borrow().clone()
         }
+
+        /// Set the value returned by read_temperature()
+        pub fn set_temperature(&self, celsius: i16) {
+            *self.temperature.borrow_mut() = celsius;
+        }
+
+        /// Set the value returned by read_instant_rssi()
+        pub fn set_instant_rssi(&self, dbm: i16) {
+            *self.rssi.borrow_mut() = dbm;
+        }
+
+        /// Set the value returned by self_test(), to simulate failed checks
+        pub fn set_self_test_flags(&self, flags: u8) {
+            *self.self_test_flags.borrow_mut() = flags;
+        }
+
+        /// Set the value returned by random_u32()
+        pub fn set_random(&self, value: u32) {
+            *self.random.borrow_mut() = value;
+        }
+
+        /// Set the frequency error reported for packets received from now on
+        pub fn set_freq_error(&self, hz: i32) {
+            *self.freq_error_hz.borrow_mut() = hz;
+        }
+
+        /// Make the next `count` init() calls fail with SpiError
+        pub fn fail_next_inits(&self, count: u32) {
+            *self.init_failures.borrow_mut() = count;
+        }
+
+        /// Make the next `count` receive() calls fail with SpiError
+        pub fn fail_next_receives(&self, count: u32) {
+            *self.rx_failures.borrow_mut() = count;
+        }
+
+        /// Get the receive windows in force (None = continuous RX)
+        pub fn rx_duty_cycle(&self) -> Option<RxDutyCycle> {
+            *self.rx_duty_cycle.borrow()
+        }
+
+        /// Get the value last written to a register, if any
+        pub fn register(&self, addr: u16) -> Option<u8> {
+            self.registers
+                .borrow()
+                .iter()
+                .find(|(a, _)| *a == addr)
+                .map(|&(_, value)| value)
+        }
+
+        /// Get the duration of the last carrier-wave request, if any
+        pub fn carrier_requested(&self) -> Option<u32> {
+            *self.carrier_ms.borrow()
+        }
+
+        /// Hear every transmitted packet back with this RSSI and SNR, as
+        /// through a loopback fixture (None = off)
+        pub fn set_loopback(&self, link: Option<(i16, i8)>) {
+            *self.loopback.borrow_mut() = link;
+        }
+
+        /// Get the sleep mode, if the radio is asleep
+        pub fn sleep_mode(&self) -> Option<SleepMode> {
+            *self.sleep_mode.borrow()
+        }
     }
 
     impl Default for MockLoraRadio {
@@ -169,6 +631,11 @@ pub mod mock {
 
     impl LoraRadio for MockLoraRadio {
         async fn init(&mut self) -> Result<(), LoraError> {
+            let mut failures = self.init_failures.borrow_mut();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(LoraError::SpiError);
+            }
             *self.initialised.borrow_mut() = true;
             Ok(())
         }
@@ -182,8 +649,27 @@ pub mod mock {
             packet
                 .extend_from_slice(data)
                 .map_err(|_| LoraError::TransmitFailed)?;
+            if let Some((rssi, snr)) = *self.loopback.borrow() {
+                self.queue_rx_packet(RxPacket {
+                    data: packet.clone(),
+                    rssi,
+                    snr,
+                });
+            }
             let _ = self.tx_history.borrow_mut().push(packet);
+            let _ = self.tx_power_history.borrow_mut().push(None);
+
+            Ok(())
+        }
 
+        async fn transmit_with_power(&mut self, data: &[u8], power_dbm: i8) -> Result<(), LoraError> {
+            if !(MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&power_dbm) {
+                return Err(LoraError::InvalidConfig);
+            }
+            self.transmit(data).await?;
+            if let Some(last) = self.tx_power_history.borrow_mut().last_mut() {
+                *last = Some(power_dbm);
+            }
             Ok(())
         }
 
@@ -191,6 +677,11 @@ pub mod mock {
             if let Some(error) = self.next_rx_error.borrow_mut().take() {
                 return Err(error);
             }
+            let mut failures = self.rx_failures.borrow_mut();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(LoraError::SpiError);
+            }
 
             // Pop from front (FIFO order)
             let mut queue = self.rx_queue.borrow_mut();
@@ -200,10 +691,18 @@ pub mod mock {
 
             // Remove first element
             let packet = queue.remove(0);
+            // Canned status: the signal RSSI is 3 dB under the packet RSSI
+            *self.last_packet.borrow_mut() = Some(PacketStatus {
+                rssi: packet.rssi,
+                signal_rssi: packet.rssi - 3,
+                snr: packet.snr,
+                freq_error_hz: *self.freq_error_hz.borrow(),
+            });
             Ok(packet)
         }
 
         async fn configure(&mut self, config: &LoraConfig) -> Result<(), LoraError> {
+            config.validate()?;
             *self.config.borrow_mut() = Some(config.clone());
             Ok(())
         }
@@ -211,6 +710,94 @@ pub mod mock {
         async fn set_standby(&mut self) -> Result<(), LoraError> {
             Ok(())
         }
+
+        async fn sleep(&mut self, mode: SleepMode) -> Result<(), LoraError> {
+            *self.sleep_mode.borrow_mut() = Some(mode);
+            Ok(())
+        }
+
+        async fn wake(&mut self) -> Result<(), LoraError> {
+            *self.sleep_mode.borrow_mut() = None;
+            Ok(())
+        }
+
+        async fn read_temperature(&mut self) -> Result<i16, LoraError> {
+            Ok(*self.temperature.borrow())
+        }
+
+        async fn transmit_carrier(&mut self, duration_ms: u32) -> Result<(), LoraError> {
+            *self.carrier_ms.borrow_mut() = Some(duration_ms);
+            Ok(())
+        }
+
+        async fn read_instant_rssi(&mut self) -> Result<i16, LoraError> {
+            if !*self.initialised.borrow() {
+                return Err(LoraError::NotInitialised);
+            }
+            Ok(*self.rssi.borrow())
+        }
+
+        async fn scan_rssi(&mut self, _start_hz: u32, _step_hz: u32, rssi: &mut [i16]) -> Result<(), LoraError> {
+            if !*self.initialised.borrow() {
+                return Err(LoraError::NotInitialised);
+            }
+            // The instant RSSI, one dB quieter per step up the band
+            for (step, sample) in (0i16..).zip(rssi.iter_mut()) {
+                *sample = *self.rssi.borrow() - step;
+            }
+            Ok(())
+        }
+
+        async fn self_test(&mut self) -> Result<u8, LoraError> {
+            Ok(*self.self_test_flags.borrow())
+        }
+
+        async fn random_u32(&mut self) -> Result<u32, LoraError> {
+            if !*self.initialised.borrow() {
+                return Err(LoraError::NotInitialised);
+            }
+            Ok(*self.random.borrow())
+        }
+
+        async fn set_rx_duty_cycle(&mut self, windows: Option<RxDutyCycle>) -> Result<(), LoraError> {
+            if !*self.initialised.borrow() {
+                return Err(LoraError::NotInitialised);
+            }
+            if let Some(windows) = &windows {
+                windows.validate()?;
+            }
+            *self.rx_duty_cycle.borrow_mut() = windows;
+            Ok(())
+        }
+
+        async fn read_register(&mut self, addr: u16) -> Result<u8, LoraError> {
+            // Unwritten registers read as 0
+            Ok(self.register(addr).unwrap_or(0))
+        }
+
+        async fn write_register(&mut self, addr: u16, value: u8) -> Result<(), LoraError> {
+            let mut registers = self.registers.borrow_mut();
+            match registers.iter_mut().find(|(a, _)| *a == addr) {
+                Some(entry) => entry.1 = value,
+                None => registers.push((addr, value)).map_err(|_| LoraError::InvalidConfig)?,
+            }
+            Ok(())
+        }
+
+        fn last_packet_status(&self) -> Option<PacketStatus> {
+            *self.last_packet.borrow()
+        }
+
+        async fn get_state(&mut self) -> Result<RadioState, LoraError> {
+            // Between calls the radio is listening once it is up
+            if self.sleep_mode.borrow().is_some() {
+                Ok(RadioState::Sleep)
+            } else if *self.initialised.borrow() {
+                Ok(RadioState::Rx)
+            } else {
+                Ok(RadioState::StandbyRc)
+            }
+        }
     }
 
     #[cfg(test)]
@@ -266,6 +853,83 @@ pub mod mock {
             });
         }
 
+        #[test]
+        fn test_default_config_is_valid() {
+            assert_eq!(LoraConfig::default().validate(), Ok(()));
+        }
+
+        #[test]
+        fn test_time_on_air_follows_config() {
+            // Semtech calculator: SF7/125 kHz/4-5, 8-symbol preamble, CRC on,
+            // explicit header, 10 bytes is 41.2 ms.
+            let config = LoraConfig {
+                spreading_factor: 7,
+                bandwidth_khz: 125,
+                coding_rate: 5,
+                ..LoraConfig::default()
+            };
+            assert_eq!(lora_time_on_air_ms(&config, 10), 42);
+
+            // SF9/125 kHz/4-5, 20 bytes is 185.3 ms.
+            let config = LoraConfig { spreading_factor: 9, ..config };
+            assert_eq!(lora_time_on_air_ms(&config, 20), 186);
+
+            // The defaults (SF11/250 kHz/4-8) need no LDRO: 50 bytes is 821.2 ms.
+            assert_eq!(lora_time_on_air_ms(&LoraConfig::default(), 50), 822);
+
+            // SF12/125 kHz turns LDRO on: 10 bytes is 991.2 ms.
+            let config = LoraConfig {
+                spreading_factor: 12,
+                bandwidth_khz: 125,
+                coding_rate: 5,
+                ..LoraConfig::default()
+            };
+            assert_eq!(lora_time_on_air_ms(&config, 10), 992);
+        }
+
+        #[test]
+        fn test_config_modulation_matrix() {
+            let bandwidths = [7, 10, 15, 20, 31, 41, 62, 125, 250, 500];
+            for spreading_factor in 7..=12 {
+                for &bandwidth_khz in &bandwidths {
+                    for coding_rate in 5..=8 {
+                        let config = LoraConfig {
+                            spreading_factor,
+                            bandwidth_khz,
+                            coding_rate,
+                            ..LoraConfig::default()
+                        };
+                        assert_eq!(config.validate(), Ok(()), "{:?} should be accepted", config);
+                    }
+                }
+            }
+
+            let invalid = [(5, 125, 5), (6, 125, 5), (13, 125, 5), (9, 0, 5), (9, 100, 5), (9, 1000, 5), (9, 125, 4), (9, 125, 9)];
+            for (spreading_factor, bandwidth_khz, coding_rate) in invalid {
+                let config = LoraConfig {
+                    spreading_factor,
+                    bandwidth_khz,
+                    coding_rate,
+                    ..LoraConfig::default()
+                };
+                assert_eq!(config.validate(), Err(LoraError::InvalidConfig), "{:?} should be rejected", config);
+            }
+        }
+
+        #[test]
+        fn test_config_rejects_out_of_range_values() {
+            let invalid = [
+                LoraConfig { tx_power_dbm: 23, ..LoraConfig::default() },
+                LoraConfig { preamble_symbols: 0, ..LoraConfig::default() },
+                LoraConfig { implicit_header: true, implicit_len: 0, ..LoraConfig::default() },
+                LoraConfig { ocp_ma: 241, ..LoraConfig::default() },
+                LoraConfig { ramp_time: 0x08, ..LoraConfig::default() },
+            ];
+            for config in invalid {
+                assert_eq!(config.validate(), Err(LoraError::InvalidConfig), "{:?} should be rejected", config);
+            }
+        }
+
         #[test]
         fn test_mock_tx_error() {
             let mut radio = MockLoraRadio::new();
@@ -9,7 +9,7 @@ esp_bootloader_esp_idf::esp_app_desc!(
     env!("CARGO_PKG_VERSION"),  // version
     env!("CARGO_PKG_NAME"),     // project_name
     "00:00:00",                 // build_time
-    "2025-01-01",               // build_date
+    env!("WT_BUILD_DATE"),      // build_date
     "0.0.0",                    // idf_ver (not using IDF)
     0x10000,                    // mmu_page_size (64KB)
     0,                          // min_efuse_blk_rev_full (accept all)
@@ -20,24 +20,39 @@ use embassy_executor::Spawner;
 use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
 use embassy_usb::UsbDevice;
 use esp_backtrace as _;
-use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
+use esp_hal::gpio::{AnyPin, Input, InputConfig, Level, Output, OutputConfig, Pull, WakeEvent};
 use esp_hal::otg_fs::asynch::{Config as DriverConfig, Driver};
 use esp_hal::otg_fs::Usb;
-use esp_hal::spi::master::{Config as SpiConfig, Spi};
+use esp_hal::dma::{DmaRxBuf, DmaTxBuf};
+use esp_hal::dma_buffers;
+use esp_hal::spi::master::{Config as SpiConfig, Spi, SpiDmaBus};
 use esp_hal::spi::Mode as SpiMode;
 use esp_hal::time::Rate;
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::Async;
 use static_cell::StaticCell;
 
+mod battery;
 mod ble;
+mod clock;
 mod config;
+mod crash_log;
+mod crc16;
+mod crc32;
+mod crypto;
 mod debug;
 mod dispatcher;
+mod firmware;
+mod framing;
+mod label;
+mod led;
 mod lora;
+mod record;
+mod reset_reason;
 mod tasks;
 mod usb;
 
+use config::board::BOARD;
 use dispatcher::COMMAND_CHANNEL;
 use lora::driver::{Sx1262Driver, Sx1262Pins};
 use tasks::{AdminReceiver, CommandReceiver, CommandSender, LedReceiver, LedSender, ADMIN_CHANNEL, LED_CHANNEL};
@@ -48,6 +63,11 @@ static EXECUTOR: StaticCell<esp_rtos::embassy::Executor> = StaticCell::new();
 /// Static cell for esp-radio controller (needed for 'static lifetime)
 static RADIO_CONTROLLER: StaticCell<esp_radio::Controller<'static>> = StaticCell::new();
 
+/// Socket storage for the WiFi network stack: the command server's TCP
+/// socket, DHCP and DNS
+#[cfg(feature = "wifi")]
+static NET_RESOURCES: StaticCell<embassy_net::StackResources<3>> = StaticCell::new();
+
 // USB static buffers (must be 'static for embassy-usb)
 static EP_OUT_BUFFER: StaticCell<[u8; 1024]> = StaticCell::new();
 static DATA_CDC_STATE: StaticCell<State<'static>> = StaticCell::new();
@@ -63,56 +83,101 @@ static USB_SERIAL: StaticCell<[u8; 9]> = StaticCell::new();
 fn main() -> ! {
     // Initialise heap allocator for BLE support (64KB - BLE requires significant heap)
     esp_alloc::heap_allocator!(size: 64 * 1024);
+    // WiFi buffers on top, in a second heap region
+    #[cfg(feature = "wifi")]
+    esp_alloc::heap_allocator!(size: 64 * 1024);
 
     let peripherals = esp_hal::init(esp_hal::Config::default());
+    // Kept for GetResetReason
+    reset_reason::capture();
 
     // Turn on LED (active low)
-    let led = Output::new(peripherals.GPIO48, Level::Low, OutputConfig::default());
+    let led = Output::new(board_pin(BOARD.led), Level::Low, OutputConfig::default());
 
     // Initialise the RTOS scheduler with timer - MUST be done before any async operations
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_rtos::start(timg0.timer0);
 
+    // Main watchdog for the task watchdog (armed when its task starts)
+    let wdt = TimerGroup::new(peripherals.TIMG1).wdt;
+
     // Configure SPI for LoRa
-    let sclk = peripherals.GPIO7;
-    let miso = peripherals.GPIO8;
-    let mosi = peripherals.GPIO9;
+    let sclk = board_pin(BOARD.spi_sclk);
+    let miso = board_pin(BOARD.spi_miso);
+    let mosi = board_pin(BOARD.spi_mosi);
+
+    // Buffer reads and writes go through DMA rather than the 64-byte SPI FIFO
+    let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) =
+        dma_buffers!(config::spi::DMA_BUFFER_LEN);
+    let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer).unwrap();
+    let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).unwrap();
 
     let spi = Spi::new(
         peripherals.SPI2,
         SpiConfig::default()
-            .with_frequency(Rate::from_mhz(1))
+            .with_frequency(Rate::from_mhz(config::spi::FREQUENCY_MHZ))
             .with_mode(SpiMode::_0),
     )
     .unwrap()
     .with_sck(sclk)
     .with_miso(miso)
     .with_mosi(mosi)
+    .with_dma(peripherals.DMA_CH0)
+    .with_buffers(dma_rx_buf, dma_tx_buf)
     .into_async();
 
 
     // Configure LoRa control pins
-    let nss = Output::new(peripherals.GPIO41, Level::High, OutputConfig::default());
-    let dio1 = Input::new(peripherals.GPIO39, InputConfig::default().with_pull(Pull::Down));
-    let nrst = Output::new(peripherals.GPIO42, Level::High, OutputConfig::default());
-    let busy = Input::new(peripherals.GPIO40, InputConfig::default().with_pull(Pull::Down));
+    let nss = Output::new(board_pin(BOARD.lora_nss), Level::High, OutputConfig::default());
+    let mut dio1 = Input::new(board_pin(BOARD.lora_dio1), InputConfig::default().with_pull(Pull::Down));
+    // DIO1 wakes the MCU from a wake-on-LoRa light sleep (Sleep with 0 ms)
+    let _ = dio1.wakeup_enable(true, WakeEvent::HighLevel);
+    let nrst = Output::new(board_pin(BOARD.lora_nrst), Level::High, OutputConfig::default());
+    let busy = Input::new(board_pin(BOARD.lora_busy), InputConfig::default().with_pull(Pull::Down));
+    // External TX/RX switch, on boards that do not drive it from DIO2
+    let rf_switch = BOARD
+        .lora_rf_switch
+        .map(|pin| Output::new(board_pin(pin), Level::Low, OutputConfig::default()));
 
     let lora_pins = Sx1262Pins {
         nss,
         dio1,
         nrst,
         busy,
+        rf_switch,
     };
 
     // Create LoRa driver
     let lora_driver = Sx1262Driver::new(spi, lora_pins);
 
-    // Read unique device ID from eFuse MAC address (last 3 bytes). Used for both
-    // the USB serial and the BLE advertised name so each board is distinct.
+    // Read unique device ID from eFuse MAC address (last 3 bytes). Used for the
+    // USB serial, the BLE advertised name and the LoRa source address so each
+    // board is distinct.
     let mac = esp_hal::efuse::Efuse::read_base_mac_address();
     let device_id: [u8; 3] = [mac[3], mac[4], mac[5]];
     let usb_serial = format_usb_serial(USB_SERIAL.init([0u8; 9]), device_id);
 
+    // Flash access for the VerifyFirmware integrity check, the device label,
+    // the BLE name and the crash log
+    firmware::init(esp_storage::FlashStorage::new(peripherals.FLASH));
+    label::load();
+    ble::name::load();
+    crash_log::init();
+
+    // RTC control, used by the admin task for light sleep
+    let rtc = esp_hal::rtc_cntl::Rtc::new(peripherals.LPWR);
+
+    // Battery sense on the configured ADC1 pin (None if not wired)
+    let battery_sense = match config::battery::SENSE_GPIO {
+        Some(1) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO1))),
+        Some(2) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO2))),
+        Some(3) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO3))),
+        Some(4) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO4))),
+        Some(5) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO5))),
+        Some(6) => Some(leak_sense(battery::AdcBatterySense::new(peripherals.ADC1, peripherals.GPIO6))),
+        _ => None,
+    };
+
     // Configure USB OTG with dual CDC-ACM (data + debug ports)
     let usb = Usb::new(peripherals.USB0, peripherals.GPIO20, peripherals.GPIO19);
 
@@ -152,10 +217,27 @@ fn main() -> ! {
     let usb_device = builder.build();
 
     // Initialise esp-radio for BLE support (must be after esp_rtos::start)
-    let radio_controller = RADIO_CONTROLLER.init(
+    let radio_controller = &*RADIO_CONTROLLER.init(
         esp_radio::init().expect("Failed to initialize esp-radio")
     );
 
+    // WiFi station and network stack for the TCP command server, sharing the
+    // radio with BLE
+    #[cfg(feature = "wifi")]
+    let (wifi_controller, net_stack, net_runner) = {
+        let (controller, interfaces) = esp_radio::wifi::new(radio_controller, peripherals.WIFI, Default::default())
+            .expect("Failed to initialize WiFi");
+        let rng = esp_hal::rng::Rng::new();
+        let seed = (u64::from(rng.random()) << 32) | u64::from(rng.random());
+        let (stack, runner) = embassy_net::new(
+            interfaces.sta,
+            embassy_net::Config::dhcpv4(Default::default()),
+            NET_RESOURCES.init(embassy_net::StackResources::new()),
+            seed,
+        );
+        (controller, stack, runner)
+    };
+
     // Create BLE connector (ownership is passed to ExternalController)
     let ble_connector = esp_radio::ble::controller::BleConnector::new(
         radio_controller,
@@ -170,7 +252,13 @@ fn main() -> ! {
     // Create and run the embassy executor
     let executor = EXECUTOR.init(esp_rtos::embassy::Executor::new());
     executor.run(|spawner| {
-        spawner.must_spawn(async_main(spawner, usb_device, data_cdc, debug_cdc, lora_driver, led, controller, device_id));
+        spawner.must_spawn(async_main(spawner, usb_device, data_cdc, debug_cdc, lora_driver, led, controller, device_id, battery_sense, rtc, wdt));
+        #[cfg(feature = "wifi")]
+        {
+            spawner.must_spawn(wifi_connection_wrapper(wifi_controller));
+            spawner.must_spawn(net_wrapper(net_runner));
+            spawner.must_spawn(wifi_command_wrapper(net_stack, COMMAND_CHANNEL.sender()));
+        }
     })
 }
 
@@ -189,12 +277,29 @@ fn format_usb_serial(buf: &'static mut [u8; 9], device_id: [u8; 3]) -> &'static
     core::str::from_utf8(buf).unwrap_or("WT-000000")
 }
 
+/// GPIO by number, for the pins assigned in `config::board`
+fn board_pin(number: u8) -> AnyPin<'static> {
+    // SAFETY: each board pin is taken exactly once, here at boot, and never
+    // through `peripherals`; `config::board` checks at compile time that the
+    // assignment repeats no pin and avoids the USB and battery sense pins.
+    unsafe { AnyPin::steal(number) }
+}
+
+/// Move a battery sense onto the heap for the lifetime of the program, so the
+/// battery task can take it without knowing which GPIO it reads.
+fn leak_sense<S: battery::BatterySense + 'static>(sense: S) -> &'static mut dyn battery::BatterySense {
+    alloc::boxed::Box::leak(alloc::boxed::Box::new(sense))
+}
+
 /// Type alias for the BLE controller
 type BleController = trouble_host::prelude::ExternalController<
     esp_radio::ble::controller::BleConnector<'static>,
     10,
 >;
 
+/// Type alias for the hardware watchdog used by the task watchdog
+type Watchdog = esp_hal::timer::timg::Wdt<esp_hal::peripherals::TIMG1<'static>>;
+
 /// Type alias for the USB driver
 type UsbDriver = Driver<'static>;
 
@@ -208,15 +313,19 @@ async fn async_main(
     data_cdc: CdcClass,
     debug_cdc: CdcClass,
     lora_driver: Sx1262Driver<
-        esp_hal::spi::master::Spi<'static, Async>,
+        SpiDmaBus<'static, Async>,
         Output<'static>,
         Input<'static>,
         Output<'static>,
         Input<'static>,
+        Output<'static>,
     >,
     led: Output<'static>,
     ble_controller: BleController,
     device_id: [u8; 3],
+    battery_sense: Option<&'static mut dyn battery::BatterySense>,
+    rtc: esp_hal::rtc_cntl::Rtc<'static>,
+    wdt: Watchdog,
 ) {
     // Get channel handles
     let command_sender = COMMAND_CHANNEL.sender();
@@ -238,8 +347,9 @@ async fn async_main(
     spawner.spawn(serial_reader_wrapper(data_reader, command_sender)).unwrap();
     spawner.spawn(serial_writer_wrapper(data_writer)).unwrap();
 
-    // Spawn debug writer task
+    // Spawn debug writer task, and the task copying log lines to flash
     spawner.spawn(debug_writer_wrapper(debug_tx)).unwrap();
+    spawner.spawn(crash_log_wrapper()).unwrap();
 
     // Log startup message
     debug!("Walkie-Textie v{}.{}.{} starting...",
@@ -248,13 +358,20 @@ async fn async_main(
         crate::config::protocol::VERSION_PATCH
     );
     debug!("Device ID: {:02X}{:02X}{:02X}", device_id[0], device_id[1], device_id[2]);
+    debug!("Reset reason: {:#04x}", reset_reason::code());
 
     // Spawn other tasks
     debug!("Starting tasks...");
-    spawner.spawn(admin_wrapper(admin_receiver)).unwrap();
-    spawner.spawn(lora_wrapper(lora_driver, command_receiver, led_sender)).unwrap();
+    spawner.spawn(admin_wrapper(admin_receiver, rtc)).unwrap();
+    spawner.spawn(lora_wrapper(lora_driver, command_receiver, led_sender, device_id)).unwrap();
     spawner.spawn(led_wrapper(led, led_receiver)).unwrap();
-    spawner.spawn(ble_wrapper(ble_controller, device_id)).unwrap();
+    spawner.spawn(ble_wrapper(ble_controller, device_id, LED_CHANNEL.sender())).unwrap();
+    if let Some(sense) = battery_sense {
+        spawner.spawn(battery_wrapper(sense)).unwrap();
+    }
+    spawner.spawn(watchdog_wrapper(wdt)).unwrap();
+    spawner.spawn(beacon_wrapper(COMMAND_CHANNEL.sender(), device_id)).unwrap();
+    spawner.spawn(heartbeat_wrapper()).unwrap();
     debug!("All tasks started");
 }
 
@@ -285,10 +402,23 @@ async fn debug_writer_wrapper(debug_tx: embassy_usb::class::cdc_acm::Sender<'sta
     debug::debug_writer_task(debug_tx).await;
 }
 
-/// Wrapper task for admin commands (reboot, etc.)
+/// Wrapper task for the flash crash log
 #[embassy_executor::task]
-async fn admin_wrapper(receiver: AdminReceiver) {
-    tasks::admin_task(receiver).await;
+async fn crash_log_wrapper() {
+    crash_log::crash_log_task().await;
+}
+
+/// Called by esp-backtrace before it prints a panic, so the next boot keeps
+/// the crash log for the host
+#[no_mangle]
+fn custom_pre_backtrace() {
+    crash_log::mark_panic();
+}
+
+/// Wrapper task for admin commands (reboot, sleep)
+#[embassy_executor::task]
+async fn admin_wrapper(receiver: AdminReceiver, rtc: esp_hal::rtc_cntl::Rtc<'static>) {
+    tasks::admin_task(receiver, rtc).await;
 }
 
 /// Wrapper task for LED control
@@ -299,22 +429,69 @@ async fn led_wrapper(led: Output<'static>, receiver: LedReceiver) {
 
 /// Wrapper task for BLE connectivity
 #[embassy_executor::task]
-async fn ble_wrapper(controller: BleController, device_id: [u8; 3]) {
-    tasks::ble_task(controller, device_id).await;
+async fn ble_wrapper(controller: BleController, device_id: [u8; 3], led_sender: LedSender) {
+    tasks::ble_task(controller, device_id, led_sender).await;
+}
+
+/// Wrapper task for battery monitoring
+#[embassy_executor::task]
+async fn battery_wrapper(sense: &'static mut dyn battery::BatterySense) {
+    tasks::battery_task(sense).await;
+}
+
+/// Wrapper task for the task watchdog
+#[embassy_executor::task]
+async fn watchdog_wrapper(wdt: Watchdog) {
+    tasks::watchdog_task(wdt).await;
+}
+
+/// Wrapper task for presence beacons
+#[embassy_executor::task]
+async fn beacon_wrapper(command_sender: CommandSender, device_id: [u8; 3]) {
+    tasks::beacon_task(command_sender, device_id).await;
+}
+
+/// Wrapper task for host keepalive events
+#[embassy_executor::task]
+async fn heartbeat_wrapper() {
+    tasks::heartbeat_task().await;
+}
+
+/// Wrapper task keeping the WiFi station joined
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn wifi_connection_wrapper(controller: esp_radio::wifi::WifiController<'static>) {
+    tasks::wifi::wifi_connection_task(controller).await;
+}
+
+/// Wrapper task for the WiFi network stack
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn net_wrapper(runner: embassy_net::Runner<'static, esp_radio::wifi::WifiDevice<'static>>) {
+    tasks::wifi::net_task(runner).await
+}
+
+/// Wrapper task for the WiFi command server
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn wifi_command_wrapper(stack: embassy_net::Stack<'static>, command_sender: CommandSender) {
+    tasks::wifi::wifi_command_task(stack, command_sender).await;
 }
 
 /// Wrapper task for LoRa operations
 #[embassy_executor::task]
 async fn lora_wrapper(
     radio: Sx1262Driver<
-        Spi<'static, Async>,
+        SpiDmaBus<'static, Async>,
         Output<'static>,
         Input<'static>,
         Output<'static>,
         Input<'static>,
+        Output<'static>,
     >,
     command_receiver: CommandReceiver,
     led_sender: LedSender,
+    device_id: [u8; 3],
 ) {
-    tasks::lora_task(radio, command_receiver, led_sender).await;
+    tasks::lora_task(radio, command_receiver, led_sender, device_id).await;
 }
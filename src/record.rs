@@ -0,0 +1,85 @@
+//! Small settings kept in flash
+//!
+//! A record is a two-byte magic, a length byte, the value padded with 0xFF
+//! and a CRC32 (LE) of the length and value. The magic tells records apart
+//! and lets erased flash (all 0xFF) read as no value; the CRC catches a write
+//! cut short. The device label and the BLE name are stored this way, side by
+//! side in the `settings` partition.
+
+use crate::crc32::Crc32;
+
+/// Bytes a record adds around its value
+pub const OVERHEAD: usize = 2 + 1 + 4;
+
+/// Fill `record` with `value` under `magic`
+///
+/// `record` must be `OVERHEAD` bytes longer than the longest value it holds,
+/// which must fit the length byte.
+pub fn encode(magic: [u8; 2], value: &[u8], record: &mut [u8]) {
+    record.fill(0xFF);
+    record[..2].copy_from_slice(&magic);
+    record[2] = value.len() as u8;
+    record[3..][..value.len()].copy_from_slice(value);
+    let crc_at = record.len() - 4;
+    let crc = crc(record);
+    record[crc_at..].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Value held in `record` under `magic`, or `None` if it was never written,
+/// holds another record or is corrupt
+pub fn decode(magic: [u8; 2], record: &[u8]) -> Option<&[u8]> {
+    if record.len() < OVERHEAD || record[..2] != magic {
+        return None;
+    }
+    let crc_at = record.len() - 4;
+    let stored = u32::from_le_bytes(record[crc_at..].try_into().ok()?);
+    if stored != crc(record) {
+        return None;
+    }
+    record[3..crc_at].get(..record[2] as usize)
+}
+
+/// CRC32 of a record's length byte and value area
+fn crc(record: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&record[2..record.len() - 4]);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; 2] = *b"WT";
+
+    #[test]
+    fn value_round_trips() {
+        let mut record = [0u8; 8 + OVERHEAD];
+        for value in [&b""[..], b"gate", b"12345678"] {
+            encode(MAGIC, value, &mut record);
+            assert_eq!(decode(MAGIC, &record), Some(value));
+        }
+    }
+
+    #[test]
+    fn erased_foreign_or_corrupt_records_hold_nothing() {
+        let mut record = [0xFF; 8 + OVERHEAD];
+        assert_eq!(decode(MAGIC, &record), None);
+
+        encode(MAGIC, b"gate", &mut record);
+        assert_eq!(decode(*b"XX", &record), None);
+        record[4] ^= 0x01;
+        assert_eq!(decode(MAGIC, &record), None);
+    }
+
+    #[test]
+    fn length_past_the_value_area_holds_nothing() {
+        let mut record = [0u8; 8 + OVERHEAD];
+        encode(MAGIC, b"gate", &mut record);
+        record[2] = 9;
+        let crc_at = record.len() - 4;
+        let crc = crc(&record);
+        record[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(decode(MAGIC, &record), None);
+    }
+}
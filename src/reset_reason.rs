@@ -0,0 +1,45 @@
+//! Why the chip last reset
+//!
+//! `main` reads the reset reason once at boot and keeps it here for
+//! GetResetReason, so it is still available after the tasks have started.
+//! The code is the ESP32-S3's own (esp-hal's `SocResetReason`); the ones
+//! worth telling apart in the field are named below.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// The reason could not be read
+pub const UNKNOWN: u8 = 0x00;
+/// Power-on (or the reset pin)
+pub const POWER_ON: u8 = 0x01;
+/// Software reset, e.g. the Reboot command
+pub const SOFTWARE: u8 = 0x03;
+/// Woken from deep sleep
+pub const DEEP_SLEEP: u8 = 0x05;
+/// Timer group 0 watchdog (the task watchdog)
+pub const TASK_WATCHDOG: u8 = 0x07;
+/// Supply dropped below the brownout threshold
+pub const BROWNOUT: u8 = 0x0F;
+/// RTC watchdog
+pub const RTC_WATCHDOG: u8 = 0x10;
+/// Reset over USB serial/JTAG, e.g. by espflash
+pub const USB_JTAG: u8 = 0x16;
+
+/// Reset reason captured at boot
+static CODE: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// Reset reason captured at boot
+pub fn code() -> u8 {
+    CODE.load(Ordering::Relaxed)
+}
+
+/// Record the reset reason
+pub fn set(code: u8) {
+    CODE.store(code, Ordering::Relaxed);
+}
+
+/// Read the reset reason from the chip and record it. Call once, early in
+/// `main`.
+#[cfg(feature = "embedded")]
+pub fn capture() {
+    set(esp_hal::rtc_cntl::reset_reason(esp_hal::system::Cpu::ProCpu).map_or(UNKNOWN, |reason| reason as u8));
+}
@@ -4,19 +4,35 @@
 
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Channel, Receiver};
+use embassy_sync::signal::Signal;
 #[cfg(feature = "embedded")]
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Timer};
+use wt_protocol::{CommandId, Response};
 
-/// Admin command types
-#[derive(Clone, Copy, Debug)]
-pub enum AdminCommand {
-    /// Normal reboot (restart firmware)
-    Reboot,
-}
+pub use crate::dispatcher::AdminCommand;
 
-/// Channel for admin commands
+/// Channel for admin commands. The serial and BLE readers send reboots here
+/// directly, so one still works while the command channel is backed up
+/// behind slow LoRa transmits.
 pub static ADMIN_CHANNEL: Channel<CriticalSectionRawMutex, AdminCommand, 4> = Channel::new();
 
+/// Raised by the admin task when the MCU wakes from light sleep
+pub static WOKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Raised by an interface writer once the Ack for a Reboot is written and
+/// flushed, so the reset does not cut it off
+pub static REBOOT_ACK_SENT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Longest the admin task waits for `REBOOT_ACK_SENT` before resetting anyway
+/// (the host may have gone, or the reboot came from somewhere with no Ack)
+#[cfg(feature = "embedded")]
+const REBOOT_ACK_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Whether `response` is the Ack the readers send for a Reboot
+pub fn is_reboot_ack(response: &Response) -> bool {
+    matches!(response, Response::Ack { command: CommandId::Reboot })
+}
+
 /// Type alias for the admin command receiver
 pub type AdminReceiver = Receiver<'static, CriticalSectionRawMutex, AdminCommand, 4>;
 
@@ -26,21 +42,51 @@ fn reboot() -> ! {
     esp_hal::system::software_reset()
 }
 
+/// Enter light sleep until the timer expires, or until DIO1 rises when
+/// `duration_ms` is 0 (DIO1 is armed as a wakeup source in `main`).
+///
+/// Blocks the whole chip: no task runs until it returns. USB and BLE stop
+/// while asleep, so hosts see the device go away.
+#[cfg(feature = "embedded")]
+fn light_sleep(rtc: &mut esp_hal::rtc_cntl::Rtc<'static>, duration_ms: u32) {
+    use esp_hal::rtc_cntl::sleep::{GpioWakeupSource, TimerWakeupSource};
+
+    if duration_ms == 0 {
+        rtc.sleep_light(&[&GpioWakeupSource::new()]);
+    } else {
+        let timer = TimerWakeupSource::new(core::time::Duration::from_millis(duration_ms as u64));
+        rtc.sleep_light(&[&timer]);
+    }
+}
+
 /// Admin task that handles system commands
 ///
 /// This task listens for admin commands on the ADMIN_CHANNEL and executes them.
 #[cfg(feature = "embedded")]
-pub async fn admin_task(receiver: AdminReceiver) {
+pub async fn admin_task(receiver: AdminReceiver, mut rtc: esp_hal::rtc_cntl::Rtc<'static>) {
     loop {
         let cmd = receiver.receive().await;
 
         match cmd {
             AdminCommand::Reboot => {
                 crate::debug!("Rebooting...");
-                // Allow the debug message to send
+                // Wait for the requester's interface to get the Ack out
+                let _ = with_timeout(REBOOT_ACK_TIMEOUT, REBOOT_ACK_SENT.wait()).await;
+                // Allow the debug message (and a BLE notification still in
+                // the controller) to send
                 Timer::after(Duration::from_millis(500)).await;
                 reboot();
             }
+            AdminCommand::Sleep { duration_ms } => {
+                crate::debug!("Sleeping ({} ms, 0 = until LoRa RX)...", duration_ms);
+                // Allow the Ack and debug message to send
+                Timer::after(Duration::from_millis(100)).await;
+                light_sleep(&mut rtc, duration_ms);
+                // No task ran while asleep; do not count that as a hang.
+                super::watchdog::pulse_all();
+                crate::debug!("Awake");
+                WOKE.signal(());
+            }
         }
     }
 }
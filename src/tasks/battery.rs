@@ -0,0 +1,30 @@
+//! Battery monitoring task
+//!
+//! Periodically samples the battery voltage and publishes the charge level for
+//! the BLE Battery Service, and the voltage for the LoRa task's low-battery
+//! policy.
+
+use embassy_time::{Duration, Timer};
+
+use crate::battery::{self, BatterySense};
+use crate::ble::battery::LEVEL_UPDATED;
+use crate::config;
+
+/// Sample the battery every `SAMPLE_INTERVAL_SECS` and publish the level
+///
+/// Only spawned when `config::battery::SENSE_GPIO` is set; otherwise the level
+/// stays at its initial 100%.
+pub async fn battery_task(sense: &'static mut dyn BatterySense) {
+    loop {
+        let pin_mv = sense.read_pin_mv();
+        let battery_mv = battery::battery_mv(pin_mv);
+        let percent = battery::percent_from_mv(battery_mv);
+
+        crate::trace!("Battery: {} mV ({}%)", battery_mv, percent);
+        battery::set_millivolts(battery_mv);
+        battery::set_level(percent);
+        LEVEL_UPDATED.signal(percent);
+
+        Timer::after(Duration::from_secs(config::battery::SAMPLE_INTERVAL_SECS)).await;
+    }
+}
@@ -0,0 +1,71 @@
+//! Beacon task for periodic presence announcements
+//!
+//! When enabled with SetBeaconInterval, broadcasts a small beacon so other
+//! nodes can build a neighbour list. Beacons are queued as ordinary LoraTx
+//! commands, so they share the TX path (encryption, addressing) and the
+//! duty-cycle budget with host traffic. Off by default.
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+
+use crate::config::protocol::{VERSION_MAJOR, VERSION_MINOR, VERSION_PATCH};
+use crate::dispatcher::{CommandEnvelope, CommandSource, BEACON_INTERVAL};
+use crate::lora::addressing::Address;
+use wt_protocol::Command;
+
+use super::serial::CommandSender;
+
+/// First byte of a beacon payload, so hosts can tell beacons from messages
+pub const BEACON_MAGIC: u8 = 0xBE;
+
+/// Beacon payload: magic, device id (3 bytes), firmware major/minor/patch
+fn beacon_payload(device_id: Address) -> [u8; 7] {
+    [
+        BEACON_MAGIC,
+        device_id[0],
+        device_id[1],
+        device_id[2],
+        VERSION_MAJOR,
+        VERSION_MINOR,
+        VERSION_PATCH,
+    ]
+}
+
+/// Task that queues a broadcast beacon every beacon interval
+///
+/// Idles until SetBeaconInterval enables it; a new interval restarts the
+/// timer. A beacon is skipped (not queued late) if the command channel is
+/// full.
+pub async fn beacon_task(command_sender: CommandSender, device_id: Address) {
+    let mut interval_secs = 0;
+
+    loop {
+        if interval_secs == 0 {
+            interval_secs = BEACON_INTERVAL.wait().await;
+            continue;
+        }
+
+        match select(
+            BEACON_INTERVAL.wait(),
+            Timer::after(Duration::from_secs(interval_secs as u64)),
+        )
+        .await
+        {
+            Either::First(new_interval) => interval_secs = new_interval,
+            Either::Second(()) => {
+                let mut data = Vec::new();
+                let _ = data.extend_from_slice(&beacon_payload(device_id));
+                let envelope = CommandEnvelope {
+                    command: Command::LoraTx { data },
+                    source: CommandSource::Internal,
+                    sequence_id: 0,
+                    host_sequence: None,
+                };
+                if command_sender.try_send(envelope).is_err() {
+                    crate::debug!("Beacon: Command queue full, skipped");
+                }
+            }
+        }
+    }
+}
@@ -3,63 +3,102 @@
 //! Implements the BLE host task that manages connections and routes
 //! commands/responses through the Nordic UART Service.
 
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use core::cell::Cell;
+
+use embassy_futures::select::{select, select4, select_array, Either, Either4};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
 use trouble_host::prelude::*;
 
+use crate::battery;
+use crate::ble::battery::{BatteryService, LEVEL_UPDATED};
+use crate::ble::debug_log::{DebugLogService, LOG_MAX_PACKET_SIZE};
+use crate::ble::device_info::{self, DeviceInfoService};
+use crate::ble::name::{self, BleName, NAME_CHANGED};
 use crate::ble::service::{NordicUartService, NUS_MAX_PACKET_SIZE};
 use crate::config;
-use crate::dispatcher::{CommandEnvelope, CommandSource, ResponseMessage, COMMAND_CHANNEL, RESPONSE_CHANNEL};
-use wt_protocol::{Command, FrameAccumulator, Response, ResponseStatus};
+use crate::dispatcher::{
+    reset_interface, response_trailer, BleReader, CommandSource, ResponseMessage, ResponseSubscriber, COMMAND_CHANNEL,
+    RESPONSE_CHANNEL,
+};
+use crate::led::LedPattern;
+use super::admin::{is_reboot_ack, ADMIN_CHANNEL, REBOOT_ACK_SENT};
+use super::watchdog::{self, WatchedTask};
+use super::LedSender;
+use crate::framing;
+use wt_protocol::{Event, Response};
 
 /// Device name prefix for BLE advertising
 const DEVICE_NAME_PREFIX: &str = "WalkieTextie-";
 
-/// Format device ID bytes as uppercase hex into a buffer
-/// Returns the formatted string slice
-fn format_device_name<'a>(buf: &'a mut [u8; 20], device_id: &[u8; 3]) -> &'a str {
+/// Build the default device name: the prefix followed by the device ID as
+/// uppercase hex
+fn format_device_name(device_id: &[u8; 3]) -> BleName {
     const HEX_CHARS: &[u8; 16] = b"0123456789ABCDEF";
-    let prefix = DEVICE_NAME_PREFIX.as_bytes();
-
-    // Copy prefix
-    buf[..prefix.len()].copy_from_slice(prefix);
+    let mut name = BleName::new();
 
-    // Format 3 bytes as 6 hex characters
-    let mut pos = prefix.len();
+    // Prefix plus 6 hex characters always fits in MAX_NAME_LEN
+    let _ = name.push_str(DEVICE_NAME_PREFIX);
     for &byte in device_id {
-        buf[pos] = HEX_CHARS[(byte >> 4) as usize];
-        buf[pos + 1] = HEX_CHARS[(byte & 0x0F) as usize];
-        pos += 2;
+        let _ = name.push(HEX_CHARS[(byte >> 4) as usize] as char);
+        let _ = name.push(HEX_CHARS[(byte & 0x0F) as usize] as char);
     }
-
-    // All bytes are ASCII, so this will always succeed
-    core::str::from_utf8(&buf[..pos]).unwrap_or(DEVICE_NAME_PREFIX)
+    name
 }
 
 /// Number of maximum concurrent connections
-const CONNECTIONS_MAX: usize = 1;
-/// Number of L2CAP channels
-const L2CAP_CHANNELS_MAX: usize = 3;
+const CONNECTIONS_MAX: usize = config::ble::MAX_CONNECTIONS;
+/// Number of L2CAP channels (three per connection)
+const L2CAP_CHANNELS_MAX: usize = 3 * CONNECTIONS_MAX;
 
-/// BLE GATT Server with Nordic UART Service
+/// BLE GATT Server with Nordic UART Service, Battery Service, Device
+/// Information Service and Debug Log Service
 #[gatt_server(mutex_type = CriticalSectionRawMutex)]
 struct Server {
     nus: NordicUartService,
+    battery: BatteryService,
+    device_info: DeviceInfoService,
+    debug_log: DebugLogService,
+}
+
+/// A connected client, held in one of the `CONNECTIONS_MAX` slots
+struct Client<'stack, 'server> {
+    conn: GattConnection<'stack, 'server, DefaultPacketPool>,
+    /// This client's own response subscriber, dropped with the client so
+    /// messages don't queue up for it after it disconnects
+    responses: ResponseSubscriber,
+    reader: BleReader<'static>,
+}
+
+/// Next thing to happen on a client's connection
+enum ClientEvent<'stack, 'server> {
+    Gatt(GattConnectionEvent<'stack, 'server, DefaultPacketPool>),
+    Response(ResponseMessage),
 }
 
 /// Main BLE task that manages the Bluetooth stack and connections
 ///
 /// This task:
 /// 1. Initialises the BLE controller
-/// 2. Starts advertising as "WalkieTextie-XXXXXX" (unique per device)
-/// 3. Handles connections and GATT events
-/// 4. Routes received data to COMMAND_CHANNEL
-/// 5. Sends responses via notifications
-pub async fn ble_task<C: Controller>(controller: C, device_id: [u8; 3]) {
-    // Generate unique device name from chip ID
-    let mut device_name_buf = [0u8; 20];
-    let device_name = format_device_name(&mut device_name_buf, &device_id);
-
-    crate::debug!("BLE: Starting as '{}'", device_name);
+/// 2. Advertises as "WalkieTextie-XXXXXX" (unique per device), or the name set
+///    by the host with SetBleName, for as long as a connection slot is free
+/// 3. Handles up to `CONNECTIONS_MAX` connections and their GATT events
+/// 4. Routes received data to COMMAND_CHANNEL, tagged with the connection slot
+/// 5. Sends command responses to the originating connection and unsolicited
+///    packets to every connection
+/// 6. Notifies the battery level whenever the battery task takes a sample
+/// 7. Shows the connection state on the LED
+/// 8. Streams the debug log to connected clients
+pub async fn ble_task<C: Controller>(controller: C, device_id: [u8; 3], led_sender: LedSender) {
+    // Generate unique default device name from chip ID
+    let default_name = format_device_name(&device_id);
+
+    let serial_number = device_info::serial_number(&device_id);
+    let firmware_revision = device_info::firmware_revision();
+
+    crate::debug!("BLE: Starting as '{}'", default_name);
 
     // Create BLE host resources
     let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
@@ -78,187 +117,322 @@ pub async fn ble_task<C: Controller>(controller: C, device_id: [u8; 3]) {
         ..
     } = stack.build();
 
-    // Create GATT server with GAP configuration
-    let gap = GapConfig::Peripheral(PeripheralConfig {
-        name: device_name,
-        appearance: &appearance::UNKNOWN,
-    });
-    let server: Server = match Server::new_with_config(gap) {
-        Ok(s) => s,
-        Err(_) => return,
-    };
-
     // Run both the BLE runner and peripheral logic concurrently using select
     let runner_task = runner.run();
 
     let peripheral_task = async {
-        let mut adv_data = [0u8; 31];
-        let len = match AdStructure::encode_slice(
-            &[
-                AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-                AdStructure::CompleteLocalName(device_name.as_bytes()),
-            ],
-            &mut adv_data,
-        ) {
-            Ok(l) => l,
-            Err(_) => return,
-        };
-
-        // Shared state for command processing
-        let command_sender = COMMAND_CHANNEL.sender();
+        // Idle waits time out after this long so the watchdog keeps getting pulsed
+        let idle = Duration::from_secs(config::watchdog::IDLE_PULSE_SECS);
 
         loop {
-            // Start advertising
-            crate::debug!("BLE: Advertising...");
-            let advertiser = match peripheral
-                .advertise(
-                    &Default::default(),
-                    Advertisement::ConnectableScannableUndirected {
-                        adv_data: &adv_data[..len],
-                        scan_data: &[],
-                    },
-                )
-                .await
-            {
-                Ok(a) => a,
-                Err(_) => continue,
-            };
-
-            // Wait for connection
-            let acceptor = match advertiser.accept().await {
-                Ok(a) => {
-                    crate::debug!("BLE: Connected");
-                    a
-                }
-                Err(_) => continue,
+            watchdog::pulse(WatchedTask::Ble);
+
+            // Pick up any name set by the host since the last cycle
+            NAME_CHANGED.reset();
+            let device_name = name::custom_name().unwrap_or_else(|| default_name.clone());
+
+            // Create GATT server with GAP configuration (rebuilt each cycle so the
+            // GAP device name matches the advertised one)
+            let gap = GapConfig::Peripheral(PeripheralConfig {
+                name: device_name.as_str(),
+                appearance: &appearance::UNKNOWN,
+            });
+            let server: Server = match Server::new_with_config(gap) {
+                Ok(s) => s,
+                Err(_) => return,
             };
-
-            // Attach to attribute server (using Deref to get &AttributeServer)
-            let conn = match acceptor.with_attribute_server(&*server) {
-                Ok(c) => c,
-                Err(_) => continue,
+            let _ = server.set(&server.battery.level, &battery::level());
+            let _ = server.set(&server.device_info.serial_number, &serial_number);
+            let _ = server.set(&server.device_info.firmware_revision, &firmware_revision);
+
+            let mut adv_data = [0u8; 31];
+            let len = match AdStructure::encode_slice(
+                &[
+                    AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+                    AdStructure::CompleteLocalName(device_name.as_bytes()),
+                ],
+                &mut adv_data,
+            ) {
+                Ok(l) => l,
+                Err(_) => return,
             };
 
-            // Handle this connection
-            let mut accumulator = FrameAccumulator::new();
-            let mut sequence_id: u16 = 0;
+            // Connections accepted by the advertiser, waiting for a client slot
+            let accepted: Channel<NoopRawMutex, GattConnection<'_, '_, DefaultPacketPool>, 1> = Channel::new();
+            // Clients connected or accepted; the advertiser pauses when all
+            // slots are taken, so a slot is always free for an accepted one
+            let connected = Cell::new(0usize);
+            let slot_freed: Signal<NoopRawMutex, ()> = Signal::new();
+
+            // Keep advertising while a connection slot is free
+            let advertise = async {
+                loop {
+                    while connected.get() >= CONNECTIONS_MAX {
+                        slot_freed.wait().await;
+                    }
 
-            // Subscribe to unified response channel for this connection
-            // Subscriber is dropped when connection ends, so messages don't queue up
-            let mut response_sub = match RESPONSE_CHANNEL.subscriber() {
-                Ok(s) => s,
-                Err(_) => continue,  // No subscriber slots available
+                    crate::debug!("BLE: Advertising as '{}'...", device_name);
+                    let advertiser = match peripheral
+                        .advertise(
+                            &Default::default(),
+                            Advertisement::ConnectableScannableUndirected {
+                                adv_data: &adv_data[..len],
+                                scan_data: &[],
+                            },
+                        )
+                        .await
+                    {
+                        Ok(a) => a,
+                        Err(_) => {
+                            Timer::after(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    };
+
+                    let acceptor = match advertiser.accept().await {
+                        Ok(a) => a,
+                        Err(_) => continue,
+                    };
+
+                    // Attach to attribute server (using Deref to get &AttributeServer)
+                    let conn = match acceptor.with_attribute_server(&*server) {
+                        Ok(c) => c,
+                        Err(_) => continue,
+                    };
+                    connected.set(connected.get() + 1);
+                    accepted.send(conn).await;
+                }
             };
 
-            loop {
-                // Use select to handle GATT events and response messages
-                let gatt_future = conn.next();
-                let response_future = response_sub.next_message_pure();
-
-                match embassy_futures::select::select(gatt_future, response_future).await {
-                    embassy_futures::select::Either::First(gatt_event) => {
-                        match gatt_event {
-                            GattConnectionEvent::Disconnected { reason: _ } => {
-                                crate::debug!("BLE: Disconnected");
-                                break;
+            // Serve every connected client; returns when the name changes so
+            // the server is rebuilt and the new name advertised
+            let serve = async {
+                let mut clients: [Option<Client<'_, '_>>; CONNECTIONS_MAX] = core::array::from_fn(|_| None);
+
+                loop {
+                    watchdog::pulse(WatchedTask::Ble);
+
+                    // Use select to handle new connections, client events (GATT
+                    // and response messages), name changes, battery updates and
+                    // debug output. The name change is polled after the clients
+                    // so a pending SetBleName ack is notified before the
+                    // connections are dropped.
+                    let new_client_future = accepted.receive();
+                    let client_future = select_array(clients.each_mut().map(next_client_event));
+                    let name_future = NAME_CHANGED.wait();
+                    let status_future = select(LEVEL_UPDATED.wait(), crate::debug::next_ble_message());
+                    let event = with_timeout(
+                        idle,
+                        select4(new_client_future, client_future, name_future, status_future),
+                    );
+
+                    // An idle timeout just loops round to pulse the watchdog
+                    let Ok(event) = event.await else {
+                        continue;
+                    };
+                    match event {
+                        Either4::First(conn) => {
+                            // Subscribe to the unified response channel for this connection.
+                            // RESPONSE_SUBSCRIBERS has a slot for every connection and the
+                            // advertiser waits for a free client slot, so neither should
+                            // run out; if one does, hang up rather than leave the client
+                            // connected to nothing.
+                            let index = clients.iter().position(|c| c.is_none());
+                            let (Some(index), Ok(responses)) = (index, RESPONSE_CHANNEL.subscriber()) else {
+                                crate::error!("BLE: No client slot or response subscriber free, refusing connection");
+                                conn.raw().disconnect();
+                                connected.set(connected.get() - 1);
+                                slot_freed.signal(());
+                                continue;
+                            };
+
+                            crate::debug!("BLE: Connected ({} of {})", connected.get(), CONNECTIONS_MAX);
+                            publish_event(Event::BleConnected { slot: index as u8 });
+                            // The central normally opens with an Exchange MTU request, which the
+                            // host answers with the largest MTU the packet pool supports.
+                            crate::trace!("BLE: ATT MTU {}", conn.raw().att_mtu());
+                            if connected.get() == 1 {
+                                let _ = led_sender.try_send(LedPattern::BleConnected);
+                                crate::debug::set_ble_forwarding(true);
                             }
-                            GattConnectionEvent::Gatt { event } => {
-                                match event {
-                                    GattEvent::Write(write_event) => {
-                                        // Check if this is a write to the RX characteristic
-                                        if write_event.handle() == server.nus.rx.handle {
-                                            let data = write_event.data();
-
-                                            // Process each byte through the accumulator
-                                            for &byte in data {
-                                                if let Some(frame) = accumulator.push(byte) {
-                                                    sequence_id = sequence_id.wrapping_add(1);
-
-                                                    // Decode COBS and parse command
-                                                    match decode_and_parse(frame) {
-                                                        Ok(command) => {
-                                                            let envelope = CommandEnvelope {
-                                                                command,
-                                                                source: CommandSource::Ble,
-                                                                sequence_id,
-                                                            };
-                                                            let _ = command_sender.try_send(envelope);
-                                                        }
-                                                        Err(response) => {
-                                                            // Send error response directly via notification
-                                                            let encoded = wt_protocol::encode_response(&response);
-                                                            let mut tx_buf = [0u8; NUS_MAX_PACKET_SIZE];
-                                                            let len = encoded.len().min(tx_buf.len());
-                                                            tx_buf[..len].copy_from_slice(&encoded[..len]);
-                                                            let _ = server.nus.tx.notify(&conn, &tx_buf).await;
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        // Accept the write
-                                        let _ = write_event.accept();
-                                    }
-                                    GattEvent::Read(read_event) => {
-                                        let _ = read_event.accept();
+
+                            clients[index] = Some(Client {
+                                conn,
+                                responses,
+                                reader: BleReader::new(index as u8, COMMAND_CHANNEL.dyn_sender(), ADMIN_CHANNEL.dyn_sender()),
+                            });
+                        }
+                        Either4::Second((client_event, index)) => {
+                            let Some(client) = clients[index].as_mut() else {
+                                continue;
+                            };
+                            match client_event {
+                                ClientEvent::Gatt(GattConnectionEvent::Disconnected { reason: _ }) => {
+                                    clients[index] = None;
+                                    reset_interface(CommandSource::Ble(index as u8));
+                                    connected.set(connected.get() - 1);
+                                    slot_freed.signal(());
+                                    crate::debug!("BLE: Disconnected ({} left)", connected.get());
+                                    publish_event(Event::BleDisconnected { slot: index as u8 });
+                                    if connected.get() == 0 {
+                                        crate::debug::set_ble_forwarding(false);
+                                        let _ = led_sender.try_send(LedPattern::Idle);
                                     }
-                                    GattEvent::Other(other_event) => {
-                                        let _ = other_event.accept();
+                                }
+                                ClientEvent::Gatt(GattConnectionEvent::Gatt { event }) => {
+                                    handle_gatt_event(&server, client, index as u8, event).await;
+                                }
+                                ClientEvent::Gatt(_) => {}
+                                ClientEvent::Response(msg) => {
+                                    // This connection's command responses and
+                                    // every unsolicited packet
+                                    if let Some((response, trailer)) = msg.for_interface(CommandSource::Ble(index as u8)) {
+                                        let encoded = framing::encode_response(&response, trailer);
+                                        notify_frame(&server, &client.conn, &encoded).await;
                                     }
                                 }
                             }
-                            _ => {}
                         }
-                    }
-                    embassy_futures::select::Either::Second(msg) => {
-                        // Filter and process response messages
-                        let response = match msg {
-                            ResponseMessage::Command { source, response, .. } => {
-                                // Only process responses for BLE source
-                                if source == CommandSource::Ble {
-                                    Some(response)
-                                } else {
-                                    None
+                        Either4::Third(()) => {
+                            // Drop every link so the new name is advertised
+                            crate::debug!("BLE: Name changed, disconnecting to re-advertise");
+                            for (index, client) in clients.iter().enumerate() {
+                                if let Some(client) = client {
+                                    client.conn.raw().disconnect();
+                                    reset_interface(CommandSource::Ble(index as u8));
+                                    publish_event(Event::BleDisconnected { slot: index as u8 });
                                 }
                             }
-                            ResponseMessage::Unsolicited(response) => {
-                                // Always process unsolicited packets
-                                Some(response)
+                            if let Ok(conn) = accepted.try_receive() {
+                                conn.raw().disconnect();
+                            }
+                            if connected.get() > 0 {
+                                crate::debug::set_ble_forwarding(false);
+                                let _ = led_sender.try_send(LedPattern::Idle);
+                            }
+                            return;
+                        }
+                        Either4::Fourth(Either::First(level)) => {
+                            // Notify only reaches clients that enabled it; the stored
+                            // value is updated either way for reads.
+                            let _ = server.set(&server.battery.level, &level);
+                            for client in clients.iter().flatten() {
+                                let _ = server.battery.level.notify(&client.conn, &level).await;
+                            }
+                        }
+                        Either4::Fourth(Either::Second(line)) => {
+                            // Likewise dropped unless the client subscribed to the log
+                            for client in clients.iter().flatten() {
+                                notify_log_line(&server, &client.conn, line.as_bytes()).await;
                             }
-                        };
-
-                        if let Some(response) = response {
-                            let encoded = wt_protocol::encode_response(&response);
-                            let mut tx_buf = [0u8; NUS_MAX_PACKET_SIZE];
-                            let len = encoded.len().min(tx_buf.len());
-                            tx_buf[..len].copy_from_slice(&encoded[..len]);
-                            let _ = server.nus.tx.notify(&conn, &tx_buf).await;
                         }
                     }
                 }
-            }
-            // response_sub dropped here - no longer receiving broadcasts
+            };
+
+            select(advertise, serve).await;
         }
     };
 
-    embassy_futures::select::select(runner_task, peripheral_task).await;
+    select(runner_task, peripheral_task).await;
 }
 
-/// Decode a COBS frame (delimiter included) and parse it into a command.
-fn decode_and_parse(
-    frame: heapless::Vec<u8, { config::protocol::MAX_FRAME_SIZE }>,
-) -> Result<Command, Response> {
-    let decoded = match wt_protocol::cobs_decode(&frame) {
-        Ok(d) => d,
-        Err(_) => return Err(Response::error_raw(ResponseStatus::CrcError, 0x00)),
+/// Tell every interface that a BLE client connected or disconnected
+fn publish_event(event: Event) {
+    RESPONSE_CHANNEL
+        .immediate_publisher()
+        .publish_immediate(ResponseMessage::Unsolicited(Response::Event(event)));
+}
+
+/// Wait for the next event on a client slot (never completes for an empty one)
+async fn next_client_event<'stack, 'server>(slot: &mut Option<Client<'stack, 'server>>) -> ClientEvent<'stack, 'server> {
+    let Some(client) = slot else {
+        return core::future::pending().await;
     };
+    match select(client.conn.next(), client.responses.next_message_pure()).await {
+        Either::First(event) => ClientEvent::Gatt(event),
+        Either::Second(msg) => ClientEvent::Response(msg),
+    }
+}
 
-    if decoded.is_empty() {
-        return Err(Response::error_raw(ResponseStatus::InvalidLength, 0x00));
+/// Handle a GATT event from the client in `slot`.
+///
+/// Writes to the NUS RX characteristic go through the client's `BleReader`,
+/// which queues the commands; the replies it makes itself (parse errors,
+/// `Busy`, the Reboot Ack) are notified directly.
+async fn handle_gatt_event(
+    server: &Server<'_>,
+    client: &mut Client<'_, '_>,
+    slot: u8,
+    event: GattEvent<'_, '_, DefaultPacketPool>,
+) {
+    match event {
+        GattEvent::Write(write_event) => {
+            // Check if this is a write to the RX characteristic
+            if write_event.handle() == server.nus.rx.handle {
+                for &byte in write_event.data() {
+                    let Some(reply) = client.reader.push(byte) else {
+                        continue;
+                    };
+                    let trailer = response_trailer(CommandSource::Ble(slot), reply.sequence_id, reply.host_sequence);
+                    let encoded = framing::encode_response(&reply.response, trailer);
+                    notify_frame(server, &client.conn, &encoded).await;
+                    // The admin task resets once the Ack has been notified
+                    if is_reboot_ack(&reply.response) {
+                        REBOOT_ACK_SENT.signal(());
+                    }
+                }
+            }
+            // Accept the write
+            let _ = write_event.accept();
+        }
+        GattEvent::Read(read_event) => {
+            let _ = read_event.accept();
+        }
+        GattEvent::Other(other_event) => {
+            let _ = other_event.accept();
+        }
     }
+}
 
-    // Byte 1 is the command id (byte 0 is the protocol version); echoed back on error.
-    let command_id = decoded.get(1).copied().unwrap_or(0);
+/// Notify an encoded frame on the NUS TX characteristic.
+///
+/// Sends the frame in one notification when it fits the negotiated ATT MTU,
+/// otherwise splits it across several; the client joins them back up at the
+/// frame boundary.
+async fn notify_frame<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>, frame: &[u8]) {
+    // ATT notifications carry MTU - 3 bytes of value
+    let mtu_payload = (conn.raw().att_mtu() as usize).saturating_sub(3);
+    let chunk_len = mtu_payload.clamp(1, NUS_MAX_PACKET_SIZE);
+
+    for chunk in frame.chunks(chunk_len) {
+        let mut value: heapless::Vec<u8, NUS_MAX_PACKET_SIZE> = heapless::Vec::new();
+        // chunk_len never exceeds the capacity
+        let _ = value.extend_from_slice(chunk);
+        if server.nus.tx.notify(conn, &value).await.is_err() {
+            break;
+        }
+    }
+}
 
-    wt_protocol::parse_command(&decoded).map_err(|status| Response::error_raw(status, command_id))
+/// Notify a debug log line on the Debug Log characteristic.
+///
+/// The line is newline-terminated and split to fit the negotiated ATT MTU,
+/// like `notify_frame`.
+async fn notify_log_line<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>, line: &[u8]) {
+    let mut message: heapless::Vec<u8, LOG_MAX_PACKET_SIZE> = heapless::Vec::new();
+    // Debug messages are at most LOG_MAX_PACKET_SIZE - 1 bytes
+    let _ = message.extend_from_slice(line);
+    let _ = message.push(b'\n');
+
+    let mtu_payload = (conn.raw().att_mtu() as usize).saturating_sub(3);
+    let chunk_len = mtu_payload.clamp(1, LOG_MAX_PACKET_SIZE);
+
+    for chunk in message.chunks(chunk_len) {
+        let mut value: heapless::Vec<u8, LOG_MAX_PACKET_SIZE> = heapless::Vec::new();
+        let _ = value.extend_from_slice(chunk);
+        if server.debug_log.log.notify(conn, &value).await.is_err() {
+            break;
+        }
+    }
 }
@@ -0,0 +1,44 @@
+//! Heartbeat task for host keepalive
+//!
+//! When enabled with SetHeartbeatInterval, publishes an unsolicited Heartbeat
+//! event carrying the uptime, so hosts on serial and BLE can tell the device
+//! is alive without polling. It only publishes to the response channel and
+//! never touches the radio, so RX is unaffected. Off by default.
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::dispatcher::{ResponseMessage, HEARTBEAT_INTERVAL, RESPONSE_CHANNEL};
+use wt_protocol::{Event, Response};
+
+/// Task that publishes a Heartbeat event every heartbeat interval
+///
+/// Idles until SetHeartbeatInterval enables it; a new interval restarts the
+/// timer.
+pub async fn heartbeat_task() {
+    let publisher = RESPONSE_CHANNEL.immediate_publisher();
+    let mut interval_secs = 0;
+
+    loop {
+        if interval_secs == 0 {
+            interval_secs = HEARTBEAT_INTERVAL.wait().await;
+            continue;
+        }
+
+        match select(
+            HEARTBEAT_INTERVAL.wait(),
+            Timer::after(Duration::from_secs(interval_secs as u64)),
+        )
+        .await
+        {
+            Either::First(new_interval) => interval_secs = new_interval,
+            Either::Second(()) => {
+                // Wraps after about 49 days; hosts only need it to advance
+                let uptime_ms = Instant::now().as_millis() as u32;
+                publisher.publish_immediate(ResponseMessage::Unsolicited(Response::Event(Event::Heartbeat {
+                    uptime_ms,
+                })));
+            }
+        }
+    }
+}
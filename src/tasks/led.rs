@@ -1,47 +1,107 @@
-//! LED task for non-blocking LED control
+//! LED task for non-blocking LED status patterns
 //!
-//! Handles LED flashing operations without blocking other tasks.
+//! Runs a small state machine so the LED shows connection and error state at a
+//! glance, with short flashes overlaid for TX/RX activity. The host can take
+//! the LED over with SetLed to pick out one device among many. The LED is
+//! active low and normally on.
 
+use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Receiver, Sender};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::gpio::Output;
 
-/// Duration of LED flash in milliseconds
-const LED_FLASH_MS: u64 = 50;
-
-/// LED flash duration configuration
-#[derive(Clone, Copy)]
-pub enum LedFlashDuration {
-    /// Use the default flash duration
-    Default,
-    /// Use a custom flash duration in milliseconds
-    #[allow(dead_code)]
-    Ms(u64),
-}
+use crate::led::LedPattern;
 
-/// Type alias for the LED flash channel sender
-pub type LedSender = Sender<'static, CriticalSectionRawMutex, LedFlashDuration, 4>;
+/// Type alias for the LED pattern channel sender
+pub type LedSender = Sender<'static, CriticalSectionRawMutex, LedPattern, 4>;
 
-/// Type alias for the LED flash channel receiver
-pub type LedReceiver = Receiver<'static, CriticalSectionRawMutex, LedFlashDuration, 4>;
+/// Type alias for the LED pattern channel receiver
+pub type LedReceiver = Receiver<'static, CriticalSectionRawMutex, LedPattern, 4>;
 
-/// Channel for LED flash signals
-pub static LED_CHANNEL: embassy_sync::channel::Channel<CriticalSectionRawMutex, LedFlashDuration, 4> =
+/// Channel for LED pattern requests
+pub static LED_CHANNEL: embassy_sync::channel::Channel<CriticalSectionRawMutex, LedPattern, 4> =
     embassy_sync::channel::Channel::new();
 
-/// Task that handles LED flashing without blocking other operations
+/// Task that plays LED patterns without blocking other operations
+///
+/// Holds each step for its duration while also waiting on the channel, so a
+/// new request takes effect immediately.
 pub async fn led_task(mut led: Output<'static>, receiver: LedReceiver) {
+    // Idle or BleConnected, whatever the error state
+    let mut background = LedPattern::Idle;
+    // Shown in place of the background until ClearError
+    let mut error = None;
+    let mut current = background;
+    let mut index = 0;
+    // Set while the host holds the LED with SetLed
+    let mut manual = false;
+
     loop {
-        // Wait for flash signal
-        let flash_duration = receiver.receive().await;
-        let duration_ms = match flash_duration {
-            LedFlashDuration::Default => LED_FLASH_MS,
-            LedFlashDuration::Ms(ms) => ms,
-        };
-
-        // Flash LED (turn off then back on, since active low)
-        led.set_high(); // LED off
-        embassy_time::Timer::after(embassy_time::Duration::from_millis(duration_ms)).await;
-        led.set_low(); // LED on
+        if index >= current.len() {
+            // A one-shot flash returns to the background; others repeat.
+            if current.is_transient() {
+                current = error.unwrap_or(background);
+            }
+            index = 0;
+        }
+
+        let (lit, hold_ms) = current.step(index);
+        if lit {
+            led.set_low();
+        } else {
+            led.set_high();
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(hold_ms);
+        loop {
+            let shown = match select(receiver.receive(), Timer::at(deadline)).await {
+                // The host can take the LED over even from an error
+                Either::First(pattern) if pattern.is_manual() => {
+                    manual = true;
+                    pattern
+                }
+                Either::First(LedPattern::Auto) => {
+                    manual = false;
+                    error.unwrap_or(background)
+                }
+                Either::First(pattern @ LedPattern::Error(_)) => {
+                    // A repeated report must not restart the blink count
+                    let repeated = error == Some(pattern);
+                    error = Some(pattern);
+                    if manual || repeated {
+                        continue;
+                    }
+                    pattern
+                }
+                Either::First(LedPattern::ClearError) => {
+                    error = None;
+                    if manual {
+                        continue;
+                    }
+                    background
+                }
+                // Background changes are kept for when the host lets go of
+                // the LED or the error clears
+                Either::First(pattern) if !pattern.is_transient() => {
+                    background = pattern;
+                    if manual || error.is_some() {
+                        continue;
+                    }
+                    pattern
+                }
+                // Flashes are dropped while the host holds the LED or an
+                // error is showing
+                Either::First(_) if manual || error.is_some() => continue,
+                Either::First(pattern) => pattern,
+                Either::Second(()) => {
+                    index += 1;
+                    break;
+                }
+            };
+            current = shown;
+            index = 0;
+            break;
+        }
     }
 }
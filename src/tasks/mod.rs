@@ -3,13 +3,23 @@
 //! Contains all async tasks for the firmware, organised by functionality.
 
 pub mod admin;
+pub mod battery;
+pub mod beacon;
 pub mod ble;
+pub mod heartbeat;
 pub mod led;
 pub mod lora;
 pub mod serial;
+pub mod watchdog;
+#[cfg(feature = "wifi")]
+pub mod wifi;
 
 pub use admin::{admin_task, AdminReceiver, ADMIN_CHANNEL};
+pub use battery::battery_task;
+pub use beacon::beacon_task;
 pub use ble::ble_task;
+pub use heartbeat::heartbeat_task;
 pub use led::{led_task, LedReceiver, LedSender, LED_CHANNEL};
 pub use lora::lora_task;
 pub use serial::{serial_reader_task, serial_writer_task, CommandReceiver, CommandSender};
+pub use watchdog::watchdog_task;
@@ -6,19 +6,12 @@
 
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Receiver, Sender};
+use embassy_time::{Duration, Timer};
 use embedded_io_async::{Read, Write};
 
-use crate::config;
-use crate::dispatcher::{CommandEnvelope, CommandSource, ResponseMessage, RESPONSE_CHANNEL};
-use wt_protocol::{Command, FrameAccumulator, Response, ResponseStatus};
-
-/// Result of attempting to parse a frame
-enum ReadResult {
-    /// Successfully parsed a command
-    Command(Command),
-    /// Parse error (should send error response)
-    ParseError(ResponseStatus, u8),
-}
+use crate::dispatcher::{CommandEnvelope, CommandSource, FrameReader, RESPONSE_CHANNEL};
+use super::admin::{self, ADMIN_CHANNEL, REBOOT_ACK_SENT};
+use super::watchdog::{self, WatchedTask};
 
 /// Type alias for the command channel sender
 pub type CommandSender = Sender<'static, CriticalSectionRawMutex, CommandEnvelope, 8>;
@@ -30,83 +23,27 @@ pub type CommandReceiver = Receiver<'static, CriticalSectionRawMutex, CommandEnv
 ///
 /// Generic over any type implementing `embedded_io_async::Read`.
 pub async fn serial_reader_task<R: Read>(
-    mut reader: R,
+    reader: R,
     command_sender: CommandSender,
 ) {
-    let mut accumulator = FrameAccumulator::new();
-    let mut sequence_counter: u16 = 0;
-
-    // Get publisher for sending parse error responses
-    let response_pub = RESPONSE_CHANNEL.immediate_publisher();
+    let mut reader = FrameReader::new(
+        reader,
+        CommandSource::Serial,
+        command_sender.into(),
+        ADMIN_CHANNEL.dyn_sender(),
+        RESPONSE_CHANNEL.dyn_immediate_publisher(),
+    );
 
     loop {
-        // Read bytes from serial
-        let mut buf = [0u8; 64];
-        match reader.read(&mut buf).await {
-            Ok(0) => continue,
-            Ok(n) => {
-                // Process each byte through the frame accumulator
-                for &byte in &buf[..n] {
-                    if let Some(frame) = accumulator.push(byte) {
-                        // Frame complete, try to decode and parse
-                        let seq_id = sequence_counter;
-                        sequence_counter = sequence_counter.wrapping_add(1);
+        watchdog::pulse(WatchedTask::Serial);
 
-                        match process_frame(frame) {
-                            Some(ReadResult::Command(cmd)) => {
-                                let envelope = CommandEnvelope {
-                                    command: cmd,
-                                    source: CommandSource::Serial,
-                                    sequence_id: seq_id,
-                                };
-                                command_sender.send(envelope).await;
-                            }
-                            Some(ReadResult::ParseError(status, cmd_id)) => {
-                                let response = Response::error_raw(status, cmd_id);
-                                let msg = ResponseMessage::Command {
-                                    source: CommandSource::Serial,
-                                    sequence_id: seq_id,
-                                    response,
-                                };
-                                response_pub.publish_immediate(msg);
-                            }
-                            None => {
-                                // Invalid frame, ignore
-                            }
-                        }
-                    }
-                }
-            }
-            Err(_) => {
-                // UART error, just continue
-                embassy_time::Timer::after(embassy_time::Duration::from_millis(10)).await;
-            }
+        if reader.step().await.is_err() {
+            // Port error, e.g. the host disconnected; back off before retrying
+            Timer::after(Duration::from_millis(10)).await;
         }
     }
 }
 
-/// Process a complete COBS frame (delimiter included).
-fn process_frame(
-    frame: heapless::Vec<u8, { config::protocol::MAX_FRAME_SIZE }>,
-) -> Option<ReadResult> {
-    let decoded = match wt_protocol::cobs_decode(&frame) {
-        Ok(d) => d,
-        Err(_) => return None,
-    };
-
-    if decoded.is_empty() {
-        return None;
-    }
-
-    // Byte 1 is the command id (byte 0 is the protocol version); echoed back on error.
-    let command_id = decoded.get(1).copied().unwrap_or(0);
-
-    match wt_protocol::parse_command(&decoded) {
-        Ok(cmd) => Some(ReadResult::Command(cmd)),
-        Err(status) => Some(ReadResult::ParseError(status, command_id)),
-    }
-}
-
 /// Task that writes responses to a serial interface.
 ///
 /// Generic over any type implementing `embedded_io_async::Write`.
@@ -117,25 +54,16 @@ pub async fn serial_writer_task<W: Write>(mut writer: W) {
     loop {
         let msg = response_sub.next_message_pure().await;
 
-        // Filter and process messages
-        let response = match msg {
-            ResponseMessage::Command { source, response, .. } => {
-                // Only process responses for Serial source
-                if source == CommandSource::Serial {
-                    Some(response)
-                } else {
-                    None
-                }
-            }
-            ResponseMessage::Unsolicited(response) => {
-                // Always process unsolicited packets
-                Some(response)
-            }
-        };
-
-        if let Some(response) = response {
-            let encoded = wt_protocol::encode_response(&response);
+        // Serial command responses and every unsolicited packet, with the
+        // sequence trailer if the host asked for it
+        if let Some((response, trailer)) = msg.for_interface(CommandSource::Serial) {
+            let encoded = crate::framing::encode_response(&response, trailer);
             let _ = writer.write_all(&encoded).await;
+            // The device resets once this is out, so push it to the host now
+            if admin::is_reboot_ack(&response) {
+                let _ = writer.flush().await;
+                REBOOT_ACK_SENT.signal(());
+            }
         }
     }
 }
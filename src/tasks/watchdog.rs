@@ -0,0 +1,85 @@
+//! Watchdog task that reboots the device if a core task hangs
+//!
+//! The LoRa, serial reader and BLE tasks each pulse a heartbeat on every loop
+//! iteration (tasks that wait on input time out and pulse while idle). This
+//! task feeds the hardware watchdog only while every heartbeat is fresh, so a
+//! wedged SPI transaction or IRQ wait stops the feeding and the chip resets.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::timer::timg::{MwdtStage, MwdtStageAction, Wdt};
+
+use crate::config::watchdog::{FEED_INTERVAL_MS, HW_TIMEOUT_SECS, TASK_TIMEOUT_SECS};
+
+/// Tasks whose heartbeat is monitored
+#[derive(Clone, Copy, Debug)]
+pub enum WatchedTask {
+    Lora = 0,
+    Serial = 1,
+    Ble = 2,
+}
+
+/// Number of monitored tasks
+const TASK_COUNT: usize = 3;
+
+/// Names for the debug log, indexed by `WatchedTask`
+const TASK_NAMES: [&str; TASK_COUNT] = ["LoRa", "serial", "BLE"];
+
+/// Time of each task's last heartbeat, in milliseconds since boot (wrapping)
+static LAST_PULSE_MS: [AtomicU32; TASK_COUNT] = [const { AtomicU32::new(0) }; TASK_COUNT];
+
+/// Current time in milliseconds, truncated to wrap every ~49 days
+fn now_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+/// Record a heartbeat for `task`
+pub fn pulse(task: WatchedTask) {
+    LAST_PULSE_MS[task as usize].store(now_ms(), Ordering::Relaxed);
+}
+
+/// Record a heartbeat for every task
+///
+/// Used after light sleep, during which no task can run.
+pub fn pulse_all() {
+    let now = now_ms();
+    for last in LAST_PULSE_MS.iter() {
+        last.store(now, Ordering::Relaxed);
+    }
+}
+
+/// Index of the first task whose heartbeat is older than the timeout, if any
+fn stale_task(now: u32) -> Option<usize> {
+    let timeout_ms = (TASK_TIMEOUT_SECS * 1000) as u32;
+    LAST_PULSE_MS
+        .iter()
+        .position(|last| now.wrapping_sub(last.load(Ordering::Relaxed)) > timeout_ms)
+}
+
+/// Task that feeds the hardware watchdog while every core task is alive
+///
+/// Arms the MWDT to reset the system after `HW_TIMEOUT_SECS` without a feed.
+/// Once any heartbeat goes stale the feeding stops for good, so the device
+/// reboots `HW_TIMEOUT_SECS` later.
+pub async fn watchdog_task<TG: esp_hal::timer::timg::TimerGroupInstance>(mut wdt: Wdt<TG>) {
+    // Tasks have not started looping yet; give them a full timeout.
+    pulse_all();
+
+    wdt.set_timeout(MwdtStage::Stage0, esp_hal::time::Duration::from_secs(HW_TIMEOUT_SECS));
+    wdt.set_stage_action(MwdtStage::Stage0, MwdtStageAction::ResetSystem);
+    wdt.enable();
+
+    loop {
+        if let Some(index) = stale_task(now_ms()) {
+            crate::error!("Watchdog: {} task stalled, rebooting", TASK_NAMES[index]);
+            // Stop feeding; the hardware watchdog resets the chip.
+            loop {
+                Timer::after(Duration::from_secs(1)).await;
+            }
+        }
+
+        wdt.feed();
+        Timer::after(Duration::from_millis(FEED_INTERVAL_MS)).await;
+    }
+}
@@ -0,0 +1,127 @@
+//! WiFi command server (`wifi` feature)
+//!
+//! Keeps the station joined to the network in `config::wifi` and serves the
+//! same COBS-framed protocol as the serial port over TCP, one client at a
+//! time. Commands are tagged `CommandSource::WiFi`, and the client gets their
+//! responses plus every unsolicited packet and event.
+
+use embassy_futures::select::select;
+use embassy_net::tcp::{Error as TcpError, TcpReader, TcpSocket};
+use embassy_net::{Runner, Stack};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{ErrorType, Read, Write};
+use esp_radio::wifi::{ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent};
+
+use crate::config;
+use crate::dispatcher::{reset_interface, CommandSource, FrameReader, RESPONSE_CHANNEL};
+use super::admin::{self, ADMIN_CHANNEL, REBOOT_ACK_SENT};
+use super::serial::CommandSender;
+
+/// TCP reader that reports the client closing as an error, so the frame
+/// reader loop ends instead of spinning on zero-length reads
+struct ClientReader<'a>(TcpReader<'a>);
+
+impl ErrorType for ClientReader<'_> {
+    type Error = TcpError;
+}
+
+impl Read for ClientReader<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.0.read(buf).await? {
+            0 if !buf.is_empty() => Err(TcpError::ConnectionReset),
+            n => Ok(n),
+        }
+    }
+}
+
+/// Task that joins the network and rejoins whenever the link drops
+pub async fn wifi_connection_task(mut controller: WifiController<'static>) {
+    let client = ClientConfig::default()
+        .with_ssid(config::wifi::SSID.into())
+        .with_password(config::wifi::PASSWORD.into());
+    if controller.set_config(&ModeConfig::Client(client)).is_err() {
+        crate::error!("WiFi: invalid configuration, server disabled");
+        return;
+    }
+
+    loop {
+        if !matches!(controller.is_started(), Ok(true)) && controller.start_async().await.is_err() {
+            crate::error!("WiFi: failed to start");
+        } else if controller.connect_async().await.is_ok() {
+            crate::debug!("WiFi: joined {}", config::wifi::SSID);
+            controller.wait_for_event(WifiEvent::StaDisconnected).await;
+            crate::debug!("WiFi: disconnected");
+        } else {
+            crate::error!("WiFi: failed to join {}", config::wifi::SSID);
+        }
+        Timer::after(Duration::from_secs(config::wifi::RECONNECT_SECS)).await;
+    }
+}
+
+/// Task that drives the network stack
+pub async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) -> ! {
+    runner.run().await
+}
+
+/// Task that serves the command protocol to one TCP client at a time
+pub async fn wifi_command_task(stack: Stack<'static>, command_sender: CommandSender) {
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 2048];
+
+    loop {
+        stack.wait_config_up().await;
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        if socket.accept(config::wifi::TCP_PORT).await.is_err() {
+            continue;
+        }
+        crate::debug!("WiFi: client connected");
+
+        serve(&mut socket, command_sender).await;
+
+        // The next client starts with plain frames
+        reset_interface(CommandSource::WiFi);
+        socket.close();
+        let _ = socket.flush().await;
+        crate::debug!("WiFi: client disconnected");
+    }
+}
+
+/// Relay frames between the connected client and the dispatcher until either
+/// side of the connection fails
+async fn serve(socket: &mut TcpSocket<'_>, command_sender: CommandSender) {
+    // Subscribe per connection so a new client does not get a backlog of
+    // packets received while nobody was connected
+    let Ok(mut responses) = RESPONSE_CHANNEL.subscriber() else {
+        crate::error!("WiFi: no response subscriber free");
+        return;
+    };
+    let (reader, mut writer) = socket.split();
+    let mut reader = FrameReader::new(
+        ClientReader(reader),
+        CommandSource::WiFi,
+        command_sender.into(),
+        ADMIN_CHANNEL.dyn_sender(),
+        RESPONSE_CHANNEL.dyn_immediate_publisher(),
+    );
+
+    let read_loop = async {
+        while reader.step().await.is_ok() {}
+    };
+    let write_loop = async {
+        loop {
+            let msg = responses.next_message_pure().await;
+            if let Some((response, trailer)) = msg.for_interface(CommandSource::WiFi) {
+                let encoded = crate::framing::encode_response(&response, trailer);
+                if writer.write_all(&encoded).await.is_err() {
+                    break;
+                }
+                if admin::is_reboot_ack(&response) {
+                    let _ = writer.flush().await;
+                    REBOOT_ACK_SENT.signal(());
+                }
+            }
+        }
+    };
+    select(read_loop, write_loop).await;
+}
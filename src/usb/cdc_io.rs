@@ -36,6 +36,8 @@ impl<'d, D: Driver<'d>> Read for CdcReader<'d, D> {
         // Wait for DTR (Data Terminal Ready) before reading
         self.inner.wait_connection().await;
 
+        // A disconnect fails the read (the endpoint is disabled), which the
+        // serial reader takes as its cue to drop any half-received frame
         match self.inner.read_packet(buf).await {
             Ok(n) => Ok(n),
             Err(_) => Err(CdcError),